@@ -21,6 +21,24 @@ pub trait EnergyModel {
         sequence: &[Base], 
         nn_loop: &NearestNeighborLoop
     ) -> i32;
+
+    /// Free energy of a loop with a strand break between the positions
+    /// `nick` and `nick + 1` of the (concatenated) sequence. Defaults to
+    /// the energy of the loop without the break, for models that do not
+    /// support multiple strands.
+    fn energy_of_nicked_loop(&self, 
+        sequence: &[Base], 
+        nn_loop: &NearestNeighborLoop,
+        _nick: usize,
+    ) -> i32 {
+        self.energy_of_loop(sequence, nn_loop)
+    }
+
+    /// Free energy penalty for every additional strand in a complex.
+    /// Defaults to no penalty.
+    fn association_energy(&self) -> i32 {
+        0
+    }
 }
 
 #[cfg(test)]
@@ -58,6 +76,7 @@ mod tests {
         ) -> i32 {
             5 
         }
+
     }
 
     #[test]
@@ -84,6 +103,9 @@ mod tests {
 
         let energy = model.energy_of_loop(&sequence, &nn_loop);
         assert_eq!(energy, 5);
+        // Models without multi-strand support ignore nicks and strand association.
+        assert_eq!(model.energy_of_nicked_loop(&sequence, &nn_loop, 1), 5);
+        assert_eq!(model.association_energy(), 0);
     }
 }
 
@@ -22,7 +22,10 @@ use crate::K0;
 /// parameter files, but only the standard
 /// settings (dangle=2, tetraloops, etc.)
 ///
-/// Only single-stranded folding is supported.
+/// Multi-stranded complexes are supported on the level of individual
+/// loops: a loop that contains a strand break is evaluated as exterior 
+/// loop (see `energy_of_nicked_loop`), and the duplex initiation term is 
+/// exposed as `association_energy`.
 ///
 pub struct ViennaRNA {
     min_hp_size: usize,
@@ -366,6 +369,44 @@ impl EnergyModel for ViennaRNA {
             }
         }
     }
+
+    fn energy_of_nicked_loop(&self, 
+        sequence: &[Base], 
+        nn_loop: &NearestNeighborLoop, 
+        nick: usize
+    ) -> i32 {
+        // A loop with a strand break is evaluated like an exterior loop: we
+        // walk around the loop starting right after the nick, such that the
+        // closing pair (if any) becomes an ordinary (reversed) branch.
+        let (i, j) = nn_loop.closing()
+            .map(|(i, j)| (i as usize, j as usize))
+            .expect("The exterior loop cannot contain a nick.");
+
+        let mut ranges = Vec::new();
+        let mut start = i;
+        for (k, l) in nn_loop.pairs().into_iter().skip(1) {
+            ranges.push((start, k as usize));
+            start = l as usize;
+        }
+        ranges.push((start, j));
+
+        let s = ranges.iter()
+            .position(|&(a, b)| a <= nick && nick < b)
+            .unwrap_or_else(|| panic!("Nick {} is not part of loop {}", nick, nn_loop));
+
+        let (a, b) = ranges[s];
+        let mut rotated: Vec<&[Base]> = Vec::with_capacity(ranges.len() + 1);
+        rotated.push(&sequence[nick + 1..=b]);
+        for &(p, q) in ranges[s + 1..].iter().chain(&ranges[..s]) {
+            rotated.push(&sequence[p..=q]);
+        }
+        rotated.push(&sequence[a..=nick]);
+        self.exterior(&rotated)
+    }
+
+    fn association_energy(&self) -> i32 {
+        self.energy_tables.misc.duplex_initiation_en37
+    }
 }
 
 #[cfg(test)]
@@ -611,6 +652,39 @@ mod tests {
     }
 
  
+    #[test]
+    fn test_vrna_nicked_loops() {
+        let model = ViennaRNA::default();
+
+        // A nicked hairpin is an exterior loop with the reversed closing pair.
+        let seq = NucleotideVec::from_lossy("GAAAC");
+        let hp = NearestNeighborLoop::Hairpin { closing: (0, 4) };
+        let seg1 = &NucleotideVec::from_lossy("AAC");
+        let seg2 = &NucleotideVec::from_lossy("GA");
+        assert_eq!(model.energy_of_nicked_loop(&seq, &hp, 1), model.exterior(&[seg1, seg2]));
+
+        // The nick between the two helices of an interior loop.
+        let seq = NucleotideVec::from_lossy("GAGAAACUC");
+        let il = NearestNeighborLoop::Interior { closing: (0, 8), inner: (2, 7) };
+        let seg1 = &NucleotideVec::from_lossy("G");
+        let seg2 = &NucleotideVec::from_lossy("UC");
+        let seg3 = &NucleotideVec::from_lossy("GA");
+        assert_eq!(model.energy_of_nicked_loop(&seq, &il, 1), model.exterior(&[seg1, seg2, seg3]));
+
+        // GGGGG+CCCCC: 4 stacks and the duplex initiation penalty.
+        let seq = NucleotideVec::from_lossy("GGGGGCCCCC");
+        let pt = PairTable::try_from("((((()))))").expect("valid");
+        let mut total = model.association_energy();
+        pt.for_each_loop(|l| {
+            total += if l.closing() == Some((4, 5)) {
+                model.energy_of_nicked_loop(&seq, l, 4)
+            } else {
+                model.energy_of_loop(&seq, l)
+            };
+        });
+        assert_eq!(total, -910);
+    }
+
     #[test]
     fn test_evaluations() {
         let model = ViennaRNA::default();
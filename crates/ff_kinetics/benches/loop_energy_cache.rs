@@ -0,0 +1,102 @@
+//! Is it worth to share loop energies between trajectories?
+//!
+//! Compares evaluating the loops of random structures with looking them up
+//! by `NearestNeighborLoop::loop_key` in a map that is shared between
+//! threads. With the default ViennaRNA model, the lookup is five to eight
+//! times slower than the evaluation (500-2500 nt, 4 threads), which is why
+//! ff_kinetics has no loop energy cache.
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::sync::RwLock;
+use std::hint::black_box;
+use ahash::AHashMap;
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::Criterion;
+
+use ff_structure::PairTable;
+use ff_energy::NucleotideVec;
+use ff_energy::NearestNeighborLoop;
+use ff_energy::LoopDecomposition;
+use ff_energy::EnergyModel;
+use ff_energy::ViennaRNA;
+
+const THREADS: usize = 4;
+
+/// Loop energies by (input index, loop key).
+type SharedCache = RwLock<AHashMap<(usize, Vec<u32>), i32>>;
+
+/// All (sequence, loops) of a benchmark file.
+fn read_loops(path: &str) -> Vec<(NucleotideVec, Vec<NearestNeighborLoop>)> {
+    let reader = BufReader::new(File::open(path).expect("Cannot open input file"));
+    let mut lines = reader.lines();
+    let mut result = Vec::new();
+    while let Some(Ok(header)) = lines.next() {
+        if !header.starts_with('>') {
+            panic!("Malformed benchmarking input.");
+        }
+        let sequence = lines.next().unwrap().unwrap();
+        let structure = lines.next().unwrap().unwrap();
+        let pairings = PairTable::try_from(structure.as_str()).expect("invalid structure in input");
+        result.push((NucleotideVec::try_from(sequence.as_str()).unwrap(), pairings.loops()));
+    }
+    result
+}
+
+/// Evaluate every loop on THREADS threads, either directly or by a lookup
+/// in the shared map.
+fn loop_energies(
+    inputs: &[(NucleotideVec, Vec<NearestNeighborLoop>)],
+    model: &ViennaRNA,
+    cache: Option<&SharedCache>,
+) {
+    std::thread::scope(|scope| {
+        for thread in 0..THREADS {
+            scope.spawn(move || {
+                for (k, (sequence, loops)) in inputs.iter().enumerate().skip(thread).step_by(THREADS) {
+                    for l in loops {
+                        let energy = match cache {
+                            Some(cache) => *cache.read().unwrap()
+                                .get(&(k, l.loop_key()))
+                                .expect("all loops are cached"),
+                            None => model.energy_of_loop(sequence, l),
+                        };
+                        black_box(energy);
+                    }
+                }
+            });
+        }
+    });
+}
+
+const INPUT_L500: &str = concat!(env!("CARGO_MANIFEST_DIR"),
+    "/benches/data/benchmark_random_structures_len500.vrna");
+
+const INPUT_L1000: &str = concat!(env!("CARGO_MANIFEST_DIR"),
+    "/benches/data/benchmark_random_structures_len1000.vrna");
+
+const INPUT_L2500: &str = concat!(env!("CARGO_MANIFEST_DIR"),
+    "/benches/data/benchmark_random_structures_len2500.vrna");
+
+fn loop_energy_cache_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("Shared loop energy cache.");
+    let model = &ViennaRNA::default();
+    for (name, path) in [("0500", INPUT_L500), ("1000", INPUT_L1000), ("2500", INPUT_L2500)] {
+        let inputs = read_loops(path);
+        let cache: SharedCache = RwLock::new(inputs.iter().enumerate()
+            .flat_map(|(k, (sequence, loops))| loops.iter()
+                .map(move |l| ((k, l.loop_key()), model.energy_of_loop(sequence, l))))
+            .collect::<AHashMap<_, _>>());
+        group.bench_function(format!("evaluated_len_{}", name), |b| {
+            b.iter(|| loop_energies(&inputs, model, None))
+        });
+        group.bench_function(format!("cached_len_{}", name), |b| {
+            b.iter(|| loop_energies(&inputs, model, Some(&cache)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, loop_energy_cache_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,174 @@
+//! Checkpoints of long-running stochastic simulations.
+//!
+//! A [`Checkpoint`] stores everything needed to continue an ensemble of
+//! simulations after the process has been killed: the merged timeline and
+//! first-passage times of all completed trajectories, and a snapshot of every
+//! trajectory that is still in progress (current structure, simulation time,
+//! partial timeline and random number generator state).
+//!
+//! Trajectories are resumed with
+//! [`LoopStructureSSA::simulate_from`](crate::LoopStructureSSA::simulate_from).
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use rand_chacha::ChaCha8Rng;
+
+use crate::timeline_io::SerializableTimeline;
+use crate::first_passage::FirstPassageTimes;
+
+/// The (serializable) random number generator used for checkpointed trajectories.
+pub type CheckpointRng = ChaCha8Rng;
+
+/// Snapshot of a trajectory that is still in progress.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrajectoryCheckpoint {
+    /// Trajectory identifier (unique within a checkpoint).
+    pub id: usize,
+    /// Simulation time of the snapshot.
+    pub time: f64,
+    /// The structure (dot-bracket) at `time`.
+    pub structure: String,
+    /// Index of the next output time point.
+    pub t_idx: usize,
+    /// Random number generator state.
+    pub rng: CheckpointRng,
+    /// The output time points recorded so far.
+    pub timeline: SerializableTimeline,
+}
+
+/// The state of an ensemble of simulations.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Input sequence, to make sure we resume the same system.
+    pub sequence: String,
+    /// Total number of requested trajectories.
+    pub num_sims: usize,
+    /// Number of completed trajectories.
+    pub completed: usize,
+    /// Merged timeline of all completed trajectories.
+    pub timeline: SerializableTimeline,
+    /// First-passage times of all completed trajectories.
+    pub fpt: FirstPassageTimes,
+    /// Snapshots of the trajectories in progress.
+    pub in_progress: Vec<TrajectoryCheckpoint>,
+    /// All trajectory ids below this one have been given out (completed,
+    /// in progress or scheduled), so new trajectories start here. With a
+    /// seed, the id is the random stream of the trajectory.
+    pub next_id: usize,
+}
+
+impl Checkpoint {
+    /// Number of trajectories that have not been started yet.
+    pub fn num_pending(&self) -> usize {
+        self.num_sims.saturating_sub(self.completed + self.in_progress.len())
+    }
+
+    /// Write the checkpoint as JSON. The file is replaced atomically, such
+    /// that an interrupted write never corrupts an existing checkpoint.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, serde_json::to_string(self)?)?;
+        fs::rename(&tmp, path)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let data = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&data)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use rand::Rng;
+    use rand::SeedableRng;
+    use ff_structure::PairTable;
+    use ff_structure::DotBracketVec;
+    use ff_energy::EnergyModel;
+    use ff_energy::ViennaRNA;
+    use ff_energy::NucleotideVec;
+    use crate::Metropolis;
+    use crate::LoopStructure;
+    use crate::LoopStructureSSA;
+    use crate::MacrostateRegistry;
+    use crate::timeline::Timeline;
+
+    #[test]
+    fn test_checkpoint_roundtrip_and_resume() {
+        let emodel = ViennaRNA::default();
+        let rmodel = Metropolis::new(emodel.temperature(), 1.0);
+        let sequence = NucleotideVec::try_from("GGGGAAAACCCC").unwrap();
+        let registry = Arc::new(MacrostateRegistry::from((&sequence, &emodel)));
+        let times = [1.0, 10.0, 100.0];
+
+        // Run a trajectory until t = 5 and take a snapshot.
+        let mut rng = CheckpointRng::seed_from_u64(1);
+        let pairings = PairTable::try_from("............").unwrap();
+        let loops = LoopStructure::try_from((&sequence[..], &pairings, &emodel)).unwrap();
+        let mut simulator = LoopStructureSSA::from((loops, &rmodel));
+        let mut timeline = Timeline::new(&times, Arc::clone(&registry));
+        let mut t_idx = 0;
+        let mut snapshot = (0., String::new());
+        simulator.simulate(&mut rng, 5.0, |t, tinc, _, ls| {
+            snapshot = (t, ls.to_string());
+            while t_idx < times.len() && t + tinc >= times[t_idx] && t + tinc < 5.0 {
+                timeline.assign_structure(t_idx, &DotBracketVec::from(ls));
+                t_idx += 1;
+            }
+            true
+        });
+
+        let checkpoint = Checkpoint {
+            sequence: sequence.to_string(),
+            num_sims: 3,
+            completed: 1,
+            timeline: Timeline::new(&times, Arc::clone(&registry)).to_serializable(),
+            fpt: FirstPassageTimes::new(100.0),
+            in_progress: vec![TrajectoryCheckpoint {
+                id: 1,
+                time: snapshot.0,
+                structure: snapshot.1.clone(),
+                t_idx,
+                rng: rng.clone(),
+                timeline: timeline.to_serializable(),
+            }],
+            next_id: 3,
+        };
+        assert_eq!(checkpoint.num_pending(), 1);
+
+        let path = std::env::temp_dir().join("ff_kinetics_checkpoint_test.json");
+        checkpoint.save(&path).unwrap();
+        let loaded = Checkpoint::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.next_id, 3);
+        let traj = &loaded.in_progress[0];
+        assert_eq!(traj.structure, snapshot.1);
+        assert_eq!(traj.t_idx, t_idx);
+        let mut resumed_rng = traj.rng.clone();
+        assert_eq!(resumed_rng.random::<u64>(), rng.clone().random::<u64>());
+
+        // Resume and complete the trajectory.
+        let mut timeline = Timeline::from_serializable(traj.timeline.clone(),
+            &times, Arc::clone(&registry)).unwrap();
+        let pairings = PairTable::try_from(traj.structure.as_str()).unwrap();
+        let loops = LoopStructure::try_from((&sequence[..], &pairings, &emodel)).unwrap();
+        let mut simulator = LoopStructureSSA::from((loops, &rmodel));
+        let mut t_idx = traj.t_idx;
+        simulator.simulate_from(&mut resumed_rng, traj.time, times[2], |t, tinc, _, ls| {
+            assert!(t >= traj.time);
+            while t_idx < times.len() && t + tinc >= times[t_idx] {
+                timeline.assign_structure(t_idx, &DotBracketVec::from(ls));
+                t_idx += 1;
+            }
+            true
+        });
+        assert_eq!(t_idx, times.len());
+        assert!(timeline.iter().all(|(_, tp)| tp.iter().map(|(_, c)| c).sum::<f64>() == 1.0));
+    }
+}
@@ -1,6 +1,7 @@
 use std::sync::Arc;
 use std::convert::TryFrom;
 use rand::rng;
+use rand::Rng;
 use ndarray::Array2;
 use ff_structure::PairTable;
 use ff_structure::DotBracketVec;
@@ -59,9 +60,35 @@ impl ReactiveTrajectoryEnsemble {
     }
 }
 
+/// Coarse-grained kinetics between macrostates.
+///
+/// Every macrostate α is left at rate `k_α` (the equilibrium flux out of
+/// α, see [`ExitMacrostate`](crate::commit_and_delay::ExitMacrostate)).
+/// Reactive trajectories start from exit structures of α and end when they
+/// enter any macrostate β (possibly α itself), which yields the *commit*
+/// probabilities p(α→β) and the *delay* times τ(α→β) spent outside of all
+/// macrostates.
+///
+/// Failed attempts (β = α) repeat geometrically, such that the expected
+/// time to leave α for good is
+///
+/// ```text
+/// T_α = (p_αα / p_esc) (1/k_α + τ_αα) + 1/k_α + τ_esc,
+/// ```
+///
+/// with escape probability p_esc = 1 - p_αα and τ_esc the mean delay of
+/// escaping trajectories. The coarse-grained rate is then
+/// k(α→β) = p(α→β) / (p_esc T_α).
+///
+/// Trajectories that do not reach a macrostate within `t_max` are censored.
+/// They count as failed attempts that spent `t_max` outside of all
+/// macrostates, so with censoring the rates are lower bounds.
 pub struct CommitAndDelay<'a, E: EnergyModel, R: RateModel> {
     exit_registry: Arc<ExitMacrostateRegistry<'a, E, R>>,
     trajectories: Array2<Option<ReactiveTrajectoryEnsemble>>,
+    /// Trajectories that did not reach a macrostate within t_max.
+    censored: Vec<usize>,
+    t_max: f64,
 }
 
 impl<'a, E: EnergyModel, R: RateModel> From<Arc<ExitMacrostateRegistry<'a, E, R>>> 
@@ -72,12 +99,64 @@ for CommitAndDelay<'a, E, R> {
         Self {
             exit_registry,
             trajectories: Array2::from_elem((n, n), None),
+            censored: vec![0; n],
+            t_max: f64::MAX,
         }
     }
 }
 
+/// Coarse-grained rates out of one macrostate from the outcomes (target
+/// macrostate, delay) of its reactive trajectories. The diagonal entry
+/// holds the negative total rate.
+fn rates_from_outcomes(source: usize, k_alpha: f64, outcomes: &[(usize, f64)], n: usize) -> Vec<f64> {
+    let mut rates = vec![0.0; n];
+    if outcomes.is_empty() || k_alpha <= 0.0 {
+        return rates;
+    }
+    let total = outcomes.len() as f64;
+    let (returns, escapes): (Vec<_>, Vec<_>) = outcomes.iter().partition(|(b, _)| *b == source);
+    if escapes.is_empty() {
+        return rates;
+    }
+    let mean = |v: &[&(usize, f64)]| if v.is_empty() {
+        0.0
+    } else {
+        v.iter().map(|(_, t)| t).sum::<f64>() / v.len() as f64
+    };
+    let p_return = returns.len() as f64 / total;
+    let p_esc = escapes.len() as f64 / total;
+    let t_exit = (p_return / p_esc) * (1.0 / k_alpha + mean(&returns))
+        + 1.0 / k_alpha + mean(&escapes);
+
+    for &&(b, _) in &escapes {
+        rates[b] += 1.0 / (escapes.len() as f64 * t_exit);
+    }
+    rates[source] = -rates.iter().sum::<f64>();
+    rates
+}
+
 impl<'a, E: EnergyModel, R: RateModel> CommitAndDelay<'a, E, R> {
 
+    /// Stop reactive trajectories that do not reach a macrostate within t_max.
+    pub fn with_t_max(mut self, t_max: f64) -> Self {
+        self.t_max = t_max;
+        self
+    }
+
+    /// Number of reactive trajectories from a macrostate (including censored ones).
+    pub fn num_trajectories(&self, start_id: MacrostateID) -> usize {
+        self.censored[start_id] + self.trajectories.row(start_id)
+            .iter()
+            .map(|ens| ens.as_ref().map_or(0, |e| e.len()))
+            .sum::<usize>()
+    }
+
+    pub fn num_censored(&self, start_id: MacrostateID) -> usize {
+        self.censored[start_id]
+    }
+
+    /// Simulate one reactive trajectory, starting from an exit structure of
+    /// the macrostate, until it enters any macrostate.
     pub fn simulate_from(&mut self, start_id: MacrostateID) {
         let sequence = self.exit_registry.parent_registry().sequence();
         let energy_model = self.exit_registry.parent_registry().energy_model();
@@ -93,9 +172,10 @@ impl<'a, E: EnergyModel, R: RateModel> CommitAndDelay<'a, E, R> {
         let mut simulator = LoopStructureSSA::from((loops, rate_model));
 
         let mut mean_time = 0.0;
+        let mut reached = false;
         simulator.simulate(
             &mut rng(), 
-            f64::MAX,
+            self.t_max,
             |t, _tinc, flux, ls| {
                 let stop_db = DotBracketVec::from(ls);
                 let stop_id = self.exit_registry.parent_registry().classify(&stop_db);
@@ -119,12 +199,16 @@ impl<'a, E: EnergyModel, R: RateModel> CommitAndDelay<'a, E, R> {
                         })
                         .successes
                         .push(traj);
+                    reached = true;
                     return false;
                 }
                 mean_time += 1.0/flux;
                 true
             },
         );
+        if !reached {
+            self.censored[start_id] += 1;
+        }
     }
 
     pub fn simulate_between(&mut self, start_id: MacrostateID, stop_id: MacrostateID) {
@@ -189,12 +273,80 @@ impl<'a, E: EnergyModel, R: RateModel> CommitAndDelay<'a, E, R> {
         );
     }
 
-    pub fn gather_data(&mut self) {
-        // let's make an executable. 
+    /// Simulate `num_trajectories` reactive trajectories from every
+    /// macrostate that can be left.
+    pub fn gather_data(&mut self, num_trajectories: usize) {
+        for start_id in 1..self.exit_registry.len() {
+            if self.exit_registry.exit_macrostates()[start_id].is_empty() {
+                continue;
+            }
+            for _ in 0..num_trajectories {
+                self.simulate_from(start_id);
+            }
+        }
+    }
+
+    /// The (target, delay) outcomes of all reactive trajectories from a
+    /// macrostate. Censored trajectories return to the macrostate after t_max.
+    fn outcomes(&self, start_id: MacrostateID) -> Vec<(usize, f64)> {
+        self.trajectories.row(start_id)
+            .iter()
+            .flatten()
+            .flat_map(|ens| ens.successes.iter().map(|t| (ens.stop, t.mean_time)))
+            .chain(std::iter::repeat_n((start_id, self.t_max), self.censored[start_id]))
+            .collect()
+    }
+
+    fn rate_row(&self, start_id: MacrostateID, outcomes: &[(usize, f64)]) -> Vec<f64> {
+        let k_alpha = self.exit_registry.exit_macrostates()[start_id].k_alpha();
+        rates_from_outcomes(start_id, k_alpha, outcomes, self.exit_registry.len())
     }
 
+    /// The coarse-grained rate matrix, where entry (α, β) is the rate from
+    /// α to β and the diagonal holds the negative total outflow. Index 0
+    /// (unassigned) has no rates.
     pub fn to_rate_matrix(&self) -> Array2<f64> {
-        todo!("let's do only k_commit for now")
+        let n = self.exit_registry.len();
+        let mut matrix = Array2::zeros((n, n));
+        for a in 1..n {
+            let row = self.rate_row(a, &self.outcomes(a));
+            for (b, r) in row.into_iter().enumerate() {
+                matrix[(a, b)] = r;
+            }
+        }
+        matrix
+    }
+
+    /// The rate matrix together with standard errors, estimated from
+    /// `num_bootstrap` resamples of the reactive trajectories.
+    pub fn to_rate_matrix_with_errors<G: Rng>(&self, rng: &mut G, num_bootstrap: usize
+    ) -> (Array2<f64>, Array2<f64>) {
+        let n = self.exit_registry.len();
+        let matrix = self.to_rate_matrix();
+        let mut sum = Array2::<f64>::zeros((n, n));
+        let mut sum_sq = Array2::<f64>::zeros((n, n));
+        for a in 1..n {
+            let outcomes = self.outcomes(a);
+            if outcomes.is_empty() {
+                continue;
+            }
+            for _ in 0..num_bootstrap {
+                let sample: Vec<_> = (0..outcomes.len())
+                    .map(|_| outcomes[rng.random_range(0..outcomes.len())])
+                    .collect();
+                for (b, r) in self.rate_row(a, &sample).into_iter().enumerate() {
+                    sum[(a, b)] += r;
+                    sum_sq[(a, b)] += r * r;
+                }
+            }
+        }
+        let errors = if num_bootstrap < 2 {
+            Array2::zeros((n, n))
+        } else {
+            let b = num_bootstrap as f64;
+            ((&sum_sq - &(&sum * &sum) / b) / (b - 1.0)).mapv(|v| v.max(0.0).sqrt())
+        };
+        (matrix, errors)
     }
 }
 
@@ -293,6 +445,71 @@ mod tests {
         assert_eq!(cad.trajectories.get((1, 1)).and_then(|opt| opt.as_ref()).unwrap().len(), 3);
     }
 
+    #[test]
+    fn test_rates_from_outcomes() {
+        // Only returns: no coarse-grained transitions.
+        assert_eq!(rates_from_outcomes(1, 2.0, &[(1, 0.1), (1, 0.3)], 3), vec![0.0; 3]);
+
+        // Every attempt escapes: T = 1/k + tau.
+        let rates = rates_from_outcomes(1, 2.0, &[(2, 0.5), (2, 0.5)], 3);
+        assert!((rates[2] - 1.0).abs() < 1e-12);
+        assert!((rates[1] + 1.0).abs() < 1e-12);
+
+        // Half of the attempts return after 0.5, the others escape to
+        // 0 and 2 after 1.5: T = 1 * (0.5 + 0.5) + 0.5 + 1.5 = 3.0
+        let rates = rates_from_outcomes(1, 2.0, &[(1, 0.5), (1, 0.5), (0, 1.5), (2, 1.5)], 3);
+        assert!((rates[0] - 1.0 / 6.0).abs() < 1e-12);
+        assert!((rates[2] - 1.0 / 6.0).abs() < 1e-12);
+        assert!(rates.iter().sum::<f64>().abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_commit_and_delay_rate_matrix() {
+        let energy_model = ViennaRNA::default();
+        let seq = NucleotideVec::try_from("UCAGUCUUCGCUGCGCUGUAUCGAUUCGGUUUCAGUUUUUAUUGC").unwrap();
+        let mut registry = MacrostateRegistry::from((&seq, &energy_model));
+        registry.insert_from_reader(test_ms1(), "manual").unwrap();
+
+        let rate_model = Metropolis::new(energy_model.temperature(), 1.0);
+        let exitreg = ExitMacrostateRegistry::from((&registry, &rate_model));
+        let mut cad = CommitAndDelay::from(Arc::new(exitreg));
+        cad.gather_data(3);
+        assert_eq!(cad.num_trajectories(1), 3);
+        assert_eq!(cad.num_censored(1), 0);
+
+        // A single macrostate can only be re-entered.
+        let (matrix, errors) = cad.to_rate_matrix_with_errors(&mut rng(), 10);
+        assert_eq!(matrix.dim(), (2, 2));
+        assert!(matrix.iter().all(|&r| r == 0.0));
+        assert!(errors.iter().all(|&e| e == 0.0));
+    }
+
+    #[test]
+    fn test_commit_and_delay_censored() {
+        let energy_model = ViennaRNA::default();
+        let seq = NucleotideVec::try_from("UCAGUCUUCGCUGCGCUGUAUCGAUUCGGUUUCAGUUUUUAUUGC").unwrap();
+        let mut registry = MacrostateRegistry::from((&seq, &energy_model));
+        registry.insert_from_reader(test_ms1(), "manual").unwrap();
+        registry.insert_from_reader(test_ms2(), "manual").unwrap();
+
+        let rate_model = Metropolis::new(energy_model.temperature(), 1.0);
+        let exitreg = ExitMacrostateRegistry::from((&registry, &rate_model));
+        let mut cad = CommitAndDelay::from(Arc::new(exitreg)).with_t_max(0.0);
+        cad.gather_data(3);
+        assert_eq!(cad.num_trajectories(1), 3);
+        assert_eq!(cad.num_censored(1), 3);
+        assert_eq!(cad.outcomes(1), vec![(1, 0.0); 3]);
+
+        // Censored attempts are failed attempts that take t_max.
+        let esc = [(2, 0.5), (2, 0.5)];
+        let cens = [(2, 0.5), (2, 0.5), (1, 1.0), (1, 1.0)];
+        let rates = rates_from_outcomes(1, 2.0, &esc, 3);
+        let censored = rates_from_outcomes(1, 2.0, &cens, 3);
+        // T = 1 * (0.5 + 1.0) + 0.5 + 0.5 = 2.5
+        assert!((censored[2] - 0.4).abs() < 1e-12);
+        assert!(censored[2] < rates[2]);
+    }
+
     #[test]
     fn test_commit_and_delay() {
         let energy_model = ViennaRNA::default();
@@ -0,0 +1,303 @@
+//! First-passage times of stochastic trajectories.
+//!
+//! A trajectory is stopped as soon as it enters one of the target structures
+//! or macrostates of a [`StopCondition`], and the time of entry is recorded as
+//! its first-passage time. Trajectories that do not reach a target before the
+//! end of the simulation are *censored*: we only know that their first-passage
+//! time is larger than `t_max`.
+//!
+//! [`FirstPassageTimes`] collects these observations and summarizes the
+//! distribution. Quantiles are taken from the empirical distribution of *all*
+//! trajectories, where censored trajectories count as +infinity. Hence, a
+//! quantile is `None` if it falls into the censored fraction.
+
+use std::fmt;
+use ahash::AHashSet;
+use serde::{Serialize, Deserialize};
+use ff_structure::DotBracketVec;
+use ff_structure::PairTable;
+use ff_structure::StructureError;
+
+use crate::structure_hash;
+
+/// The quantiles reported by [`FirstPassageTimes::summary`].
+pub const FPT_QUANTILES: [f64; 5] = [0.05, 0.25, 0.5, 0.75, 0.95];
+
+/// Target structures and macrostates that end a trajectory.
+#[derive(Debug, Clone, Default)]
+pub struct StopCondition {
+    structures: AHashSet<DotBracketVec>,
+    /// Structure hashes of the stop structures.
+    hashes: AHashSet<u64>,
+    macrostates: Vec<usize>,
+}
+
+impl StopCondition {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stop when the trajectory enters this secondary structure. Fails if
+    /// the structure has unbalanced brackets.
+    pub fn add_structure(&mut self, structure: DotBracketVec) -> Result<(), StructureError> {
+        let pt = PairTable::try_from(&structure)?;
+        self.hashes.insert(structure_hash(&pt));
+        self.structures.insert(structure);
+        Ok(())
+    }
+
+    /// Stop when the trajectory enters the macrostate with this registry index.
+    pub fn add_macrostate(&mut self, macro_idx: usize) {
+        if !self.macrostates.contains(&macro_idx) {
+            self.macrostates.push(macro_idx);
+        }
+    }
+
+    /// True if no stop structures or macrostates have been specified.
+    pub fn is_empty(&self) -> bool {
+        self.structures.is_empty() && self.macrostates.is_empty()
+    }
+
+    /// True if macrostate targets are specified (i.e. structures need to be classified).
+    pub fn has_macrostates(&self) -> bool {
+        !self.macrostates.is_empty()
+    }
+
+    pub fn structures(&self) -> &AHashSet<DotBracketVec> {
+        &self.structures
+    }
+
+    pub fn macrostates(&self) -> &[usize] {
+        &self.macrostates
+    }
+
+    /// Check if the structure is one of the stop structures.
+    pub fn is_stop_structure(&self, structure: &DotBracketVec) -> bool {
+        self.structures.contains(structure)
+    }
+
+    /// Check if a structure hash (see [`structure_hash`]) is one of the stop structures.
+    pub fn is_stop_hash(&self, hash: u64) -> bool {
+        self.hashes.contains(&hash)
+    }
+
+    /// Check if the macrostate index is one of the stop macrostates.
+    pub fn is_stop_macrostate(&self, macro_idx: usize) -> bool {
+        self.macrostates.contains(&macro_idx)
+    }
+}
+
+/// First-passage times of an ensemble of trajectories simulated up to `t_max`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FirstPassageTimes {
+    /// Simulation stop time, i.e. the censoring time.
+    t_max: f64,
+    /// First-passage times of trajectories that reached a stop condition.
+    times: Vec<f64>,
+    /// Number of trajectories that did not reach a stop condition.
+    censored: usize,
+}
+
+impl FirstPassageTimes {
+    pub fn new(t_max: f64) -> Self {
+        Self {
+            t_max,
+            times: Vec::new(),
+            censored: 0,
+        }
+    }
+
+    /// Record the outcome of one trajectory (None = censored).
+    pub fn add(&mut self, fpt: Option<f64>) {
+        match fpt {
+            Some(t) => self.times.push(t),
+            None => self.censored += 1,
+        }
+    }
+
+    pub fn t_max(&self) -> f64 {
+        self.t_max
+    }
+
+    /// First-passage times of all trajectories that reached a stop condition.
+    pub fn times(&self) -> &[f64] {
+        &self.times
+    }
+
+    /// Number of trajectories that did not reach a stop condition.
+    pub fn num_censored(&self) -> usize {
+        self.censored
+    }
+
+    /// Total number of recorded trajectories.
+    pub fn len(&self) -> usize {
+        self.times.len() + self.censored
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Fraction of trajectories that did not reach a stop condition.
+    pub fn censored_fraction(&self) -> f64 {
+        if self.is_empty() {
+            0.0
+        } else {
+            self.censored as f64 / self.len() as f64
+        }
+    }
+
+    /// Mean first-passage time of the trajectories that reached a stop
+    /// condition (i.e. conditioned on not being censored).
+    pub fn mean(&self) -> Option<f64> {
+        if self.times.is_empty() {
+            None
+        } else {
+            Some(self.times.iter().sum::<f64>() / self.times.len() as f64)
+        }
+    }
+
+    /// The q-quantile of the first-passage time distribution, counting
+    /// censored trajectories as +infinity. Returns None if the quantile
+    /// lies within the censored fraction.
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        assert!((0.0..=1.0).contains(&q), "Quantile must be in [0, 1]");
+        if self.is_empty() {
+            return None;
+        }
+        let mut sorted = self.times.clone();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let rank = ((q * self.len() as f64).ceil() as usize).max(1);
+        sorted.get(rank - 1).copied()
+    }
+
+    pub fn median(&self) -> Option<f64> {
+        self.quantile(0.5)
+    }
+
+    /// Combine with the first-passage times of another ensemble.
+    pub fn merge(&mut self, other: FirstPassageTimes) {
+        assert!((self.t_max - other.t_max).abs() < 1e-9,
+            "Cannot merge first-passage times with different t_max");
+        self.times.extend(other.times);
+        self.censored += other.censored;
+    }
+
+    pub fn summary(&self) -> FirstPassageSummary {
+        FirstPassageSummary {
+            num_trajectories: self.len(),
+            num_censored: self.censored,
+            censored_fraction: self.censored_fraction(),
+            t_max: self.t_max,
+            mean: self.mean(),
+            median: self.median(),
+            quantiles: FPT_QUANTILES.iter().map(|&q| (q, self.quantile(q))).collect(),
+        }
+    }
+}
+
+/// Summary statistics of a first-passage time distribution.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FirstPassageSummary {
+    pub num_trajectories: usize,
+    pub num_censored: usize,
+    pub censored_fraction: f64,
+    pub t_max: f64,
+    /// Mean over the uncensored trajectories.
+    pub mean: Option<f64>,
+    pub median: Option<f64>,
+    /// Pairs of (q, q-quantile).
+    pub quantiles: Vec<(f64, Option<f64>)>,
+}
+
+impl fmt::Display for FirstPassageSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fmt_opt = |x: Option<f64>| match x {
+            Some(t) => format!("{:.6e}", t),
+            None => format!("> {:.6e}", self.t_max),
+        };
+        writeln!(f, "First-passage times ({} trajectories, t_max = {:e}):",
+            self.num_trajectories, self.t_max)?;
+        writeln!(f, " - {:<10} {} ({:.4})", "censored", self.num_censored, self.censored_fraction)?;
+        writeln!(f, " - {:<10} {}", "mean",
+            self.mean.map_or("NA".to_string(), |t| format!("{:.6e}", t)))?;
+        writeln!(f, " - {:<10} {}", "median", fmt_opt(self.median))?;
+        for (q, value) in &self.quantiles {
+            writeln!(f, " - {:<10} {}", format!("q{:.2}", q), fmt_opt(*value))?;
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use ff_structure::PairTable;
+    use ff_energy::EnergyModel;
+    use ff_energy::ViennaRNA;
+    use ff_energy::NucleotideVec;
+    use crate::Metropolis;
+    use crate::LoopStructure;
+    use crate::LoopStructureSSA;
+
+    #[test]
+    fn test_fpt_statistics() {
+        let mut fpt = FirstPassageTimes::new(10.0);
+        for t in [4.0, 1.0, 3.0, 2.0] {
+            fpt.add(Some(t));
+        }
+        fpt.add(None);
+        assert_eq!(fpt.len(), 5);
+        assert_eq!(fpt.num_censored(), 1);
+        assert!((fpt.censored_fraction() - 0.2).abs() < 1e-12);
+        assert_eq!(fpt.mean(), Some(2.5));
+        assert_eq!(fpt.median(), Some(3.0));
+        assert_eq!(fpt.quantile(0.0), Some(1.0));
+        assert_eq!(fpt.quantile(0.8), Some(4.0));
+        // Falls into the censored fraction.
+        assert_eq!(fpt.quantile(0.9), None);
+
+        let mut other = FirstPassageTimes::new(10.0);
+        other.add(None);
+        fpt.merge(other);
+        assert_eq!(fpt.len(), 6);
+        assert_eq!(fpt.num_censored(), 2);
+        assert_eq!(fpt.summary().quantiles.len(), FPT_QUANTILES.len());
+    }
+
+    #[test]
+    fn test_fpt_simulation_stops_at_target() {
+        let emodel = ViennaRNA::default();
+        let rmodel = Metropolis::new(emodel.temperature(), 1.0);
+        let mut rng = StdRng::seed_from_u64(42);
+
+        let sequence = NucleotideVec::try_from("GGGGAAAACCCC").unwrap();
+        let pairings = PairTable::try_from("............").unwrap();
+        let loops = LoopStructure::try_from((&sequence[..], &pairings, &emodel)).unwrap();
+        let mut simulator = LoopStructureSSA::from((loops, &rmodel));
+
+        let mut stop = StopCondition::new();
+        let target = DotBracketVec::try_from("((((....))))").unwrap();
+        stop.add_structure(target.clone()).unwrap();
+        assert!(stop.add_structure(DotBracketVec::try_from("((((....))).").unwrap()).is_err());
+        assert_eq!(stop.structures().len(), 1);
+
+        let mut fpt = FirstPassageTimes::new(1e6);
+        let mut reached = None;
+        simulator.simulate(&mut rng, fpt.t_max(), |t, _, _, ls| {
+            assert_eq!(stop.is_stop_hash(ls.structure_hash()),
+                stop.is_stop_structure(&DotBracketVec::from(ls)));
+            if stop.is_stop_hash(ls.structure_hash()) {
+                reached = Some(t);
+                return false;
+            }
+            true
+        });
+        fpt.add(reached);
+        assert_eq!(fpt.num_censored(), 0);
+        assert_eq!(simulator.current_structure(), format!("{}", target));
+    }
+}
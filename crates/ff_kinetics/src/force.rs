@@ -0,0 +1,316 @@
+//! Mechanical force on the ends of the molecule (optical tweezers).
+//!
+//! A force F pulls on the 5' and 3' ends. Only the exterior loop is
+//! stretched: its unpaired nucleotides form a freely-jointed chain (FJC)
+//! and every exterior helix is a rigid segment of length `helix_width`
+//! that aligns with the force. At force F, a structure gains the free
+//! energy
+//!
+//!   G(F) = -kT (n l / b) ln(sinh(Fb/kT) / (Fb/kT)) - h kT ln(sinh(Fd/kT) / (Fd/kT)),
+//!
+//! where n is the number of unpaired exterior nucleotides, h the number of
+//! exterior helices, l the contour length per nucleotide, b the Kuhn
+//! length and d the helix width. The mean end-to-end extension is
+//!
+//!   x(F) = n l L(Fb/kT) + h d L(Fd/kT),   L(y) = coth(y) - 1/y.
+//!
+//! [`ForceModel`] adds G(F) to the exterior loop energy of any energy
+//! model, so loop energies and reaction rates of the simulation account
+//! for the force. A constant loading rate is simulated as a sequence of
+//! constant-force segments (see [`ForceProtocol`]).
+
+use serde::{Serialize, Deserialize};
+use plotters::prelude::*;
+
+use ff_structure::PairTable;
+use ff_energy::Base;
+use ff_energy::EnergyModel;
+use ff_energy::LoopDecomposition;
+use ff_energy::NearestNeighborLoop;
+
+use crate::K0;
+use crate::piecewise::piecewise_constant;
+
+/// Boltzmann constant in pN nm / K.
+pub const KB_PN_NM: f64 = 0.01380649;
+/// 1 pN nm per molecule in kcal/mol.
+pub const KCAL_PER_PN_NM: f64 = 1.0 / 69.4786;
+
+/// ln(sinh(y) / y), numerically stable for small and large y.
+fn ln_sinh_ratio(y: f64) -> f64 {
+    if y < 1e-4 {
+        y * y / 6.0
+    } else {
+        y + (-(-2.0 * y).exp()).ln_1p() - (2.0 * y).ln()
+    }
+}
+
+/// The Langevin function coth(y) - 1/y.
+fn langevin(y: f64) -> f64 {
+    if y < 1e-4 {
+        y / 3.0
+    } else {
+        1.0 / y.tanh() - 1.0 / y
+    }
+}
+
+/// Elastic parameters of single- and double-stranded regions (nm).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ChainParameters {
+    /// Kuhn length of single-stranded RNA.
+    pub kuhn_length: f64,
+    /// Contour length per nucleotide.
+    pub nt_length: f64,
+    /// Length of an exterior helix along the force (the helix diameter).
+    pub helix_width: f64,
+}
+
+impl Default for ChainParameters {
+    fn default() -> Self {
+        Self { kuhn_length: 1.9, nt_length: 0.7, helix_width: 2.0 }
+    }
+}
+
+impl ChainParameters {
+    /// Free energy (pN nm) of the exterior loop at `force` (pN), relative
+    /// to zero force.
+    pub fn free_energy(&self, unpaired: usize, helices: usize, force: f64, kt: f64) -> f64 {
+        let segments = unpaired as f64 * self.nt_length / self.kuhn_length;
+        -kt * (segments * ln_sinh_ratio(force * self.kuhn_length / kt)
+            + helices as f64 * ln_sinh_ratio(force * self.helix_width / kt))
+    }
+
+    /// Mean end-to-end extension (nm) of the exterior loop at `force` (pN).
+    pub fn extension(&self, unpaired: usize, helices: usize, force: f64, kt: f64) -> f64 {
+        unpaired as f64 * self.nt_length * langevin(force * self.kuhn_length / kt)
+            + helices as f64 * self.helix_width * langevin(force * self.helix_width / kt)
+    }
+}
+
+/// Unpaired nucleotides and helices of the exterior loop.
+pub fn exterior_loop(length: usize, branches: &[(u16, u16)]) -> (usize, usize) {
+    let paired: usize = branches.iter().map(|&(i, j)| (j - i) as usize + 1).sum();
+    (length - paired, branches.len())
+}
+
+/// Unpaired nucleotides and helices of the exterior loop of a structure.
+pub fn exterior_loop_of(pt: &PairTable) -> (usize, usize) {
+    match pt.loop_enclosed_by(None) {
+        NearestNeighborLoop::Exterior { branches } => exterior_loop(pt.len(), &branches),
+        _ => unreachable!("The loop enclosed by no pair is the exterior loop"),
+    }
+}
+
+/// An energy model with a constant force on the ends of the molecule.
+pub struct ForceModel<'a, M: EnergyModel> {
+    model: &'a M,
+    force: f64,
+    chain: ChainParameters,
+    /// k_B T in pN nm.
+    kt: f64,
+}
+
+impl<'a, M: EnergyModel> ForceModel<'a, M> {
+    /// A force (pN) at the temperature of the energy model.
+    pub fn new(model: &'a M, force: f64, chain: ChainParameters) -> Self {
+        let kt = KB_PN_NM * (K0 + model.temperature());
+        Self { model, force, chain, kt }
+    }
+
+    pub fn force(&self) -> f64 {
+        self.force
+    }
+
+    pub fn chain(&self) -> &ChainParameters {
+        &self.chain
+    }
+
+    /// The force contribution (dcal/mol) to the exterior loop energy.
+    pub fn force_energy(&self, unpaired: usize, helices: usize) -> i32 {
+        let g = self.chain.free_energy(unpaired, helices, self.force, self.kt);
+        (g * KCAL_PER_PN_NM * 100.0).round() as i32
+    }
+
+    /// Mean end-to-end extension (nm) of a structure.
+    pub fn extension(&self, pt: &PairTable) -> f64 {
+        let (unpaired, helices) = exterior_loop_of(pt);
+        self.chain.extension(unpaired, helices, self.force, self.kt)
+    }
+}
+
+impl<'a, M: EnergyModel> EnergyModel for ForceModel<'a, M> {
+    fn can_pair(&self, b1: Base, b2: Base) -> bool {
+        self.model.can_pair(b1, b2)
+    }
+
+    fn min_hairpin_size(&self) -> usize {
+        self.model.min_hairpin_size()
+    }
+
+    fn temperature(&self) -> f64 {
+        self.model.temperature()
+    }
+
+    fn energy_of_structure<T: LoopDecomposition>(&self, sequence: &[Base], structure: &T) -> i32 {
+        let mut total = 0;
+        structure.for_each_loop(|l| total += self.energy_of_loop(sequence, l));
+        total
+    }
+
+    fn energy_of_loop(&self, sequence: &[Base], nn_loop: &NearestNeighborLoop) -> i32 {
+        let energy = self.model.energy_of_loop(sequence, nn_loop);
+        match nn_loop {
+            NearestNeighborLoop::Exterior { branches } => {
+                let (unpaired, helices) = exterior_loop(sequence.len(), branches);
+                energy + self.force_energy(unpaired, helices)
+            }
+            _ => energy,
+        }
+    }
+
+    fn energy_of_nicked_loop(&self, sequence: &[Base], nn_loop: &NearestNeighborLoop, nick: usize) -> i32 {
+        self.model.energy_of_nicked_loop(sequence, nn_loop, nick)
+    }
+
+    fn association_energy(&self) -> i32 {
+        self.model.association_energy()
+    }
+}
+
+/// A time interval of constant force.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ForceSegment {
+    pub start: f64,
+    pub end: f64,
+    /// Force in pN.
+    pub force: f64,
+}
+
+/// Constant force (`loading_rate` = 0) or a force that increases at a
+/// constant loading rate (pN/s).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ForceProtocol {
+    pub initial_force: f64,
+    pub loading_rate: f64,
+}
+
+impl ForceProtocol {
+    pub fn force_at(&self, t: f64) -> f64 {
+        self.initial_force + self.loading_rate * t
+    }
+
+    /// Split [0, t_end) into segments of constant force, where the force
+    /// changes by at most `resolution` pN between segments (see
+    /// [`piecewise_constant`]).
+    pub fn segments(&self, t_end: f64, resolution: f64) -> Vec<ForceSegment> {
+        piecewise_constant(&[], t_end, resolution, |t| self.force_at(t))
+            .into_iter()
+            .map(|(start, end, force)| ForceSegment { start, end, force })
+            .collect()
+    }
+}
+
+/// Plot extension-versus-time traces (one per trajectory) and their mean.
+pub fn plot_extension_traces(
+    traces: &[Vec<(f64, f64)>],
+    title: &str,
+    filename: &str,
+) {
+    let t_max = traces.iter().flatten().map(|&(t, _)| t).fold(0.0, f64::max);
+    let x_max = traces.iter().flatten().map(|&(_, x)| x).fold(0.0, f64::max);
+
+    let root = SVGBackend::new(filename, (1024, 480)).into_drawing_area();
+    root.fill(&WHITE).unwrap();
+    let mut chart = ChartBuilder::on(&root)
+        .caption(title, ("sans-serif", 28))
+        .margin(20)
+        .x_label_area_size(40)
+        .y_label_area_size(60)
+        .build_cartesian_2d(0.0..t_max.max(f64::MIN_POSITIVE), 0.0..(1.05 * x_max).max(1.0)).unwrap();
+    chart.configure_mesh()
+        .x_desc("time (s)")
+        .y_desc("extension (nm)")
+        .light_line_style(RGBColor(220, 220, 220))
+        .axis_desc_style(("sans-serif", 18))
+        .label_style(("sans-serif", 16))
+        .draw().unwrap();
+
+    for trace in traces {
+        chart.draw_series(LineSeries::new(
+            trace.iter().copied(),
+            RGBColor(70, 130, 180).mix(0.3).stroke_width(1),
+        )).unwrap();
+    }
+
+    // All traces share the sampling times.
+    let samples = traces.iter().map(|t| t.len()).min().unwrap_or(0);
+    let mean: Vec<(f64, f64)> = (0..samples).map(|k| {
+        let x = traces.iter().map(|t| t[k].1).sum::<f64>() / traces.len() as f64;
+        (traces[0][k].0, x)
+    }).collect();
+    chart.draw_series(LineSeries::new(mean, BLACK.stroke_width(2))).unwrap()
+        .label("mean")
+        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], BLACK.stroke_width(2)));
+    chart.configure_series_labels()
+        .border_style(BLACK)
+        .background_style(WHITE.mix(0.8))
+        .position(SeriesLabelPosition::UpperLeft)
+        .label_font(("sans-serif", 16).into_font())
+        .draw().unwrap();
+
+    root.present().unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ff_energy::NucleotideVec;
+    use ff_energy::ViennaRNA;
+
+    #[test]
+    fn test_chain_limits() {
+        let chain = ChainParameters::default();
+        let kt = KB_PN_NM * (K0 + 37.0);
+        assert_eq!(chain.free_energy(10, 1, 0.0, kt), 0.0);
+        assert_eq!(chain.extension(10, 1, 0.0, kt), 0.0);
+        // Fully stretched at high force, linear response at low force.
+        assert!((chain.extension(10, 0, 1e4, kt) - 7.0).abs() < 0.01);
+        let x = chain.extension(10, 0, 0.01, kt);
+        assert!((x - 10.0 * 0.7 * 0.01 * 1.9 / kt / 3.0).abs() < 1e-6);
+        // The free energy is minus the integral of the extension.
+        let (f, df) = (5.0, 1e-4);
+        let dg = chain.free_energy(10, 2, f + df, kt) - chain.free_energy(10, 2, f, kt);
+        assert!((dg / df + chain.extension(10, 2, f, kt)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_force_model() {
+        let model = ViennaRNA::default();
+        let sequence = NucleotideVec::try_from("GGGGAAAACCCC").unwrap();
+        let open = PairTable::try_from("............").unwrap();
+        let mfe = PairTable::try_from("((((....))))").unwrap();
+        assert_eq!(exterior_loop_of(&open), (12, 0));
+        assert_eq!(exterior_loop_of(&mfe), (0, 1));
+
+        let relaxed = ForceModel::new(&model, 0.0, ChainParameters::default());
+        assert_eq!(relaxed.energy_of_structure(&sequence, &mfe), model.energy_of_structure(&sequence, &mfe));
+
+        let pulled = ForceModel::new(&model, 20.0, ChainParameters::default());
+        let gap = |m: &ForceModel<ViennaRNA>| m.energy_of_structure(&sequence, &open)
+            - m.energy_of_structure(&sequence, &mfe);
+        assert!(gap(&pulled) < gap(&relaxed));
+        assert!(pulled.extension(&open) > pulled.extension(&mfe));
+        assert!(pulled.extension(&mfe) > 0.0 && pulled.extension(&mfe) < 2.0);
+    }
+
+    #[test]
+    fn test_force_protocol() {
+        let constant = ForceProtocol { initial_force: 5.0, loading_rate: 0.0 };
+        assert_eq!(constant.segments(1.0, 0.5), vec![ForceSegment { start: 0.0, end: 1.0, force: 5.0 }]);
+        let ramp = ForceProtocol { initial_force: 0.0, loading_rate: 10.0 };
+        let segments = ramp.segments(1.0, 2.5);
+        assert_eq!(segments.len(), 4);
+        assert_eq!(segments[0].force, 1.25);
+        assert_eq!(segments[3].end, 1.0);
+    }
+}
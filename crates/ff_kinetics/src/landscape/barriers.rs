@@ -0,0 +1,338 @@
+use std::io;
+use std::io::Write;
+use ahash::AHashMap;
+
+use ff_structure::DotBracketVec;
+use ff_structure::PairTable;
+use ff_energy::EnergyModel;
+use ff_energy::NucleotideVec;
+
+use crate::{K0, KB};
+use crate::RateModel;
+use crate::LoopStructure;
+use crate::reaction::MoveSet;
+use crate::reaction::ApplyMove;
+use crate::landscape::Basin;
+
+/// A leaf of the barrier tree.
+#[derive(Debug, Clone)]
+pub struct LocalMinimum {
+    pub structure: DotBracketVec,
+    pub energy: i32,
+    /// The (lower) minimum this one merges into, None for the global minimum.
+    pub father: Option<usize>,
+    /// The saddle structure connecting to the father.
+    pub saddle: Option<DotBracketVec>,
+    pub saddle_energy: Option<i32>,
+}
+
+impl LocalMinimum {
+    /// Energy barrier to reach the father (in dcal/mol).
+    pub fn barrier(&self) -> Option<i32> {
+        self.saddle_energy.map(|e| e - self.energy)
+    }
+}
+
+/// Intermediate basins during flooding.
+#[derive(Debug, Clone)]
+struct RawBasin {
+    minimum: usize,
+    father: Option<usize>,
+    saddle: Option<usize>,
+    merged: bool,
+}
+
+/// The result of flooding an energy landscape (barriers-style).
+///
+/// Structures are processed in order of increasing free energy. A structure
+/// without lower neighbors (with respect to the move set) is a new local
+/// minimum, otherwise it joins the basin of its lowest neighbor. A
+/// structure connecting two or more basins is a saddle: the higher basins
+/// merge into the deepest one. If the barrier of a merged basin is lower
+/// than `min_barrier`, the basin is removed and its structures are assigned
+/// to its father.
+///
+/// Only the given structures are considered, the landscape is truncated at
+/// the highest energy (e.g. the energy range of RNAsubopt output).
+#[derive(Debug, Clone)]
+pub struct BarrierTree {
+    /// Energy-sorted structures.
+    structures: Vec<(DotBracketVec, i32)>,
+    lookup: AHashMap<DotBracketVec, usize>,
+    /// Pairs of neighboring structures (indices into `structures`).
+    edges: Vec<(usize, usize)>,
+    minima: Vec<LocalMinimum>,
+    /// The local minimum of every structure.
+    membership: Vec<usize>,
+    rt: f64,
+}
+
+fn find(uf: &mut [usize], mut x: usize) -> usize {
+    while uf[x] != x {
+        uf[x] = uf[uf[x]];
+        x = uf[x];
+    }
+    x
+}
+
+fn resolve(raw: &[RawBasin], mut b: usize) -> usize {
+    while raw[b].merged {
+        b = raw[b].father.expect("Merged basins must have a father");
+    }
+    b
+}
+
+impl BarrierTree {
+    /// Fails if a structure is not well-formed or does not match the sequence.
+    pub fn flood<E: EnergyModel>(
+        sequence: &NucleotideVec,
+        model: &E,
+        move_set: MoveSet,
+        structures: &[DotBracketVec],
+        min_barrier: i32,
+    ) -> Result<Self, String> {
+        let mut sorted: Vec<(DotBracketVec, i32)> = structures.iter().map(|dbv| {
+            let pt = PairTable::try_from(dbv)
+                .map_err(|e| format!("Invalid structure for flooding {}: {}", dbv, e))?;
+            if pt.len() != sequence.len() {
+                return Err(format!("Structure length mismatch for flooding: {}", dbv));
+            }
+            Ok((dbv.clone(), model.energy_of_structure(sequence, &pt)))
+        }).collect::<Result<_, String>>()?;
+        sorted.sort_by_cached_key(|(dbv, en)| (*en, dbv.to_string()));
+        sorted.dedup_by(|a, b| a.0 == b.0);
+
+        let lookup: AHashMap<DotBracketVec, usize> = sorted.iter()
+            .enumerate()
+            .map(|(k, (dbv, _))| (dbv.clone(), k))
+            .collect();
+
+        let mut edges = Vec::new();
+        let mut raw: Vec<RawBasin> = Vec::new();
+        let mut uf: Vec<usize> = Vec::new();
+        let mut basin_of: Vec<usize> = Vec::with_capacity(sorted.len());
+
+        for (s, (dbv, energy)) in sorted.iter().enumerate() {
+            let pt = PairTable::try_from(dbv).unwrap();
+            let ls = LoopStructure::try_from((&sequence[..], &pt, model))?
+                .with_move_set(move_set);
+            let mut lower: Vec<usize> = Vec::new();
+            let mut neighbor = dbv.clone();
+            for (mv, _) in ls.all_moves() {
+                neighbor.apply_move(mv);
+                if let Some(&t) = lookup.get(&neighbor)
+                    && t < s {
+                    lower.push(t);
+                }
+                neighbor.undo_move(mv);
+            }
+            lower.sort_unstable();
+            lower.dedup();
+            edges.extend(lower.iter().map(|&t| (s, t)));
+
+            let Some(&lowest) = lower.first() else {
+                raw.push(RawBasin { minimum: s, father: None, saddle: None, merged: false });
+                uf.push(uf.len());
+                basin_of.push(raw.len() - 1);
+                continue;
+            };
+            basin_of.push(basin_of[lowest]);
+
+            let mut roots: Vec<usize> = lower.iter().map(|&t| find(&mut uf, basin_of[t])).collect();
+            roots.sort_unstable_by_key(|&r| raw[r].minimum);
+            roots.dedup();
+            let deepest = roots[0];
+            for &r in &roots[1..] {
+                raw[r].father = Some(deepest);
+                raw[r].saddle = Some(s);
+                raw[r].merged = energy - sorted[raw[r].minimum].1 < min_barrier;
+                uf[r] = deepest;
+            }
+        }
+
+        // Renumber the remaining minima (in order of increasing energy).
+        let mut index = vec![usize::MAX; raw.len()];
+        let mut minima = Vec::new();
+        for (b, basin) in raw.iter().enumerate() {
+            if basin.merged {
+                continue;
+            }
+            index[b] = minima.len();
+            let (structure, energy) = sorted[basin.minimum].clone();
+            minima.push(LocalMinimum {
+                structure,
+                energy,
+                father: None,
+                saddle: basin.saddle.map(|s| sorted[s].0.clone()),
+                saddle_energy: basin.saddle.map(|s| sorted[s].1),
+            });
+        }
+        for (b, basin) in raw.iter().enumerate() {
+            if !basin.merged && let Some(f) = basin.father {
+                minima[index[b]].father = Some(index[resolve(&raw, f)]);
+            }
+        }
+        let membership = basin_of.iter().map(|&b| index[resolve(&raw, b)]).collect();
+
+        Ok(Self {
+            structures: sorted,
+            lookup,
+            edges,
+            minima,
+            membership,
+            rt: KB * (K0 + model.temperature()),
+        })
+    }
+
+    pub fn minima(&self) -> &[LocalMinimum] {
+        &self.minima
+    }
+
+    /// Energy-sorted structures with their free energies.
+    pub fn structures(&self) -> &[(DotBracketVec, i32)] {
+        &self.structures
+    }
+
+    /// The index of the local minimum whose basin contains the structure.
+    pub fn basin_of(&self, structure: &DotBracketVec) -> Option<usize> {
+        self.lookup.get(structure).map(|&s| self.membership[s])
+    }
+
+    /// All basins, in the order of their local minima.
+    pub fn basins(&self) -> Vec<Basin> {
+        let mut basins: Vec<Basin> = self.minima.iter().map(|lm| Basin {
+            minimum: lm.structure.clone(),
+            energy: lm.energy,
+            members: Vec::new(),
+        }).collect();
+        for (s, &b) in self.membership.iter().enumerate() {
+            basins[b].members.push(self.structures[s].clone());
+        }
+        basins
+    }
+
+    /// Transition rates between basins, where row `a` contains the rates
+    /// from basin `a`. Microscopic rates between neighboring structures are
+    /// weighted by the Boltzmann probability of the source structure within
+    /// its basin. The diagonal holds the negative total outflow, such that
+    /// every row sums to zero.
+    pub fn rate_matrix<R: RateModel>(&self, rate_model: &R) -> Vec<Vec<f64>> {
+        let n = self.minima.len();
+        let weights: Vec<f64> = self.structures.iter()
+            .map(|(_, en)| (-(*en as f64) / 100.0 / self.rt).exp())
+            .collect();
+        let mut z = vec![0.0; n];
+        for (s, &b) in self.membership.iter().enumerate() {
+            z[b] += weights[s];
+        }
+
+        let mut rates = vec![vec![0.0; n]; n];
+        for &(s, t) in &self.edges {
+            let (a, b) = (self.membership[s], self.membership[t]);
+            if a == b {
+                continue;
+            }
+            let delta = self.structures[t].1 - self.structures[s].1;
+            rates[a][b] += weights[s] / z[a] * rate_model.rate(delta);
+            rates[b][a] += weights[t] / z[b] * rate_model.rate(-delta);
+        }
+        for (a, row) in rates.iter_mut().enumerate() {
+            row[a] = -row.iter().sum::<f64>();
+        }
+        rates
+    }
+
+    /// Write the tree in the format of barriers: the sequence, followed by
+    /// one line per minimum with (1-based) index, structure, energy, father
+    /// (0 for the root) and barrier height (kcal/mol).
+    pub fn write_tree<W: Write>(&self, writer: &mut W, sequence: &NucleotideVec) -> io::Result<()> {
+        writeln!(writer, "     {}", sequence)?;
+        for (k, lm) in self.minima.iter().enumerate() {
+            writeln!(writer, "{:4} {} {:6.2} {:4} {:6.2}",
+                k + 1,
+                lm.structure,
+                lm.energy as f64 / 100.,
+                lm.father.map_or(0, |f| f + 1),
+                lm.barrier().unwrap_or(0) as f64 / 100.,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ff_energy::ViennaRNA;
+    use crate::Metropolis;
+    use crate::landscape::GradientBasins;
+    use crate::landscape::enumerate_structures;
+
+    fn landscape(seq: &str) -> (NucleotideVec, ViennaRNA, Vec<DotBracketVec>) {
+        let model = ViennaRNA::default();
+        let sequence = NucleotideVec::try_from(seq).unwrap();
+        let mut all = Vec::new();
+        enumerate_structures(&sequence, &model, |s| all.push(s.clone()));
+        (sequence, model, all)
+    }
+
+    #[test]
+    fn test_flooding_consistent_with_gradient_basins() {
+        let (sequence, model, all) = landscape("GGGGAAAACCCC");
+        let tree = BarrierTree::flood(&sequence, &model, MoveSet::default(), &all, 0).unwrap();
+
+        // Without a barrier threshold, every minimum is a gradient basin minimum.
+        let mut gradient = GradientBasins::new(&sequence, &model, MoveSet::default());
+        for s in &all {
+            gradient.insert(s);
+        }
+        let gmin: Vec<_> = gradient.into_sorted_basins().into_iter().map(|b| b.minimum).collect();
+        assert_eq!(tree.minima().len(), gmin.len());
+        for lm in tree.minima() {
+            assert!(gmin.contains(&lm.structure));
+        }
+
+        let root = &tree.minima()[0];
+        assert_eq!(root.structure, DotBracketVec::try_from("((((....))))").unwrap());
+        assert!(root.father.is_none());
+        for lm in &tree.minima()[1..] {
+            let f = lm.father.unwrap();
+            assert!(tree.minima()[f].energy <= lm.energy);
+            assert!(lm.barrier().unwrap() >= 0);
+        }
+        assert_eq!(tree.basins().iter().map(|b| b.members.len()).sum::<usize>(), all.len());
+    }
+
+    #[test]
+    fn test_flooding_barrier_threshold() {
+        let (sequence, model, all) = landscape("GGGGAAAACCCC");
+        let full = BarrierTree::flood(&sequence, &model, MoveSet::default(), &all, 0).unwrap();
+        let tree = BarrierTree::flood(&sequence, &model, MoveSet::default(), &all, 300).unwrap();
+        assert!(tree.minima().len() < full.minima().len());
+        for lm in &tree.minima()[1..] {
+            assert!(lm.barrier().unwrap() >= 300);
+        }
+        let huge = BarrierTree::flood(&sequence, &model, MoveSet::default(), &all, 100000).unwrap();
+        assert_eq!(huge.minima().len(), 1);
+        assert!(all.iter().all(|s| huge.basin_of(s) == Some(0)));
+
+        let rmodel = Metropolis::new(model.temperature(), 1.0);
+        let rates = tree.rate_matrix(&rmodel);
+        assert_eq!(rates.len(), tree.minima().len());
+        for (a, row) in rates.iter().enumerate() {
+            assert!(row.iter().sum::<f64>().abs() < 1e-9);
+            assert!(row.iter().enumerate().all(|(b, &r)| a == b || r >= 0.0));
+        }
+
+        let mut out = Vec::new();
+        tree.write_tree(&mut out, &sequence).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(text.lines().count(), tree.minima().len() + 1);
+        assert!(text.lines().nth(1).unwrap().starts_with("   1 ((((....))))  -5.40    0"));
+
+        for bad in ["((((....))).", "((((....))))."] {
+            let structures = vec![DotBracketVec::try_from(bad).unwrap()];
+            assert!(BarrierTree::flood(&sequence, &model, MoveSet::default(), &structures, 0).is_err());
+        }
+    }
+}
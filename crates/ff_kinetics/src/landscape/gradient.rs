@@ -0,0 +1,294 @@
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use ahash::AHashMap;
+
+use ff_structure::DotBracket;
+use ff_structure::DotBracketVec;
+use ff_structure::PairTable;
+use ff_energy::EnergyModel;
+use ff_energy::NucleotideVec;
+
+use crate::LoopStructure;
+use crate::reaction::Move;
+use crate::reaction::MoveSet;
+use crate::reaction::ApplyMove;
+use crate::write_macrostate;
+
+/// Enumerate all secondary structures of a sequence that are compatible
+/// with the energy model (canonical pairs, minimal hairpin size).
+///
+/// The number of structures grows exponentially with sequence length,
+/// this is only feasible for short sequences.
+pub fn enumerate_structures<E: EnergyModel, F: FnMut(&DotBracketVec)>(
+    sequence: &NucleotideVec,
+    model: &E,
+    mut callback: F,
+) {
+    fn fill<E: EnergyModel, F: FnMut(&DotBracketVec)>(
+        pos: usize,
+        closing: &mut Vec<usize>,
+        dbv: &mut DotBracketVec,
+        sequence: &NucleotideVec,
+        model: &E,
+        callback: &mut F,
+    ) {
+        let n = sequence.len();
+        if pos == n {
+            callback(dbv);
+            return;
+        }
+        if closing.last() == Some(&pos) {
+            closing.pop();
+            fill(pos + 1, closing, dbv, sequence, model, callback);
+            closing.push(pos);
+            return;
+        }
+        fill(pos + 1, closing, dbv, sequence, model, callback);
+
+        let bound = closing.last().copied().unwrap_or(n);
+        for j in (pos + model.min_hairpin_size() + 1)..bound {
+            if !model.can_pair(sequence[pos], sequence[j]) {
+                continue;
+            }
+            dbv[pos] = DotBracket::Open;
+            dbv[j] = DotBracket::Close;
+            closing.push(j);
+            fill(pos + 1, closing, dbv, sequence, model, callback);
+            closing.pop();
+            dbv[pos] = DotBracket::Unpaired;
+            dbv[j] = DotBracket::Unpaired;
+        }
+    }
+
+    let mut dbv = DotBracketVec(vec![DotBracket::Unpaired; sequence.len()]);
+    fill(0, &mut Vec::new(), &mut dbv, sequence, model, &mut callback);
+}
+
+/// The number of structures that [`enumerate_structures`] would report,
+/// computed without enumerating them (saturates at `u128::MAX`).
+pub fn count_structures<E: EnergyModel>(sequence: &NucleotideVec, model: &E) -> u128 {
+    // counts[i][j]: number of structures on the interval [i, j).
+    let n = sequence.len();
+    let mut counts = vec![vec![1u128; n + 1]; n + 2];
+    for i in (0..n).rev() {
+        for j in (i + 1)..=n {
+            let mut c = counts[i + 1][j];
+            for k in (i + model.min_hairpin_size() + 1)..j {
+                if model.can_pair(sequence[i], sequence[k]) {
+                    c = c.saturating_add(counts[i + 1][k].saturating_mul(counts[k + 1][j]));
+                }
+            }
+            counts[i][j] = c;
+        }
+    }
+    counts[0][n]
+}
+
+/// A gradient basin: the local minimum and all structures that descend into it.
+#[derive(Debug, Clone)]
+pub struct Basin {
+    pub minimum: DotBracketVec,
+    pub energy: i32,
+    /// Members (including the minimum) with their free energies.
+    pub members: Vec<(DotBracketVec, i32)>,
+}
+
+/// Assigns structures to local minima by steepest descent (gradient walks).
+///
+/// A gradient walk repeatedly applies the move with the lowest (negative)
+/// energy change until no move decreases the free energy. Ties are broken
+/// by choosing the lexicographically smallest resulting dot-bracket string,
+/// such that every structure has a unique local minimum. Every structure
+/// visited during a walk is a member of the same basin, walks stop early if
+/// they reach a structure that has been classified already.
+pub struct GradientBasins<'a, E: EnergyModel> {
+    sequence: &'a NucleotideVec,
+    model: &'a E,
+    move_set: MoveSet,
+    lookup: AHashMap<DotBracketVec, usize>,
+    basins: Vec<Basin>,
+}
+
+impl<'a, E: EnergyModel> GradientBasins<'a, E> {
+    pub fn new(sequence: &'a NucleotideVec, model: &'a E, move_set: MoveSet) -> Self {
+        Self {
+            sequence,
+            model,
+            move_set,
+            lookup: AHashMap::new(),
+            basins: Vec::new(),
+        }
+    }
+
+    /// Steepest descent from `structure`, returns the local minimum and its energy.
+    pub fn gradient_walk(&self, structure: &DotBracketVec) -> (DotBracketVec, i32) {
+        let (path, _) = self.walk(structure, false);
+        path.last().cloned().unwrap()
+    }
+
+    /// The steepest descent path, stopped early at a known structure (if requested).
+    fn walk(&self, structure: &DotBracketVec, stop_known: bool
+    ) -> (Vec<(DotBracketVec, i32)>, Option<usize>) {
+        let pairings = PairTable::try_from(structure)
+            .expect("Invalid structure for gradient walk");
+        let mut ls = LoopStructure::try_from((&self.sequence[..], &pairings, self.model))
+            .expect("Incompatible structure for gradient walk")
+            .with_move_set(self.move_set);
+        let mut current = structure.clone();
+        let mut path = vec![(current.clone(), ls.energy())];
+
+        loop {
+            let moves = ls.all_moves();
+            let Some(best) = moves.iter().map(|&(_, d)| d).min().filter(|&d| d < 0) else {
+                return (path, None);
+            };
+            let (mv, next) = moves.iter()
+                .filter(|&&(_, d)| d == best)
+                .map(|&(mv, _)| {
+                    let mut next = current.clone();
+                    next.apply_move(mv);
+                    (mv, next)
+                })
+                .min_by_key(|(_, next): &(Move, DotBracketVec)| next.to_string())
+                .unwrap();
+            ls.apply_move(mv);
+            current = next;
+            if stop_known && let Some(&idx) = self.lookup.get(&current) {
+                return (path, Some(idx));
+            }
+            path.push((current.clone(), ls.energy()));
+        }
+    }
+
+    /// Assign a structure to its basin and return the basin index.
+    pub fn insert(&mut self, structure: &DotBracketVec) -> usize {
+        if let Some(&idx) = self.lookup.get(structure) {
+            return idx;
+        }
+        let (path, known) = self.walk(structure, true);
+        let idx = known.unwrap_or_else(|| {
+            let (minimum, energy) = path.last().cloned().unwrap();
+            self.basins.push(Basin { minimum, energy, members: Vec::new() });
+            self.basins.len() - 1
+        });
+        for (dbv, en) in path {
+            self.lookup.insert(dbv.clone(), idx);
+            self.basins[idx].members.push((dbv, en));
+        }
+        idx
+    }
+
+    /// The basin index of a structure, if it has been inserted.
+    pub fn classify(&self, structure: &DotBracketVec) -> Option<usize> {
+        self.lookup.get(structure).copied()
+    }
+
+    pub fn basins(&self) -> &[Basin] {
+        &self.basins
+    }
+
+    /// Consume the collection and return basins sorted by the energy
+    /// of their local minimum (and members sorted by energy).
+    pub fn into_sorted_basins(self) -> Vec<Basin> {
+        let mut basins = self.basins;
+        for basin in basins.iter_mut() {
+            basin.members.sort_by_cached_key(|(dbv, en)| (*en, dbv.to_string()));
+        }
+        basins.sort_by_cached_key(|b| (b.energy, b.minimum.to_string()));
+        basins
+    }
+}
+
+/// The macrostate name of a basin, following the barriers convention
+/// (e.g. `lmin=lm3_bh=2.0`). Gradient basins correspond to a barrier
+/// height of zero.
+pub fn basin_name(index: usize, barrier_height: f64) -> String {
+    format!("lmin=lm{}_bh={:.1}", index + 1, barrier_height)
+}
+
+/// Write every basin as a macrostate file `<name>.txt` into `dir` and
+/// return the paths (which can be passed to `--macrostates`).
+pub fn write_basin_files<P: AsRef<Path>>(
+    dir: P,
+    sequence: &NucleotideVec,
+    basins: &[Basin],
+    barrier_height: f64,
+) -> io::Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(&dir)?;
+    let mut paths = Vec::with_capacity(basins.len());
+    for (k, basin) in basins.iter().enumerate() {
+        let name = basin_name(k, barrier_height);
+        let path = dir.as_ref().join(format!("{}.txt", name));
+        let mut fh = io::BufWriter::new(std::fs::File::create(&path)?);
+        let structures: Vec<_> = basin.members.iter().map(|(s, _)| s.clone()).collect();
+        write_macrostate(&mut fh, &name, sequence, &structures)?;
+        fh.flush()?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ff_energy::ViennaRNA;
+    use crate::MacrostateRegistry;
+
+    #[test]
+    fn test_enumerate_structures() {
+        let model = ViennaRNA::default();
+        let sequence = NucleotideVec::try_from("GGGAAACCC").unwrap();
+        let mut structures = Vec::new();
+        enumerate_structures(&sequence, &model, |s| structures.push(s.to_string()));
+        assert!(structures.contains(&".........".to_string()));
+        assert!(structures.contains(&"(((...)))".to_string()));
+        assert!(structures.contains(&"(.(...).)".to_string()));
+        // No hairpins smaller than the minimum size.
+        assert!(!structures.iter().any(|s| s.contains("(..)")));
+        let unique: std::collections::HashSet<_> = structures.iter().collect();
+        assert_eq!(unique.len(), structures.len());
+        assert_eq!(count_structures(&sequence, &model), structures.len() as u128);
+    }
+
+    #[test]
+    fn test_gradient_basins() {
+        let model = ViennaRNA::default();
+        let sequence = NucleotideVec::try_from("GGGGAAAACCCC").unwrap();
+        let mut basins = GradientBasins::new(&sequence, &model, MoveSet::default());
+
+        let mfe = DotBracketVec::try_from("((((....))))").unwrap();
+        let (lmin, _) = basins.gradient_walk(&DotBracketVec::try_from("(((......)))").unwrap());
+        assert_eq!(lmin, mfe);
+
+        let mut all = Vec::new();
+        enumerate_structures(&sequence, &model, |s| all.push(s.clone()));
+        for s in &all {
+            basins.insert(s);
+        }
+        // Every structure belongs to exactly one basin and its minimum is a
+        // fixed point of the gradient walk.
+        assert_eq!(basins.basins().iter().map(|b| b.members.len()).sum::<usize>(), all.len());
+        for basin in basins.basins() {
+            assert_eq!(basins.gradient_walk(&basin.minimum).0, basin.minimum);
+            for (s, _) in &basin.members {
+                assert_eq!(basins.gradient_walk(s).0, basin.minimum);
+            }
+        }
+
+        let sorted = basins.into_sorted_basins();
+        assert_eq!(sorted[0].minimum, mfe);
+        assert!(sorted.windows(2).all(|w| w[0].energy <= w[1].energy));
+
+        // The files can be read as macrostates.
+        let dir = std::env::temp_dir().join("ff_kinetics_gradient_basins_test");
+        let paths = write_basin_files(&dir, &sequence, &sorted, 0.0).unwrap();
+        let mut registry = MacrostateRegistry::from((&sequence, &model));
+        registry.insert_files(&paths).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(registry.len(), sorted.len() + 1);
+        assert_eq!(registry.macrostates()[1].name(), "lmin=lm1_bh=0.0");
+        assert_eq!(registry.classify(&mfe), 1);
+    }
+}
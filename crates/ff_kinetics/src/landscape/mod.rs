@@ -0,0 +1,8 @@
+//! Construction of macrostates from the energy landscape.
+mod gradient;
+mod barriers;
+mod sampling;
+
+pub use gradient::*;
+pub use barriers::*;
+pub use sampling::*;
@@ -0,0 +1,118 @@
+use rand::Rng;
+
+use ff_structure::DotBracketVec;
+use ff_structure::PairTable;
+use ff_energy::EnergyModel;
+use ff_energy::NucleotideVec;
+
+use crate::KB;
+use crate::K0;
+use crate::landscape::count_structures;
+use crate::landscape::enumerate_structures;
+
+/// The largest number of structures a [`BoltzmannSampler`] enumerates
+/// (roughly a second and a few hundred MB).
+pub const MAX_SAMPLER_STRUCTURES: u128 = 2_000_000;
+
+/// Draw structures from the Boltzmann distribution at the temperature of
+/// the energy model. All structures are enumerated, so this is only
+/// feasible for short sequences (see [`enumerate_structures`]), at most
+/// [`MAX_SAMPLER_STRUCTURES`] are allowed.
+#[derive(Debug, Clone)]
+pub struct BoltzmannSampler {
+    structures: Vec<(DotBracketVec, i32)>,
+    /// Cumulative (unnormalized) Boltzmann weights.
+    cumulative: Vec<f64>,
+    ensemble_energy: f64,
+}
+
+impl BoltzmannSampler {
+    pub fn new<E: EnergyModel>(sequence: &NucleotideVec, model: &E) -> Result<Self, String> {
+        let count = count_structures(sequence, model);
+        if count > MAX_SAMPLER_STRUCTURES {
+            return Err(format!("Cannot sample from {} structures (at most {} are enumerated).",
+                count, MAX_SAMPLER_STRUCTURES));
+        }
+        let mut structures = Vec::new();
+        enumerate_structures(sequence, model, |s| {
+            let pt = PairTable::try_from(s).expect("Enumerated structures are valid");
+            structures.push((s.clone(), model.energy_of_structure(sequence, &pt)));
+        });
+        let rt = KB * (K0 + model.temperature());
+        let e_min = structures.iter().map(|&(_, e)| e).min().expect("The open chain always exists");
+        let cumulative: Vec<f64> = structures.iter()
+            .scan(0.0, |acc, &(_, e)| {
+                *acc += (-(e - e_min) as f64 / 100.0 / rt).exp();
+                Some(*acc)
+            })
+            .collect();
+        let z = *cumulative.last().unwrap();
+        Ok(Self {
+            structures,
+            cumulative,
+            ensemble_energy: e_min as f64 / 100.0 - rt * z.ln(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.structures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.structures.is_empty()
+    }
+
+    /// Ensemble free energy (kcal/mol).
+    pub fn ensemble_energy(&self) -> f64 {
+        self.ensemble_energy
+    }
+
+    /// The equilibrium probability of the k-th enumerated structure.
+    pub fn probability(&self, k: usize) -> f64 {
+        let prev = if k == 0 { 0.0 } else { self.cumulative[k - 1] };
+        (self.cumulative[k] - prev) / self.cumulative.last().unwrap()
+    }
+
+    pub fn structures(&self) -> &[(DotBracketVec, i32)] {
+        &self.structures
+    }
+
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> &DotBracketVec {
+        let x = rng.random::<f64>() * self.cumulative.last().unwrap();
+        let k = self.cumulative.partition_point(|&c| c <= x).min(self.len() - 1);
+        &self.structures[k].0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+    use ff_energy::ViennaRNA;
+
+    #[test]
+    fn test_boltzmann_sampler() {
+        let mut model = ViennaRNA::default();
+        let sequence = NucleotideVec::try_from("GGGGAAAACCCC").unwrap();
+        let mfe = DotBracketVec::try_from("((((....))))").unwrap();
+        let sampler = BoltzmannSampler::new(&sequence, &model).unwrap();
+        let k_mfe = sampler.structures().iter().position(|(s, _)| *s == mfe).unwrap();
+        let total: f64 = (0..sampler.len()).map(|k| sampler.probability(k)).sum();
+        assert!((total - 1.0).abs() < 1e-12);
+
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let hits = (0..2000).filter(|_| *sampler.sample(&mut rng) == mfe).count();
+        let expected = sampler.probability(k_mfe) * 2000.0;
+        assert!((hits as f64 - expected).abs() < 5.0 * expected.sqrt());
+
+        // Melting: the MFE is less likely at high temperature.
+        model.set_temperature(90.0);
+        let hot = BoltzmannSampler::new(&sequence, &model).unwrap();
+        assert!(hot.probability(k_mfe) < sampler.probability(k_mfe));
+        assert!(hot.ensemble_energy() > sampler.ensemble_energy());
+
+        let long = NucleotideVec::try_from("GGGGAAAACCCC".repeat(5).as_str()).unwrap();
+        assert!(BoltzmannSampler::new(&long, &model).is_err());
+    }
+}
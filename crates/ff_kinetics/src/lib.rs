@@ -1,15 +1,36 @@
 pub mod timeline;
 pub mod timeline_io;
 pub mod timeline_plotting;
+pub mod timeline_statistics;
 pub mod reaction;
 pub mod commit_and_delay;
+pub mod first_passage;
+pub mod multistrand;
+pub mod checkpoint;
+pub mod trajectory_io;
+pub mod landscape;
+pub mod master_equation;
+pub mod treekin;
+pub mod observables;
+pub mod pair_probabilities;
+pub mod structure_discovery;
+pub mod temperature;
+pub mod force;
+pub mod piecewise;
+pub mod transition_paths;
+pub mod msm;
+pub mod weighted_ensemble;
 
 mod rate_model;
 mod loop_structure;
+mod structure_hash;
 mod stochastic_simulation;
 mod macrostates;
+mod macrostate_rules;
 
 pub use rate_model::*;
 pub use loop_structure::*;
+pub use structure_hash::*;
 pub use stochastic_simulation::*;
 pub use macrostates::*;
+pub use macrostate_rules::*;
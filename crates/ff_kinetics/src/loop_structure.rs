@@ -2,10 +2,13 @@ use std::fmt;
 use nohash_hasher::IntMap;
 use nohash_hasher::IntSet;
 use crate::reaction::Move;
+use crate::reaction::MoveSet;
+use crate::pair_key;
 
 use ff_structure::NAIDX;
 use ff_structure::DotBracket;
 use ff_structure::DotBracketVec;
+use ff_structure::PairTable;
 use ff_energy::NearestNeighborLoop;
 use ff_energy::LoopDecomposition;
 use ff_energy::EnergyModel;
@@ -104,6 +107,89 @@ impl<'a, M: EnergyModel> LoopCache<'a, M> {
         neighbors
     }
 
+    /// All helix zipping moves of `len` stacked pairs within a loop.
+    fn get_zip_neighbors(&self, index: usize, len: NAIDX) -> Vec<(Move, i32)> {
+        let (combo, energy) = self.loop_list.get(&index).expect("where's the loop?");
+        let unpaired = combo.unpaired_indices(self.sequence.len());
+        let l = len as usize;
+        let min_span = 2 * (l - 1) + self.model.min_hairpin_size();
+
+        let mut neighbors = Vec::new(); 
+        for (idx_i, &i) in unpaired.iter().enumerate() {
+            for &j in &unpaired[idx_i + 1..] {
+                if j <= i + min_span {
+                    continue;
+                }
+                // NOTE: unpaired is sorted, consecutive positions cannot
+                // be separated by a branch of this loop.
+                let is_helix = (0..l).all(|x| {
+                    unpaired.binary_search(&(i + x)).is_ok()
+                        && unpaired.binary_search(&(j - x)).is_ok()
+                        && self.model.can_pair(self.sequence[i + x], self.sequence[j - x])
+                });
+                if !is_helix {
+                    continue;
+                }
+                let (outer, mut inner) = combo.split_loop(i as NAIDX, j as NAIDX);
+                let mut new_energy = self.model.energy_of_loop(self.sequence, &outer);
+                for x in 1..l {
+                    let (stack, next) = inner.split_loop((i + x) as NAIDX, (j - x) as NAIDX);
+                    new_energy += self.model.energy_of_loop(self.sequence, &stack);
+                    inner = next;
+                }
+                new_energy += self.model.energy_of_loop(self.sequence, &inner);
+                neighbors.push((Move::Zip { i: i as NAIDX, j: j as NAIDX, len }, new_energy - energy));
+            }
+        }
+        neighbors
+    }
+
+    /// All shift moves of the pair (i, j), which is the closing pair of 
+    /// the inner loop and a branch of the outer loop.
+    fn get_shift_neighbors(&self, 
+        outer_index: usize, inner_index: usize, i: NAIDX, j: NAIDX
+    ) -> Vec<(Move, i32)> {
+        let (outer, o_en) = self.loop_list.get(&outer_index).expect("Missing outer loop_list entry.");
+        let (inner, i_en) = self.loop_list.get(&inner_index).expect("Missing inner loop_list entry.");
+        let combo = outer.join_loop(inner);
+        let (ui, uj) = (i as usize, j as usize);
+
+        let mut neighbors = Vec::new(); 
+        for k in combo.unpaired_indices(self.sequence.len()) {
+            if k == ui || k == uj {
+                continue;
+            }
+            for (p, q) in [(ui.min(k), ui.max(k)), (uj.min(k), uj.max(k))] {
+                if q <= p + self.model.min_hairpin_size() 
+                    || !self.model.can_pair(self.sequence[p], self.sequence[q]) {
+                    continue;
+                }
+                let (new_outer, new_inner) = combo.split_loop(p as NAIDX, q as NAIDX);
+                let new_energy = self.model.energy_of_loop(self.sequence, &new_outer)
+                    + self.model.energy_of_loop(self.sequence, &new_inner);
+                neighbors.push((
+                    Move::Shift { i, j, k: p as NAIDX, l: q as NAIDX },
+                    new_energy - (o_en + i_en),
+                ));
+            }
+        }
+        neighbors
+    }
+
+    /// The energy change of joining a sequence of nested loops, 
+    /// starting from the outermost loop.
+    fn calc_join_energy(&self, indices: &[usize]) -> i32 {
+        let (first, f_en) = self.loop_list.get(&indices[0]).expect("Missing loop_list entry.");
+        let mut combo = first.clone();
+        let mut energy = *f_en;
+        for index in &indices[1..] {
+            let (inner, i_en) = self.loop_list.get(index).expect("Missing loop_list entry.");
+            combo = combo.join_loop(inner);
+            energy += i_en;
+        }
+        self.model.energy_of_loop(self.sequence, &combo) - energy
+    }
+
     pub fn allocate_index(&mut self) -> usize {
         if let Some(&x) = self.l_indices.iter().next() {
             self.l_indices.remove(&x);
@@ -125,8 +211,18 @@ pub struct LoopStructure<'a, M: EnergyModel> {
     loop_neighbors: IntMap<usize, MoveEnergies>,
     /// Current pairs, i<j where i is the id.
     pair_list: IntMap<NAIDX, NAIDX>,
+    /// Zobrist hash of the current pairs (see [`structure_hash`](crate::structure_hash)).
+    hash: u64,
     /// pair id to deltaE
     pair_neighbors: IntMap<NAIDX, i32>, 
+    /// The moves considered in addition to base-pair addition/deletion.
+    move_set: MoveSet,
+    /// registry index to list of helix zipping moves
+    loop_zip_neighbors: IntMap<usize, Vec<(Move, i32)>>,
+    /// pair id to list of shift and helix unzipping moves
+    pair_ext_neighbors: IntMap<NAIDX, Vec<(Move, i32)>>,
+    /// pair ids where pair_ext_neighbors changed since the last drain.
+    ext_updates: IntSet<NAIDX>,
 }
 
 impl<'a, M: EnergyModel> Clone for LoopStructure<'a, M> {
@@ -141,7 +237,12 @@ impl<'a, M: EnergyModel> Clone for LoopStructure<'a, M> {
             loop_lookup: self.loop_lookup.clone(),
             loop_neighbors: self.loop_neighbors.clone(),
             pair_list: self.pair_list.clone(),
+            hash: self.hash,
             pair_neighbors: self.pair_neighbors.clone(),
+            move_set: self.move_set,
+            loop_zip_neighbors: self.loop_zip_neighbors.clone(),
+            pair_ext_neighbors: self.pair_ext_neighbors.clone(),
+            ext_updates: self.ext_updates.clone(),
         }
     }
 }
@@ -182,10 +283,109 @@ impl<'a, M: EnergyModel> LoopStructure<'a, M> {
         for (i, j, delta) in self.get_del_neighbors() {
             result.push((Move::Del { i, j }, delta));
         }
+        for moves in self.loop_zip_neighbors.values() {
+            result.extend(moves.iter().copied());
+        }
+        for moves in self.pair_ext_neighbors.values() {
+            result.extend(moves.iter().copied());
+        }
 
         result
     }
 
+    /// Use a different move set (the default are base-pair additions and deletions only).
+    pub fn with_move_set(mut self, move_set: MoveSet) -> Self {
+        self.move_set = move_set;
+        self.loop_zip_neighbors.clear();
+        self.pair_ext_neighbors.clear();
+        if move_set.has_helix_moves() {
+            let indices: Vec<usize> = self.registry.loop_list.keys().copied().collect();
+            self.update_zip_neighbors(&indices);
+        }
+        let pairs: Vec<(NAIDX, NAIDX)> = self.pair_list.iter().map(|(&i, &j)| (i, j)).collect();
+        self.update_pair_ext_neighbors(&pairs);
+        self.ext_updates.clear();
+        self
+    }
+
+    pub fn move_set(&self) -> &MoveSet {
+        &self.move_set
+    }
+
+    /// Return the helix zipping moves of a loop (see get_add_neighbors_per_loop).
+    pub fn get_zip_neighbors(&self, index: usize) -> Option<&Vec<(Move, i32)>> {
+        self.loop_zip_neighbors.get(&index)
+    }
+
+    /// Return shift and helix unzipping moves, grouped by pair id.
+    pub fn get_ext_neighbors_per_pair(&self) -> &IntMap<NAIDX, Vec<(Move, i32)>> {
+        &self.pair_ext_neighbors
+    }
+
+    /// Return the pair ids for which the shift and helix unzipping moves 
+    /// have been modified (or removed) since the last call.
+    pub fn drain_ext_updates(&mut self) -> Vec<NAIDX> {
+        self.ext_updates.drain().collect()
+    }
+
+    fn update_zip_neighbors(&mut self, indices: &[usize]) {
+        if !self.move_set.has_helix_moves() {
+            return;
+        }
+        let len = self.move_set.helix_length as NAIDX;
+        for &index in indices {
+            let zips = self.registry.get_zip_neighbors(index, len);
+            self.loop_zip_neighbors.insert(index, zips);
+        }
+    }
+
+    /// Recompute shift and unzip moves of the given pairs, including the 
+    /// unzip moves of helices which are stacked on top of these pairs.
+    fn update_pair_ext_neighbors(&mut self, pairs: &[(NAIDX, NAIDX)]) {
+        if !self.move_set.is_extended() {
+            return;
+        }
+        let len = self.move_set.helix_length as NAIDX;
+        let mut ids = Vec::with_capacity(pairs.len());
+        for &(i, j) in pairs {
+            ids.push(i);
+            for x in 1..len.min(i + 1) {
+                if self.pair_list.get(&(i - x)) != Some(&(j + x)) {
+                    break;
+                }
+                ids.push(i - x);
+            }
+        }
+        for i in ids {
+            let j = self.pair_list[&i];
+            let mut moves = Vec::new();
+            if self.move_set.shift {
+                let o_index = self.loop_lookup[&i];
+                let i_index = self.loop_lookup[&j];
+                moves.extend(self.registry.get_shift_neighbors(o_index, i_index, i, j));
+            }
+            if self.move_set.has_helix_moves() {
+                moves.extend(self.unzip_neighbor(i, j));
+            }
+            self.pair_ext_neighbors.insert(i, moves);
+            self.ext_updates.insert(i);
+        }
+    }
+
+    /// The helix unzipping move starting at the outermost pair (i, j), if 
+    /// the pair is followed by helix_length - 1 stacked pairs.
+    fn unzip_neighbor(&self, i: NAIDX, j: NAIDX) -> Option<(Move, i32)> {
+        let len = self.move_set.helix_length as NAIDX;
+        for x in 1..len {
+            if self.pair_list.get(&(i + x)) != Some(&(j - x)) {
+                return None;
+            }
+        }
+        let mut indices = vec![self.loop_lookup[&i]];
+        indices.extend((0..len).map(|x| self.loop_lookup[&(j - x)]));
+        Some((Move::Unzip { i, j, len }, self.registry.calc_join_energy(&indices)))
+    }
+
     /// Return all add neighbors, including an index that 
     /// is necessary to access the actual loop via loop_lookup.
     pub fn get_add_neighbors_per_loop(&self) -> &IntMap<usize, MoveEnergies> {
@@ -207,6 +407,17 @@ impl<'a, M: EnergyModel> LoopStructure<'a, M> {
         &self.loop_lookup
     }
 
+    /// The current base-pairs (i, j) with i < j, indexed by i.
+    pub fn pair_list(&self) -> &IntMap<NAIDX, NAIDX> {
+        &self.pair_list
+    }
+
+    /// The Zobrist hash of the current structure, maintained as moves are
+    /// applied. Equal to [`structure_hash`](crate::structure_hash) of its pair table.
+    pub fn structure_hash(&self) -> u64 {
+        self.hash
+    }
+
     pub fn energy(&self) -> i32 {
         self.registry.loop_list
             .values()
@@ -236,6 +447,7 @@ impl<'a, M: EnergyModel> LoopStructure<'a, M> {
         let &delta = self.pair_neighbors.get(&i).expect("Missing pair_neighbors entry.");
         self.pair_list.remove(&i); 
         self.pair_neighbors.remove(&i); 
+        self.hash ^= pair_key(i, j);
 
         let &o_index = self.loop_lookup.get(&i).expect("Missing loop_lookup entry for i.");
         let &i_index = self.loop_lookup.get(&j).expect("Missing loop_lookup entry for j.");
@@ -252,7 +464,16 @@ impl<'a, M: EnergyModel> LoopStructure<'a, M> {
             self.loop_lookup.insert(*k as NAIDX, o_index);
         }
 
-        let pair_changes = self.update_pair_neighbors(&combo.pairs());
+        let combo_pairs = combo.pairs();
+        let pair_changes = self.update_pair_neighbors(&combo_pairs);
+
+        if self.move_set.is_extended() {
+            self.loop_zip_neighbors.remove(&i_index);
+            self.update_zip_neighbors(&[o_index]);
+            self.pair_ext_neighbors.remove(&i);
+            self.ext_updates.insert(i);
+            self.update_pair_ext_neighbors(&combo_pairs);
+        }
         ((o_index, loop_neighbors), pair_changes)
     }
 
@@ -282,6 +503,7 @@ impl<'a, M: EnergyModel> LoopStructure<'a, M> {
         self.loop_neighbors.insert(i_id, new_inner_add_neighbors.clone());
         self.pair_list.insert(i, j);
         self.pair_neighbors.insert(i, delta);
+        self.hash ^= pair_key(i, j);
 
         let (outer, _) = self.registry.get_loop_by_index(&o_id);
         for k in &outer.inclusive_unpaired_indices(self.registry.sequence.len()) {
@@ -295,6 +517,13 @@ impl<'a, M: EnergyModel> LoopStructure<'a, M> {
         let mut pair_changes = self.update_pair_neighbors(combo_pairs);
         pair_changes.push((i, j, delta));
 
+        if self.move_set.is_extended() {
+            self.update_zip_neighbors(&[o_id, i_id]);
+            let mut ext_pairs = combo_pairs.clone();
+            ext_pairs.push((i, j));
+            self.update_pair_ext_neighbors(&ext_pairs);
+        }
+
         ((o_id, new_outer_add_neighbors),
          (i_id, new_inner_add_neighbors),
         pair_changes)
@@ -339,17 +568,34 @@ impl<'a, T: LoopDecomposition, M: EnergyModel> TryFrom<(&'a [Base], &T, &'a M)>
             pair_neighbors.insert(*i, delta);
         }
 
+        let hash = pair_list.iter().fold(0, |h, (&i, &j)| h ^ pair_key(i, j));
         Ok(LoopStructure {
             registry,
             loop_lookup,
             loop_neighbors,
             pair_list,
+            hash,
             pair_neighbors,
+            move_set: MoveSet::default(),
+            loop_zip_neighbors: IntMap::default(),
+            pair_ext_neighbors: IntMap::default(),
+            ext_updates: IntSet::default(),
         })
     }
 
 }
 
+impl<'a, M: EnergyModel> From<&LoopStructure<'a, M>> for PairTable {
+    fn from(ls: &LoopStructure<'a, M>) -> Self {
+        let mut pt = vec![None; ls.registry.sequence.len()];
+        for (&i, &j) in &ls.pair_list {
+            pt[i as usize] = Some(j);
+            pt[j as usize] = Some(i);
+        }
+        PairTable(pt)
+    }
+}
+
 impl<'a, M: EnergyModel> From<&LoopStructure<'a, M>> for DotBracketVec {
     fn from(ls: &LoopStructure<'a, M>) -> Self {
         // Use the same logic as your Display impl, but avoid allocating a String unnecessarily
@@ -425,5 +671,44 @@ mod tests {
         assert_eq!(neighbors, ls.get_del_neighbors());
     }
 
+    fn sorted_moves<M: EnergyModel>(ls: &LoopStructure<M>) -> Vec<(Move, i32)> {
+        let mut moves = ls.all_moves();
+        moves.sort_by_key(|(mv, de)| (format!("{:?}", mv), *de));
+        moves
+    }
+
+    #[test]
+    fn test_extended_moves_reversible() {
+        use crate::reaction::ApplyMove;
+        let seq = NucleotideVec::from_lossy("GGGAAACCCAGCGAAAGCUA");
+        let model = ViennaRNA::default();
+        let move_set = MoveSet { shift: true, helix_length: 2 };
+
+        for dbr in ["....................", "(((...))).((....))..", "((.....))..........."] {
+            let structure = PairTable::try_from(dbr).unwrap();
+            let ls = LoopStructure::try_from((&seq[..], &structure, &model))
+                .unwrap().with_move_set(move_set);
+            let moves = ls.all_moves();
+            assert!(moves.iter().any(|(mv, _)| matches!(mv, Move::Zip { .. } | Move::Unzip { .. })));
+            assert!(dbr.starts_with('.') || moves.iter().any(|(mv, _)| matches!(mv, Move::Shift { .. })));
+
+            for (mv, de) in moves {
+                let mut next = ls.clone();
+                next.apply_move(mv);
+                assert_eq!(next.energy() - ls.energy(), de, "{:?} on {}", mv, dbr);
+
+                // The inverse move must exist with the inverse energy change.
+                assert!(next.all_moves().contains(&(mv.inverse(), -de)), 
+                    "Missing inverse of {:?} on {}", mv, dbr);
+
+                // Incremental updates agree with a fresh loop structure.
+                let pt = PairTable::try_from(&DotBracketVec::from(&next)).unwrap();
+                let fresh = LoopStructure::try_from((&seq[..], &pt, &model))
+                    .unwrap().with_move_set(move_set);
+                assert_eq!(sorted_moves(&next), sorted_moves(&fresh), "{:?} on {}", mv, dbr);
+            }
+        }
+    }
+
 }
 
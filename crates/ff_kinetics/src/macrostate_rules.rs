@@ -0,0 +1,199 @@
+use std::fmt;
+
+use ff_structure::NAIDX;
+use ff_structure::DotBracket;
+use ff_structure::DotBracketVec;
+use ff_structure::PairTable;
+
+/// One position of a structure pattern.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatternSymbol {
+    /// Matches exactly this dot-bracket symbol.
+    Exact(DotBracket),
+    /// '|': matches any paired position.
+    Paired,
+    /// '?' (or '*'): matches anything.
+    Any,
+}
+
+/// A condition on a secondary structure. Indices are 0-based.
+///
+/// In macrostate files, rules are written one per line with 1-based
+/// indices:
+///
+/// ```text
+/// pair 3 20            # required base-pair
+/// nopair 4 19          # forbidden base-pair
+/// helix 1 30 4         # the stacked pairs (1,30), (2,29), (3,28), (4,27)
+/// pattern ((((??|?))))....
+/// distance 5 ((((....))))....
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum StructureRule {
+    RequiredPair(NAIDX, NAIDX),
+    ForbiddenPair(NAIDX, NAIDX),
+    /// All pairs (i+k, j-k) for k < len are present.
+    Helix { i: NAIDX, j: NAIDX, len: NAIDX },
+    Pattern(Vec<PatternSymbol>),
+    /// Base-pair distance to the reference is at most `distance`.
+    MaxDistance { reference: PairTable, distance: usize },
+}
+
+/// Number of base-pairs that are present in exactly one of two structures.
+pub fn base_pair_distance(a: &PairTable, b: &PairTable) -> usize {
+    a.iter().zip(b.iter())
+        .enumerate()
+        .map(|(i, (pa, pb))| {
+            if pa == pb {
+                0
+            } else {
+                pa.is_some_and(|j| j as usize > i) as usize
+                    + pb.is_some_and(|j| j as usize > i) as usize
+            }
+        })
+        .sum()
+}
+
+impl StructureRule {
+    /// True if evaluating the rule requires the pair table of a structure.
+    pub fn needs_pair_table(&self) -> bool {
+        !matches!(self, StructureRule::Pattern(_))
+    }
+
+    /// Evaluate the rule (`pairs` must be given if `needs_pair_table()`).
+    pub fn matches(&self, structure: &DotBracketVec, pairs: Option<&PairTable>) -> bool {
+        let pair = |i: NAIDX, j: NAIDX| {
+            pairs.expect("Missing pair table for rule evaluation")
+                .get(i as usize)
+                .is_some_and(|&p| p == Some(j))
+        };
+        match self {
+            StructureRule::RequiredPair(i, j) => pair(*i, *j),
+            StructureRule::ForbiddenPair(i, j) => !pair(*i, *j),
+            StructureRule::Helix { i, j, len } => (0..*len).all(|k| k <= *j && pair(i + k, j - k)),
+            StructureRule::Pattern(symbols) => {
+                symbols.len() == structure.len() &&
+                    symbols.iter().zip(structure.iter()).all(|(p, s)| match p {
+                        PatternSymbol::Exact(d) => d == s,
+                        PatternSymbol::Paired => matches!(s, DotBracket::Open | DotBracket::Close),
+                        PatternSymbol::Any => true,
+                    })
+            }
+            StructureRule::MaxDistance { reference, distance } => {
+                let pt = pairs.expect("Missing pair table for rule evaluation");
+                pt.len() == reference.len() && base_pair_distance(pt, reference) <= *distance
+            }
+        }
+    }
+
+    /// Parse a rule from a line of a macrostate file (1-based indices).
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let index = |t: &str| -> Result<NAIDX, String> {
+            match t.parse::<NAIDX>() {
+                Ok(i) if i > 0 => Ok(i - 1),
+                _ => Err(format!("Invalid (1-based) index '{}'", t)),
+            }
+        };
+        let number = |t: &str| -> Result<usize, String> {
+            t.parse::<usize>().map_err(|_| format!("Invalid number '{}'", t))
+        };
+        match tokens.as_slice() {
+            ["pair", i, j] => Ok(StructureRule::RequiredPair(index(i)?, index(j)?)),
+            ["nopair", i, j] => Ok(StructureRule::ForbiddenPair(index(i)?, index(j)?)),
+            ["helix", i, j, len] => Ok(StructureRule::Helix {
+                i: index(i)?,
+                j: index(j)?,
+                len: number(len)? as NAIDX,
+            }),
+            ["pattern", p] => p.chars().map(|c| match c {
+                '|' => Ok(PatternSymbol::Paired),
+                '?' | '*' => Ok(PatternSymbol::Any),
+                c => DotBracket::try_from(c)
+                    .map(PatternSymbol::Exact)
+                    .map_err(|_| format!("Invalid pattern symbol '{}'", c)),
+            }).collect::<Result<Vec<_>, _>>().map(StructureRule::Pattern),
+            ["distance", d, reference] => Ok(StructureRule::MaxDistance {
+                reference: PairTable::try_from(*reference)
+                    .map_err(|e| format!("Invalid reference structure: {:?}", e))?,
+                distance: number(d)?,
+            }),
+            _ => Err(format!("Unknown macrostate rule '{}'", line)),
+        }
+    }
+}
+
+impl fmt::Display for StructureRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StructureRule::RequiredPair(i, j) => write!(f, "pair {} {}", i + 1, j + 1),
+            StructureRule::ForbiddenPair(i, j) => write!(f, "nopair {} {}", i + 1, j + 1),
+            StructureRule::Helix { i, j, len } => write!(f, "helix {} {} {}", i + 1, j + 1, len),
+            StructureRule::Pattern(symbols) => {
+                let p: String = symbols.iter().map(|s| match s {
+                    PatternSymbol::Exact(d) => char::from(*d),
+                    PatternSymbol::Paired => '|',
+                    PatternSymbol::Any => '?',
+                }).collect();
+                write!(f, "pattern {}", p)
+            }
+            StructureRule::MaxDistance { reference, distance } => {
+                write!(f, "distance {} {}", distance, DotBracketVec::from(reference))
+            }
+        }
+    }
+}
+
+/// True if the structure satisfies all rules.
+pub fn matches_all(rules: &[StructureRule], structure: &DotBracketVec) -> bool {
+    let pairs = if rules.iter().any(|r| r.needs_pair_table()) {
+        match PairTable::try_from(structure) {
+            Ok(pt) => Some(pt),
+            Err(_) => return false,
+        }
+    } else {
+        None
+    };
+    rules.iter().all(|r| r.matches(structure, pairs.as_ref()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dbv(s: &str) -> DotBracketVec {
+        DotBracketVec::try_from(s).unwrap()
+    }
+
+    #[test]
+    fn test_structure_rules() {
+        let s = dbv("((((....))))....");
+        let rules = [
+            "pair 1 12",
+            "nopair 1 11",
+            "helix 1 12 4",
+            "pattern ((||????))??....",
+            "distance 1 .(((....))).....",
+        ];
+        for line in rules {
+            let rule = StructureRule::parse(line).unwrap();
+            assert!(matches_all(std::slice::from_ref(&rule), &s), "{}", line);
+            assert_eq!(StructureRule::parse(&rule.to_string()).unwrap(), rule);
+        }
+
+        let other = dbv(".(((....))).....");
+        assert!(!matches_all(&[StructureRule::parse("pair 1 12").unwrap()], &other));
+        assert!(!matches_all(&[StructureRule::parse("helix 1 12 2").unwrap()], &other));
+        assert!(!matches_all(&[StructureRule::parse("pattern ((((????))))....").unwrap()], &other));
+        assert!(!matches_all(&[StructureRule::parse("distance 0 ((((....))))....").unwrap()], &other));
+        assert!(matches_all(&[StructureRule::parse("pattern ?|||????|||?????").unwrap()], &other));
+
+        assert!(StructureRule::parse("pair 0 12").is_err());
+        assert!(StructureRule::parse("pattern ((x))").is_err());
+        assert!(StructureRule::parse("unknown 1 2").is_err());
+
+        let a = PairTable::try_from("((((....))))....").unwrap();
+        let b = PairTable::try_from("..((....))((..))").unwrap();
+        assert_eq!(base_pair_distance(&a, &b), 4);
+    }
+}
@@ -1,9 +1,11 @@
 use std::fs::File;
 use std::io::BufRead;
 use std::io::BufReader;
+use std::io::Write;
 use std::io;
 use std::path::PathBuf;
 use ahash::AHashMap;
+use nohash_hasher::IntMap;
 use rand::Rng;
 
 use ff_structure::DotBracketVec;
@@ -12,6 +14,10 @@ use ff_energy::NucleotideVec;
 use ff_energy::EnergyModel;
 
 use crate::{K0, KB};
+use crate::StructureRule;
+use crate::matches_all;
+use crate::structure_hash;
+use crate::LoopStructure;
 
 /// Represents a **macrostate**, i.e. an ensemble of secondary structures
 /// sharing a common label or coarse-grained feature.
@@ -26,6 +32,10 @@ use crate::{K0, KB};
 /// The optional `ensemble_energy` field stores the free energy of the
 /// macrostate (-kt ln(Q)) calculated at initialization. 
 ///
+/// Alternatively, a macrostate can be defined by [`StructureRule`]s: it then
+/// contains every structure that satisfies all rules, and it has neither an
+/// explicit ensemble nor an ensemble energy.
+///
 /// # Fields
 /// - `name`: Identifier or label for this macrostate `α` (e.g. `"Local minimum 1"`
 ///   or `"Hairpin A"`).
@@ -63,6 +73,7 @@ pub struct Macrostate {
     name: String,
     ensemble: AHashMap<DotBracketVec, (i32, f64)>,
     ensemble_energy: Option<f64>,
+    rules: Vec<StructureRule>,
 }
 
 impl Macrostate {
@@ -73,6 +84,18 @@ impl Macrostate {
             name: name.to_owned(),
             ensemble: AHashMap::new(),
             ensemble_energy: None,
+            rules: Vec::new(),
+        }
+    }
+
+    /// A macrostate of all structures that satisfy every rule.
+    pub fn from_rules(name: &str, rules: Vec<StructureRule>) -> Self {
+        assert!(!rules.is_empty(), "A rule-based macrostate needs at least one rule");
+        Macrostate { 
+            name: name.to_owned(),
+            ensemble: AHashMap::new(),
+            ensemble_energy: None,
+            rules,
         }
     }
 
@@ -102,6 +125,7 @@ impl Macrostate {
             name: name.to_owned(),
             ensemble,
             ensemble_energy: Some(-rt * q_sum.ln()),
+            rules: Vec::new(),
         }
     }
 
@@ -117,7 +141,15 @@ impl Macrostate {
         self.ensemble_energy
     }
 
-    /// Number of secondary structures.
+    pub fn rules(&self) -> &[StructureRule] {
+        &self.rules
+    }
+
+    pub fn is_rule_based(&self) -> bool {
+        !self.rules.is_empty()
+    }
+
+    /// Number of (explicitly listed) secondary structures.
     pub fn len(&self) -> usize {
         self.ensemble.len()
     }
@@ -128,7 +160,22 @@ impl Macrostate {
     
     /// Check if a secondary structure is contained in this macrostate.
     pub fn contains(&self, structure: &DotBracketVec) -> bool {
-        self.ensemble.contains_key(structure)
+        if self.is_rule_based() {
+            matches_all(&self.rules, structure)
+        } else {
+            self.ensemble.contains_key(structure)
+        }
+    }
+
+    /// The probability P(s|α) of a structure within this macrostate, or None
+    /// if the structure is not contained. Rule-based macrostates have no
+    /// explicit ensemble, they report P(s|α) = 1 for all members.
+    pub fn probability(&self, structure: &DotBracketVec) -> Option<f64> {
+        if self.is_rule_based() {
+            self.contains(structure).then_some(1.0)
+        } else {
+            self.ensemble.get(structure).map(|&(_, p)| p)
+        }
     }
 
     /// Randomly pick a structure according to its probability in the ensemble.
@@ -155,12 +202,36 @@ impl Macrostate {
     }
 }
 
+/// How to classify a structure that belongs to more than one macrostate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverlapPolicy {
+    /// The macrostate that was inserted first wins.
+    #[default]
+    FirstMatch,
+    /// The macrostate in which the structure has the highest probability
+    /// P(s|α) wins (ties are resolved by insertion order). Rule-based
+    /// macrostates have no ensemble and report P(s|α) = 1, so they win
+    /// against every list-based macrostate.
+    HighestProbability,
+    /// The structure is split between all matching macrostates, weighted
+    /// by P(s|α). Where a single macrostate is required, this behaves like
+    /// `HighestProbability`. As rule-based macrostates report P(s|α) = 1,
+    /// they take (almost) the full weight of a structure that they share
+    /// with list-based macrostates.
+    Fractional,
+}
+
 /// A registy to collect macrostate definitions.
 pub struct MacrostateRegistry<'a, E: EnergyModel> {
     sequence: &'a NucleotideVec,
     energy_model: &'a E,
     /// By convention: macrostates[0] = unassigned.
     macrostates: Vec<Macrostate>,
+    overlap_policy: OverlapPolicy,
+    /// Structure hash to the list-based macrostates containing it, with P(s|α).
+    index: IntMap<u64, Vec<(usize, f64)>>,
+    /// Indices of rule-based macrostates.
+    rule_based: Vec<usize>,
 }
 
 impl<'a, E: EnergyModel> From<(&'a NucleotideVec, &'a E)> for MacrostateRegistry<'a, E> {
@@ -171,16 +242,59 @@ impl<'a, E: EnergyModel> From<(&'a NucleotideVec, &'a E)> for MacrostateRegistry
             sequence,
             energy_model,
             macrostates,
+            overlap_policy: OverlapPolicy::default(),
+            index: IntMap::default(),
+            rule_based: Vec::new(),
         }
     }
 }
 
+/// Write a macrostate file: a `>name` header line, the sequence and one
+/// structure per line (see [`MacrostateRegistry::insert_from_reader`]).
+pub fn write_macrostate<W: Write>(
+    writer: &mut W,
+    name: &str,
+    sequence: &NucleotideVec,
+    structures: &[DotBracketVec],
+) -> io::Result<()> {
+    writeln!(writer, ">{}", name)?;
+    writeln!(writer, "{}", sequence)?;
+    for dbv in structures {
+        writeln!(writer, "{}", dbv)?;
+    }
+    Ok(())
+}
+
 fn io_err(msg: &str, src: &str) -> io::Error {
     io::Error::new(io::ErrorKind::InvalidData, format!("{} in {}", msg, src))
 }
 
 impl<'a, E: EnergyModel> MacrostateRegistry<'a, E> {
 
+    /// Add a macrostate and return its index. Returns an error if a
+    /// structure is unbalanced or does not match the sequence length.
+    pub fn insert(&mut self, macrostate: Macrostate) -> Result<usize, String> {
+        let idx = self.macrostates.len();
+        let mut hashes = Vec::with_capacity(macrostate.len());
+        for (dbv, &(_, p)) in macrostate.ensemble() {
+            let pt = PairTable::try_from(dbv)
+                .map_err(|e| format!("Invalid structure in macrostate '{}': {}", macrostate.name(), e))?;
+            if pt.len() != self.sequence.len() {
+                return Err(format!("Structure length mismatch in macrostate '{}': {}",
+                    macrostate.name(), dbv));
+            }
+            hashes.push((structure_hash(&pt), p));
+        }
+        for (hash, p) in hashes {
+            self.index.entry(hash).or_default().push((idx, p));
+        }
+        if macrostate.is_rule_based() {
+            self.rule_based.push(idx);
+        }
+        self.macrostates.push(macrostate);
+        Ok(idx)
+    }
+
     /// High-level entry: read one or more macrostate files from disk.
     pub fn insert_files(&mut self, files: &[PathBuf]) -> io::Result<()> {
         for file in files {
@@ -219,14 +333,43 @@ impl<'a, E: EnergyModel> MacrostateRegistry<'a, E> {
         }
 
         let mut structures = Vec::new();
+        let mut rules = Vec::new();
         for (lineno, line) in lines.enumerate() {
             let line = line?;
             let line = line.trim();
             if line.is_empty() || line.starts_with('#') {
                 continue;
             }
+            if line.starts_with(|c: char| c.is_ascii_alphabetic()) {
+                // Rules may end with a comment.
+                let rule = line.split('#').next().unwrap().trim();
+                match StructureRule::parse(rule) {
+                    Ok(rule) => rules.push(rule),
+                    Err(e) => {
+                        return Err(io_err(
+                            &format!("Invalid rule at line {}: {}", lineno + 3, e),
+                            source,
+                        ));
+                    }
+                }
+                continue;
+            }
             match DotBracketVec::try_from(line) {
-                Ok(dbv) => structures.push(dbv),
+                Ok(dbv) if dbv.len() != self.sequence.len() => {
+                    return Err(io_err(
+                        &format!("Structure length mismatch at line {}", lineno + 3),
+                        source,
+                    ));
+                }
+                Ok(dbv) => match PairTable::try_from(&dbv) {
+                    Ok(_) => structures.push(dbv),
+                    Err(e) => {
+                        return Err(io_err(
+                            &format!("Invalid structure at line {}: {}", lineno + 3, e),
+                            source,
+                        ));
+                    }
+                },
                 Err(e) => {
                     return Err(io_err(
                         &format!("Invalid dot-bracket at line {}: {:?}", lineno + 3, e),
@@ -236,40 +379,132 @@ impl<'a, E: EnergyModel> MacrostateRegistry<'a, E> {
             }
         }
 
+        if !rules.is_empty() {
+            if !structures.is_empty() {
+                return Err(io_err("Cannot mix structures and rules in one macrostate", source));
+            }
+            self.insert(Macrostate::from_rules(&name, rules))
+                .map_err(|e| io_err(&e, source))?;
+            return Ok(());
+        }
+
         if structures.is_empty() {
             return Err(io_err("No structures found", source));
         }
 
-        self.macrostates.push(Macrostate::from_list(
+        self.insert(Macrostate::from_list(
             &name,
             self.sequence,
             &structures,
             self.energy_model,
-        ));
+        )).map_err(|e| io_err(&e, source))?;
         Ok(())
     }
 
-    /// Try to classify a structure:
-    /// - Returns Some(index) if exactly one macrostate contains the structure
-    /// - Returns None if no macrostate matches
-    /// - Panics if more than one macrostate matches (unimplemented)
+    /// Use a different policy for structures in overlapping macrostates.
+    pub fn with_overlap_policy(mut self, policy: OverlapPolicy) -> Self {
+        self.overlap_policy = policy;
+        self
+    }
+
+    pub fn overlap_policy(&self) -> OverlapPolicy {
+        self.overlap_policy
+    }
+
+    /// Rename the catch-all macrostate (default: "Unassigned").
+    pub fn with_catch_all_name(mut self, name: &str) -> Self {
+        self.macrostates[0].name = name.to_owned();
+        self
+    }
+
+    /// All macrostates containing the structure, with P(s|α).
+    fn matches(&self, structure: &DotBracketVec) -> Vec<(usize, f64)> {
+        self.macrostates.iter()
+            .enumerate()
+            .filter_map(|(i, ms)| ms.probability(structure).map(|p| (i, p)))
+            .collect()
+    }
+
+    /// Classify a structure:
+    /// - Returns the index of the matching macrostate
+    /// - Returns 0 (unassigned) if no macrostate matches
+    /// - Resolves multiple matches according to the [`OverlapPolicy`]
     pub fn classify(&self, structure: &DotBracketVec) -> usize {
-        let mut matches = Vec::new();
+        self.resolve(self.matches(structure))
+    }
 
-        for (i, ms) in self.macrostates.iter().enumerate() {
-            if ms.contains(structure) {
-                matches.push(i);
-            }
+    /// Pick one of the matching macrostates according to the [`OverlapPolicy`].
+    fn resolve<I: IntoIterator<Item = (usize, f64)>>(&self, matches: I) -> usize {
+        matches.into_iter()
+            .reduce(|best, m| {
+                let better = match self.overlap_policy {
+                    OverlapPolicy::FirstMatch => m.0 < best.0,
+                    OverlapPolicy::HighestProbability | OverlapPolicy::Fractional => {
+                        m.1 > best.1 || (m.1 == best.1 && m.0 < best.0)
+                    }
+                };
+                if better { m } else { best }
+            })
+            .map_or(0, |(i, _)| i)
+    }
+
+    /// All macrostates containing the current structure of a loop structure,
+    /// with P(s|α). List-based macrostates are looked up by the structure
+    /// hash, so only rule-based macrostates need a dot-bracket structure.
+    fn matches_loops<M: EnergyModel>(&self, ls: &LoopStructure<'_, M>
+    ) -> impl Iterator<Item = (usize, f64)> + '_ {
+        let listed = self.index.get(&ls.structure_hash())
+            .map_or(&[][..], |m| &m[..])
+            .iter()
+            .copied();
+        debug_assert!(listed.clone().all(|(i, _)| self.macrostates[i].contains(&DotBracketVec::from(ls))),
+            "Structure hash collision in the macrostate index");
+        let structure = (!self.rule_based.is_empty()).then(|| DotBracketVec::from(ls));
+        let rules = self.rule_based.iter().filter_map(move |&i| {
+            structure.as_ref()
+                .filter(|s| self.macrostates[i].contains(s))
+                .map(|_| (i, 1.0))
+        });
+        listed.chain(rules)
+    }
+
+    /// Classify the current structure of a loop structure (see
+    /// [`classify`](Self::classify)). This uses the structure hash maintained
+    /// by the loop structure and does not allocate, unless the registry
+    /// contains rule-based macrostates.
+    pub fn classify_loops<M: EnergyModel>(&self, ls: &LoopStructure<'_, M>) -> usize {
+        self.resolve(self.matches_loops(ls))
+    }
+
+    /// Like [`classify_weighted`](Self::classify_weighted), for the current
+    /// structure of a loop structure.
+    pub fn classify_loops_weighted<M: EnergyModel>(&self, ls: &LoopStructure<'_, M>
+    ) -> Vec<(usize, f64)> {
+        if self.overlap_policy != OverlapPolicy::Fractional {
+            return vec![(self.classify_loops(ls), 1.0)];
         }
+        let mut matches: Vec<_> = self.matches_loops(ls).collect();
+        if matches.is_empty() {
+            return vec![(0, 1.0)];
+        }
+        matches.sort_by_key(|&(i, _)| i);
+        let total: f64 = matches.iter().map(|(_, p)| p).sum();
+        matches.into_iter().map(|(i, p)| (i, p / total)).collect()
+    }
 
-        match matches.len() {
-            0 => 0,
-            1 => matches[0],
-            _ => {
-                // For now: raise a panic, since overlapping macrostates are ambiguous
-                panic!("Structure {:?} belongs to multiple macrostates — not implemented", structure);
-            }
+    /// Classify a structure into (macrostate index, weight) pairs, where
+    /// the weights sum to one. Only the `Fractional` policy returns more
+    /// than one macrostate.
+    pub fn classify_weighted(&self, structure: &DotBracketVec) -> Vec<(usize, f64)> {
+        if self.overlap_policy != OverlapPolicy::Fractional {
+            return vec![(self.classify(structure), 1.0)];
         }
+        let matches = self.matches(structure);
+        if matches.is_empty() {
+            return vec![(0, 1.0)];
+        }
+        let total: f64 = matches.iter().map(|(_, p)| p).sum();
+        matches.into_iter().map(|(i, p)| (i, p / total)).collect()
     }
 
     pub fn sequence(&self) -> &NucleotideVec {
@@ -391,4 +626,162 @@ mod tests {
         assert!(all_names.contains(&"Unassigned".to_string()));
         assert!(all_names.contains(&"test".to_string()));
     }
+
+    #[test]
+    fn test_overlap_policies() {
+        let energy_model = ViennaRNA::default();
+        let seq = NucleotideVec::try_from("UCAGUCUUCGCUGCGCUGUAUCGAUUCGGUUUCAGUUUUUAUUGC").unwrap();
+        let mut registry = MacrostateRegistry::from((&seq, &energy_model));
+        let input = b">test
+        UCAGUCUUCGCUGCGCUGUAUCGAUUCGGUUUCAGUUUUUAUUGC
+        .((((....)))).((((........))))...............
+        .((((....)))).((((.(....).))))...............
+        .((((....))))..(((........)))................
+        ";
+        registry.insert_from_reader(Cursor::new(input), "manual").unwrap();
+        let s1 = DotBracketVec::try_from(".((((....)))).((((........))))...............").unwrap();
+        let s2 = DotBracketVec::try_from(".((((....)))).((((.(....).))))...............").unwrap();
+        let s4 = DotBracketVec::try_from(".............................................").unwrap();
+
+        // Overlapping macrostates
+        let input = b">overlap
+        UCAGUCUUCGCUGCGCUGUAUCGAUUCGGUUUCAGUUUUUAUUGC
+        .((((....)))).((((.(....).))))...............
+        ";
+        registry.insert_from_reader(Cursor::new(input), "manual").unwrap();
+        assert_eq!(registry.classify(&s2), 1);
+        assert_eq!(registry.classify_weighted(&s2), vec![(1, 1.0)]);
+
+        let registry = registry.with_overlap_policy(OverlapPolicy::HighestProbability);
+        assert_eq!(registry.classify(&s2), 2);
+        assert_eq!(registry.classify(&s1), 1);
+
+        let registry = registry.with_overlap_policy(OverlapPolicy::Fractional);
+        let weights = registry.classify_weighted(&s2);
+        assert_eq!(weights.len(), 2);
+        assert!((weights.iter().map(|(_, w)| w).sum::<f64>() - 1.0).abs() < 1e-12);
+        assert!(weights[1].1 > weights[0].1);
+        assert_eq!(registry.classify_weighted(&s4), vec![(0, 1.0)]);
+    }
+
+    #[test]
+    fn test_rule_based_macrostates() {
+        let energy_model = ViennaRNA::default();
+        let seq = NucleotideVec::try_from("UCAGUCUUCGCUGCGCUGUAUCGAUUCGGUUUCAGUUUUUAUUGC").unwrap();
+        let mut registry = MacrostateRegistry::from((&seq, &energy_model))
+            .with_overlap_policy(OverlapPolicy::HighestProbability);
+        let input = b">test
+        UCAGUCUUCGCUGCGCUGUAUCGAUUCGGUUUCAGUUUUUAUUGC
+        # a comment line
+        .((((....)))).((((........))))...............
+        .((((....)))).((((.(....).))))...............
+        ";
+        registry.insert_from_reader(Cursor::new(input), "manual").unwrap();
+        let s1 = DotBracketVec::try_from(".((((....)))).((((........))))...............").unwrap();
+        let s4 = DotBracketVec::try_from(".............................................").unwrap();
+        assert_eq!(registry.classify(&s1), 1);
+
+        // Inline comments are only allowed after rules: structure lines
+        // are parsed as before.
+        let commented = b">commented
+        UCAGUCUUCGCUGCGCUGUAUCGAUUCGGUUUCAGUUUUUAUUGC
+        .((((....)))).((((........))))............... # comment
+        ";
+        assert!(registry.insert_from_reader(Cursor::new(commented), "manual").is_err());
+
+        // Rule-based macrostates mix with list-based ones.
+        let input = b">rules
+        UCAGUCUUCGCUGCGCUGUAUCGAUUCGGUUUCAGUUUUUAUUGC
+        helix 2 13 4 # first hairpin
+        nopair 19 26
+        ";
+        registry.insert_from_reader(Cursor::new(input), "manual").unwrap();
+        assert!(registry.macrostates()[2].is_rule_based());
+        let s5 = DotBracketVec::try_from(".((((....))))................................").unwrap();
+        assert_eq!(registry.classify(&s5), 2);
+        assert_eq!(registry.classify(&s1), 2); // P(s|rules) = 1
+        assert_eq!(registry.classify(&s4), 0);
+
+        let mixed = b">mixed
+        UCAGUCUUCGCUGCGCUGUAUCGAUUCGGUUUCAGUUUUUAUUGC
+        helix 2 13 4
+        .((((....)))).((((........))))...............
+        ";
+        assert!(registry.insert_from_reader(Cursor::new(mixed), "manual").is_err());
+    }
+
+    #[test]
+    fn test_insert_invalid_structures() {
+        let energy_model = ViennaRNA::default();
+        let seq = NucleotideVec::try_from("UCAGUCUUCGCUGCGCUGUAUCGAUUCGGUUUCAGUUUUUAUUGC").unwrap();
+        let mut registry = MacrostateRegistry::from((&seq, &energy_model));
+
+        // A shorter open chain must not match the open chain by its hash.
+        let short = DotBracketVec::try_from("............").unwrap();
+        let ms = Macrostate::from_list("short", &seq, &[short], &energy_model);
+        assert!(registry.insert(ms).is_err());
+        assert_eq!(registry.len(), 1);
+        let open = DotBracketVec::try_from(".............................................").unwrap();
+        assert_eq!(registry.classify(&open), 0);
+
+        let unbalanced = b">unbalanced
+        UCAGUCUUCGCUGCGCUGUAUCGAUUCGGUUUCAGUUUUUAUUGC
+        .((((....)))).((((........)))................
+        ";
+        assert!(registry.insert_from_reader(Cursor::new(unbalanced), "manual").is_err());
+        let short = b">short
+        UCAGUCUUCGCUGCGCUGUAUCGAUUCGGUUUCAGUUUUUAUUGC
+        .((((....)))).
+        ";
+        assert!(registry.insert_from_reader(Cursor::new(short), "manual").is_err());
+        assert_eq!(registry.len(), 1);
+    }
+
+    #[test]
+    fn test_classify_loops() {
+        let energy_model = ViennaRNA::default();
+        let seq = NucleotideVec::try_from("UCAGUCUUCGCUGCGCUGUAUCGAUUCGGUUUCAGUUUUUAUUGC").unwrap();
+        let mut registry = MacrostateRegistry::from((&seq, &energy_model));
+        let input = b">test
+        UCAGUCUUCGCUGCGCUGUAUCGAUUCGGUUUCAGUUUUUAUUGC
+        .((((....)))).((((........))))...............
+        .((((....)))).((((.(....).))))...............
+        ";
+        registry.insert_from_reader(Cursor::new(input), "manual").unwrap();
+        let input = b">overlap
+        UCAGUCUUCGCUGCGCUGUAUCGAUUCGGUUUCAGUUUUUAUUGC
+        .((((....)))).((((.(....).))))...............
+        ";
+        registry.insert_from_reader(Cursor::new(input), "manual").unwrap();
+
+        let structures = [
+            ".((((....)))).((((........))))...............",
+            ".((((....)))).((((.(....).))))...............",
+            ".((((....))))................................",
+            ".............................................",
+        ];
+        let check = |registry: &MacrostateRegistry<ViennaRNA>| {
+            for s in structures {
+                let pt = PairTable::try_from(s).unwrap();
+                let ls = LoopStructure::try_from((&seq[..], &pt, &energy_model)).unwrap();
+                let dbv = DotBracketVec::try_from(s).unwrap();
+                assert_eq!(registry.classify_loops(&ls), registry.classify(&dbv));
+                assert_eq!(registry.classify_loops_weighted(&ls), registry.classify_weighted(&dbv));
+            }
+        };
+        check(&registry);
+        let registry = registry.with_overlap_policy(OverlapPolicy::HighestProbability);
+        check(&registry);
+        let mut registry = registry.with_overlap_policy(OverlapPolicy::Fractional);
+        check(&registry);
+
+        let input = b">rules
+        UCAGUCUUCGCUGCGCUGUAUCGAUUCGGUUUCAGUUUUUAUUGC
+        helix 2 13 4
+        ";
+        registry.insert_from_reader(Cursor::new(input), "manual").unwrap();
+        check(&registry);
+        let registry = registry.with_overlap_policy(OverlapPolicy::FirstMatch);
+        check(&registry);
+    }
 }
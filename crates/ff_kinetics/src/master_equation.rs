@@ -0,0 +1,244 @@
+//! Exact time evolution for small state spaces.
+//!
+//! The chemical master equation dp/dt = p Q is solved for a generator Q,
+//! where Q[(a, b)] is the rate from state a to state b and every row sums
+//! to zero. States are either secondary structures (with rates from a
+//! [`RateModel`]) or macrostates (e.g. a coarse-grained rate matrix from
+//! barrier trees or commit-and-delay).
+
+use std::sync::Arc;
+use ahash::AHashMap;
+use ndarray::Array1;
+use ndarray::Array2;
+
+use ff_structure::DotBracketVec;
+use ff_structure::PairTable;
+use ff_energy::EnergyModel;
+use ff_energy::NucleotideVec;
+
+use crate::RateModel;
+use crate::LoopStructure;
+use crate::MacrostateRegistry;
+use crate::timeline::Timeline;
+use crate::timeline_statistics::ConfidenceMethod;
+use crate::reaction::MoveSet;
+use crate::reaction::ApplyMove;
+
+/// The matrix exponential exp(A), by scaling and squaring of a truncated
+/// Taylor series.
+pub fn expm(a: &Array2<f64>) -> Array2<f64> {
+    let n = a.nrows();
+    assert_eq!(n, a.ncols(), "Matrix exponential of a non-square matrix");
+    let norm = a.rows().into_iter()
+        .map(|row| row.iter().map(|x| x.abs()).sum::<f64>())
+        .fold(0.0, f64::max);
+    let squarings = if norm > 0.5 { (norm / 0.5).log2().ceil() as i32 } else { 0 };
+    let scaled = a / 2f64.powi(squarings);
+
+    let mut result = Array2::<f64>::eye(n);
+    let mut term = Array2::<f64>::eye(n);
+    for k in 1..=16 {
+        term = term.dot(&scaled) / k as f64;
+        result += &term;
+    }
+    for _ in 0..squarings {
+        result = result.dot(&result);
+    }
+    result
+}
+
+/// A master equation with a fixed generator.
+#[derive(Debug, Clone)]
+pub struct MasterEquation {
+    generator: Array2<f64>,
+}
+
+impl MasterEquation {
+    /// Use a given generator (rows must sum to zero).
+    pub fn new(generator: Array2<f64>) -> Self {
+        assert_eq!(generator.nrows(), generator.ncols(), "Generator must be square");
+        debug_assert!(generator.rows().into_iter().all(|row| {
+            row.sum().abs() <= 1e-9 * row.iter().map(|x| x.abs()).sum::<f64>().max(1.0)
+        }), "Generator rows must sum to zero");
+        Self { generator }
+    }
+
+    /// The generator of the truncated state space spanned by `structures`:
+    /// moves to structures that are not in the list are ignored. Returns an
+    /// error if a structure is unbalanced or does not match the sequence.
+    pub fn from_structures<E: EnergyModel, R: RateModel>(
+        sequence: &NucleotideVec,
+        model: &E,
+        rate_model: &R,
+        move_set: MoveSet,
+        structures: &[DotBracketVec],
+    ) -> Result<Self, String> {
+        let n = structures.len();
+        let lookup: AHashMap<&DotBracketVec, usize> = structures.iter()
+            .enumerate()
+            .map(|(k, s)| (s, k))
+            .collect();
+        let mut generator = Array2::<f64>::zeros((n, n));
+        for (a, dbv) in structures.iter().enumerate() {
+            let pt = PairTable::try_from(dbv)
+                .map_err(|e| format!("Invalid structure for master equation {}: {}", dbv, e))?;
+            if pt.len() != sequence.len() {
+                return Err(format!("Structure length mismatch for master equation: {}", dbv));
+            }
+            let ls = LoopStructure::try_from((&sequence[..], &pt, model))?
+                .with_move_set(move_set);
+            let mut neighbor = dbv.clone();
+            for (mv, delta) in ls.all_moves() {
+                neighbor.apply_move(mv);
+                if let Some(&b) = lookup.get(&neighbor) {
+                    let k = rate_model.rate(delta);
+                    generator[(a, b)] += k;
+                    generator[(a, a)] -= k;
+                }
+                neighbor.undo_move(mv);
+            }
+        }
+        Ok(Self { generator })
+    }
+
+    pub fn generator(&self) -> &Array2<f64> {
+        &self.generator
+    }
+
+    pub fn len(&self) -> usize {
+        self.generator.nrows()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.generator.is_empty()
+    }
+
+    /// Occupancies after time `t`, starting from `p0`.
+    pub fn propagate(&self, p0: &Array1<f64>, t: f64) -> Array1<f64> {
+        p0.dot(&expm(&(&self.generator * t)))
+    }
+
+    /// Occupancies at each of the (ascending) times, starting from `p0` at t = 0.
+    pub fn occupancies(&self, p0: &Array1<f64>, times: &[f64]) -> Vec<Array1<f64>> {
+        assert!(times.windows(2).all(|w| w[0] <= w[1]), "Times must be sorted");
+        let mut p = p0.clone();
+        let mut t_prev = 0.0;
+        times.iter().map(|&t| {
+            if t > t_prev {
+                p = self.propagate(&p, t - t_prev);
+                t_prev = t;
+            }
+            p.clone()
+        }).collect()
+    }
+}
+
+impl<'a, E: EnergyModel> Timeline<'a, E> {
+    /// A timeline of exact occupancies, where `occupancies[k][m]` is the
+    /// probability of macrostate `m` (registry index) at `times[k]`. The
+    /// timeline counts as a single observation per time point, without
+    /// confidence intervals.
+    pub fn from_occupancies(
+        times: &[f64],
+        registry: Arc<MacrostateRegistry<'a, E>>,
+        occupancies: &[Vec<f64>],
+    ) -> Self {
+        assert_eq!(times.len(), occupancies.len(), "One occupancy vector per time point");
+        let mut timeline = Timeline::new(times, registry)
+            .with_confidence(ConfidenceMethod::None);
+        for (tp, occu) in timeline.points.iter_mut().zip(occupancies) {
+            let weights: Vec<(usize, f64)> = occu.iter()
+                .copied()
+                .enumerate()
+                .filter(|&(_, p)| p > 0.0)
+                .collect();
+            tp.add_weighted(&weights);
+        }
+        timeline
+    }
+}
+
+/// Sum structure occupancies into macrostate occupancies (registry indices).
+pub fn macrostate_occupancies<E: EnergyModel>(
+    registry: &MacrostateRegistry<'_, E>,
+    structures: &[DotBracketVec],
+    occupancy: &Array1<f64>,
+) -> Vec<f64> {
+    let mut result = vec![0.0; registry.len()];
+    for (dbv, &p) in structures.iter().zip(occupancy) {
+        for (m, w) in registry.classify_weighted(dbv) {
+            result[m] += w * p;
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+    use ff_energy::ViennaRNA;
+    use crate::{K0, KB};
+    use crate::Metropolis;
+    use crate::landscape::enumerate_structures;
+
+    #[test]
+    fn test_two_state_master_equation() {
+        let (a, b) = (2.0, 0.5);
+        let me = MasterEquation::new(array![[-a, a], [b, -b]]);
+        let p0 = array![1.0, 0.0];
+        for t in [0.0, 0.1, 1.0, 10.0, 1000.0] {
+            let p = me.propagate(&p0, t);
+            let expected = b / (a + b) + a / (a + b) * (-(a + b) * t).exp();
+            assert!((p[0] - expected).abs() < 1e-10, "t = {}: {} vs {}", t, p[0], expected);
+            assert!((p.sum() - 1.0).abs() < 1e-10);
+        }
+        let occu = me.occupancies(&p0, &[0.0, 1.0, 2.0]);
+        assert_eq!(occu.len(), 3);
+        assert!((occu[2][0] - me.propagate(&p0, 2.0)[0]).abs() < 1e-10);
+    }
+
+    #[test]
+    fn test_master_equation_equilibrium() {
+        let model = ViennaRNA::default();
+        let sequence = NucleotideVec::try_from("GGGAAAUCC").unwrap();
+        let mut structures = Vec::new();
+        enumerate_structures(&sequence, &model, |s| structures.push(s.clone()));
+
+        let rate_model = Metropolis::new(model.temperature(), 1.0);
+        let me = MasterEquation::from_structures(&sequence, &model, &rate_model,
+            MoveSet::default(), &structures).unwrap();
+        assert_eq!(me.len(), structures.len());
+
+        for bad in ["((...)).", "((....)))"] {
+            let structures = vec![DotBracketVec::try_from(bad).unwrap()];
+            assert!(MasterEquation::from_structures(&sequence, &model, &rate_model,
+                MoveSet::default(), &structures).is_err());
+        }
+
+        let mut p0 = Array1::zeros(me.len());
+        p0[0] = 1.0;
+        let p = me.propagate(&p0, 1e6);
+        assert!((p.sum() - 1.0).abs() < 1e-9);
+
+        // Metropolis rates satisfy detailed balance.
+        let rt = KB * (K0 + model.temperature());
+        let weights: Vec<f64> = structures.iter().map(|s| {
+            let pt = PairTable::try_from(s).unwrap();
+            (-(model.energy_of_structure(&sequence, &pt) as f64) / 100. / rt).exp()
+        }).collect();
+        let z: f64 = weights.iter().sum();
+        for (k, w) in weights.iter().enumerate() {
+            assert!((p[k] - w / z).abs() < 1e-6);
+        }
+
+        // Macrostate timeline.
+        let registry = Arc::new(MacrostateRegistry::from((&sequence, &model)));
+        let times = [0.0, 1.0];
+        let occu: Vec<_> = me.occupancies(&p0, &times).iter()
+            .map(|p| macrostate_occupancies(&registry, &structures, p))
+            .collect();
+        let timeline = Timeline::from_occupancies(&times, registry, &occu);
+        assert!((timeline.point(1).occupancy(0) - 1.0).abs() < 1e-9);
+    }
+}
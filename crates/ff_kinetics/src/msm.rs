@@ -0,0 +1,466 @@
+//! Markov state models (MSMs) from macrostate-labelled trajectories.
+//!
+//! Trajectories are sampled at a fixed interval `dt` and every sample is
+//! labelled with its macrostate (registry index, including the unassigned
+//! macrostate 0). Transitions are counted with a sliding window at the lag
+//! time τ = `lag_steps` · dt, and a reversible transition matrix T(τ) is
+//! estimated by maximum likelihood (the fixed-point iteration of Prinz et
+//! al. 2011, J. Chem. Phys. 134, 174105).
+//!
+//! The eigenvalues λ_k of T(τ) give the implied timescales t_k = -τ / ln|λ_k|,
+//! which should not depend on τ if the model is Markovian. The
+//! Chapman–Kolmogorov test compares the prediction T(τ)^k with the matrix
+//! estimated directly at lag kτ.
+
+use std::fmt;
+use std::io;
+use std::io::Write;
+use ndarray::Array1;
+use ndarray::Array2;
+
+/// Macrostate labels of trajectories, sampled every `dt`.
+#[derive(Debug, Clone)]
+pub struct DiscreteTrajectories {
+    dt: f64,
+    num_states: usize,
+    trajectories: Vec<Vec<usize>>,
+}
+
+impl DiscreteTrajectories {
+    pub fn new(num_states: usize, dt: f64) -> Self {
+        assert!(dt > 0.0, "Sampling interval must be positive");
+        Self { dt, num_states, trajectories: Vec::new() }
+    }
+
+    pub fn dt(&self) -> f64 {
+        self.dt
+    }
+
+    pub fn num_states(&self) -> usize {
+        self.num_states
+    }
+
+    pub fn len(&self) -> usize {
+        self.trajectories.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.trajectories.is_empty()
+    }
+
+    pub fn add(&mut self, labels: Vec<usize>) {
+        assert!(labels.iter().all(|&m| m < self.num_states), "Invalid macrostate label");
+        self.trajectories.push(labels);
+    }
+
+    pub fn merge(&mut self, other: DiscreteTrajectories) {
+        assert_eq!(self.num_states, other.num_states,
+            "Cannot merge trajectories with different numbers of states");
+        assert_eq!(self.dt, other.dt,
+            "Cannot merge trajectories with different sampling intervals");
+        self.trajectories.extend(other.trajectories);
+    }
+
+    /// Sliding-window transition counts at `lag_steps` · dt, where entry
+    /// (a, b) counts transitions from a to b.
+    pub fn count_transitions(&self, lag_steps: usize) -> Array2<f64> {
+        assert!(lag_steps > 0, "Lag must be at least one step");
+        let mut counts = Array2::zeros((self.num_states, self.num_states));
+        for labels in &self.trajectories {
+            for (a, b) in labels.iter().zip(labels.iter().skip(lag_steps)) {
+                counts[(*a, *b)] += 1.0;
+            }
+        }
+        counts
+    }
+}
+
+/// The states of the largest strongly connected set of the directed
+/// transition graph (Kosaraju's algorithm), ranked by the number of counts
+/// within the set. Only in such a set, every state can be reached from every
+/// other one, which the reversible estimate requires.
+fn largest_connected_set(counts: &Array2<f64>) -> Vec<usize> {
+    let n = counts.nrows();
+    let edge = |a: usize, b: usize| a != b && counts[(a, b)] > 0.0;
+
+    // Depth-first search on the graph, recording states by finishing time.
+    let mut visited = vec![false; n];
+    let mut finished = Vec::with_capacity(n);
+    for start in 0..n {
+        if visited[start] {
+            continue;
+        }
+        visited[start] = true;
+        let mut stack = vec![(start, 0)];
+        while let Some((a, next)) = stack.last_mut() {
+            match (*next..n).find(|&b| !visited[b] && edge(*a, b)) {
+                Some(b) => {
+                    *next = b + 1;
+                    visited[b] = true;
+                    stack.push((b, 0));
+                }
+                None => {
+                    finished.push(*a);
+                    stack.pop();
+                }
+            }
+        }
+    }
+
+    // Depth-first search on the transposed graph, in reverse finishing
+    // order: every search tree is a strongly connected component.
+    let mut assigned = vec![false; n];
+    let mut best: (f64, Vec<usize>) = (0.0, Vec::new());
+    for &start in finished.iter().rev() {
+        if assigned[start] {
+            continue;
+        }
+        assigned[start] = true;
+        let mut members = vec![start];
+        let mut k = 0;
+        while k < members.len() {
+            let a = members[k];
+            for (b, done) in assigned.iter_mut().enumerate() {
+                if !*done && edge(b, a) {
+                    *done = true;
+                    members.push(b);
+                }
+            }
+            k += 1;
+        }
+        let total: f64 = members.iter()
+            .flat_map(|&a| members.iter().map(move |&b| (a, b)))
+            .map(|(a, b)| counts[(a, b)])
+            .sum();
+        if total > best.0 {
+            members.sort_unstable();
+            best = (total, members);
+        }
+    }
+    best.1
+}
+
+/// Eigenvalues of a symmetric matrix (cyclic Jacobi rotations), in
+/// decreasing order.
+fn symmetric_eigenvalues(mut a: Array2<f64>) -> Vec<f64> {
+    let n = a.nrows();
+    for _ in 0..100 {
+        let off: f64 = (0..n).flat_map(|p| (0..n).filter(move |&q| q != p).map(move |q| (p, q)))
+            .map(|(p, q)| a[(p, q)] * a[(p, q)])
+            .sum();
+        if off < 1e-24 {
+            break;
+        }
+        for p in 0..n {
+            for q in p + 1..n {
+                if a[(p, q)].abs() < 1e-300 {
+                    continue;
+                }
+                let theta = (a[(q, q)] - a[(p, p)]) / (2.0 * a[(p, q)]);
+                let sign = if theta >= 0.0 { 1.0 } else { -1.0 };
+                let t = sign / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+                for k in 0..n {
+                    let (akp, akq) = (a[(k, p)], a[(k, q)]);
+                    a[(k, p)] = c * akp - s * akq;
+                    a[(k, q)] = s * akp + c * akq;
+                }
+                for k in 0..n {
+                    let (apk, aqk) = (a[(p, k)], a[(q, k)]);
+                    a[(p, k)] = c * apk - s * aqk;
+                    a[(q, k)] = s * apk + c * aqk;
+                }
+            }
+        }
+    }
+    let mut eigenvalues: Vec<f64> = a.diag().to_vec();
+    eigenvalues.sort_by(|x, y| y.total_cmp(x));
+    eigenvalues
+}
+
+/// A reversible transition matrix on a set of macrostates.
+#[derive(Debug, Clone)]
+pub struct MarkovStateModel {
+    lag: f64,
+    /// Macrostate (registry) index of every row.
+    states: Vec<usize>,
+    transition: Array2<f64>,
+    stationary: Array1<f64>,
+}
+
+impl MarkovStateModel {
+    /// The reversible maximum-likelihood estimate from transition counts at
+    /// lag time `lag`, restricted to the largest connected set of states.
+    pub fn estimate(counts: &Array2<f64>, lag: f64) -> Result<Self, String> {
+        let states = largest_connected_set(counts);
+        if states.is_empty() {
+            return Err("No transitions observed".to_string());
+        }
+        let n = states.len();
+        let c = Array2::from_shape_fn((n, n), |(a, b)| counts[(states[a], states[b])]);
+        let c_sym = &c + &c.t();
+        let c_row: Vec<f64> = c.rows().into_iter().map(|r| r.sum()).collect();
+
+        let mut x = &c_sym / 2.0;
+        for _ in 0..100_000 {
+            let x_row: Vec<f64> = x.rows().into_iter().map(|r| r.sum()).collect();
+            let next = Array2::from_shape_fn((n, n), |(a, b)| {
+                if c_sym[(a, b)] == 0.0 {
+                    0.0
+                } else {
+                    c_sym[(a, b)] / (c_row[a] / x_row[a] + c_row[b] / x_row[b])
+                }
+            });
+            let change = (&next - &x).iter().map(|d| d.abs()).fold(0.0, f64::max);
+            x = next;
+            if change <= 1e-12 * x.sum() {
+                break;
+            }
+        }
+        let x_row: Array1<f64> = x.rows().into_iter().map(|r| r.sum()).collect();
+        let transition = Array2::from_shape_fn((n, n), |(a, b)| x[(a, b)] / x_row[a]);
+        let stationary = &x_row / x_row.sum();
+        Ok(Self { lag, states, transition, stationary })
+    }
+
+    pub fn lag(&self) -> f64 {
+        self.lag
+    }
+
+    /// Macrostate (registry) indices of the rows of the transition matrix.
+    pub fn states(&self) -> &[usize] {
+        &self.states
+    }
+
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    pub fn transition_matrix(&self) -> &Array2<f64> {
+        &self.transition
+    }
+
+    pub fn stationary_distribution(&self) -> &Array1<f64> {
+        &self.stationary
+    }
+
+    /// Eigenvalues of the transition matrix in decreasing order (the first
+    /// one is 1). As T is reversible, it is similar to the symmetric matrix
+    /// Π^½ T Π^-½.
+    pub fn eigenvalues(&self) -> Vec<f64> {
+        let sqrt_pi = self.stationary.mapv(f64::sqrt);
+        let n = self.len();
+        let s = Array2::from_shape_fn((n, n), |(a, b)| {
+            sqrt_pi[a] * self.transition[(a, b)] / sqrt_pi[b]
+        });
+        symmetric_eigenvalues((&s + &s.t()) / 2.0)
+    }
+
+    /// Implied timescales of all but the stationary eigenvalue.
+    pub fn implied_timescales(&self) -> Vec<f64> {
+        self.eigenvalues()
+            .into_iter()
+            .skip(1)
+            .map(|l| -self.lag / l.abs().ln())
+            .collect()
+    }
+
+    /// The distribution after `steps` lag times, starting from `p0`.
+    pub fn propagate(&self, p0: &Array1<f64>, steps: usize) -> Array1<f64> {
+        let mut p = p0.clone();
+        for _ in 0..steps {
+            p = p.dot(&self.transition);
+        }
+        p
+    }
+
+    /// Write the model as text: a commented header with the lag time and
+    /// the macrostate indices of the columns, then one row per macrostate
+    /// with its index, its name (from `names`, by macrostate index), its
+    /// stationary probability and the transition probabilities (rows: from,
+    /// columns: to).
+    pub fn write_text<W: Write>(&self, writer: &mut W, names: &[String]) -> io::Result<()> {
+        writeln!(writer, "# Markov state model transition matrix at lag time {:.6e}", self.lag)?;
+        write!(writer, "# {:>4} {:>20} {:>12}", "from", "macrostate", "stationary")?;
+        for m in &self.states {
+            write!(writer, " {:>12}", m)?;
+        }
+        writeln!(writer)?;
+        for (a, row) in self.transition.rows().into_iter().enumerate() {
+            let m = self.states[a];
+            write!(writer, "{:>6} {:>20} {:12.6e}", m, names[m], self.stationary[a])?;
+            for p in row {
+                write!(writer, " {:12.6e}", p)?;
+            }
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+
+    /// Chapman–Kolmogorov test for k = 1..=`max_k`: the probability to be
+    /// in the initial state after k lag times, predicted from T(τ)^k and
+    /// estimated from the data at lag kτ. If no model can be estimated at
+    /// lag kτ (e.g. as the trajectories are shorter), the estimates are NaN.
+    pub fn chapman_kolmogorov(
+        &self,
+        data: &DiscreteTrajectories,
+        lag_steps: usize,
+        max_k: usize,
+    ) -> ChapmanKolmogorov {
+        let n = self.len();
+        let mut predicted = Vec::with_capacity(max_k);
+        let mut estimated = Vec::with_capacity(max_k);
+        for k in 1..=max_k {
+            let counts = data.count_transitions(k * lag_steps);
+            let model = Self::estimate(&counts, k as f64 * self.lag).ok();
+            predicted.push((0..n).map(|a| {
+                let mut p0 = Array1::zeros(n);
+                p0[a] = 1.0;
+                self.propagate(&p0, k)[a]
+            }).collect());
+            estimated.push(self.states.iter().map(|m| {
+                model.as_ref()
+                    .and_then(|model| model.states.iter()
+                        .position(|x| x == m)
+                        .map(|a| model.transition[(a, a)]))
+                    .unwrap_or(f64::NAN)
+            }).collect());
+        }
+        ChapmanKolmogorov { lag: self.lag, states: self.states.clone(), predicted, estimated }
+    }
+}
+
+/// Self-transition probabilities after k = 1, 2, ... lag times. Estimates
+/// are NaN if the state is not in the connected set at lag kτ, or if there
+/// is no connected set at that lag.
+#[derive(Debug, Clone)]
+pub struct ChapmanKolmogorov {
+    pub lag: f64,
+    pub states: Vec<usize>,
+    pub predicted: Vec<Vec<f64>>,
+    pub estimated: Vec<Vec<f64>>,
+}
+
+impl ChapmanKolmogorov {
+    /// The largest deviation between prediction and estimate.
+    pub fn max_error(&self) -> f64 {
+        self.predicted.iter().flatten()
+            .zip(self.estimated.iter().flatten())
+            .map(|(p, e)| (p - e).abs())
+            .filter(|d| !d.is_nan())
+            .fold(0.0, f64::max)
+    }
+}
+
+impl fmt::Display for MarkovStateModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (a, row) in self.transition.rows().into_iter().enumerate() {
+            write!(f, "{:>4} {:8.5} |", self.states[a], self.stationary[a])?;
+            for p in row {
+                write!(f, " {:8.5}", p)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    #[test]
+    fn test_symmetric_eigenvalues() {
+        let a = Array2::from_shape_vec((3, 3), vec![
+            2.0, 1.0, 0.0,
+            1.0, 2.0, 0.0,
+            0.0, 0.0, 5.0,
+        ]).unwrap();
+        let ev = symmetric_eigenvalues(a);
+        for (x, y) in ev.iter().zip([5.0, 3.0, 1.0]) {
+            assert!((x - y).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_msm_estimation() {
+        // A two-state chain with p(0->1) = 0.1, p(1->0) = 0.2, and an
+        // unvisited third state.
+        let truth = [[0.9, 0.1, 0.0], [0.2, 0.8, 0.0]];
+        let mut rng = ChaCha8Rng::seed_from_u64(7);
+        let mut data = DiscreteTrajectories::new(3, 0.5);
+        for _ in 0..20 {
+            let mut s = 0;
+            let labels = (0..2000).map(|_| {
+                let current = s;
+                s = if rng.random::<f64>() < truth[s][0] { 0 } else { 1 };
+                current
+            }).collect();
+            data.add(labels);
+        }
+        let msm = MarkovStateModel::estimate(&data.count_transitions(1), 0.5).unwrap();
+        assert_eq!(msm.states(), &[0, 1]);
+        let t = msm.transition_matrix();
+        assert!((t[(0, 1)] - 0.1).abs() < 0.01);
+        assert!((t[(1, 0)] - 0.2).abs() < 0.02);
+        // Detailed balance.
+        let pi = msm.stationary_distribution();
+        assert!((pi[0] * t[(0, 1)] - pi[1] * t[(1, 0)]).abs() < 1e-9);
+        assert!((pi[0] - 2.0 / 3.0).abs() < 0.03);
+
+        // Second eigenvalue 1 - 0.1 - 0.2 = 0.7.
+        let ev = msm.eigenvalues();
+        assert!((ev[0] - 1.0).abs() < 1e-9);
+        assert!((ev[1] - 0.7).abs() < 0.02);
+        let its = msm.implied_timescales();
+        assert!((its[0] - (-0.5 / 0.7f64.ln())).abs() < 0.1);
+
+        let mut out = Vec::new();
+        let names = vec!["U".to_string(), "A".to_string(), "B".to_string()];
+        msm.write_text(&mut out, &names).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].split_whitespace().eq(["#", "from", "macrostate", "stationary", "0", "1"]));
+        let row: Vec<&str> = lines[3].split_whitespace().collect();
+        assert_eq!(&row[..2], &["1", "A"]);
+        assert!((row[4].parse::<f64>().unwrap() - t[(1, 1)]).abs() < 1e-6);
+
+        // A Markov chain passes the Chapman-Kolmogorov test.
+        let ck = msm.chapman_kolmogorov(&data, 1, 4);
+        assert_eq!(ck.predicted.len(), 4);
+        assert!(ck.max_error() < 0.02);
+
+        // Beyond the length of the trajectories, there is nothing to compare.
+        let slow = MarkovStateModel::estimate(&data.count_transitions(800), 400.0).unwrap();
+        let ck = slow.chapman_kolmogorov(&data, 800, 3);
+        assert!(ck.estimated[..2].iter().flatten().all(|e| !e.is_nan()));
+        assert!(ck.estimated[2].iter().all(|e| e.is_nan()));
+        assert!(ck.max_error() < 0.05);
+    }
+
+    #[test]
+    fn test_largest_connected_set() {
+        // 0 <-> 1 -> 2 <-> 3, and 4 -> 0. Weakly, all states are connected,
+        // but only {0, 1} and {2, 3} are strongly connected.
+        let mut counts = Array2::zeros((6, 6));
+        for (a, b, c) in [(0, 0, 20.0), (0, 1, 5.0), (1, 0, 5.0), (1, 2, 1.0),
+                          (2, 3, 4.0), (3, 2, 4.0), (3, 3, 8.0), (4, 0, 50.0)] {
+            counts[(a, b)] = c;
+        }
+        assert_eq!(largest_connected_set(&counts), vec![0, 1]);
+        counts[(2, 2)] = 20.0;
+        assert_eq!(largest_connected_set(&counts), vec![2, 3]);
+        counts[(3, 1)] = 1.0;
+        assert_eq!(largest_connected_set(&counts), vec![0, 1, 2, 3]);
+        assert!(largest_connected_set(&Array2::zeros((3, 3))).is_empty());
+    }
+}
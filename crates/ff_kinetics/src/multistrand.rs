@@ -0,0 +1,679 @@
+//! Multi-stranded kinetics with bimolecular association and dissociation.
+//!
+//! The state of a multi-stranded system is a set of [`Complex`]es. Each
+//! complex stores its strands in a circular order for which its secondary
+//! structure is pseudoknot-free on the concatenated sequence. A loop that
+//! contains a strand break (nick) is evaluated with
+//! [`EnergyModel::energy_of_nicked_loop`], and every additional strand in a
+//! complex costs [`EnergyModel::association_energy`].
+//!
+//! There are three kinds of moves:
+//!  - base-pair formation and breaking within a complex (unimolecular),
+//!  - *join* moves, which form a base-pair between two *exposed* bases of
+//!    different complexes (unpaired bases in the exterior loop or in a loop
+//!    containing a nick),
+//!  - *split* moves, which break the last base-pair that connects two parts
+//!    of a complex.
+//!
+//! Join and split rates are given by a [`BimolecularRateModel`]. Strands
+//! are treated as distinguishable molecules, i.e. there are no symmetry
+//! corrections for complexes of identical strands.
+//!
+//! NOTE: Complexes are re-evaluated from scratch whenever they change, so
+//! this simulator is meant for systems of a few (short) strands, such as
+//! toehold-mediated strand displacement. Single-stranded simulations should
+//! use [`LoopStructureSSA`](crate::LoopStructureSSA).
+
+use std::fmt;
+use rand::Rng;
+use ahash::AHashMap;
+
+use ff_structure::NAIDX;
+use ff_structure::PairTable;
+use ff_structure::DotBracket;
+use ff_structure::DotBracketVec;
+use ff_structure::MultiPairTable;
+use ff_energy::Base;
+use ff_energy::BCOUNT;
+use ff_energy::EnergyModel;
+use ff_energy::NucleotideVec;
+use ff_energy::LoopDecomposition;
+use ff_energy::NearestNeighborLoop;
+
+use crate::BimolecularRateModel;
+
+/// A nucleotide location: (strand index, position on strand).
+pub type StrandLoc = (usize, usize);
+
+/// A base-pair between two nucleotide locations.
+pub type StrandPair = (StrandLoc, StrandLoc);
+
+/// A move within a complex, indices refer to the concatenated sequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComplexMove {
+    Add { i: NAIDX, j: NAIDX },
+    Del { i: NAIDX, j: NAIDX },
+    /// Deleting the base-pair (i, j) disconnects the complex.
+    Split { i: NAIDX, j: NAIDX },
+}
+
+/// A connected complex of one or more strands.
+#[derive(Debug, Clone)]
+pub struct Complex {
+    /// Strand indices in the (circular) order of concatenation.
+    order: Vec<usize>,
+    /// Start of each strand (in `order`) in the concatenated sequence.
+    offsets: Vec<usize>,
+    sequence: NucleotideVec,
+    pairs: PairTable,
+    energy: i32,
+    /// All unimolecular (add, del, split) moves and their energy change.
+    moves: Vec<(ComplexMove, i32)>,
+    /// Unpaired bases accessible to join moves, and the nick of their loop.
+    exposed: Vec<(usize, Option<usize>)>,
+    exposed_counts: [usize; BCOUNT],
+}
+
+impl fmt::Display for Complex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.structure())
+    }
+}
+
+impl Complex {
+    /// Build a complex from its strand order and the base-pairs between strands.
+    fn new<M: EnergyModel>(
+        order: Vec<usize>,
+        pairs: &[StrandPair],
+        strands: &[NucleotideVec],
+        model: &M,
+    ) -> Self {
+        let mut offsets = Vec::with_capacity(order.len());
+        let mut start = vec![None; strands.len()];
+        let mut sequence = Vec::new();
+        for &s in &order {
+            start[s] = Some(sequence.len());
+            offsets.push(sequence.len());
+            sequence.extend_from_slice(&strands[s]);
+        }
+
+        let mut pt = PairTable(vec![None; sequence.len()]);
+        for &((s1, p1), (s2, p2)) in pairs {
+            let i = start[s1].expect("Strand must be part of the complex.") + p1;
+            let j = start[s2].expect("Strand must be part of the complex.") + p2;
+            pt[i] = Some(j as NAIDX);
+            pt[j] = Some(i as NAIDX);
+        }
+
+        let mut complex = Complex {
+            order,
+            offsets,
+            sequence: NucleotideVec(sequence),
+            pairs: pt,
+            energy: 0,
+            moves: Vec::new(),
+            exposed: Vec::new(),
+            exposed_counts: [0; BCOUNT],
+        };
+        complex.update(strands, model);
+        complex
+    }
+
+    pub fn len(&self) -> usize {
+        self.sequence.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sequence.is_empty()
+    }
+
+    /// The strand indices in order of concatenation.
+    pub fn strands(&self) -> &[usize] {
+        &self.order
+    }
+
+    pub fn sequence(&self) -> &NucleotideVec {
+        &self.sequence
+    }
+
+    pub fn pair_table(&self) -> &PairTable {
+        &self.pairs
+    }
+
+    pub fn energy(&self) -> i32 {
+        self.energy
+    }
+
+    pub fn moves(&self) -> &[(ComplexMove, i32)] {
+        &self.moves
+    }
+
+    /// Positions after which a strand ends (excluding the last strand).
+    pub fn nicks(&self) -> impl Iterator<Item = usize> + '_ {
+        self.offsets.iter().skip(1).map(|&o| o - 1)
+    }
+
+    /// The dot-bracket structure with '+' between strands.
+    pub fn structure(&self) -> DotBracketVec {
+        let mut result = Vec::with_capacity(self.len() + self.order.len());
+        let db = DotBracketVec::from(&self.pairs);
+        for (k, &start) in self.offsets.iter().enumerate() {
+            if k > 0 {
+                result.push(DotBracket::Break);
+            }
+            let end = self.offsets.get(k + 1).copied().unwrap_or(self.len());
+            result.extend_from_slice(&db[start..end]);
+        }
+        DotBracketVec(result)
+    }
+
+    /// Translate a position of the concatenated sequence into a strand location.
+    pub fn locate(&self, k: usize) -> StrandLoc {
+        let s = self.offsets.partition_point(|&o| o <= k) - 1;
+        (self.order[s], k - self.offsets[s])
+    }
+
+    /// All base-pairs in strand coordinates.
+    pub fn strand_pairs(&self) -> Vec<StrandPair> {
+        self.pairs.iter().enumerate()
+            .filter_map(|(i, &j)| j.filter(|&j| i < j as usize)
+                .map(|j| (self.locate(i), self.locate(j as usize))))
+            .collect()
+    }
+
+    /// The nick contained in a loop (if any).
+    fn loop_nick(&self, l: &NearestNeighborLoop) -> Option<usize> {
+        let mut ranges = Vec::new();
+        let mut start = l.closing().map_or(0, |(i, _)| i as usize);
+        let branches = l.pairs().into_iter().skip(l.closing().map_or(0, |_| 1));
+        for (k, q) in branches {
+            ranges.push((start, k as usize));
+            start = q as usize;
+        }
+        ranges.push((start, l.closing().map_or(self.len() - 1, |(_, j)| j as usize)));
+        self.nicks().find(|&c| ranges.iter().any(|&(a, b)| a <= c && c < b))
+    }
+
+    fn loop_energy<M: EnergyModel>(&self,
+        l: &NearestNeighborLoop,
+        nick: Option<usize>,
+        model: &M
+    ) -> i32 {
+        match nick {
+            Some(c) => model.energy_of_nicked_loop(&self.sequence, l, c),
+            None => model.energy_of_loop(&self.sequence, l),
+        }
+    }
+
+    /// Split the complex into its connected components after removing (i, j).
+    fn split<M: EnergyModel>(&self,
+        i: NAIDX,
+        j: NAIDX,
+        strands: &[NucleotideVec],
+        model: &M
+    ) -> Vec<Complex> {
+        let (li, lj) = (self.locate(i as usize), self.locate(j as usize));
+        let pairs: Vec<_> = self.strand_pairs().into_iter()
+            .filter(|&p| p != (li, lj))
+            .collect();
+        connected_components(&self.order, &pairs).into_iter()
+            .map(|(order, pairs)| Complex::new(order, &pairs, strands, model))
+            .collect()
+    }
+
+    /// The strand order rotated such that the given nick becomes the end.
+    fn rotated_order(&self, nick: Option<usize>) -> Vec<usize> {
+        match nick {
+            None => self.order.clone(),
+            Some(c) => {
+                let s = self.offsets.partition_point(|&o| o <= c);
+                self.order[s..].iter().chain(&self.order[..s]).copied().collect()
+            }
+        }
+    }
+
+    /// Recompute energy, moves and exposed bases.
+    fn update<M: EnergyModel>(&mut self, strands: &[NucleotideVec], model: &M) {
+        let n = self.len();
+        let seq = self.sequence.clone();
+        let mh = model.min_hairpin_size();
+
+        let loops: Vec<_> = self.pairs.loops().into_iter().map(|l| {
+            let nick = self.loop_nick(&l);
+            let en = self.loop_energy(&l, nick, model);
+            (l, nick, en)
+        }).collect();
+        let by_closing: AHashMap<(NAIDX, NAIDX), usize> = loops.iter().enumerate()
+            .filter_map(|(x, (l, _, _))| l.closing().map(|c| (c, x)))
+            .collect();
+
+        self.energy = loops.iter().map(|(_, _, en)| en).sum::<i32>()
+            + (self.order.len() as i32 - 1) * model.association_energy();
+        self.moves.clear();
+        self.exposed.clear();
+        self.exposed_counts = [0; BCOUNT];
+
+        for (l, nick, en) in &loops {
+            let unpaired = l.unpaired_indices(n);
+            let exterior = l.closing().is_none();
+            if exterior || nick.is_some() {
+                for &k in &unpaired {
+                    self.exposed.push((k, *nick));
+                    self.exposed_counts[seq[k] as usize] += 1;
+                }
+            }
+
+            for (x, &p) in unpaired.iter().enumerate() {
+                for &q in &unpaired[x + 1..] {
+                    if !model.can_pair(seq[p], seq[q]) {
+                        continue;
+                    }
+                    let inner_nick = nick.filter(|&c| p <= c && c < q);
+                    if inner_nick.is_none() && q - p <= mh {
+                        continue;
+                    }
+                    let outer_nick = if inner_nick.is_some() { None } else { *nick };
+                    let (outer, inner) = l.split_loop(p as NAIDX, q as NAIDX);
+                    let delta = self.loop_energy(&outer, outer_nick, model)
+                        + self.loop_energy(&inner, inner_nick, model) - en;
+                    self.moves.push((ComplexMove::Add { i: p as NAIDX, j: q as NAIDX }, delta));
+                }
+            }
+
+            let branches = l.pairs().into_iter().skip(if exterior { 0 } else { 1 });
+            for (i, j) in branches {
+                let (inner, inner_nick, inner_en) = &loops[by_closing[&(i, j)]];
+                let mv = match (nick, inner_nick) {
+                    (Some(_), Some(_)) => None,
+                    (None, Some(_)) if exterior => None,
+                    (a, b) => {
+                        let merged = l.join_loop(inner);
+                        Some(self.loop_energy(&merged, a.or(*b), model) - en - inner_en)
+                    }
+                };
+                match mv {
+                    Some(delta) => self.moves.push((ComplexMove::Del { i, j }, delta)),
+                    None => {
+                        let delta = self.split(i, j, strands, model).iter()
+                            .map(|c| c.energy).sum::<i32>() - self.energy;
+                        self.moves.push((ComplexMove::Split { i, j }, delta));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Format complexes as strand indices and structure, e.g. "0+1:((((+))))  2:......".
+pub fn format_complexes(complexes: &[Complex]) -> String {
+    complexes.iter()
+        .map(|c| format!("{}:{}",
+            c.order.iter().map(|s| s.to_string()).collect::<Vec<_>>().join("+"), c))
+        .collect::<Vec<_>>()
+        .join("  ")
+}
+
+/// Group strands (in the given order) into connected complexes.
+fn connected_components(
+    order: &[usize],
+    pairs: &[StrandPair],
+) -> Vec<(Vec<usize>, Vec<StrandPair>)> {
+    let mut parent: AHashMap<usize, usize> = order.iter().map(|&s| (s, s)).collect();
+    fn find(parent: &mut AHashMap<usize, usize>, s: usize) -> usize {
+        let p = parent[&s];
+        if p == s { return s; }
+        let r = find(parent, p);
+        parent.insert(s, r);
+        r
+    }
+    for &((s1, _), (s2, _)) in pairs {
+        let (r1, r2) = (find(&mut parent, s1), find(&mut parent, s2));
+        if r1 != r2 {
+            parent.insert(r1, r2);
+        }
+    }
+
+    let mut roots: Vec<usize> = Vec::new();
+    let mut result: Vec<(Vec<usize>, Vec<_>)> = Vec::new();
+    for &s in order {
+        let r = find(&mut parent, s);
+        match roots.iter().position(|&x| x == r) {
+            Some(x) => result[x].0.push(s),
+            None => {
+                roots.push(r);
+                result.push((vec![s], Vec::new()));
+            }
+        }
+    }
+    for &p in pairs {
+        let r = find(&mut parent, p.0.0);
+        let x = roots.iter().position(|&x| x == r).unwrap();
+        result[x].1.push(p);
+    }
+    result
+}
+
+/// Stochastic simulation of a multi-stranded system.
+pub struct MultiStrandSSA<'a, M: EnergyModel, K: BimolecularRateModel> {
+    strands: Vec<NucleotideVec>,
+    complexes: Vec<Complex>,
+    model: &'a M,
+    ratemodel: &'a K,
+}
+
+impl<'a, M: EnergyModel, K: BimolecularRateModel> TryFrom<(&[NucleotideVec], &MultiPairTable, &'a M, &'a K)>
+    for MultiStrandSSA<'a, M, K>
+{
+    type Error = String;
+
+    fn try_from((strands, pairings, model, ratemodel): (&[NucleotideVec], &MultiPairTable, &'a M, &'a K)
+    ) -> Result<Self, Self::Error> {
+        if strands.len() != pairings.num_strands() {
+            return Err(format!("Number of strands ({}) and structure strands ({}) do not match.",
+                strands.len(), pairings.num_strands()));
+        }
+        for (s, (seq, pt)) in strands.iter().zip(&pairings.0).enumerate() {
+            if seq.len() != pt.len() {
+                return Err(format!("Strand {} has sequence length {} but structure length {}.",
+                    s, seq.len(), pt.len()));
+            }
+        }
+
+        let mut pairs = Vec::new();
+        for ((s1, p1), partner) in pairings.iter() {
+            if let Some((s2, p2)) = partner {
+                let (s2, p2) = (*s2 as usize, *p2 as usize);
+                if (s1, p1) < (s2, p2) {
+                    if !model.can_pair(strands[s1][p1], strands[s2][p2]) {
+                        return Err(format!("Invalid base-pair {:?}-{:?}.", (s1, p1), (s2, p2)));
+                    }
+                    pairs.push(((s1, p1), (s2, p2)));
+                }
+            }
+        }
+
+        let order: Vec<usize> = (0..strands.len()).collect();
+        let complexes = connected_components(&order, &pairs).into_iter()
+            .map(|(order, pairs)| Complex::new(order, &pairs, strands, model))
+            .collect();
+
+        Ok(MultiStrandSSA {
+            strands: strands.to_vec(),
+            complexes,
+            model,
+            ratemodel,
+        })
+    }
+}
+
+impl<'a, M: EnergyModel, K: BimolecularRateModel> MultiStrandSSA<'a, M, K> {
+
+    pub fn complexes(&self) -> &[Complex] {
+        &self.complexes
+    }
+
+    pub fn strands(&self) -> &[NucleotideVec] {
+        &self.strands
+    }
+
+    /// Total free energy of all complexes.
+    pub fn energy(&self) -> i32 {
+        self.complexes.iter().map(|c| c.energy).sum()
+    }
+
+    /// The current state with strands in input order (may contain pseudoknots).
+    pub fn multi_pair_table(&self) -> MultiPairTable {
+        let mut table: Vec<Vec<Option<(NAIDX, NAIDX)>>> = self.strands.iter()
+            .map(|s| vec![None; s.len()])
+            .collect();
+        for c in &self.complexes {
+            for ((s1, p1), (s2, p2)) in c.strand_pairs() {
+                table[s1][p1] = Some((s2 as NAIDX, p2 as NAIDX));
+                table[s2][p2] = Some((s1 as NAIDX, p1 as NAIDX));
+            }
+        }
+        MultiPairTable(table)
+    }
+
+    /// The current complexes, e.g. "0+1:((((+))))  2:......".
+    pub fn current_structure(&self) -> String {
+        format_complexes(&self.complexes)
+    }
+
+    fn move_rate(&self, mv: &ComplexMove, delta: i32) -> f64 {
+        match mv {
+            ComplexMove::Split { .. } => self.ratemodel.split_rate(delta),
+            _ => self.ratemodel.rate(delta),
+        }
+    }
+
+    /// Number of possible join moves between two complexes.
+    fn num_joins(&self, a: &Complex, b: &Complex) -> usize {
+        let mut count = 0;
+        for (x, &na) in a.exposed_counts.iter().enumerate() {
+            for (y, &nb) in b.exposed_counts.iter().enumerate() {
+                if na > 0 && nb > 0 && self.model.can_pair(BASES[x], BASES[y]) {
+                    count += na * nb;
+                }
+            }
+        }
+        count
+    }
+
+    fn apply_move(&mut self, c: usize, mv: ComplexMove) {
+        match mv {
+            ComplexMove::Add { i, j } => {
+                let complex = &mut self.complexes[c];
+                complex.pairs[i as usize] = Some(j);
+                complex.pairs[j as usize] = Some(i);
+                complex.update(&self.strands, self.model);
+            }
+            ComplexMove::Del { i, j } => {
+                let complex = &mut self.complexes[c];
+                complex.pairs[i as usize] = None;
+                complex.pairs[j as usize] = None;
+                complex.update(&self.strands, self.model);
+            }
+            ComplexMove::Split { i, j } => {
+                let parts = self.complexes[c].split(i, j, &self.strands, self.model);
+                self.complexes.swap_remove(c);
+                self.complexes.extend(parts);
+            }
+        }
+    }
+
+    fn apply_join(&mut self, a: usize, b: usize, x: (usize, Option<usize>), y: (usize, Option<usize>)) {
+        let (ca, cb) = (&self.complexes[a], &self.complexes[b]);
+        let mut order = ca.rotated_order(x.1);
+        order.extend(cb.rotated_order(y.1));
+        let mut pairs = ca.strand_pairs();
+        pairs.extend(cb.strand_pairs());
+        pairs.push((ca.locate(x.0), cb.locate(y.0)));
+        let joined = Complex::new(order, &pairs, &self.strands, self.model);
+
+        let (lo, hi) = if a < b { (a, b) } else { (b, a) };
+        self.complexes.swap_remove(hi);
+        self.complexes.swap_remove(lo);
+        self.complexes.push(joined);
+    }
+
+    /// Simulate until `t_max`. The callback receives the time, the waiting
+    /// time, the total flux and the current complexes. If the callback
+    /// returns false, the simulation is aborted.
+    pub fn simulate<R, F>(
+        &mut self,
+        rng: &mut R,
+        t_max: f64,
+        mut callback: F,
+    )
+    where
+        R: Rng + ?Sized,
+        F: FnMut(f64, f64, f64, &[Complex]) -> bool,
+    {
+        let mut t = 0.;
+        let k_join = self.ratemodel.join_rate();
+
+        while t < t_max {
+            let uni_flux: Vec<f64> = self.complexes.iter()
+                .map(|c| c.moves.iter().map(|(mv, d)| self.move_rate(mv, *d)).sum())
+                .collect();
+            let mut joins = Vec::new();
+            for a in 0..self.complexes.len() {
+                for b in a + 1..self.complexes.len() {
+                    let n = self.num_joins(&self.complexes[a], &self.complexes[b]);
+                    if n > 0 {
+                        joins.push((a, b, n as f64 * k_join));
+                    }
+                }
+            }
+            let flux = uni_flux.iter().sum::<f64>() + joins.iter().map(|j| j.2).sum::<f64>();
+            if flux <= 0. {
+                // No moves possible, the system is frozen.
+                callback(t, f64::INFINITY, flux, &self.complexes);
+                break;
+            }
+
+            let tinc = -rng.random::<f64>().ln() / flux;
+            if !callback(t, tinc, flux, &self.complexes) {
+                break;
+            }
+            t += tinc;
+
+            let mut thresh = rng.random::<f64>() * flux;
+            if let Some((c, mv)) = self.sample_unimolecular(&uni_flux, &mut thresh) {
+                self.apply_move(c, mv);
+                continue;
+            }
+            let &(a, b, jflux) = joins.iter()
+                .find(|j| { thresh -= j.2; thresh < 0. })
+                .unwrap_or_else(|| joins.last().expect("Flux must belong to a move."));
+            // Pick one of the (equally likely) join moves.
+            let pick = ((thresh + jflux) / k_join).max(0.) as usize;
+            let (x, y) = self.join_candidates(a, b)
+                .nth(pick.min(self.num_joins(&self.complexes[a], &self.complexes[b]) - 1))
+                .expect("Join move must exist.");
+            self.apply_join(a, b, x, y);
+        }
+    }
+
+    fn sample_unimolecular(&self, uni_flux: &[f64], thresh: &mut f64) -> Option<(usize, ComplexMove)> {
+        for (c, &cflux) in uni_flux.iter().enumerate() {
+            if *thresh >= cflux {
+                *thresh -= cflux;
+                continue;
+            }
+            let moves = &self.complexes[c].moves;
+            for (mv, d) in moves {
+                *thresh -= self.move_rate(mv, *d);
+                if *thresh < 0. {
+                    return Some((c, *mv));
+                }
+            }
+            // Numerical corner case.
+            return moves.last().map(|(mv, _)| (c, *mv));
+        }
+        None
+    }
+
+    fn join_candidates(&self, a: usize, b: usize
+    ) -> impl Iterator<Item = ((usize, Option<usize>), (usize, Option<usize>))> + '_ {
+        let (ca, cb) = (&self.complexes[a], &self.complexes[b]);
+        ca.exposed.iter().flat_map(move |&x| {
+            cb.exposed.iter()
+                .filter(move |&&y| self.model.can_pair(ca.sequence[x.0], cb.sequence[y.0]))
+                .map(move |&y| (x, y))
+        })
+    }
+}
+
+const BASES: [Base; BCOUNT] = [Base::A, Base::C, Base::G, Base::U, Base::N];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use ff_energy::ViennaRNA;
+    use crate::BimolecularMetropolis;
+
+    fn strands(seqs: &[&str]) -> Vec<NucleotideVec> {
+        seqs.iter().map(|s| NucleotideVec::try_from(*s).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_complex_energy_and_moves() {
+        let emodel = ViennaRNA::default();
+        let rmodel = BimolecularMetropolis::new(emodel.temperature(), 1e6, 1e6, 1e-6);
+        let seqs = strands(&["GGGGG", "CCCCC"]);
+        let pt = MultiPairTable::try_from("(((((+)))))").unwrap();
+        let ssa = MultiStrandSSA::try_from((&seqs[..], &pt, &emodel, &rmodel)).unwrap();
+
+        assert_eq!(ssa.complexes().len(), 1);
+        let complex = &ssa.complexes()[0];
+        assert_eq!(complex.strands(), &[0, 1]);
+        assert_eq!(complex.energy(), -910);
+        assert_eq!(format!("{}", complex), "(((((+)))))");
+        assert_eq!(ssa.multi_pair_table(), pt);
+
+        // Removing a single base-pair does not disconnect the duplex.
+        let dels: Vec<_> = complex.moves().iter()
+            .filter(|(m, _)| !matches!(m, ComplexMove::Add { .. }))
+            .collect();
+        assert_eq!(dels.len(), 5);
+        assert!(dels.iter().all(|(m, _)| matches!(m, ComplexMove::Del { .. })));
+    }
+
+    #[test]
+    fn test_split_and_join_are_reversible() {
+        let emodel = ViennaRNA::default();
+        let rmodel = BimolecularMetropolis::new(emodel.temperature(), 1e6, 1e6, 1e-6);
+        let seqs = strands(&["GGGAAA", "UUUCCC", "AAAA"]);
+        let pt = MultiPairTable::try_from("(.....+.....)+....").unwrap();
+        let mut ssa = MultiStrandSSA::try_from((&seqs[..], &pt, &emodel, &rmodel)).unwrap();
+        assert_eq!(ssa.complexes().len(), 2);
+
+        let dimer = ssa.complexes().iter().position(|c| c.strands().len() == 2).unwrap();
+        let (split, delta) = ssa.complexes()[dimer].moves().iter()
+            .find(|(m, _)| matches!(m, ComplexMove::Split { .. }))
+            .copied()
+            .unwrap();
+        let before = ssa.energy();
+        ssa.apply_move(dimer, split);
+        assert_eq!(ssa.complexes().len(), 3);
+        assert_eq!(ssa.energy() - before, delta);
+
+        // Rejoin the same two bases.
+        let (a, b) = (ssa.complexes().iter().position(|c| c.strands() == [0]).unwrap(),
+                      ssa.complexes().iter().position(|c| c.strands() == [1]).unwrap());
+        let (x, y) = ssa.join_candidates(a, b)
+            .find(|&(x, y)| x.0 == 0 && y.0 == 5)
+            .unwrap();
+        ssa.apply_join(a, b, x, y);
+        assert_eq!(ssa.complexes().len(), 2);
+        assert_eq!(ssa.energy(), before);
+        assert_eq!(ssa.multi_pair_table(), pt);
+    }
+
+    #[test]
+    fn test_multistrand_simulation() {
+        let emodel = ViennaRNA::default();
+        let rmodel = BimolecularMetropolis::new(emodel.temperature(), 1e6, 1e6, 1e-3);
+        let mut rng = StdRng::seed_from_u64(7);
+        let seqs = strands(&["GGGGAGGG", "CCCUCCCC"]);
+        let pt = MultiPairTable::try_from("........+........").unwrap();
+        let mut ssa = MultiStrandSSA::try_from((&seqs[..], &pt, &emodel, &rmodel)).unwrap();
+
+        let mut dimerized = false;
+        ssa.simulate(&mut rng, 1e-2, |_, _, _, complexes| {
+            for c in complexes {
+                // Consistency of the incremental state with a fresh evaluation.
+                let fresh = Complex::new(c.order.clone(), &c.strand_pairs(), &seqs, &emodel);
+                assert_eq!(c.energy(), fresh.energy());
+                assert_eq!(c.moves().len(), fresh.moves().len());
+                dimerized |= c.strands().len() == 2;
+            }
+            true
+        });
+        assert!(dimerized);
+    }
+}
@@ -0,0 +1,359 @@
+//! Observables of secondary structures, averaged over trajectories.
+//!
+//! An [`Observable`] maps the structure (and free energy) at an output time
+//! to one or more values. An [`ObservableTimecourse`] aggregates them over
+//! all trajectories as mean and variance per time point, either alongside
+//! a macrostate [`Timeline`](crate::timeline::Timeline) or on its own.
+
+use std::fmt;
+use std::io;
+use std::io::Write;
+use std::sync::Arc;
+use serde::{Serialize, Deserialize};
+
+use nohash_hasher::IntMap;
+
+use ff_structure::NAIDX;
+use ff_structure::DotBracket;
+use ff_structure::DotBracketVec;
+use ff_structure::PairTable;
+use ff_energy::EnergyModel;
+
+use crate::LoopStructure;
+
+/// A quantity evaluated on the structure of a trajectory at an output time.
+pub trait Observable: Send + Sync {
+    fn name(&self) -> String;
+
+    /// Number of values returned by `evaluate`.
+    fn dimension(&self) -> usize {
+        1
+    }
+
+    /// Evaluate the observable for a structure, given as its base-pairs
+    /// (i, j) indexed by i < j (see [`LoopStructure::pair_list`]), with free
+    /// energy `energy` (dcal/mol).
+    fn evaluate(&self, pairs: &IntMap<NAIDX, NAIDX>, energy: i32) -> Vec<f64>;
+}
+
+/// The base-pairs of a dot-bracket structure, indexed by i < j.
+fn pair_list(structure: &DotBracketVec) -> IntMap<NAIDX, NAIDX> {
+    let mut stack = Vec::new();
+    let mut pairs = IntMap::default();
+    for (k, s) in structure.iter().enumerate() {
+        match s {
+            DotBracket::Open => stack.push(k as NAIDX),
+            DotBracket::Close => if let Some(i) = stack.pop() {
+                pairs.insert(i, k as NAIDX);
+            },
+            _ => (),
+        }
+    }
+    pairs
+}
+
+/// Free energy in kcal/mol.
+#[derive(Debug, Clone, Copy)]
+pub struct Energy;
+
+impl Observable for Energy {
+    fn name(&self) -> String {
+        "energy".to_string()
+    }
+
+    fn evaluate(&self, _: &IntMap<NAIDX, NAIDX>, energy: i32) -> Vec<f64> {
+        vec![energy as f64 / 100.]
+    }
+}
+
+/// Number of base-pairs.
+#[derive(Debug, Clone, Copy)]
+pub struct BasePairCount;
+
+impl Observable for BasePairCount {
+    fn name(&self) -> String {
+        "pairs".to_string()
+    }
+
+    fn evaluate(&self, pairs: &IntMap<NAIDX, NAIDX>, _: i32) -> Vec<f64> {
+        vec![pairs.len() as f64]
+    }
+}
+
+/// Whether each position is paired (averaged: the pairing probability).
+#[derive(Debug, Clone, Copy)]
+pub struct PairingProbability {
+    pub length: usize,
+}
+
+impl Observable for PairingProbability {
+    fn name(&self) -> String {
+        "pairing".to_string()
+    }
+
+    fn dimension(&self) -> usize {
+        self.length
+    }
+
+    fn evaluate(&self, pairs: &IntMap<NAIDX, NAIDX>, _: i32) -> Vec<f64> {
+        let mut paired = vec![0.0; self.length];
+        for (&i, &j) in pairs {
+            paired[i as usize] = 1.0;
+            paired[j as usize] = 1.0;
+        }
+        paired
+    }
+}
+
+/// Base-pair distance to a target structure.
+#[derive(Debug, Clone)]
+pub struct TargetDistance {
+    target: PairTable,
+    num_pairs: usize,
+}
+
+impl TargetDistance {
+    pub fn new(target: PairTable) -> Self {
+        let num_pairs = target.iter().flatten().count() / 2;
+        Self { target, num_pairs }
+    }
+
+    pub fn target(&self) -> &PairTable {
+        &self.target
+    }
+}
+
+impl Observable for TargetDistance {
+    fn name(&self) -> String {
+        format!("distance:{}", DotBracketVec::from(&self.target))
+    }
+
+    fn evaluate(&self, pairs: &IntMap<NAIDX, NAIDX>, _: i32) -> Vec<f64> {
+        let shared = pairs.iter()
+            .filter(|&(&i, &j)| self.target.get(i as usize) == Some(&Some(j)))
+            .count();
+        vec![(pairs.len() + self.num_pairs - 2 * shared) as f64]
+    }
+}
+
+/// Mean and variance of a stream of values (Welford's algorithm).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Moments {
+    pub count: usize,
+    pub mean: f64,
+    m2: f64,
+}
+
+impl Moments {
+    pub fn add(&mut self, x: f64) {
+        self.count += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    /// Combine with the moments of another sample.
+    pub fn merge(&mut self, other: &Moments) {
+        if other.count == 0 {
+            return;
+        }
+        let n = (self.count + other.count) as f64;
+        let delta = other.mean - self.mean;
+        self.m2 += other.m2 + delta * delta * self.count as f64 * other.count as f64 / n;
+        self.mean += delta * other.count as f64 / n;
+        self.count += other.count;
+    }
+
+    /// Sample variance (zero for fewer than two values).
+    pub fn variance(&self) -> f64 {
+        if self.count < 2 { 0.0 } else { self.m2 / (self.count - 1) as f64 }
+    }
+
+    /// Standard error of the mean.
+    pub fn std_error(&self) -> f64 {
+        if self.count == 0 { 0.0 } else { (self.variance() / self.count as f64).sqrt() }
+    }
+}
+
+/// Observables aggregated over trajectories at each output time.
+#[derive(Clone)]
+pub struct ObservableTimecourse {
+    observables: Vec<Arc<dyn Observable>>,
+    times: Vec<f64>,
+    /// Indexed by time point, observable and component.
+    moments: Vec<Vec<Vec<Moments>>>,
+}
+
+impl ObservableTimecourse {
+    pub fn new(times: &[f64], observables: Vec<Arc<dyn Observable>>) -> Self {
+        let empty: Vec<Vec<Moments>> = observables.iter()
+            .map(|o| vec![Moments::default(); o.dimension()])
+            .collect();
+        Self {
+            observables,
+            times: times.to_vec(),
+            moments: vec![empty; times.len()],
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.observables.is_empty()
+    }
+
+    pub fn observables(&self) -> &[Arc<dyn Observable>] {
+        &self.observables
+    }
+
+    pub fn times(&self) -> &[f64] {
+        &self.times
+    }
+
+    /// Evaluate all observables for a structure at time index `t_idx`.
+    pub fn record(&mut self, t_idx: usize, structure: &DotBracketVec, energy: i32) {
+        self.record_pairs(t_idx, &pair_list(structure), energy);
+    }
+
+    /// Evaluate all observables for the current structure of a loop structure.
+    pub fn record_loops<E: EnergyModel>(&mut self, t_idx: usize, ls: &LoopStructure<'_, E>) {
+        if !self.observables.is_empty() {
+            self.record_pairs(t_idx, ls.pair_list(), ls.energy());
+        }
+    }
+
+    fn record_pairs(&mut self, t_idx: usize, pairs: &IntMap<NAIDX, NAIDX>, energy: i32) {
+        for (obs, moments) in self.observables.iter().zip(self.moments[t_idx].iter_mut()) {
+            let values = obs.evaluate(pairs, energy);
+            debug_assert_eq!(values.len(), moments.len());
+            for (m, x) in moments.iter_mut().zip(values) {
+                m.add(x);
+            }
+        }
+    }
+
+    /// The moments of observable `obs` (component `k`) at time index `t_idx`.
+    pub fn moments(&self, t_idx: usize, obs: usize, k: usize) -> &Moments {
+        &self.moments[t_idx][obs][k]
+    }
+
+    pub fn merge(&mut self, other: &ObservableTimecourse) {
+        assert_eq!(self.times.len(), other.times.len(),
+            "Cannot merge observables with different numbers of timepoints");
+        assert_eq!(self.observables.len(), other.observables.len(),
+            "Cannot merge different observables");
+        for (tp, other_tp) in self.moments.iter_mut().zip(&other.moments) {
+            for (obs, other_obs) in tp.iter_mut().zip(other_tp) {
+                for (m, other_m) in obs.iter_mut().zip(other_obs) {
+                    m.merge(other_m);
+                }
+            }
+        }
+    }
+
+    /// One row per time point, observable and component (1-based for
+    /// observables with more than one component).
+    pub fn write_table<W: Write>(&self, writer: &mut W, delimiter: char) -> io::Result<()> {
+        let d = delimiter;
+        writeln!(writer, "time{d}observable{d}index{d}count{d}mean{d}variance")?;
+        for (time, tp) in self.times.iter().zip(&self.moments) {
+            for (obs, moments) in self.observables.iter().zip(tp) {
+                for (k, m) in moments.iter().enumerate() {
+                    writeln!(writer, "{}{d}{}{d}{}{d}{}{d}{}{d}{}",
+                        time, obs.name(), k + 1, m.count, m.mean, m.variance())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ObservableTimecourse {
+    /// Scalar observables only (see `write_table` for all components).
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:>13} {:>14} {:>14} {:>25}", "time", "mean", "std_error", "observable")?;
+        for (time, tp) in self.times.iter().zip(&self.moments) {
+            for (obs, moments) in self.observables.iter().zip(tp) {
+                if let [m] = moments.as_slice() {
+                    writeln!(f, "{:13.9} {:14.6} {:14.6} {:>25}",
+                        time, m.mean, m.std_error(), obs.name())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ff_energy::NucleotideVec;
+    use ff_energy::ViennaRNA;
+
+    #[test]
+    fn test_moments() {
+        let values = [1.0, 2.0, 4.0, 7.0, 11.0];
+        let mut all = Moments::default();
+        values.iter().for_each(|&x| all.add(x));
+        assert_eq!(all.mean, 5.0);
+        assert!((all.variance() - 16.5).abs() < 1e-12);
+
+        let mut a = Moments::default();
+        let mut b = Moments::default();
+        values[..2].iter().for_each(|&x| a.add(x));
+        values[2..].iter().for_each(|&x| b.add(x));
+        a.merge(&b);
+        assert_eq!(a.count, 5);
+        assert!((a.mean - 5.0).abs() < 1e-12);
+        assert!((a.variance() - 16.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_observable_timecourse() {
+        let target = PairTable::try_from("((((....))))").unwrap();
+        let observables: Vec<Arc<dyn Observable>> = vec![
+            Arc::new(Energy),
+            Arc::new(BasePairCount),
+            Arc::new(PairingProbability { length: 12 }),
+            Arc::new(TargetDistance::new(target)),
+        ];
+        let mut oc = ObservableTimecourse::new(&[0.0, 1.0], observables.clone());
+        let open = DotBracketVec::try_from("............").unwrap();
+        let mfe = DotBracketVec::try_from("((((....))))").unwrap();
+        oc.record(0, &open, 0);
+        oc.record(1, &mfe, -540);
+        let mut other = ObservableTimecourse::new(&[0.0, 1.0], observables);
+        other.record(0, &open, 0);
+        other.record(1, &DotBracketVec::try_from(".(((....))).").unwrap(), -300);
+        oc.merge(&other);
+
+        assert_eq!(oc.moments(0, 3, 0).mean, 4.0);
+        assert!((oc.moments(1, 0, 0).mean + 4.2).abs() < 1e-12);
+        assert_eq!(oc.moments(1, 1, 0).mean, 3.5);
+        assert_eq!(oc.moments(1, 2, 0).mean, 0.5);
+        assert_eq!(oc.moments(1, 2, 1).mean, 1.0);
+        assert_eq!(oc.moments(1, 3, 0).mean, 0.5);
+        assert!((oc.moments(1, 3, 0).variance() - 0.5).abs() < 1e-12);
+
+        let mut table = Vec::new();
+        oc.write_table(&mut table, ',').unwrap();
+        let table = String::from_utf8(table).unwrap();
+        assert_eq!(table.lines().count(), 1 + 2 * (3 + 12));
+        assert!(oc.to_string().contains("distance:((((....))))"));
+
+        // Loop structures are evaluated through their pair list.
+        let model = ViennaRNA::default();
+        let sequence = NucleotideVec::try_from("GGGGAAAACCCC").unwrap();
+        let pairings = PairTable::try_from(".(((....))).").unwrap();
+        let ls = LoopStructure::try_from((&sequence[..], &pairings, &model)).unwrap();
+        let observables = oc.observables().to_vec();
+        let mut a = ObservableTimecourse::new(&[0.0], observables.clone());
+        let mut b = ObservableTimecourse::new(&[0.0], observables);
+        a.record_loops(0, &ls);
+        b.record(0, &DotBracketVec::from(&ls), ls.energy());
+        for (obs, dim) in [(0, 1), (1, 1), (2, 12), (3, 1)] {
+            for k in 0..dim {
+                assert_eq!(a.moments(0, obs, k), b.moments(0, obs, k));
+            }
+        }
+        assert_eq!(a.moments(0, 3, 0).mean, 1.0);
+    }
+}
@@ -0,0 +1,252 @@
+//! Time-resolved base-pair probabilities.
+//!
+//! At each output time, the fraction of trajectories that contain a
+//! base-pair (i, j), and the fraction in which position i is unpaired.
+//! Only observed pairs are stored, so the matrices stay sparse.
+
+use std::io;
+use std::io::Write;
+use ahash::AHashMap;
+use plotters::prelude::*;
+
+use ff_structure::NAIDX;
+use ff_energy::EnergyModel;
+
+use crate::LoopStructure;
+
+/// Base-pair and unpaired counts at each output time.
+#[derive(Debug, Clone)]
+pub struct PairProbabilities {
+    length: usize,
+    times: Vec<f64>,
+    /// Number of structures recorded per time point.
+    counts: Vec<usize>,
+    /// Number of structures containing (i, j), per time point.
+    pairs: Vec<AHashMap<(NAIDX, NAIDX), usize>>,
+    /// Number of structures where i is unpaired, per time point.
+    unpaired: Vec<Vec<usize>>,
+}
+
+impl PairProbabilities {
+    pub fn new(length: usize, times: &[f64]) -> Self {
+        Self {
+            length,
+            times: times.to_vec(),
+            counts: vec![0; times.len()],
+            pairs: vec![AHashMap::new(); times.len()],
+            unpaired: vec![vec![0; length]; times.len()],
+        }
+    }
+
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    pub fn times(&self) -> &[f64] {
+        &self.times
+    }
+
+    pub fn count(&self, t_idx: usize) -> usize {
+        self.counts[t_idx]
+    }
+
+    /// Record a structure given as a list of base-pairs (i < j).
+    pub fn record<I>(&mut self, t_idx: usize, pairs: I)
+    where
+        I: IntoIterator<Item = (NAIDX, NAIDX)>,
+    {
+        let unpaired = &mut self.unpaired[t_idx];
+        unpaired.iter_mut().for_each(|u| *u += 1);
+        for (i, j) in pairs {
+            *self.pairs[t_idx].entry((i, j)).or_insert(0) += 1;
+            unpaired[i as usize] -= 1;
+            unpaired[j as usize] -= 1;
+        }
+        self.counts[t_idx] += 1;
+    }
+
+    /// Record the current structure of a loop structure.
+    pub fn record_loops<E: EnergyModel>(&mut self, t_idx: usize, ls: &LoopStructure<'_, E>) {
+        self.record(t_idx, ls.pair_list().iter().map(|(&i, &j)| (i, j)));
+    }
+
+    pub fn merge(&mut self, other: &PairProbabilities) {
+        assert_eq!(self.length, other.length,
+            "Cannot merge pair probabilities of different lengths");
+        assert_eq!(self.times.len(), other.times.len(),
+            "Cannot merge pair probabilities with different numbers of timepoints");
+        for t_idx in 0..self.times.len() {
+            self.counts[t_idx] += other.counts[t_idx];
+            for (&ij, &c) in &other.pairs[t_idx] {
+                *self.pairs[t_idx].entry(ij).or_insert(0) += c;
+            }
+            for (u, &c) in self.unpaired[t_idx].iter_mut().zip(&other.unpaired[t_idx]) {
+                *u += c;
+            }
+        }
+    }
+
+    /// The probability of every observed base-pair at time index `t_idx`,
+    /// sorted by (i, j).
+    pub fn pair_probabilities(&self, t_idx: usize) -> Vec<((NAIDX, NAIDX), f64)> {
+        let n = self.counts[t_idx];
+        if n == 0 {
+            return Vec::new();
+        }
+        let mut probs: Vec<_> = self.pairs[t_idx].iter()
+            .map(|(&ij, &c)| (ij, c as f64 / n as f64))
+            .collect();
+        probs.sort_by_key(|&(ij, _)| ij);
+        probs
+    }
+
+    /// The probability of each position to be unpaired at time index `t_idx`.
+    pub fn unpaired_probabilities(&self, t_idx: usize) -> Vec<f64> {
+        let n = self.counts[t_idx];
+        self.unpaired[t_idx].iter()
+            .map(|&c| if n == 0 { 0.0 } else { c as f64 / n as f64 })
+            .collect()
+    }
+
+    /// Sparse matrices in coordinate format, one block of rows per time
+    /// point. Indices are 1-based, the unpaired probability of position i
+    /// is stored on the diagonal (i, i). Entries below `cutoff` are skipped.
+    pub fn write_sparse<W: Write>(&self, writer: &mut W, delimiter: char, cutoff: f64) -> io::Result<()> {
+        let d = delimiter;
+        writeln!(writer, "time{d}i{d}j{d}probability")?;
+        for (t_idx, time) in self.times.iter().enumerate() {
+            let mut entries: Vec<((usize, usize), f64)> = self.pair_probabilities(t_idx)
+                .into_iter()
+                .map(|((i, j), p)| ((i as usize, j as usize), p))
+                .chain(self.unpaired_probabilities(t_idx).into_iter()
+                    .enumerate()
+                    .map(|(i, p)| ((i, i), p)))
+                .filter(|&(_, p)| p >= cutoff && p > 0.0)
+                .collect();
+            entries.sort_by_key(|&(ij, _)| ij);
+            for ((i, j), p) in entries {
+                writeln!(writer, "{}{d}{}{d}{}{d}{}", time, i + 1, j + 1, p)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The time index closest to `time`.
+    pub fn closest_time_index(&self, time: f64) -> Option<usize> {
+        self.times.iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| (*a - time).abs().total_cmp(&(*b - time).abs()))
+            .map(|(t_idx, _)| t_idx)
+    }
+
+    /// Draw a dot plot of time index `t_idx`: base-pair probabilities in the
+    /// upper triangle (square area proportional to the probability) and
+    /// unpaired probabilities on the diagonal.
+    pub fn plot_dot_plot(&self, t_idx: usize, sequence: &str, filename: &str) {
+        let n = self.length as f64;
+        // Row i is drawn at y = n + 1 - i, so that (1, 1) is in the upper left corner.
+        let row = |i: usize| n - i as f64;
+        let col = |j: usize| j as f64 + 1.0;
+
+        let root = SVGBackend::new(filename, (720, 720)).into_drawing_area();
+        root.fill(&WHITE).unwrap();
+        let title = format!("t = {:.3e} ({} simulations)", self.times[t_idx], self.counts[t_idx]);
+        let mut chart = ChartBuilder::on(&root)
+            .caption(title, ("sans-serif", 24))
+            .margin(20)
+            .x_label_area_size(40)
+            .y_label_area_size(40)
+            .build_cartesian_2d(0.5..n + 0.5, 0.5..n + 0.5).unwrap();
+
+        let bases: Vec<char> = sequence.chars().collect();
+        let label = move |x: &f64, flip: bool| {
+            let pos = if flip { n + 1.0 - x } else { *x };
+            let k = pos.round() as usize;
+            if (pos - k as f64).abs() > 1e-9 || k == 0 {
+                return String::new();
+            }
+            bases.get(k - 1).map(|b| b.to_string()).unwrap_or_default()
+        };
+        let x_label = label.clone();
+        chart.configure_mesh()
+            .disable_mesh()
+            .x_labels(self.length.min(60))
+            .y_labels(self.length.min(60))
+            .x_label_formatter(&|x| x_label(x, false))
+            .y_label_formatter(&|y| label(y, true))
+            .label_style(("sans-serif", 12))
+            .draw().unwrap();
+
+        chart.draw_series(std::iter::once(Rectangle::new(
+            [(0.5, 0.5), (n + 0.5, n + 0.5)],
+            BLACK.stroke_width(1),
+        ))).unwrap();
+
+        let square = |x: f64, y: f64, p: f64| {
+            let h = p.sqrt() / 2.0;
+            [(x - h, y - h), (x + h, y + h)]
+        };
+        chart.draw_series(self.pair_probabilities(t_idx).into_iter().map(|((i, j), p)| {
+            Rectangle::new(square(col(j as usize), row(i as usize), p), BLACK.filled())
+        })).unwrap();
+        chart.draw_series(self.unpaired_probabilities(t_idx).into_iter()
+            .enumerate()
+            .filter(|&(_, p)| p > 0.0)
+            .map(|(i, p)| Rectangle::new(square(col(i), row(i), p), RGBColor(120, 120, 120).filled()))
+        ).unwrap();
+
+        root.present().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ff_structure::PairTable;
+    use ff_energy::NucleotideVec;
+    use ff_energy::ViennaRNA;
+
+    #[test]
+    fn test_pair_probabilities() {
+        let mut pp = PairProbabilities::new(8, &[0.0, 1.0]);
+        pp.record(0, []);
+        pp.record(0, [(0, 7), (1, 6)]);
+        let mut other = PairProbabilities::new(8, &[0.0, 1.0]);
+        other.record(0, [(0, 7)]);
+        other.record(0, [(1, 7)]);
+        pp.merge(&other);
+
+        assert_eq!(pp.count(0), 4);
+        assert_eq!(pp.count(1), 0);
+        assert_eq!(pp.pair_probabilities(0),
+            vec![((0, 7), 0.5), ((1, 6), 0.25), ((1, 7), 0.25)]);
+        let unpaired = pp.unpaired_probabilities(0);
+        assert_eq!(unpaired[0], 0.5);
+        assert_eq!(unpaired[7], 0.25);
+        assert_eq!(unpaired[3], 1.0);
+        assert!(pp.pair_probabilities(1).is_empty());
+
+        let mut table = Vec::new();
+        pp.write_sparse(&mut table, '\t', 0.3).unwrap();
+        let table = String::from_utf8(table).unwrap();
+        assert!(table.contains("0\t1\t8\t0.5"));
+        assert!(!table.contains("0\t2\t7\t"));
+        // Header, (1, 8) and all unpaired positions but 8.
+        assert_eq!(table.lines().count(), 1 + 1 + 7);
+        assert_eq!(pp.closest_time_index(0.8), Some(1));
+    }
+
+    #[test]
+    fn test_record_loop_structure() {
+        let model = ViennaRNA::default();
+        let sequence = NucleotideVec::try_from("GGGGAAAACCCC").unwrap();
+        let pairs = PairTable::try_from("((((....))))").unwrap();
+        let ls = LoopStructure::try_from((&sequence[..], &pairs, &model)).unwrap();
+        let mut pp = PairProbabilities::new(12, &[0.0]);
+        pp.record_loops(0, &ls);
+        assert_eq!(pp.pair_probabilities(0).len(), 4);
+        assert_eq!(pp.pair_probabilities(0)[0], ((0, 11), 1.0));
+        assert_eq!(pp.unpaired_probabilities(0)[5], 1.0);
+        assert_eq!(pp.unpaired_probabilities(0)[0], 0.0);
+    }
+}
@@ -0,0 +1,236 @@
+//! Simulations in a piecewise-constant environment.
+//!
+//! Temperature schedules and force protocols change the energy model over
+//! time. They are simulated as a sequence of [`ModelSegment`]s, in which the
+//! energy model and the rate model are constant. At every segment boundary,
+//! the loop structure is rebuilt with the models of the next segment, so
+//! all loop energies and reaction rates are recomputed. The waiting time
+//! that crosses a boundary is discarded, which is exact as waiting times
+//! are memoryless.
+
+use rand::Rng;
+
+use ff_structure::PairTable;
+use ff_energy::Base;
+use ff_energy::EnergyModel;
+
+use crate::RateModel;
+use crate::LoopStructure;
+use crate::LoopStructureSSA;
+use crate::reaction::MoveSet;
+
+/// Split [0, t_end) at the `breakpoints` into pieces, and every piece into
+/// equally long steps in which `value` changes by at most `resolution`.
+/// Each step takes the value at its midpoint, and consecutive steps with
+/// equal values are joined. Returns the (start, end, value) of all steps.
+pub fn piecewise_constant<F>(breakpoints: &[f64], t_end: f64, resolution: f64, value: F
+) -> Vec<(f64, f64, f64)>
+where
+    F: Fn(f64) -> f64,
+{
+    assert!(resolution > 0.0, "Resolution must be positive");
+    let mut bounds: Vec<f64> = vec![0.0];
+    bounds.extend(breakpoints.iter().copied().filter(|&t| t > 0.0 && t < t_end));
+    bounds.push(t_end);
+
+    let mut steps: Vec<(f64, f64, f64)> = Vec::new();
+    for w in bounds.windows(2) {
+        let (start, end) = (w[0], w[1]);
+        let change = (value(end) - value(start)).abs();
+        let n = ((change / resolution).ceil() as usize).max(1);
+        let dt = (end - start) / n as f64;
+        for k in 0..n {
+            let s_start = start + k as f64 * dt;
+            let s_end = if k + 1 == n { end } else { start + (k + 1) as f64 * dt };
+            let v = value((s_start + s_end) / 2.0);
+            match steps.last_mut() {
+                Some(last) if last.2 == v => last.1 = s_end,
+                _ => steps.push((s_start, s_end, v)),
+            }
+        }
+    }
+    steps
+}
+
+/// The energy and rate model of the time interval [start, end).
+#[derive(Debug)]
+pub struct ModelSegment<'a, M, K> {
+    pub start: f64,
+    pub end: f64,
+    pub energy_model: &'a M,
+    pub rate_model: &'a K,
+}
+
+/// The SSA through a sequence of segments with constant models.
+#[derive(Debug)]
+pub struct SegmentedSSA<'a, M, K> {
+    sequence: &'a [Base],
+    segments: Vec<ModelSegment<'a, M, K>>,
+    move_set: MoveSet,
+}
+
+impl<'a, M: EnergyModel, K: RateModel> SegmentedSSA<'a, M, K> {
+    /// The segments must be sorted and contiguous.
+    pub fn new(sequence: &'a [Base], segments: Vec<ModelSegment<'a, M, K>>) -> Self {
+        assert!(!segments.is_empty(), "At least one segment is required");
+        assert!(segments.windows(2).all(|w| w[0].end == w[1].start),
+            "Segments must be contiguous");
+        Self { sequence, segments, move_set: MoveSet::default() }
+    }
+
+    /// Use a different move set (see [`LoopStructure::with_move_set`]).
+    pub fn with_move_set(mut self, move_set: MoveSet) -> Self {
+        self.move_set = move_set;
+        self
+    }
+
+    pub fn segments(&self) -> &[ModelSegment<'a, M, K>] {
+        &self.segments
+    }
+
+    /// The index of the segment that contains time `t`.
+    pub fn segment_index(&self, t: f64) -> usize {
+        self.segments.partition_point(|s| s.end <= t).min(self.segments.len() - 1)
+    }
+
+    fn simulator(&self, seg: usize, pairings: &PairTable) -> Result<LoopStructureSSA<'a, M, K>, String> {
+        let segment = &self.segments[seg];
+        let loops = LoopStructure::try_from((self.sequence, pairings, segment.energy_model))?
+            .with_move_set(self.move_set);
+        Ok(LoopStructureSSA::from((loops, segment.rate_model)))
+    }
+
+    /// Simulate from `pairings` at time `t_start` until `t_max`, or until
+    /// the callback returns false. The callback receives the index of the
+    /// current segment, the time, the waiting time and the loop structure.
+    /// A waiting time that crosses a segment boundary is cut at the
+    /// boundary, so the intervals [t, t + tinc) of all calls are contiguous.
+    /// Returns the structure of the last call if the callback aborted the
+    /// simulation, and the structure after the last reaction otherwise.
+    pub fn simulate_from<R, F>(
+        &self,
+        rng: &mut R,
+        pairings: &PairTable,
+        t_start: f64,
+        t_max: f64,
+        mut callback: F,
+    ) -> Result<PairTable, String>
+    where
+        R: Rng + ?Sized,
+        F: FnMut(usize, f64, f64, &LoopStructure<'a, M>) -> bool,
+    {
+        let mut seg = self.segment_index(t_start);
+        let mut simulator = self.simulator(seg, pairings)?;
+        let mut t_now = t_start;
+        loop {
+            let seg_end = self.segments[seg].end;
+            let last_segment = seg + 1 == self.segments.len();
+            let mut crossing = false;
+            let mut aborted = false;
+            simulator.simulate_from(rng, t_now, t_max, |t, tinc, _, ls| {
+                crossing = !last_segment && t + tinc >= seg_end;
+                let tinc = if crossing { seg_end - t } else { tinc };
+                if !callback(seg, t, tinc, ls) {
+                    aborted = true;
+                    return false;
+                }
+                !crossing
+            });
+            let pairings = PairTable::from(simulator.loop_structure());
+            if aborted || !crossing {
+                return Ok(pairings);
+            }
+            seg += 1;
+            t_now = self.segments[seg].start;
+            simulator = self.simulator(seg, &pairings)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+    use ff_structure::DotBracketVec;
+    use ff_energy::NucleotideVec;
+    use ff_energy::ViennaRNA;
+    use crate::Metropolis;
+
+    #[test]
+    fn test_piecewise_constant() {
+        let ramp = piecewise_constant(&[1.0, 5.0], 2.0, 1.5, |t| 3.0 * t.min(1.0));
+        assert_eq!(ramp, vec![(0.0, 0.5, 0.75), (0.5, 1.0, 2.25), (1.0, 2.0, 3.0)]);
+        let fine = piecewise_constant(&[], 1e-3, 0.1, |t| 2e4 * t);
+        assert_eq!(fine.len(), 200);
+        assert!(fine.windows(2).all(|w| w[0].1 == w[1].0));
+        let steps = piecewise_constant(&[0.0, 1.0], 2.0, f64::INFINITY,
+            |t| if t < 1.0 { 37.0 } else { 60.0 });
+        assert_eq!(steps, vec![(0.0, 1.0, 37.0), (1.0, 2.0, 60.0)]);
+    }
+
+    #[test]
+    fn test_segmented_ssa() {
+        let sequence = NucleotideVec::try_from("GGGGAAAACCCCAGGGAAACCC").unwrap();
+        let pairings = PairTable::try_from("......................").unwrap();
+        let temperatures = [37.0, 95.0, 37.0];
+        let emodels: Vec<ViennaRNA> = temperatures.iter().map(|&t| {
+            let mut m = ViennaRNA::default();
+            m.set_temperature(t);
+            m
+        }).collect();
+        let rmodels: Vec<Metropolis> = temperatures.iter()
+            .map(|&t| Metropolis::new(t, 1e6))
+            .collect();
+        let segments = (0..3).map(|k| ModelSegment {
+            start: k as f64 * 1e-3,
+            end: (k + 1) as f64 * 1e-3,
+            energy_model: &emodels[k],
+            rate_model: &rmodels[k],
+        }).collect();
+        let ssa = SegmentedSSA::new(&sequence, segments);
+        assert_eq!(ssa.segment_index(0.0), 0);
+        assert_eq!(ssa.segment_index(1e-3), 1);
+        assert_eq!(ssa.segment_index(1.0), 2);
+
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut calls: Vec<(usize, f64, f64)> = Vec::new();
+        ssa.simulate_from(&mut rng, &pairings, 0.0, 3e-3, |seg, t, tinc, ls| {
+            calls.push((seg, t, tinc));
+            // Energies are evaluated with the model of the current segment.
+            assert_eq!(ls.energy(), emodels[seg].energy_of_structure(&sequence[..],
+                &PairTable::from(ls)));
+            true
+        }).unwrap();
+
+        // Every segment is visited and the simulated intervals are contiguous.
+        assert_eq!(calls.first().unwrap().0, 0);
+        assert_eq!(calls.last().unwrap().0, 2);
+        assert!(calls.windows(2).all(|w| w[0].0 <= w[1].0 && (w[0].1 + w[0].2 - w[1].1).abs() < 1e-15));
+        for &(seg, t, tinc) in &calls[..calls.len() - 1] {
+            let segment = &ssa.segments()[seg];
+            assert!(t >= segment.start && t + tinc <= segment.end + 1e-15);
+        }
+
+        // Stop in the second segment (which returns the current structure)
+        // and continue from there.
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut stop = None;
+        let result = ssa.simulate_from(&mut rng, &pairings, 0.0, 3e-3, |seg, t, _, ls| {
+            if seg == 1 {
+                stop = Some((t, ls.to_string()));
+                return false;
+            }
+            true
+        }).unwrap();
+        let (t, structure) = stop.unwrap();
+        assert_eq!(DotBracketVec::from(&result).to_string(), structure);
+        let mut segments_seen = Vec::new();
+        ssa.simulate_from(&mut rng, &result, t, 3e-3, |seg, _, _, _| {
+            segments_seen.push(seg);
+            true
+        }).unwrap();
+        assert_eq!(segments_seen.first(), Some(&1));
+        assert_eq!(segments_seen.last(), Some(&2));
+    }
+}
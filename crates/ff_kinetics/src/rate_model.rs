@@ -49,3 +49,69 @@ impl RateModel for Metropolis {
 }
 
 
+
+/// A rate model for multi-stranded systems, where complexes can associate
+/// (join) and dissociate (split).
+pub trait BimolecularRateModel: RateModel {
+    /// Rate constant of a join move, i.e. forming a base-pair between two
+    /// different complexes.
+    fn join_rate(&self) -> f64;
+
+    /// Given ΔE of a split move (E(A) + E(B) - E(AB)), return the rate constant.
+    fn split_rate(&self, delta_e: i32) -> f64;
+}
+
+/// Metropolis rates for unimolecular moves and concentration-dependent
+/// bimolecular rates:
+///
+///  - join:  k_bi * c
+///  - split: k_bi * exp(-ΔE / kT)
+///
+/// This satisfies detailed balance with a standard concentration of 1 M.
+#[derive(Debug, Clone, Copy)]
+pub struct BimolecularMetropolis {
+    metropolis: Metropolis,
+    kt: f64, // k_B * T in kcal/mol
+    k_bi: f64,
+    concentration: f64,
+}
+
+impl BimolecularMetropolis {
+    /// The bimolecular rate constant `k_bi` is in /M/s and the 
+    /// strand `concentration` in M.
+    pub fn new(celsius: f64, k0: f64, k_bi: f64, concentration: f64) -> Self {
+        if k_bi <= 0. {
+            panic!("k_bi must be positive!");
+        }
+        if concentration <= 0. {
+            panic!("concentration must be positive!");
+        }
+        let t_kelvin = celsius + K0;
+        Self { 
+            metropolis: Metropolis::new(celsius, k0),
+            kt: KB * t_kelvin,
+            k_bi,
+            concentration,
+        }
+    }
+}
+
+impl RateModel for BimolecularMetropolis {
+    fn rate(&self, delta_e: i32) -> f64 {
+        self.metropolis.rate(delta_e)
+    }
+
+    fn log_rate(&self, delta_e: i32) -> f64 {
+        self.metropolis.log_rate(delta_e)
+    }
+}
+
+impl BimolecularRateModel for BimolecularMetropolis {
+    fn join_rate(&self) -> f64 {
+        self.k_bi * self.concentration
+    }
+
+    fn split_rate(&self, delta_e: i32) -> f64 {
+        self.k_bi * ((-delta_e as f64 / 100.) / self.kt).exp()
+    }
+}
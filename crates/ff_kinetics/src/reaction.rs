@@ -1,3 +1,4 @@
+use serde::{Serialize, Deserialize};
 use ff_structure::NAIDX;
 use ff_structure::DotBracket;
 use ff_structure::DotBracketVec;
@@ -15,14 +16,17 @@ pub trait ApplyMove {
 
 impl ApplyMove for DotBracketVec {
     fn apply_move(&mut self, mv: Move) {
-        match mv {
-            Move::Add { i, j } => {
-                self[i as usize] = DotBracket::Open;
-                self[j as usize] = DotBracket::Close;
-            }
-            Move::Del { i, j } => {
-                self[i as usize] = DotBracket::Unpaired;
-                self[j as usize] = DotBracket::Unpaired;
+        for step in mv.steps() {
+            match step {
+                Move::Add { i, j } => {
+                    self[i as usize] = DotBracket::Open;
+                    self[j as usize] = DotBracket::Close;
+                }
+                Move::Del { i, j } => {
+                    self[i as usize] = DotBracket::Unpaired;
+                    self[j as usize] = DotBracket::Unpaired;
+                }
+                _ => unreachable!("steps() returns only Add and Del moves"),
             }
         }
     }
@@ -30,18 +34,43 @@ impl ApplyMove for DotBracketVec {
 
 impl<'a, M: EnergyModel> ApplyMove for LoopStructure<'a, M> { 
     fn apply_move(&mut self, mv: Move) {
-        match mv {
-            Move::Add { i, j } => {
-                self.apply_add_move(i, j);
-            }
-            Move::Del { i, j } => {
-                self.apply_del_move(i, j);
+        for step in mv.steps() {
+            match step {
+                Move::Add { i, j } => {
+                    self.apply_add_move(i, j);
+                }
+                Move::Del { i, j } => {
+                    self.apply_del_move(i, j);
+                }
+                _ => unreachable!("steps() returns only Add and Del moves"),
             }
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+/// The elementary moves that are part of the move set, in addition to 
+/// the (always present) addition and deletion of single base-pairs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct MoveSet {
+    /// A base-pair (i, j) slides one of its partners to a new position.
+    pub shift: bool,
+    /// Zipping/unzipping of this many stacked base-pairs in a single
+    /// move. Helix moves are disabled for values < 2.
+    pub helix_length: usize,
+}
+
+impl MoveSet {
+    pub fn has_helix_moves(&self) -> bool {
+        self.helix_length >= 2
+    }
+
+    /// True if any moves beyond single base-pair addition/deletion are enabled.
+    pub fn is_extended(&self) -> bool {
+        self.shift || self.has_helix_moves()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum Move {
     Add {
         i: NAIDX,
@@ -51,6 +80,25 @@ pub enum Move {
         i: NAIDX,
         j: NAIDX,
     },
+    /// Replace (i, j) by (k, l), where both pairs share one partner.
+    Shift {
+        i: NAIDX,
+        j: NAIDX,
+        k: NAIDX,
+        l: NAIDX,
+    },
+    /// Add the helix (i, j), (i+1, j-1), ..., (i+len-1, j-len+1).
+    Zip {
+        i: NAIDX,
+        j: NAIDX,
+        len: NAIDX,
+    },
+    /// Remove the helix (i, j), (i+1, j-1), ..., (i+len-1, j-len+1).
+    Unzip {
+        i: NAIDX,
+        j: NAIDX,
+        len: NAIDX,
+    },
 }
 
 impl Move {
@@ -58,6 +106,27 @@ impl Move {
         match self {
             Move::Add { i, j } => Move::Del { i, j },
             Move::Del { i, j } => Move::Add { i, j },
+            Move::Shift { i, j, k, l } => Move::Shift { i: k, j: l, k: i, l: j },
+            Move::Zip { i, j, len } => Move::Unzip { i, j, len },
+            Move::Unzip { i, j, len } => Move::Zip { i, j, len },
+        }
+    }
+
+    /// Decompose a move into a valid sequence of single base-pair 
+    /// additions and deletions.
+    pub fn steps(self) -> Vec<Move> {
+        match self {
+            Move::Add { .. } | Move::Del { .. } => vec![self],
+            Move::Shift { i, j, k, l } => vec![
+                Move::Del { i, j }, 
+                Move::Add { i: k, j: l }
+            ],
+            Move::Zip { i, j, len } => (0..len)
+                .map(|x| Move::Add { i: i + x, j: j - x })
+                .collect(),
+            Move::Unzip { i, j, len } => (0..len).rev()
+                .map(|x| Move::Del { i: i + x, j: j - x })
+                .collect(),
         }
     }
 }
@@ -77,6 +146,28 @@ pub enum Reaction {
         delta_e: i32,
         log_rate: f64,
     },
+    Shift {
+        i: NAIDX,
+        j: NAIDX,
+        k: NAIDX,
+        l: NAIDX,
+        delta_e: i32,
+        log_rate: f64,
+    },
+    Zip {
+        i: NAIDX,
+        j: NAIDX,
+        len: NAIDX,
+        delta_e: i32,
+        log_rate: f64,
+    },
+    Unzip {
+        i: NAIDX,
+        j: NAIDX,
+        len: NAIDX,
+        delta_e: i32,
+        log_rate: f64,
+    },
 }
 
 impl Reaction {
@@ -93,24 +184,56 @@ impl Reaction {
         Reaction::Del { i, j, delta_e, log_rate: rate }
     }
 
+    /// A reaction for any kind of move (see also new_add and new_del).
+    pub fn new<K: RateModel>(model: &K, mv: Move, delta_e: i32) -> Self {
+        let log_rate = model.log_rate(delta_e);
+        match mv {
+            Move::Add { i, j } => Reaction::Add { i, j, delta_e, log_rate },
+            Move::Del { i, j } => Reaction::Del { i, j, delta_e, log_rate },
+            Move::Shift { i, j, k, l } => Reaction::Shift { i, j, k, l, delta_e, log_rate },
+            Move::Zip { i, j, len } => Reaction::Zip { i, j, len, delta_e, log_rate },
+            Move::Unzip { i, j, len } => Reaction::Unzip { i, j, len, delta_e, log_rate },
+        }
+    }
+
+    /// The (first) base-pair that is affected by the reaction.
     pub fn ij(&self) -> (NAIDX, NAIDX) {
         match self {
-            Reaction::Add { i, j, .. } => (*i, *j),
-            Reaction::Del { i, j, .. } => (*i, *j),
+            Reaction::Add { i, j, .. } 
+            | Reaction::Del { i, j, .. } 
+            | Reaction::Shift { i, j, .. } 
+            | Reaction::Zip { i, j, .. } 
+            | Reaction::Unzip { i, j, .. } => (*i, *j),
         }
     }
 
     pub fn log_rate(&self) -> f64 {
         match self {
-            Reaction::Add { log_rate, .. } => *log_rate,
-            Reaction::Del { log_rate, .. } => *log_rate,
+            Reaction::Add { log_rate, .. } 
+            | Reaction::Del { log_rate, .. } 
+            | Reaction::Shift { log_rate, .. } 
+            | Reaction::Zip { log_rate, .. } 
+            | Reaction::Unzip { log_rate, .. } => *log_rate,
         }
     }
 
     pub fn delta_e(&self) -> i32 {
         match self {
-            Reaction::Add { delta_e, .. } => *delta_e,
-            Reaction::Del { delta_e, .. } => *delta_e,
+            Reaction::Add { delta_e, .. } 
+            | Reaction::Del { delta_e, .. } 
+            | Reaction::Shift { delta_e, .. } 
+            | Reaction::Zip { delta_e, .. } 
+            | Reaction::Unzip { delta_e, .. } => *delta_e,
+        }
+    }
+
+    pub fn to_move(&self) -> Move {
+        match *self {
+            Reaction::Add { i, j, .. } => Move::Add { i, j },
+            Reaction::Del { i, j, .. } => Move::Del { i, j },
+            Reaction::Shift { i, j, k, l, .. } => Move::Shift { i, j, k, l },
+            Reaction::Zip { i, j, len, .. } => Move::Zip { i, j, len },
+            Reaction::Unzip { i, j, len, .. } => Move::Unzip { i, j, len },
         }
     }
 
@@ -3,6 +3,7 @@ use rand::Rng;
 use nohash_hasher::IntMap;
 use ff_energy::EnergyModel;
 
+use crate::reaction::Move;
 use crate::reaction::Reaction;
 use crate::LoopStructure;
 use crate::RateModel;
@@ -35,6 +36,15 @@ fn log_sum_exp(xs: &[f64]) -> f64 {
     m + (xs.iter().map(|&x| (x - m).exp()).sum::<f64>()).ln()
 }
 
+/// The total flux of pair, loop and extended reactions.
+fn total_log_flux(pair_flux: Option<f64>, loop_flux: Option<f64>, ext_flux: Option<f64>) -> f64 {
+    let fluxes: Vec<f64> = [pair_flux, loop_flux, ext_flux].into_iter().flatten().collect();
+    if fluxes.is_empty() {
+        panic!("no flux at all?");
+    }
+    log_sum_exp(&fluxes)
+}
+
 pub struct LoopStructureSSA<'a, M: EnergyModel, K: RateModel> {
     loopstructure: LoopStructure<'a, M>, // owns the RNA folding state
     ratemodel: &'a K,
@@ -43,7 +53,11 @@ pub struct LoopStructureSSA<'a, M: EnergyModel, K: RateModel> {
     loop_flux: Option<f64>,
     per_loop_flux: IntMap<usize, f64>,
     per_loop_rxns: IntMap<usize, Vec<Reaction>>,
-    pair_rxns: IntMap<u16, Reaction>
+    pair_rxns: IntMap<u16, Reaction>,
+    /// Shift and helix unzipping reactions, grouped by pair id.
+    ext_flux: Option<f64>,
+    per_pair_ext_flux: IntMap<u16, f64>,
+    per_pair_ext_rxns: IntMap<u16, Vec<Reaction>>,
 }
 
 impl<'a, M, K> fmt::Debug for LoopStructureSSA<'a, M, K>
@@ -77,6 +91,11 @@ impl<'a, M: EnergyModel, K: RateModel> From<(LoopStructure<'a, M>, &'a K)>
                 logs.push(rxn.log_rate());
                 lrxns.push(rxn);
             }
+            for &(mv, delta) in loopstructure.get_zip_neighbors(*lli).into_iter().flatten() {
+                let rxn = Reaction::new(ratemodel, mv, delta);
+                logs.push(rxn.log_rate());
+                lrxns.push(rxn);
+            }
             if !lrxns.is_empty() {
                 let lflux = log_sum_exp(&logs);
                 per_loop_flux.insert(*lli, lflux);
@@ -105,13 +124,30 @@ impl<'a, M: EnergyModel, K: RateModel> From<(LoopStructure<'a, M>, &'a K)>
             None
         };
 
-        let log_flux = match (pair_flux, loop_flux) {
-            (Some(pf), None) => pf,
-            (None, Some(lf)) => lf,
-            (Some(pf), Some(lf)) => log_add(pf, lf),
-            _ => panic!("no flux at all?"),
+        let mut per_pair_ext_flux = IntMap::default();
+        let mut per_pair_ext_rxns = IntMap::default();
+        let mut ext_logs = Vec::new();
+        for (i, ext_neighbors) in loopstructure.get_ext_neighbors_per_pair().iter() {
+            let rxns: Vec<Reaction> = ext_neighbors.iter()
+                .map(|&(mv, delta)| Reaction::new(ratemodel, mv, delta))
+                .collect();
+            if !rxns.is_empty() {
+                let logs: Vec<f64> = rxns.iter().map(|r| r.log_rate()).collect();
+                let pflux = log_sum_exp(&logs);
+                per_pair_ext_flux.insert(*i, pflux);
+                ext_logs.push(pflux);
+            }
+            per_pair_ext_rxns.insert(*i, rxns);
+        }
+
+        let ext_flux = if !ext_logs.is_empty() {
+            Some(log_sum_exp(&ext_logs))
+        } else {
+            None
         };
 
+        let log_flux = total_log_flux(pair_flux, loop_flux, ext_flux);
+
         Self {
             ratemodel,
             loopstructure,
@@ -121,6 +157,9 @@ impl<'a, M: EnergyModel, K: RateModel> From<(LoopStructure<'a, M>, &'a K)>
             per_loop_flux,
             per_loop_rxns,
             pair_rxns,
+            ext_flux,
+            per_pair_ext_flux,
+            per_pair_ext_rxns,
         }
     }
 }
@@ -130,20 +169,21 @@ impl<'a, M: EnergyModel, K: RateModel> LoopStructureSSA<'a, M, K> {
         format!("{}", self.loopstructure)
     }
 
+    pub fn loop_structure(&self) -> &LoopStructure<'a, M> {
+        &self.loopstructure
+    }
+
     fn recompute_flux(&mut self) {
         //println!("{}", self.current_structure());
         //println!("Recomputing flux: T:{} L:{:?} P:{:?}",
         //    self.log_flux, self.loop_flux, self.pair_flux);
         let loops: Vec<f64> = self.per_loop_flux.values().cloned().collect();
         let pairs: Vec<f64> = self.pair_rxns.values().map(|rxn| rxn.log_rate()).collect();
+        let exts: Vec<f64> = self.per_pair_ext_flux.values().cloned().collect();
         self.loop_flux = if !loops.is_empty() { Some(log_sum_exp(&loops)) } else { None };
         self.pair_flux = if !pairs.is_empty() { Some(log_sum_exp(&pairs)) } else { None };
-        self.log_flux = match (self.pair_flux, self.loop_flux) {
-            (Some(pf), None) => pf,
-            (None, Some(lf)) => lf,
-            (Some(pf), Some(lf)) => log_add(pf, lf),
-            _ => panic!("no flux at all?"),
-        };
+        self.ext_flux = if !exts.is_empty() { Some(log_sum_exp(&exts)) } else { None };
+        self.log_flux = total_log_flux(self.pair_flux, self.loop_flux, self.ext_flux);
         //println!("Recomputed  flux: T:{} L:{:?} P:{:?}",
         //    self.log_flux, self.loop_flux, self.pair_flux);
     }
@@ -193,6 +233,11 @@ impl<'a, M: EnergyModel, K: RateModel> LoopStructureSSA<'a, M, K> {
             logs.push(rxn.log_rate());
             lrxns.push(rxn);
         }
+        for &(mv, delta) in self.loopstructure.get_zip_neighbors(lli).into_iter().flatten() {
+            let rxn = Reaction::new(self.ratemodel, mv, delta);
+            logs.push(rxn.log_rate());
+            lrxns.push(rxn);
+        }
         if !lrxns.is_empty() {
             let lflux = log_sum_exp(&logs);
             self.per_loop_flux.insert(lli, lflux);
@@ -231,21 +276,93 @@ impl<'a, M: EnergyModel, K: RateModel> LoopStructureSSA<'a, M, K> {
         }
     }
 
+    /// Synchronize shift and helix unzipping reactions with the loop structure.
+    pub fn update_ext_reactions(&mut self) {
+        for i in self.loopstructure.drain_ext_updates() {
+            self.per_pair_ext_rxns.remove(&i);
+            if let Some(pflux) = self.per_pair_ext_flux.remove(&i) {
+                if !self.per_pair_ext_flux.is_empty() {
+                    self.ext_flux = Some(log_sub(self.ext_flux.unwrap(), pflux).expect("ef, now that one should be fine."));
+                    self.log_flux = log_sub(self.log_flux, pflux).expect("etf, now that one should be fine.");
+                } else {
+                    self.ext_flux = None;
+                    //NOTE: no log_flux update! Will be recomputed.
+                }
+            }
+            let Some(ext_neighbors) = self.loopstructure.get_ext_neighbors_per_pair().get(&i) else {
+                continue; // The pair has been removed.
+            };
+            let rxns: Vec<Reaction> = ext_neighbors.iter()
+                .map(|&(mv, delta)| Reaction::new(self.ratemodel, mv, delta))
+                .collect();
+            if !rxns.is_empty() {
+                let logs: Vec<f64> = rxns.iter().map(|r| r.log_rate()).collect();
+                let pflux = log_sum_exp(&logs);
+                self.per_pair_ext_flux.insert(i, pflux);
+                self.ext_flux = Some(match self.ext_flux {
+                    Some(ef) => log_add(ef, pflux),
+                    None => pflux,
+                });
+                self.log_flux = log_add(self.log_flux, pflux);
+            }
+            self.per_pair_ext_rxns.insert(i, rxns);
+        }
+    }
+
+    fn fire_add(&mut self, i: u16, j: u16) {
+        self.remove_loop_reaction(i);
+        let ((lli, ami), (llj, amj), pair_changes) = self
+            .loopstructure.apply_add_move(i, j);
+        self.insert_loop_reactions(lli, ami);
+        self.insert_loop_reactions(llj, amj);
+        self.update_pair_reactions(pair_changes);
+    }
+
+    fn fire_del(&mut self, i: u16, j: u16) {
+        self.remove_pair_reaction(i);
+        let ((lli, neighbors), pair_changes) = self
+            .loopstructure.apply_del_move(i, j);
+        self.insert_loop_reactions(lli, neighbors);
+        self.update_pair_reactions(pair_changes);
+    }
+
     pub fn simulate<R, F>(
         &mut self,
         rng: &mut R,
         t_max: f64,
+        callback: F,
+    )
+    where
+        R: Rng + ?Sized,
+        F: FnMut(f64, f64, f64, &LoopStructure<'a, M>) -> bool,
+    {
+        self.simulate_from(rng, 0., t_max, callback)
+    }
+
+    /// Like `simulate`, but the simulation clock starts at `t_start`, e.g. 
+    /// to resume a trajectory from a checkpoint. The waiting time of the 
+    /// current structure is sampled anew, which is exact because waiting 
+    /// times are memoryless.
+    pub fn simulate_from<R, F>(
+        &mut self,
+        rng: &mut R,
+        t_start: f64,
+        t_max: f64,
         mut callback: F,
     )
     where
         R: Rng + ?Sized,
         F: FnMut(f64, f64, f64, &LoopStructure<'a, M>) -> bool,
     {
-        let mut t = 0.;
+        let mut t = t_start;
 
         while t < t_max {
             if let (Some(pf), Some(lf)) = (self.pair_flux, self.loop_flux) {
-                if (log_add(pf, lf) - self.log_flux).abs() > 1e-8 {
+                let expected = match self.ext_flux {
+                    Some(ef) => log_add(log_add(pf, lf), ef),
+                    None => log_add(pf, lf),
+                };
+                if (expected - self.log_flux).abs() > 1e-8 {
                     self.recompute_flux();
                 }
             } else { self.recompute_flux(); };
@@ -297,24 +414,42 @@ impl<'a, M: EnergyModel, K: RateModel> LoopStructureSSA<'a, M, K> {
                     }
                 }
             }
+            if chosen.is_none() {
+                'outer: for (i, pflux) in self.per_pair_ext_flux.iter() {
+                    let rxns = &self.per_pair_ext_rxns[i];
+                    let next_acc = log_add(acc, *pflux);
+                    if next_acc > log_thresh {
+                        for rxn in rxns {
+                            acc = log_add(acc, rxn.log_rate());
+                            if acc >= log_thresh {
+                                chosen = Some(rxn.clone());
+                                break 'outer;
+                            }
+                        }
+                    } else {
+                        acc = next_acc;
+                    }
+                }
+            }
 
             if let Some(rxn) = chosen {
                 match rxn {
-                    Reaction::Add { i, j, .. } => {
-                        self.remove_loop_reaction(rxn.ij().0);
-                        let ((lli, ami), (llj, amj), pair_changes) = self
-                            .loopstructure.apply_add_move(i, j);
-                        self.insert_loop_reactions(lli, ami);
-                        self.insert_loop_reactions(llj, amj);
-                        self.update_pair_reactions(pair_changes);
-                    },
-                    Reaction::Del { i, j, .. } => {
-                        self.remove_pair_reaction(rxn.ij().0);
-                        let ((lli, neighbors), pair_changes) = self
-                            .loopstructure.apply_del_move(i, j);
-                        self.insert_loop_reactions(lli, neighbors);
-                        self.update_pair_reactions(pair_changes);
-                    },
+                    Reaction::Add { i, j, .. } => self.fire_add(i, j),
+                    Reaction::Del { i, j, .. } => self.fire_del(i, j),
+                    _ => {
+                        // Composite moves are applied as a valid 
+                        // sequence of base-pair additions/deletions.
+                        for step in rxn.to_move().steps() {
+                            match step {
+                                Move::Add { i, j } => self.fire_add(i, j),
+                                Move::Del { i, j } => self.fire_del(i, j),
+                                _ => unreachable!("steps() returns only Add and Del moves"),
+                            }
+                        }
+                    }
+                }
+                if self.loopstructure.move_set().is_extended() {
+                    self.update_ext_reactions();
                 }
             } else {
                 panic!("No reaction chosen despite positive flux");
@@ -366,6 +501,45 @@ mod tests {
         assert!(steps > 0, "Simulation must perform at least one step");
         assert!(simulator.log_flux.is_finite(), "Flux must remain finite");
     }
+
+    #[test]
+    fn test_extended_move_set_simulation() {
+        use crate::reaction::MoveSet;
+        let emodel = ViennaRNA::default();
+        let rmodel = Metropolis::new(emodel.temperature(), 1.0);
+        let mut rng = StdRng::seed_from_u64(42);
+
+        let sequence = NucleotideVec::try_from("GGGAAACCCAGCGAAAGCUA").unwrap();
+        let pairings = PairTable::try_from("....................").unwrap();
+        let move_set = MoveSet { shift: true, helix_length: 3 };
+        let loops = LoopStructure::try_from((&sequence[..], &pairings, &emodel))
+            .expect("failed to build loop structure")
+            .with_move_set(move_set);
+
+        let mut simulator = LoopStructureSSA::from((loops, &rmodel));
+        let mut steps = 0;
+        simulator.simulate(&mut rng, f64::INFINITY, |_, _, _, _| {
+            steps += 1;
+            steps < 500
+        });
+
+        // The incrementally updated fluxes must match a freshly initialized
+        // simulator. (The run ends in the callback, so there is no reaction
+        // after the last consistency check of the total flux.)
+        let pairings = PairTable::try_from(simulator.current_structure().as_str()).unwrap();
+        let loops = LoopStructure::try_from((&sequence[..], &pairings, &emodel))
+            .unwrap()
+            .with_move_set(move_set);
+        let fresh = LoopStructureSSA::from((loops, &rmodel));
+        assert!((simulator.log_flux - fresh.log_flux).abs() < 1e-8);
+        assert!((simulator.pair_flux.unwrap() - fresh.pair_flux.unwrap()).abs() < 1e-8);
+        assert!((simulator.loop_flux.unwrap() - fresh.loop_flux.unwrap()).abs() < 1e-8);
+        let (ext, fresh_ext) = (simulator.ext_flux.unwrap(), fresh.ext_flux.unwrap());
+        assert!((ext - fresh_ext).abs() < 1e-8);
+        let per_pair: Vec<f64> = simulator.per_pair_ext_flux.values().copied().collect();
+        assert!((log_sum_exp(&per_pair) - fresh_ext).abs() < 1e-8);
+        assert_eq!(simulator.per_pair_ext_rxns.len(), fresh.per_pair_ext_rxns.len());
+    }
 }
 
 
@@ -0,0 +1,289 @@
+//! Discovery of frequently visited structures.
+//!
+//! Without predefined macrostates every structure ends up in the catch-all
+//! macrostate. Instead, [`StructureCounts`] records how many trajectories
+//! visit each individual structure at each output time. The top N
+//! structures (ranked by their peak occupancy) become single-structure
+//! macrostates, all others are collected in an "other" bin. The discovered
+//! structures can be written as macrostate files for follow-up runs.
+
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use ahash::AHashMap;
+use ahash::AHashSet;
+
+use ff_structure::DotBracketVec;
+use ff_energy::EnergyModel;
+use ff_energy::NucleotideVec;
+
+use crate::Macrostate;
+use crate::MacrostateRegistry;
+use crate::write_macrostate;
+use crate::timeline::Timeline;
+
+/// The macrostate name of the k-th (0-based) discovered structure.
+pub fn discovered_name(index: usize) -> String {
+    format!("top{}", index + 1)
+}
+
+/// Approximate number of trajectories in the most frequent structures at
+/// each output time.
+///
+/// Every time point keeps at most `capacity` structures (space-saving
+/// algorithm): a new structure replaces the least frequent one and inherits
+/// its count, which is remembered as the error bound of the new entry.
+/// Memory is therefore bounded no matter how many structures are visited,
+/// and frequent structures are counted with a small error.
+#[derive(Debug, Clone)]
+pub struct StructureCounts {
+    times: Vec<f64>,
+    capacity: usize,
+    /// Per time point: structure -> (count, overestimation bound).
+    counts: Vec<AHashMap<DotBracketVec, (usize, usize)>>,
+    totals: Vec<usize>,
+}
+
+impl StructureCounts {
+    pub fn new(times: &[f64], capacity: usize) -> Self {
+        assert!(capacity > 0, "Structure counts need a capacity of at least one");
+        Self {
+            times: times.to_vec(),
+            capacity,
+            counts: vec![AHashMap::new(); times.len()],
+            totals: vec![0; times.len()],
+        }
+    }
+
+    pub fn times(&self) -> &[f64] {
+        &self.times
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn record(&mut self, t_idx: usize, structure: &DotBracketVec) {
+        let counts = &mut self.counts[t_idx];
+        if let Some((c, _)) = counts.get_mut(structure) {
+            *c += 1;
+        } else if counts.len() < self.capacity {
+            counts.insert(structure.clone(), (1, 0));
+        } else {
+            let min = evict_min(counts);
+            counts.insert(structure.clone(), (min + 1, min));
+        }
+        self.totals[t_idx] += 1;
+    }
+
+    /// Merge two summaries: a structure missing from a full summary may
+    /// have been evicted there, so it gets that summary's minimum count
+    /// (as count and as error).
+    pub fn merge(&mut self, other: &StructureCounts) {
+        assert_eq!(self.times.len(), other.times.len(),
+            "Cannot merge structure counts with different numbers of timepoints");
+        for (t_idx, theirs) in other.counts.iter().enumerate() {
+            let ours = &mut self.counts[t_idx];
+            let floor = |counts: &AHashMap<DotBracketVec, (usize, usize)>, capacity| {
+                if counts.len() < capacity { 0 } else { counts.values().map(|&(c, _)| c).min().unwrap_or(0) }
+            };
+            let our_floor = floor(ours, self.capacity);
+            let their_floor = floor(theirs, other.capacity);
+            for (s, (c, e)) in ours.iter_mut() {
+                let (tc, te) = theirs.get(s).copied().unwrap_or((their_floor, their_floor));
+                *c += tc;
+                *e += te;
+            }
+            for (s, &(c, e)) in theirs {
+                if !ours.contains_key(s) {
+                    ours.insert(s.clone(), (c + our_floor, e + our_floor));
+                }
+            }
+            while ours.len() > self.capacity {
+                evict_min(ours);
+            }
+            self.totals[t_idx] += other.totals[t_idx];
+        }
+    }
+
+    /// Number of distinct structures tracked over all time points.
+    pub fn num_structures(&self) -> usize {
+        let mut seen: AHashSet<&DotBracketVec> = AHashSet::new();
+        for counts in &self.counts {
+            seen.extend(counts.keys());
+        }
+        seen.len()
+    }
+
+    /// The highest occupancy of every structure over all time points.
+    fn peak_occupancies(&self) -> AHashMap<&DotBracketVec, f64> {
+        let mut peaks: AHashMap<&DotBracketVec, f64> = AHashMap::new();
+        for (counts, &total) in self.counts.iter().zip(&self.totals) {
+            for (s, &(c, _)) in counts {
+                let occu = c as f64 / total as f64;
+                let peak = peaks.entry(s).or_insert(0.0);
+                *peak = peak.max(occu);
+            }
+        }
+        peaks
+    }
+
+    /// The `n` structures with the highest peak occupancy, in decreasing
+    /// order (ties are broken by the dot-bracket string).
+    pub fn top_structures(&self, n: usize) -> Vec<(DotBracketVec, f64)> {
+        let mut peaks: Vec<(DotBracketVec, f64)> = self.peak_occupancies()
+            .into_iter()
+            .map(|(s, p)| (s.clone(), p))
+            .collect();
+        peaks.sort_by(|(s1, p1), (s2, p2)| p2.total_cmp(p1)
+            .then_with(|| s1.to_string().cmp(&s2.to_string())));
+        peaks.truncate(n);
+        peaks
+    }
+
+    /// A registry with one macrostate per structure (see [`discovered_name`])
+    /// and an "other" bin for all remaining structures.
+    pub fn registry<'a, E: EnergyModel>(
+        structures: &[DotBracketVec],
+        sequence: &'a NucleotideVec,
+        model: &'a E,
+    ) -> Result<MacrostateRegistry<'a, E>, String> {
+        let mut registry = MacrostateRegistry::from((sequence, model))
+            .with_catch_all_name("other");
+        for (k, s) in structures.iter().enumerate() {
+            registry.insert(Macrostate::from_list(&discovered_name(k), sequence,
+                std::slice::from_ref(s), model))?;
+        }
+        Ok(registry)
+    }
+
+    /// Classify all recorded structures into a timeline. Every trajectory
+    /// remains a separate observation, so confidence intervals are available.
+    /// Only the guaranteed count (count minus error) of a tracked structure
+    /// is classified, all other trajectories go to the catch-all macrostate.
+    pub fn timeline<'a, E: EnergyModel>(&self, registry: Arc<MacrostateRegistry<'a, E>>
+    ) -> Timeline<'a, E> {
+        let mut timeline = Timeline::new(&self.times, Arc::clone(&registry));
+        for ((tp, counts), &total) in timeline.points.iter_mut().zip(&self.counts).zip(&self.totals) {
+            let mut counts: Vec<_> = counts.iter().collect();
+            counts.sort_by_key(|(s, _)| *s);
+            let mut assigned = 0;
+            for (s, &(c, e)) in counts {
+                if c > e {
+                    tp.add_samples(&registry.classify_weighted(s), c - e);
+                    assigned += c - e;
+                }
+            }
+            if total > assigned {
+                tp.add_samples(&[(0, 1.0)], total - assigned);
+            }
+        }
+        timeline
+    }
+}
+
+/// Remove the structure with the lowest count (ties are broken by the
+/// structure itself) and return its count.
+fn evict_min(counts: &mut AHashMap<DotBracketVec, (usize, usize)>) -> usize {
+    let (s, c) = counts.iter()
+        .min_by(|(s1, (c1, _)), (s2, (c2, _))| c1.cmp(c2)
+            .then_with(|| s2.cmp(s1)))
+        .map(|(s, &(c, _))| (s.clone(), c))
+        .expect("evict from a non-empty summary");
+    counts.remove(&s);
+    c
+}
+
+/// Write every discovered structure as a macrostate file `<name>.txt`
+/// into `dir` and return the paths (which can be passed to `--macrostates`).
+pub fn write_structure_files<P: AsRef<Path>>(
+    dir: P,
+    sequence: &NucleotideVec,
+    structures: &[DotBracketVec],
+) -> io::Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(&dir)?;
+    let mut paths = Vec::with_capacity(structures.len());
+    for (k, s) in structures.iter().enumerate() {
+        let name = discovered_name(k);
+        let path = dir.as_ref().join(format!("{}.txt", name));
+        let mut fh = io::BufWriter::new(std::fs::File::create(&path)?);
+        write_macrostate(&mut fh, &name, sequence, std::slice::from_ref(s))?;
+        fh.flush()?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ff_energy::ViennaRNA;
+
+    #[test]
+    fn test_structure_discovery() {
+        let model = ViennaRNA::default();
+        let sequence = NucleotideVec::try_from("GGGGAAAACCCC").unwrap();
+        let open = DotBracketVec::try_from("............").unwrap();
+        let mfe = DotBracketVec::try_from("((((....))))").unwrap();
+        let half = DotBracketVec::try_from(".(((....))).").unwrap();
+        let rare = DotBracketVec::try_from("(((......)))").unwrap();
+
+        let mut counts = StructureCounts::new(&[0.0, 1.0], 10);
+        for _ in 0..4 {
+            counts.record(0, &open);
+        }
+        let mut other = StructureCounts::new(&[0.0, 1.0], 10);
+        other.record(1, &mfe);
+        other.record(1, &mfe);
+        other.record(1, &half);
+        other.record(1, &rare);
+        counts.merge(&other);
+        assert_eq!(counts.num_structures(), 4);
+
+        let top = counts.top_structures(2);
+        assert_eq!(top, vec![(open.clone(), 1.0), (mfe.clone(), 0.5)]);
+
+        let structures: Vec<_> = top.into_iter().map(|(s, _)| s).collect();
+        let registry = Arc::new(StructureCounts::registry(&structures, &sequence, &model).unwrap());
+        assert_eq!(registry.len(), 3);
+        assert_eq!(registry.macrostates()[0].name(), "other");
+        let timeline = counts.timeline(Arc::clone(&registry));
+        assert_eq!(timeline.point(0).occupancy(1), 1.0);
+        assert_eq!(timeline.point(1).occupancy(2), 0.5);
+        assert_eq!(timeline.point(1).occupancy(0), 0.5);
+        assert_eq!(timeline.point(1).counter, 4);
+
+        // A full summary replaces the least frequent structure.
+        let mut bounded = StructureCounts::new(&[0.0], 2);
+        bounded.record(0, &rare);
+        for _ in 0..3 {
+            bounded.record(0, &open);
+        }
+        bounded.record(0, &mfe);
+        bounded.record(0, &mfe);
+        assert_eq!(bounded.num_structures(), 2);
+        assert_eq!(bounded.top_structures(2), vec![(mfe.clone(), 0.5), (open.clone(), 0.5)]);
+        let mut other = StructureCounts::new(&[0.0], 2);
+        other.record(0, &open);
+        bounded.merge(&other);
+        assert_eq!(bounded.top_structures(1), vec![(open.clone(), 4.0 / 7.0)]);
+        // Only guaranteed counts are classified, the rest is "other".
+        let timeline = bounded.timeline(Arc::clone(&registry));
+        assert_eq!(timeline.point(0).counter, 7);
+        assert_eq!(timeline.point(0).occupancy(1), 4.0 / 7.0);
+        assert_eq!(timeline.point(0).occupancy(2), 2.0 / 7.0);
+        assert_eq!(timeline.point(0).occupancy(0), 1.0 / 7.0);
+        other.record(0, &half);
+        bounded.merge(&other);
+        assert_eq!(bounded.num_structures(), 2);
+
+        let dir = std::env::temp_dir().join(format!("ff_discovery_{}", std::process::id()));
+        let paths = write_structure_files(&dir, &sequence, &structures).unwrap();
+        let mut reloaded = MacrostateRegistry::from((&sequence, &model));
+        reloaded.insert_files(&paths).unwrap();
+        assert_eq!(reloaded.classify(&mfe), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,62 @@
+//! Incremental (Zobrist) hashes of secondary structures.
+//!
+//! Every base-pair (i, j) has a pseudo-random 64-bit key, and the hash of a
+//! structure is the XOR of the keys of all its base-pairs. Adding or removing
+//! a pair updates the hash in constant time, which is how
+//! [`LoopStructure`](crate::LoopStructure) keeps track of it while moves are
+//! applied. The keys do not depend on the sequence or the energy model, so
+//! the hash of a structure is the same for loop structures, macrostates and
+//! stop conditions.
+//!
+//! Two distinct structures collide with probability 2^-64. Unlike a full
+//! comparison, classification by hash accepts this (tiny) chance of error.
+
+use ff_structure::NAIDX;
+use ff_structure::PairTable;
+
+/// The Zobrist key of base-pair (i, j), with i < j.
+pub fn pair_key(i: NAIDX, j: NAIDX) -> u64 {
+    // SplitMix64 finalizer of the packed pair.
+    let mut z = ((i as u64) << 32 | j as u64).wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// The hash of a structure: the XOR of [`pair_key`] over all base-pairs.
+pub fn structure_hash(pairings: &PairTable) -> u64 {
+    pairings.iter()
+        .enumerate()
+        .filter_map(|(i, &j)| j.filter(|&j| i < j as usize).map(|j| pair_key(i as NAIDX, j)))
+        .fold(0, |h, k| h ^ k)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ff_energy::NucleotideVec;
+    use ff_energy::ViennaRNA;
+    use crate::LoopStructure;
+
+    #[test]
+    fn test_incremental_structure_hash() {
+        let emodel = ViennaRNA::default();
+        let sequence = NucleotideVec::try_from("GGGGAAAACCCCAGGGAAACCC").unwrap();
+        let hash = |s: &str| structure_hash(&PairTable::try_from(s).unwrap());
+        assert_eq!(hash("......................"), 0);
+        assert_ne!(hash("((....)).............."), 0);
+        assert_ne!(hash("(.....)..............."), hash(".(.....).............."));
+
+        let pairings = PairTable::try_from("((((....))))..........").unwrap();
+        let mut ls = LoopStructure::try_from((&sequence[..], &pairings, &emodel)).unwrap();
+        assert_eq!(ls.structure_hash(), hash("((((....)))).........."));
+
+        let _ = ls.apply_add_move(13, 21);
+        let _ = ls.apply_add_move(14, 20);
+        let _ = ls.apply_del_move(0, 11);
+        assert_eq!(ls.structure_hash(), hash(".(((....)))..((.....))"));
+        let _ = ls.apply_del_move(13, 21);
+        let _ = ls.apply_add_move(0, 11);
+        assert_eq!(ls.structure_hash(), hash("((((....))))..(.....)."));
+    }
+}
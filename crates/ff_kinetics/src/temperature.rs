@@ -0,0 +1,159 @@
+//! Time-dependent temperature schedules.
+//!
+//! A schedule is a list of breakpoints (time, temperature), which is
+//! either stepwise (the temperature jumps at each breakpoint) or a linear
+//! ramp between breakpoints. For the simulation, a schedule is split into
+//! segments of constant temperature: linear ramps are discretized into
+//! steps of at most `resolution` °C, and the segments are simulated with
+//! [`SegmentedSSA`](crate::piecewise::SegmentedSSA).
+
+use serde::{Serialize, Deserialize};
+
+use crate::piecewise::piecewise_constant;
+
+/// How the temperature changes between breakpoints.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Interpolation {
+    /// Temperature jumps at each breakpoint.
+    Step,
+    /// Linear ramp between breakpoints.
+    Linear,
+}
+
+/// A time interval of constant temperature.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TemperatureSegment {
+    pub start: f64,
+    pub end: f64,
+    /// Temperature in Celsius.
+    pub temperature: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TemperatureSchedule {
+    /// (time, temperature in Celsius), sorted by time.
+    breakpoints: Vec<(f64, f64)>,
+    interpolation: Interpolation,
+    /// Maximal temperature change (°C) between two segments of a linear ramp.
+    resolution: f64,
+}
+
+impl TemperatureSchedule {
+    /// A constant temperature.
+    pub fn constant(temperature: f64) -> Self {
+        Self::stepwise(vec![(0.0, temperature)]).expect("valid schedule")
+    }
+
+    /// Temperature jumps: `breakpoints[k].1` holds from `breakpoints[k].0`
+    /// until the next breakpoint.
+    pub fn stepwise(breakpoints: Vec<(f64, f64)>) -> Result<Self, String> {
+        Self::new(breakpoints, Interpolation::Step, 1.0)
+    }
+
+    /// Linear ramps between breakpoints, discretized into steps of at most
+    /// `resolution` °C.
+    pub fn linear(breakpoints: Vec<(f64, f64)>, resolution: f64) -> Result<Self, String> {
+        Self::new(breakpoints, Interpolation::Linear, resolution)
+    }
+
+    fn new(mut breakpoints: Vec<(f64, f64)>, interpolation: Interpolation, resolution: f64
+    ) -> Result<Self, String> {
+        if breakpoints.is_empty() {
+            return Err("A temperature schedule needs at least one breakpoint".to_string());
+        }
+        if resolution.is_nan() || resolution <= 0.0 {
+            return Err(format!("Ramp resolution must be positive, got {}", resolution));
+        }
+        if breakpoints.iter().any(|&(t, _)| !t.is_finite() || t < 0.0) {
+            return Err("Breakpoint times must be non-negative".to_string());
+        }
+        breakpoints.sort_by(|a, b| a.0.total_cmp(&b.0));
+        if breakpoints.windows(2).any(|w| w[0].0 == w[1].0) {
+            return Err("Breakpoint times must be unique".to_string());
+        }
+        Ok(Self { breakpoints, interpolation, resolution })
+    }
+
+    pub fn breakpoints(&self) -> &[(f64, f64)] {
+        &self.breakpoints
+    }
+
+    pub fn interpolation(&self) -> Interpolation {
+        self.interpolation
+    }
+
+    /// The exact temperature at time `t` (constant before the first and
+    /// after the last breakpoint).
+    pub fn temperature_at(&self, t: f64) -> f64 {
+        let k = self.breakpoints.partition_point(|&(tb, _)| tb <= t);
+        if k == 0 {
+            return self.breakpoints[0].1;
+        }
+        let (t0, temp0) = self.breakpoints[k - 1];
+        match (self.interpolation, self.breakpoints.get(k)) {
+            (Interpolation::Linear, Some(&(t1, temp1))) => {
+                temp0 + (temp1 - temp0) * (t - t0) / (t1 - t0)
+            }
+            _ => temp0,
+        }
+    }
+
+    /// The temperature at the start of the simulation.
+    pub fn initial_temperature(&self) -> f64 {
+        self.temperature_at(0.0)
+    }
+
+    /// Split [0, t_end) into segments of constant temperature (see
+    /// [`piecewise_constant`]). Steps keep their temperature until the next
+    /// breakpoint, linear ramps are discretized by the resolution.
+    pub fn segments(&self, t_end: f64) -> Vec<TemperatureSegment> {
+        let resolution = match self.interpolation {
+            Interpolation::Step => f64::INFINITY,
+            Interpolation::Linear => self.resolution,
+        };
+        let breakpoints: Vec<f64> = self.breakpoints.iter().map(|&(t, _)| t).collect();
+        piecewise_constant(&breakpoints, t_end, resolution, |t| self.temperature_at(t))
+            .into_iter()
+            .map(|(start, end, temperature)| TemperatureSegment { start, end, temperature })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stepwise_schedule() {
+        let schedule = TemperatureSchedule::stepwise(vec![(1.0, 60.0), (0.0, 37.0), (2.0, 37.0)]).unwrap();
+        assert_eq!(schedule.temperature_at(0.5), 37.0);
+        assert_eq!(schedule.temperature_at(1.0), 60.0);
+        assert_eq!(schedule.temperature_at(5.0), 37.0);
+
+        let segments = schedule.segments(3.0);
+        assert_eq!(segments, vec![
+            TemperatureSegment { start: 0.0, end: 1.0, temperature: 37.0 },
+            TemperatureSegment { start: 1.0, end: 2.0, temperature: 60.0 },
+            TemperatureSegment { start: 2.0, end: 3.0, temperature: 37.0 },
+        ]);
+
+        // Breakpoints beyond t_end are ignored.
+        assert_eq!(schedule.segments(0.5).len(), 1);
+        assert_eq!(TemperatureSchedule::constant(25.0).segments(1.0).len(), 1);
+        assert!(TemperatureSchedule::stepwise(vec![(0.0, 37.0), (0.0, 60.0)]).is_err());
+    }
+
+    #[test]
+    fn test_linear_schedule() {
+        let schedule = TemperatureSchedule::linear(vec![(0.0, 20.0), (1.0, 30.0)], 2.5).unwrap();
+        assert_eq!(schedule.temperature_at(0.5), 25.0);
+        assert_eq!(schedule.temperature_at(2.0), 30.0);
+
+        let segments = schedule.segments(2.0);
+        assert_eq!(segments.len(), 5);
+        assert_eq!(segments[0].temperature, 21.25);
+        assert_eq!(segments[3].temperature, 28.75);
+        assert_eq!(segments[4], TemperatureSegment { start: 1.0, end: 2.0, temperature: 30.0 });
+        assert!(segments.windows(2).all(|w| w[0].end == w[1].start));
+    }
+}
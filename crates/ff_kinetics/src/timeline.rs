@@ -6,6 +6,10 @@ use nohash_hasher::IntMap;
 use ff_structure::DotBracketVec; 
 
 use crate::macrostates::MacrostateRegistry;
+use crate::macrostates::OverlapPolicy;
+use crate::LoopStructure;
+use crate::timeline_statistics::ConfidenceMethod;
+use crate::timeline_io::RunMetadata;
 
 #[derive(Debug)]
 pub enum TimelineError {
@@ -14,6 +18,9 @@ pub enum TimelineError {
     TimepointCountMismatch { found: usize, expected: usize },
     TimeMismatch { file_time: f64, expected_time: f64 },
     MacrostateNotFound(String),
+    UnsupportedVersion(u32),
+    Incompatible(String),
+    NoTimelines,
 }
 
 impl fmt::Display for TimelineError {
@@ -27,6 +34,12 @@ impl fmt::Display for TimelineError {
                 write!(f, "Time mismatch: {file_time} vs {expected_time}"),
             Self::MacrostateNotFound(name) =>
                 write!(f, "Macrostate '{name}' not found in registry"),
+            Self::UnsupportedVersion(v) =>
+                write!(f, "Unsupported timeline format version {v}"),
+            Self::Incompatible(msg) =>
+                write!(f, "Incompatible timeline: {msg}"),
+            Self::NoTimelines =>
+                write!(f, "No timelines to merge"),
         }
     }
 }
@@ -57,9 +70,16 @@ pub struct Timepoint {
     /// Absolute time in seconds
     pub time: f64,
     /// Mapping from macrostate index → number of trajectories in this state
-    pub ensemble: IntMap<usize, usize>,
+    /// (fractional if structures are split between overlapping macrostates)
+    pub ensemble: IntMap<usize, f64>,
     /// Total number of observations recorded at this timepoint
     pub counter: usize,
+    /// Distinct observations (macrostate weights) with their multiplicity,
+    /// used for bootstrap resampling across trajectories
+    samples: Vec<(Vec<(usize, f64)>, usize)>,
+    /// Macrostate index to the slot in `samples` of observations that
+    /// count fully for this macrostate.
+    slots: IntMap<usize, usize>,
 }
 
 impl Timepoint {
@@ -69,18 +89,65 @@ impl Timepoint {
             time,
             ensemble: IntMap::default(),
             counter: 0,
+            samples: Vec::new(),
+            slots: IntMap::default(),
         }
     }
 
     /// Add a count for the given macrostate index
     pub fn add(&mut self, macro_idx: usize) {
-        *self.ensemble.entry(macro_idx).or_insert(0) += 1;
-        self.counter += 1;
+        self.add_samples(&[(macro_idx, 1.0)], 1);
+    }
+
+    /// Add one observation, split between macrostates with the given weights.
+    pub fn add_weighted(&mut self, weights: &[(usize, f64)]) {
+        self.add_samples(weights, 1);
+    }
+
+    /// Add `n` identical observations.
+    pub fn add_samples(&mut self, weights: &[(usize, f64)], n: usize) {
+        if n == 0 {
+            return;
+        }
+        for &(macro_idx, w) in weights {
+            *self.ensemble.entry(macro_idx).or_insert(0.) += w * n as f64;
+        }
+        self.counter += n;
+
+        if let &[(macro_idx, w)] = weights && w == 1.0 {
+            match self.slots.get(&macro_idx) {
+                Some(&slot) => self.samples[slot].1 += n,
+                None => {
+                    self.slots.insert(macro_idx, self.samples.len());
+                    self.samples.push((weights.to_vec(), n));
+                }
+            }
+            return;
+        }
+        if !weights.is_sorted_by_key(|&(idx, _)| idx) {
+            let mut key = weights.to_vec();
+            key.sort_by_key(|&(idx, _)| idx);
+            return self.add_sorted(&key, n);
+        }
+        self.add_sorted(weights, n);
+    }
+
+    /// Count observations with weights sorted by macrostate index.
+    fn add_sorted(&mut self, weights: &[(usize, f64)], n: usize) {
+        match self.samples.iter_mut().find(|(k, _)| k[..] == *weights) {
+            Some((_, m)) => *m += n,
+            None => self.samples.push((weights.to_vec(), n)),
+        }
+    }
+
+    /// Distinct observations (macrostate weights) with their multiplicity.
+    pub fn samples(&self) -> &[(Vec<(usize, f64)>, usize)] {
+        &self.samples
     }
 
     /// Get the count for a specific macrostate (or 0 if not present)
-    pub fn count(&self, macro_idx: usize) -> usize {
-        *self.ensemble.get(&macro_idx).unwrap_or(&0)
+    pub fn count(&self, macro_idx: usize) -> f64 {
+        *self.ensemble.get(&macro_idx).unwrap_or(&0.)
     }
 
     /// Return the occupancy (fraction of total) for a macrostate
@@ -88,12 +155,12 @@ impl Timepoint {
         if self.counter == 0 {
             0.0
         } else {
-            self.count(macro_idx) as f64 / self.counter as f64
+            self.count(macro_idx) / self.counter as f64
         }
     }
 
     /// Iterate over all macrostate counts
-    pub fn iter(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+    pub fn iter(&self) -> impl Iterator<Item = (usize, f64)> + '_ {
         self.ensemble.iter().map(|(k, v)| (*k, *v))
     }
 
@@ -105,20 +172,49 @@ pub struct Timeline<'a, E: EnergyModel> {
 
     /// One `Timepoint` per output time in the simulation
     pub points: Vec<Timepoint>,
+
+    /// How confidence intervals of occupancies are reported
+    pub confidence: ConfidenceMethod,
+
+    /// Parameters of the simulations (if known)
+    pub run: Option<RunMetadata>,
 }
 
 impl<'a, E: EnergyModel> Timeline<'a, E> {
     /// Build a new empty timeline for given times and an existing macrostate registry.
     pub fn new(times: &[f64], registry: Arc<MacrostateRegistry<'a, E>>) -> Self {
         let points = times.iter().map(|&t| Timepoint::new(t)).collect();
-        Self { registry, points }
+        Self { registry, points, confidence: ConfidenceMethod::default(), run: None }
+    }
+
+    pub fn with_confidence(mut self, confidence: ConfidenceMethod) -> Self {
+        self.confidence = confidence;
+        self
+    }
+
+    pub fn with_run_metadata(mut self, run: RunMetadata) -> Self {
+        self.run = Some(run);
+        self
     }
 
     /// Classify a structure and add it to the timeline at the given time index.
-    /// Returns `Some(macro_idx)` if classified, `None` if unclassified.
+    /// Structures in overlapping macrostates may be split between them
+    /// (see [`OverlapPolicy`](crate::OverlapPolicy)).
     pub fn assign_structure(&mut self, t_idx: usize, structure: &DotBracketVec) {
-        let m_idx = self.registry.classify(structure);
-        self.points[t_idx].add(m_idx);
+        let weights = self.registry.classify_weighted(structure);
+        self.points[t_idx].add_weighted(&weights);
+    }
+
+    /// Classify the current structure of a loop structure by its hash and
+    /// add it to the timeline (see [`MacrostateRegistry::classify_loops`]).
+    pub fn assign_loops<M: EnergyModel>(&mut self, t_idx: usize, ls: &LoopStructure<'_, M>) {
+        if self.registry.overlap_policy() == OverlapPolicy::Fractional {
+            let weights = self.registry.classify_loops_weighted(ls);
+            self.points[t_idx].add_weighted(&weights);
+        } else {
+            let macro_idx = self.registry.classify_loops(ls);
+            self.points[t_idx].add(macro_idx);
+        }
     }
 
     /// Get a reference to a timepoint by index.
@@ -131,28 +227,49 @@ impl<'a, E: EnergyModel> Timeline<'a, E> {
         self.points.iter().enumerate()
     }
 
-    pub fn merge(&mut self, other: Timeline<'a, E>) {
-        assert!(
-            Arc::ptr_eq(&self.registry, &other.registry),
-            "Cannot merge timelines with different registries"
-        );
-        assert_eq!(self.points.len(), other.points.len(),
-        "Cannot merge timelines with different numbers of timepoints");
+    /// Add the observations of another timeline with the same registry and
+    /// time points. Fails if the runs are incompatible (see
+    /// [`RunMetadata::check_compatible`]).
+    pub fn merge(&mut self, other: Timeline<'a, E>) -> Result<(), TimelineError> {
+        if !Arc::ptr_eq(&self.registry, &other.registry) {
+            return Err(TimelineError::Incompatible("different macrostate registries".to_string()));
+        }
+        if self.points.len() != other.points.len() {
+            return Err(TimelineError::TimepointCountMismatch {
+                found: other.points.len(),
+                expected: self.points.len(),
+            });
+        }
+
+        match (&mut self.run, other.run) {
+            (Some(run), Some(other_run)) => {
+                run.check_compatible(&other_run).map_err(TimelineError::Incompatible)?;
+                run.seeds.extend(other_run.seeds);
+            }
+            (run @ None, other_run) => *run = other_run,
+            (Some(_), None) => (),
+        }
 
         for (self_tp, other_tp) in self.points.iter_mut().zip(other.points) {
-            for (macro_idx, count) in other_tp.iter() {
-                *self_tp.ensemble.entry(macro_idx).or_insert(0) += count;
+            for (weights, n) in &other_tp.samples {
+                self_tp.add_samples(weights, *n);
             }
-            self_tp.counter += other_tp.counter;
         }
+        Ok(())
     }
 }
 
 impl<'a, E: EnergyModel> fmt::Display for Timeline<'a, E> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let intervals = self.confidence_intervals();
         // DRF header
-        writeln!(f, "{:>13} {:>5} {:>12} {:>10} {:>25}", "time", "id", "occupancy", "energy", "macrostate")?;
-        for tp in self.points.iter() {
+        if intervals.is_some() {
+            writeln!(f, "{:>13} {:>5} {:>12} {:>12} {:>12} {:>10} {:>25}",
+                "time", "id", "occupancy", "ci_lower", "ci_upper", "energy", "macrostate")?;
+        } else {
+            writeln!(f, "{:>13} {:>5} {:>12} {:>10} {:>25}", "time", "id", "occupancy", "energy", "macrostate")?;
+        }
+        for (t_idx, tp) in self.points.iter().enumerate() {
             let time = tp.time;
             let total = tp.counter.max(1);
 
@@ -168,20 +285,35 @@ impl<'a, E: EnergyModel> fmt::Display for Timeline<'a, E> {
 
             // Sort ensemble by energy (you could make this configurable)
             for (m_idx, count) in entries {
-                let occu = count as f64 / total as f64;
+                let occu = count / total as f64;
 
                 let name = self.registry.macrostates()[m_idx].name();
                 let energy = self.registry.macrostates()[m_idx].ensemble_energy().unwrap_or(0.0);
 
-                writeln!(
-                    f,
-                    "{:13.9} {:5} {:12.8} {:>10} {:>25}",
-                    time,
-                    m_idx,
-                    occu,
-                    format!("{:10.2}", energy),
-                    name,
-                )?;
+                if let Some(intervals) = &intervals {
+                    let (lower, upper) = intervals[t_idx][&m_idx];
+                    writeln!(
+                        f,
+                        "{:13.9} {:5} {:12.8} {:12.8} {:12.8} {:>10} {:>25}",
+                        time,
+                        m_idx,
+                        occu,
+                        lower,
+                        upper,
+                        format!("{:10.2}", energy),
+                        name,
+                    )?;
+                } else {
+                    writeln!(
+                        f,
+                        "{:13.9} {:5} {:12.8} {:>10} {:>25}",
+                        time,
+                        m_idx,
+                        occu,
+                        format!("{:10.2}", energy),
+                        name,
+                    )?;
+                }
             }
         }
         Ok(())
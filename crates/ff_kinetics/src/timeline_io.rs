@@ -1,52 +1,516 @@
 use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::Path;
 use std::result;
+use std::collections::BTreeSet;
 use std::sync::Arc;
 use serde::{Serialize, Deserialize};
 
 use ff_energy::EnergyModel;
 use crate::timeline::Timeline;
 use crate::timeline::TimelineError;
+use crate::macrostates::Macrostate;
 use crate::macrostates::MacrostateRegistry;
+use crate::reaction::MoveSet;
+use crate::temperature::TemperatureSchedule;
+use crate::timeline_statistics::wilson_interval;
+use crate::timeline_plotting::OccupancySeries;
 
-#[derive(Serialize, Deserialize)]
+/// Version of the JSON timeline format. Files without a version (version 1)
+/// contain only the time points, version 2 adds [`TimelineMetadata`].
+pub const TIMELINE_FORMAT_VERSION: u32 = 2;
+
+fn legacy_version() -> u32 {
+    1
+}
+
+/// Parameters of the simulations that produced a timeline.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunMetadata {
+    /// Energy parameter file (None for the default parameters).
+    pub energy_parameters: Option<String>,
+    /// Temperature in Celsius.
+    pub temperature: f64,
+    /// Time-dependent temperature (None for the constant `temperature`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature_schedule: Option<TemperatureSchedule>,
+    /// Metropolis rate constant.
+    pub k0: f64,
+    pub move_set: MoveSet,
+    /// Random seeds of all runs (empty if seeded from entropy).
+    #[serde(default)]
+    pub seeds: Vec<u64>,
+    pub software_version: String,
+}
+
+impl RunMetadata {
+    /// Timelines of two runs can be merged if they use the same model
+    /// parameters and different seeds (the same seed repeats trajectories).
+    pub fn check_compatible(&self, other: &RunMetadata) -> result::Result<(), String> {
+        if self.energy_parameters != other.energy_parameters {
+            return Err(format!("energy parameters differ: {:?} vs {:?}",
+                self.energy_parameters, other.energy_parameters));
+        }
+        if self.temperature != other.temperature {
+            return Err(format!("temperature differs: {} vs {}", self.temperature, other.temperature));
+        }
+        if self.temperature_schedule != other.temperature_schedule {
+            return Err("temperature schedules differ".to_string());
+        }
+        if self.k0 != other.k0 {
+            return Err(format!("k0 differs: {} vs {}", self.k0, other.k0));
+        }
+        if self.move_set != other.move_set {
+            return Err(format!("move set differs: {:?} vs {:?}", self.move_set, other.move_set));
+        }
+        if let Some(seed) = self.seeds.iter().find(|s| other.seeds.contains(s)) {
+            return Err(format!("both runs used seed {}", seed));
+        }
+        Ok(())
+    }
+}
+
+/// The definition of a macrostate: explicit structures or rules.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MacrostateDefinition {
+    pub name: String,
+    /// Ensemble free energy (kcal/mol), if defined by structures.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub energy: Option<f64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub structures: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<String>,
+}
+
+impl MacrostateDefinition {
+    /// Same name, structures and rules, and ensemble energies that agree up
+    /// to rounding errors (they are sums over hash maps).
+    pub fn is_equivalent(&self, other: &MacrostateDefinition) -> bool {
+        let energy = match (self.energy, other.energy) {
+            (Some(a), Some(b)) => (a - b).abs() <= 1e-6 * a.abs().max(1.0),
+            (a, b) => a == b,
+        };
+        energy && self.name == other.name
+            && self.structures == other.structures
+            && self.rules == other.rules
+    }
+}
+
+impl From<&Macrostate> for MacrostateDefinition {
+    fn from(macrostate: &Macrostate) -> Self {
+        let mut structures: Vec<String> = macrostate.ensemble().keys()
+            .map(|s| s.to_string())
+            .collect();
+        structures.sort();
+        Self {
+            name: macrostate.name().to_string(),
+            energy: macrostate.ensemble_energy(),
+            structures,
+            rules: macrostate.rules().iter().map(|r| r.to_string()).collect(),
+        }
+    }
+}
+
+/// Everything needed to decide whether two timelines describe the same system.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TimelineMetadata {
+    pub sequence: String,
+    /// All macrostates of the registry, in registry order.
+    pub macrostates: Vec<MacrostateDefinition>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run: Option<RunMetadata>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SerializableTimeline {
+    #[serde(default = "legacy_version")]
+    version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metadata: Option<TimelineMetadata>,
     points: Vec<SerializableTimePoint>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SerializableTimePoint {
     time: f64,
-    ensemble: Vec<(String, usize)>, // (macrostate name, count)
+    ensemble: Vec<(String, f64)>, // (macrostate name, count)
     counter: usize,
+    /// Distinct observations (macrostate name, weight) with multiplicity.
+    #[serde(default)]
+    samples: Vec<(Vec<(String, f64)>, usize)>,
+    /// Confidence intervals (macrostate name, lower, upper) of observed
+    /// macrostates, output only.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    intervals: Vec<(String, f64, f64)>,
+}
+
+impl SerializableTimePoint {
+    /// Samples for files without samples: assume that every whole count is
+    /// an observation of a single macrostate (for bootstrapping). The
+    /// fractional remainders (from overlapping macrostates) are split evenly
+    /// between the remaining observations.
+    fn legacy_samples(&self) -> Vec<(Vec<(String, f64)>, usize)> {
+        let mut samples = Vec::new();
+        let mut fractions = Vec::new();
+        for (name, count) in &self.ensemble {
+            if *count >= 1.0 {
+                samples.push((vec![(name.clone(), 1.0)], count.floor() as usize));
+            }
+            if count.fract() > 0.0 {
+                fractions.push((name.clone(), count.fract()));
+            }
+        }
+        let rest: f64 = fractions.iter().map(|(_, f)| f).sum();
+        let n = rest.round() as usize;
+        if n > 0 {
+            let weights = fractions.into_iter()
+                .map(|(name, f)| (name, f / n as f64))
+                .collect();
+            samples.push((weights, n));
+        }
+        samples
+    }
+}
+
+impl SerializableTimeline {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> result::Result<Self, TimelineError> {
+        let data = fs::read_to_string(path)?;
+        let serial: SerializableTimeline = serde_json::from_str(&data)?;
+        if serial.version > TIMELINE_FORMAT_VERSION {
+            return Err(TimelineError::UnsupportedVersion(serial.version));
+        }
+        Ok(serial)
+    }
+
+    pub fn to_file<P: AsRef<Path>>(&self, path: P) -> result::Result<(), TimelineError> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn metadata(&self) -> Option<&TimelineMetadata> {
+        self.metadata.as_ref()
+    }
+
+    pub fn times(&self) -> Vec<f64> {
+        self.points.iter().map(|tp| tp.time).collect()
+    }
+
+    /// Number of observations at the first time point.
+    pub fn num_trajectories(&self) -> usize {
+        self.points.first().map_or(0, |tp| tp.counter)
+    }
+
+    /// Macrostate names: those of the metadata (in registry order),
+    /// followed by any other names that occur in the time points.
+    pub fn macrostate_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.metadata.iter()
+            .flat_map(|m| m.macrostates.iter().map(|d| d.name.clone()))
+            .collect();
+        for tp in &self.points {
+            for (name, _) in &tp.ensemble {
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+        }
+        names
+    }
+
+    /// All macrostate names that occur in the time points.
+    fn point_names(&self) -> BTreeSet<String> {
+        self.points.iter()
+            .flat_map(|tp| tp.ensemble.iter().map(|(name, _)| name.clone()))
+            .collect()
+    }
+
+    /// Combine the observations of another timeline (by macrostate name).
+    /// Both timelines must use the same time grid, and the metadata (if
+    /// present in both) must describe compatible runs of the same system.
+    pub fn merge(&mut self, other: SerializableTimeline) -> result::Result<(), TimelineError> {
+        if self.points.len() != other.points.len() {
+            return Err(TimelineError::TimepointCountMismatch {
+                found: other.points.len(),
+                expected: self.points.len(),
+            });
+        }
+        for (tp, other_tp) in self.points.iter().zip(&other.points) {
+            if (tp.time - other_tp.time).abs() >= 1e-9 {
+                return Err(TimelineError::TimeMismatch {
+                    file_time: other_tp.time,
+                    expected_time: tp.time,
+                });
+            }
+        }
+
+        let (names, other_names) = (self.point_names(), other.point_names());
+        match (&mut self.metadata, other.metadata) {
+            (Some(meta), Some(other_meta)) => {
+                if meta.sequence != other_meta.sequence {
+                    return Err(TimelineError::Incompatible(format!(
+                        "sequence differs: {} vs {}", meta.sequence, other_meta.sequence)));
+                }
+                for def in other_meta.macrostates {
+                    match meta.macrostates.iter().find(|d| d.name == def.name) {
+                        Some(d) if !d.is_equivalent(&def) => return Err(TimelineError::Incompatible(
+                            format!("macrostate '{}' differs", def.name))),
+                        Some(_) => (),
+                        None => meta.macrostates.push(def),
+                    }
+                }
+                match (&mut meta.run, other_meta.run) {
+                    (Some(run), Some(other_run)) => {
+                        run.check_compatible(&other_run).map_err(TimelineError::Incompatible)?;
+                        run.seeds.extend(other_run.seeds);
+                    }
+                    (run @ None, other_run) => *run = other_run,
+                    (Some(_), None) => (),
+                }
+            }
+            (Some(meta), None) => check_legacy_names(meta, &other_names)?,
+            (meta @ None, Some(other_meta)) => {
+                check_legacy_names(&other_meta, &names)?;
+                *meta = Some(other_meta);
+            }
+            (None, None) => {
+                // Without metadata, only the macrostate names identify the system.
+                if let Some(name) = names.symmetric_difference(&other_names).next() {
+                    return Err(TimelineError::Incompatible(format!(
+                        "macrostate '{}' occurs in only one of the files without metadata", name)));
+                }
+            }
+        }
+
+        for (tp, mut other_tp) in self.points.iter_mut().zip(other.points) {
+            // Legacy files have no samples, which must not get lost when
+            // merged with files that do.
+            if tp.samples.is_empty() {
+                tp.samples = tp.legacy_samples();
+            }
+            if other_tp.samples.is_empty() {
+                other_tp.samples = other_tp.legacy_samples();
+            }
+            for (name, count) in other_tp.ensemble {
+                match tp.ensemble.iter_mut().find(|(n, _)| *n == name) {
+                    Some((_, c)) => *c += count,
+                    None => tp.ensemble.push((name, count)),
+                }
+            }
+            for (weights, n) in other_tp.samples {
+                match tp.samples.iter_mut().find(|(w, _)| *w == weights) {
+                    Some((_, m)) => *m += n,
+                    None => tp.samples.push((weights, n)),
+                }
+            }
+            tp.counter += other_tp.counter;
+            tp.intervals.clear();
+        }
+        self.version = TIMELINE_FORMAT_VERSION;
+        Ok(())
+    }
+
+    /// Replace the confidence intervals by Wilson score intervals.
+    pub fn update_intervals(&mut self, level: f64) {
+        for tp in self.points.iter_mut() {
+            tp.intervals = tp.ensemble.iter()
+                .map(|(name, count)| {
+                    let (lower, upper) = wilson_interval(*count, tp.counter, level);
+                    (name.clone(), lower, upper)
+                })
+                .collect();
+        }
+    }
+
+    /// Occupancies (and confidence bounds, if present) per macrostate name.
+    pub fn occupancy_series(&self) -> Vec<OccupancySeries> {
+        self.macrostate_names().into_iter().map(|name| {
+            let energy = self.metadata.iter()
+                .flat_map(|m| m.macrostates.iter())
+                .find(|d| d.name == name)
+                .and_then(|d| d.energy)
+                .unwrap_or(0.0);
+            let count = |tp: &SerializableTimePoint| tp.ensemble.iter()
+                .find(|(n, _)| *n == name)
+                .map_or(0.0, |(_, c)| *c);
+            let points = self.points.iter()
+                .map(|tp| (tp.time, if tp.counter > 0 { count(tp) / tp.counter as f64 } else { 0.0 }))
+                .collect();
+            let bounds = if self.points.iter().all(|tp| tp.intervals.is_empty()) {
+                Vec::new()
+            } else {
+                self.points.iter().map(|tp| {
+                    let (lo, hi) = tp.intervals.iter()
+                        .find(|(n, _, _)| *n == name)
+                        .map_or((0.0, 0.0), |(_, lo, hi)| (*lo, *hi));
+                    (tp.time, lo, hi)
+                }).collect()
+            };
+            OccupancySeries { name, energy, points, bounds }
+        }).collect()
+    }
+}
+
+/// A timeline without metadata can only be merged with one that defines
+/// every macrostate it observed.
+fn check_legacy_names(meta: &TimelineMetadata, names: &BTreeSet<String>
+) -> result::Result<(), TimelineError> {
+    match names.iter().find(|n| !meta.macrostates.iter().any(|d| d.name == **n)) {
+        Some(name) => Err(TimelineError::Incompatible(format!(
+            "macrostate '{}' of a file without metadata is not defined", name))),
+        None => Ok(()),
+    }
+}
+
+/// Merge timeline files (e.g. of runs on different machines), see
+/// [`SerializableTimeline::merge`].
+pub fn merge_timeline_files<P: AsRef<Path>>(paths: &[P]) -> result::Result<SerializableTimeline, TimelineError> {
+    let mut files = paths.iter();
+    let first = files.next().ok_or(TimelineError::NoTimelines)?;
+    let mut merged = SerializableTimeline::from_file(first)?;
+    for path in files {
+        merged.merge(SerializableTimeline::from_file(path)?)?;
+    }
+    Ok(merged)
+}
+
+fn quote(field: &str, delimiter: char) -> String {
+    if field.contains(delimiter) || field.contains('"') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
 }
 
 impl<'a, E: EnergyModel> Timeline<'a, E> {
+    /// The sequence and macrostates of the registry, and the run parameters.
+    pub fn metadata(&self) -> TimelineMetadata {
+        TimelineMetadata {
+            sequence: self.registry.sequence().to_string(),
+            macrostates: self.registry.macrostates().iter()
+                .map(MacrostateDefinition::from)
+                .collect(),
+            run: self.run.clone(),
+        }
+    }
+
+    /// The time points only (e.g. for checkpoints), see also
+    /// [`to_serializable_with_metadata`](Self::to_serializable_with_metadata).
     pub fn to_serializable(&self) -> SerializableTimeline {
+        self.serialize(false)
+    }
+
+    /// A self-describing timeline (see [`TimelineMetadata`]), including
+    /// confidence intervals.
+    pub fn to_serializable_with_metadata(&self) -> SerializableTimeline {
+        self.serialize(true)
+    }
+
+    fn serialize(&self, with_metadata: bool) -> SerializableTimeline {
+        let name = |id: usize| self.registry.macrostates()[id].name().to_string();
+        let intervals = if with_metadata { self.confidence_intervals() } else { None };
         SerializableTimeline {
-            points: self.points.iter().map(|tp| {
+            version: TIMELINE_FORMAT_VERSION,
+            metadata: with_metadata.then(|| self.metadata()),
+            points: self.points.iter().enumerate().map(|(t_idx, tp)| {
                 let ensemble = tp.ensemble.iter()
-                    .map(|(id, count)| {
-                        let name = self.registry.macrostates()[*id].name().to_string();
-                        (name, *count)
+                    .map(|(id, count)| (name(*id), *count))
+                    .collect();
+                let samples = tp.samples().iter()
+                    .map(|(weights, n)| {
+                        (weights.iter().map(|&(id, w)| (name(id), w)).collect(), *n)
                     })
                     .collect();
+                let intervals = intervals.as_ref()
+                    .map(|iv| tp.iter()
+                        .map(|(id, _)| {
+                            let (lower, upper) = iv[t_idx][&id];
+                            (name(id), lower, upper)
+                        })
+                        .collect())
+                    .unwrap_or_default();
                 SerializableTimePoint {
                     time: tp.time,
                     ensemble,
                     counter: tp.counter,
+                    samples,
+                    intervals,
                 }
             }).collect()
         }
     }
 
-    /// Load a timeline from a JSON file, checking against the provided registry
-    pub fn from_file<P: AsRef<std::path::Path>>(
+    /// Write the timeline with metadata as JSON.
+    pub fn to_file<P: AsRef<Path>>(&self, path: P) -> result::Result<(), TimelineError> {
+        let json = serde_json::to_string_pretty(&self.to_serializable_with_metadata())?;
+        fs::write(path, json)?;
+        Ok(())
+    }
+
+    /// Write the occupancies as a table (e.g. CSV or TSV, depending on the
+    /// delimiter), one row per time point and macrostate.
+    pub fn write_table<W: Write>(&self, writer: &mut W, delimiter: char) -> io::Result<()> {
+        let intervals = self.confidence_intervals();
+        let d = delimiter;
+        write!(writer, "time{d}id{d}macrostate{d}count{d}occupancy")?;
+        if intervals.is_some() {
+            write!(writer, "{d}ci_lower{d}ci_upper")?;
+        }
+        writeln!(writer)?;
+        for (t_idx, tp) in self.iter() {
+            for (m_idx, ms) in self.registry.iter() {
+                write!(writer, "{}{d}{}{d}{}{d}{}{d}{}",
+                    tp.time, m_idx, quote(ms.name(), d), tp.count(m_idx), tp.occupancy(m_idx))?;
+                if let Some(intervals) = &intervals {
+                    let (lower, upper) = intervals[t_idx][&m_idx];
+                    write!(writer, "{d}{}{d}{}", lower, upper)?;
+                }
+                writeln!(writer)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Load a timeline from a JSON file, checking against the provided
+    /// registry and (if given) the parameters of the current run.
+    pub fn from_file<P: AsRef<Path>>(
         path: P,
         times: &[f64],
         registry: Arc<MacrostateRegistry<'a, E>>,
+        run: Option<&RunMetadata>,
     ) -> result::Result<Self, TimelineError> {
         let data = fs::read_to_string(path)?;
         let serial: SerializableTimeline = serde_json::from_str(&data)?;
+        if let (Some(run), Some(file_run)) = (run, serial.metadata.as_ref().and_then(|m| m.run.as_ref())) {
+            run.check_compatible(file_run).map_err(TimelineError::Incompatible)?;
+        }
+        Self::from_serializable(serial, times, registry)
+    }
+
+    /// Rebuild a timeline, checking against the provided registry
+    pub fn from_serializable(
+        serial: SerializableTimeline,
+        times: &[f64],
+        registry: Arc<MacrostateRegistry<'a, E>>,
+    ) -> result::Result<Self, TimelineError> {
+        if serial.version > TIMELINE_FORMAT_VERSION {
+            return Err(TimelineError::UnsupportedVersion(serial.version));
+        }
+        let mut run = None;
+        if let Some(metadata) = serial.metadata {
+            let sequence = registry.sequence().to_string();
+            if metadata.sequence != sequence {
+                return Err(TimelineError::Incompatible(format!(
+                    "sequence differs: {} vs {}", metadata.sequence, sequence)));
+            }
+            let macrostates: Vec<MacrostateDefinition> = registry.macrostates().iter()
+                .map(MacrostateDefinition::from)
+                .collect();
+            if metadata.macrostates != macrostates {
+                return Err(TimelineError::Incompatible("macrostate definitions differ".to_string()));
+            }
+            run = metadata.run;
+        }
 
         // Sanity check: number of timepoints must match
         if serial.points.len() != times.len() {
@@ -66,18 +530,196 @@ impl<'a, E: EnergyModel> Timeline<'a, E> {
                 });
             }
 
-            for (name, count) in serial_tp.ensemble {
-                // Look up macrostate by name in registry
-                if let Some((idx, _m)) = registry.iter().find(|(_, m)| m.name() == name) {
-                    *tp.ensemble.entry(idx).or_insert(0) += count;
-                    tp.counter += count;
-                } else {
-                    return Err(TimelineError::MacrostateNotFound(name));
-                }
+            // Look up macrostate by name in registry
+            let lookup = |name: String| registry.iter()
+                .find(|(_, m)| m.name() == name)
+                .map(|(idx, _)| idx)
+                .ok_or(TimelineError::MacrostateNotFound(name));
+
+            let samples = if serial_tp.samples.is_empty() {
+                serial_tp.legacy_samples()
+            } else {
+                serial_tp.samples
+            };
+            for (weights, n) in samples {
+                let weights = weights.into_iter()
+                    .map(|(name, w)| Ok((lookup(name)?, w)))
+                    .collect::<result::Result<Vec<_>, TimelineError>>()?;
+                tp.add_samples(&weights, n);
             }
+            tp.counter = serial_tp.counter;
         }
+        timeline.run = run;
         Ok(timeline)
     }
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ff_energy::NucleotideVec;
+    use ff_energy::ViennaRNA;
+    use ff_structure::DotBracketVec;
+
+    #[test]
+    fn test_timeline_metadata_and_table() {
+        let model = ViennaRNA::default();
+        let sequence = NucleotideVec::try_from("GGGGAAAACCCC").unwrap();
+        let mut registry = MacrostateRegistry::from((&sequence, &model));
+        registry.insert_from_reader(">mfe\nGGGGAAAACCCC\n((((....))))\n".as_bytes(), "test").unwrap();
+        let registry = Arc::new(registry);
+
+        let run = RunMetadata {
+            energy_parameters: None,
+            temperature: 37.0,
+            temperature_schedule: None,
+            k0: 1e6,
+            move_set: MoveSet::default(),
+            seeds: vec![1],
+            software_version: "test".to_string(),
+        };
+        let times = [0.0, 1.0];
+        let mut timeline = Timeline::new(&times, Arc::clone(&registry))
+            .with_run_metadata(run.clone());
+        let mfe = DotBracketVec::try_from("((((....))))").unwrap();
+        timeline.assign_structure(0, &DotBracketVec::try_from("............").unwrap());
+        timeline.assign_structure(1, &mfe);
+
+        let serial = timeline.to_serializable_with_metadata();
+        let json = serde_json::to_string(&serial).unwrap();
+        assert!(json.contains("\"version\":2") && json.contains("((((....))))"));
+        let serial: SerializableTimeline = serde_json::from_str(&json).unwrap();
+        let loaded = Timeline::from_serializable(serial, &times, Arc::clone(&registry)).unwrap();
+        assert_eq!(loaded.run, Some(run.clone()));
+        assert_eq!(loaded.point(1).occupancy(1), 1.0);
+
+        // Merging runs with the same seed is refused.
+        let mut merged = Timeline::new(&times, Arc::clone(&registry)).with_run_metadata(run.clone());
+        assert!(matches!(merged.merge(loaded), Err(TimelineError::Incompatible(_))));
+        let fewer = Timeline::new(&times[..1], Arc::clone(&registry));
+        assert!(matches!(merged.merge(fewer), Err(TimelineError::TimepointCountMismatch { .. })));
+
+        // Same seed or a different sequence: refuse to mix.
+        let path = std::env::temp_dir().join("ff_kinetics_timeline_io_test.json");
+        timeline.to_file(&path).unwrap();
+        assert!(matches!(Timeline::from_file(&path, &times, Arc::clone(&registry), Some(&run)),
+            Err(TimelineError::Incompatible(_))));
+        let other_run = RunMetadata { seeds: vec![2], ..run.clone() };
+        assert!(Timeline::from_file(&path, &times, Arc::clone(&registry), Some(&other_run)).is_ok());
+        let other_seq = NucleotideVec::try_from("GGGGAAAACCCA").unwrap();
+        let other = Arc::new(MacrostateRegistry::from((&other_seq, &model)));
+        assert!(matches!(Timeline::from_file(&path, &times, other, None),
+            Err(TimelineError::Incompatible(_))));
+        std::fs::remove_file(&path).unwrap();
+
+        // Files without metadata (version 1) are accepted.
+        let legacy = r#"{"points": [
+            {"time": 0.0, "ensemble": [["Unassigned", 2.0]], "counter": 2},
+            {"time": 1.0, "ensemble": [["mfe", 2.0]], "counter": 2}]}"#;
+        let serial: SerializableTimeline = serde_json::from_str(legacy).unwrap();
+        let loaded = Timeline::from_serializable(serial, &times, Arc::clone(&registry)).unwrap();
+        assert!(loaded.run.is_none());
+        assert_eq!(loaded.point(1).samples(), [(vec![(1, 1.0)], 2)]);
+
+        // Fractional counts of overlapping macrostates are kept.
+        let legacy = r#"{"points": [
+            {"time": 0.0, "ensemble": [["Unassigned", 2.0]], "counter": 2},
+            {"time": 1.0, "ensemble": [["Unassigned", 1.5], ["mfe", 0.5]], "counter": 2}]}"#;
+        let serial: SerializableTimeline = serde_json::from_str(legacy).unwrap();
+        let loaded = Timeline::from_serializable(serial, &times, Arc::clone(&registry)).unwrap();
+        assert_eq!(loaded.point(1).count(0), 1.5);
+        assert_eq!(loaded.point(1).count(1), 0.5);
+        assert_eq!(loaded.point(1).samples(), [(vec![(0, 1.0)], 1), (vec![(0, 0.5), (1, 0.5)], 1)]);
+
+        let mut table = Vec::new();
+        timeline.write_table(&mut table, '\t').unwrap();
+        let table = String::from_utf8(table).unwrap();
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 1 + times.len() * registry.len());
+        assert_eq!(lines[0], "time\tid\tmacrostate\tcount\toccupancy\tci_lower\tci_upper");
+        assert!(lines[4].starts_with("1\t1\tmfe\t1\t1\t"));
+    }
+
+    #[test]
+    fn test_merge_serialized_timelines() {
+        let model = ViennaRNA::default();
+        let sequence = NucleotideVec::try_from("GGGGAAAACCCC").unwrap();
+        let registry = Arc::new(MacrostateRegistry::from((&sequence, &model)));
+        let run = |seed| RunMetadata {
+            energy_parameters: None,
+            temperature: 37.0,
+            temperature_schedule: None,
+            k0: 1e6,
+            move_set: MoveSet::default(),
+            seeds: vec![seed],
+            software_version: "test".to_string(),
+        };
+        let times = [0.0, 1.0];
+        let open = DotBracketVec::try_from("............").unwrap();
+        let mut a = Timeline::new(&times, Arc::clone(&registry)).with_run_metadata(run(1));
+        a.assign_structure(0, &open);
+        let mut b = Timeline::new(&times, Arc::clone(&registry)).with_run_metadata(run(2));
+        b.assign_structure(0, &open);
+        b.assign_structure(0, &open);
+
+        let mut merged = a.to_serializable_with_metadata();
+        merged.merge(b.to_serializable_with_metadata()).unwrap();
+        assert_eq!(merged.num_trajectories(), 3);
+        assert_eq!(merged.metadata().unwrap().run.as_ref().unwrap().seeds, vec![1, 2]);
+        merged.update_intervals(0.95);
+        let series = merged.occupancy_series();
+        assert_eq!(series[0].name, "Unassigned");
+        assert_eq!(series[0].points[0], (0.0, 1.0));
+        assert_eq!(series[0].bounds.len(), times.len());
+
+        let loaded = Timeline::from_serializable(merged.clone(), &times, Arc::clone(&registry)).unwrap();
+        assert_eq!(loaded.point(0).counter, 3);
+        assert_eq!(loaded.point(0).samples(), [(vec![(0, 1.0)], 3)]);
+
+        // Same seed, different time grid or different sequence.
+        assert!(merged.clone().merge(a.to_serializable_with_metadata()).is_err());
+        let c = Timeline::new(&[0.0, 2.0], Arc::clone(&registry)).with_run_metadata(run(3));
+        assert!(matches!(merged.clone().merge(c.to_serializable_with_metadata()),
+            Err(TimelineError::TimeMismatch { .. })));
+        let other_seq = NucleotideVec::try_from("GGGGAAAACCCA").unwrap();
+        let other = Arc::new(MacrostateRegistry::from((&other_seq, &model)));
+        let d = Timeline::new(&times, other).with_run_metadata(run(4));
+        assert!(matches!(merged.clone().merge(d.to_serializable_with_metadata()),
+            Err(TimelineError::Incompatible(_))));
+
+        // Files without metadata must not observe undefined macrostates.
+        let legacy = |name: &str| serde_json::from_str::<SerializableTimeline>(&format!(r#"{{"points": [
+            {{"time": 0.0, "ensemble": [["{name}", 1.0]], "counter": 1}},
+            {{"time": 1.0, "ensemble": [["{name}", 1.0]], "counter": 1}}]}}"#)).unwrap();
+        let mut mixed = merged.clone();
+        mixed.merge(legacy("Unassigned")).unwrap();
+        let loaded = Timeline::from_serializable(mixed, &times, Arc::clone(&registry)).unwrap();
+        assert_eq!(loaded.point(0).counter, 4);
+        assert_eq!(loaded.point(0).samples(), [(vec![(0, 1.0)], 4)]);
+        assert!((loaded.point(0).occupancy(0) - 1.0).abs() < 1e-12);
+        assert!((loaded.point(1).occupancy(0) - 1.0).abs() < 1e-12);
+        assert!(matches!(merged.clone().merge(legacy("other")),
+            Err(TimelineError::Incompatible(_))));
+        let mut both = legacy("Unassigned");
+        assert!(both.merge(legacy("Unassigned")).is_ok());
+        assert!(matches!(both.merge(legacy("other")), Err(TimelineError::Incompatible(_))));
+        let mut e = legacy("other");
+        assert!(e.merge(merged.clone()).is_err());
+
+        // Ensemble energies are compared with a tolerance.
+        let def = MacrostateDefinition {
+            name: "mfe".to_string(),
+            energy: Some(-4.2),
+            structures: vec!["((((....))))".to_string()],
+            rules: Vec::new(),
+        };
+        let close = MacrostateDefinition { energy: Some(-4.2 + 1e-12), ..def.clone() };
+        let far = MacrostateDefinition { energy: Some(-4.1), ..def.clone() };
+        assert!(def.is_equivalent(&close) && !def.is_equivalent(&far));
+
+        let none: [&str; 0] = [];
+        assert!(matches!(merge_timeline_files(&none), Err(TimelineError::NoTimelines)));
+    }
+}
@@ -4,21 +4,59 @@ use plotters::style::Palette99;
 
 use crate::timeline::Timeline;
 
+/// The occupancy of one macrostate over time.
+#[derive(Debug, Clone)]
+pub struct OccupancySeries {
+    pub name: String,
+    /// Ensemble free energy (shown in the legend).
+    pub energy: f64,
+    /// (time, occupancy)
+    pub points: Vec<(f64, f64)>,
+    /// (time, lower, upper) confidence bounds, empty if not available.
+    pub bounds: Vec<(f64, f64, f64)>,
+}
+
 pub fn plot_occupancy_over_time<'a, E: EnergyModel>(
     timeline: &Timeline<'a, E>, 
     filename: &str,
     t_lin: f64,
     t_log: f64,
 ) {
-    assert!(t_lin > 0.0 && t_log > t_lin, "Require 0 < t_lin < t_log");
-
+    let intervals = timeline.confidence_intervals();
+    let series: Vec<OccupancySeries> = timeline.registry.iter().map(|(id, ms)| {
+        OccupancySeries {
+            name: ms.name().to_string(),
+            energy: ms.ensemble_energy().unwrap_or(0.0),
+            points: timeline.points.iter().map(|tp| (tp.time, tp.occupancy(id))).collect(),
+            bounds: intervals.iter().flat_map(|iv| {
+                timeline.points.iter().zip(iv).map(move |(tp, iv)| {
+                    let (lo, hi) = iv.get(&id).copied().unwrap_or((0.0, 0.0));
+                    (tp.time, lo, hi)
+                })
+            }).collect(),
+        }
+    }).collect();
     let title = format!("ff-simulate ({} simulations)", timeline.points[0].counter);
+    plot_occupancy_series(&series, &title, filename, t_lin, t_log);
+}
+
+/// Plot occupancies on a linear time scale up to `t_lin`, followed by a
+/// logarithmic time scale up to `t_log`. Only macrostates that reach an
+/// occupancy of at least 0.1 are shown.
+pub fn plot_occupancy_series(
+    series: &[OccupancySeries],
+    title: &str,
+    filename: &str,
+    t_lin: f64,
+    t_log: f64,
+) {
+    assert!(t_lin > 0.0 && t_log > t_lin, "Require 0 < t_lin < t_log");
 
     // Image size; tweak as you like
     //let root = BitMapBackend::new(filename, (1024, 480)).into_drawing_area();
     let root = SVGBackend::new(filename, (1024, 480)).into_drawing_area();
     root.fill(&WHITE).unwrap();
-    root.titled(&title, ("sans-serif", 28)).unwrap();
+    root.titled(title, ("sans-serif", 28)).unwrap();
     root.draw_text(
         "time",
         &("sans-serif", 18).into_font().into_text_style(&root),
@@ -87,34 +125,28 @@ pub fn plot_occupancy_over_time<'a, E: EnergyModel>(
     ))).unwrap();
 
 
-    // Build data per structure
-    let mut trajectories: Vec<(usize, Vec<(f64, f64)>)> = Vec::new();
+    // Confidence intervals are drawn as shaded bands.
+    let band = |bounds: &[(f64, f64, f64)], keep: &dyn Fn(f64) -> bool| -> Vec<(f64, f64)> {
+        let bounds: Vec<_> = bounds.iter().filter(|(t, _, _)| keep(*t)).collect();
+        bounds.iter().map(|&&(t, _, hi)| (t, hi))
+            .chain(bounds.iter().rev().map(|&&(t, lo, _)| (t, lo)))
+            .collect()
+    };
 
-    for (id, _) in timeline.registry.iter() {
-        let mut series = Vec::new();
-        for tp in &timeline.points {
-            let count = tp.ensemble.get(&id).copied().unwrap_or(0);
-            let occu = if tp.counter > 0 {
-                count as f64 / tp.counter as f64
-            } else {
-                0.0
-            };
-            series.push((tp.time, occu));
-        }
-        if series.iter().any(|(_, occu)| *occu >= 0.1) { // threshold filter
-            trajectories.push((id, series));
-        }
-    }
-
-    // Sort by ID to have consistent colors
-    trajectories.sort_by_key(|(id, _)| *id);
+    // Series are in registry order, which keeps colors consistent
+    let shown = series.iter()
+        .filter(|s| s.points.iter().any(|(_, occu)| *occu >= 0.1)); // threshold filter
 
-    // Find global Y max for normalization
-    for (i, (id, series)) in trajectories.iter().enumerate() {
+    for (i, s) in shown.enumerate() {
         let color = Palette99::pick(i).mix(0.9); // pick a distinct color
+        let series = &s.points;
 
-        let name = timeline.registry.macrostates()[*id].name();
-        let energy = timeline.registry.macrostates()[*id].ensemble_energy().unwrap_or(0.0);
+        chart_left.draw_series(std::iter::once(
+            Polygon::new(band(&s.bounds, &|t| t <= t_lin), color.mix(0.2).filled())
+        )).unwrap();
+        chart_right.draw_series(std::iter::once(
+            Polygon::new(band(&s.bounds, &|t| t >= t_lin), color.mix(0.2).filled())
+        )).unwrap();
 
         chart_left.draw_series(LineSeries::new(
                 series.iter().cloned().filter(|(t, _)| *t <= t_lin),
@@ -125,7 +157,7 @@ pub fn plot_occupancy_over_time<'a, E: EnergyModel>(
             series.iter().cloned().filter(|(t, _)| *t >= t_lin),
             color.stroke_width(2),
         )).unwrap()
-            .label(format!("{:20} {:>6.2}", name.trim(), energy))   // <-- label for legend
+            .label(format!("{:20} {:>6.2}", s.name.trim(), s.energy))   // <-- label for legend
             .legend(move |(x, y)| {
                 PathElement::new(vec![(x, y), (x + 20, y)], color.stroke_width(2))
             });
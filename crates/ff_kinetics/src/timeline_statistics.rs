@@ -0,0 +1,208 @@
+//! Confidence intervals for timeline occupancies.
+//!
+//! The occupancy of a macrostate at a time point is the mean over all
+//! observations (one per trajectory). Intervals are either binomial
+//! (Wilson score) or percentile intervals from bootstrap resampling of the
+//! trajectories. Their width shrinks with the square root of the number of
+//! simulations, which helps to decide whether `--num-sims` was large enough.
+
+use nohash_hasher::IntMap;
+use rand::Rng;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+use ff_energy::EnergyModel;
+use crate::timeline::Timeline;
+use crate::timeline::Timepoint;
+
+/// How confidence intervals of occupancies are computed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfidenceMethod {
+    /// No intervals (e.g. for exact occupancies).
+    None,
+    /// Binomial Wilson score interval.
+    Wilson { level: f64 },
+    /// Percentile interval of bootstrap resamples (reproducible for a given seed).
+    Bootstrap { level: f64, resamples: usize, seed: u64 },
+}
+
+impl Default for ConfidenceMethod {
+    fn default() -> Self {
+        ConfidenceMethod::Wilson { level: 0.95 }
+    }
+}
+
+/// The quantile function of the standard normal distribution
+/// (Acklam's rational approximation, relative error < 1.2e-9).
+pub fn normal_quantile(p: f64) -> f64 {
+    assert!(p > 0.0 && p < 1.0, "Quantile requires 0 < p < 1");
+    const A: [f64; 6] = [-3.969683028665376e+01, 2.209460984245205e+02, -2.759285104469687e+02,
+        1.38357751867269e+02, -3.066479806614716e+01, 2.506628277459239e+00];
+    const B: [f64; 5] = [-5.447609879822406e+01, 1.615858368580409e+02, -1.556989798598866e+02,
+        6.680131188771972e+01, -1.328068155288572e+01];
+    const C: [f64; 6] = [-7.784894002430293e-03, -3.223964580411365e-01, -2.400758277161838e+00,
+        -2.549732539343734e+00, 4.374664141464968e+00, 2.938163982698783e+00];
+    const D: [f64; 4] = [7.784695709041462e-03, 3.224671290700398e-01, 2.445134137142996e+00,
+        3.754408661907416e+00];
+    let tail = |q: f64| {
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    };
+    if p < 0.02425 {
+        tail((-2.0 * p.ln()).sqrt())
+    } else if p > 1.0 - 0.02425 {
+        -tail((-2.0 * (1.0 - p).ln()).sqrt())
+    } else {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+            / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    }
+}
+
+/// Wilson score interval for `successes` out of `n` observations
+/// (successes may be fractional).
+pub fn wilson_interval(successes: f64, n: usize, level: f64) -> (f64, f64) {
+    if n == 0 {
+        return (0.0, 1.0);
+    }
+    let z = normal_quantile(0.5 + level / 2.0);
+    let n = n as f64;
+    let p = (successes / n).clamp(0.0, 1.0);
+    let z2 = z * z / n;
+    let center = (p + z2 / 2.0) / (1.0 + z2);
+    let half = z / (1.0 + z2) * (p * (1.0 - p) / n + z2 / (4.0 * n)).sqrt();
+    ((center - half).max(0.0), (center + half).min(1.0))
+}
+
+/// Percentile bootstrap intervals at a time point for the macrostates
+/// `0..num_macrostates`. Every resample draws `counter` observations with
+/// replacement.
+pub fn bootstrap_intervals<R: Rng>(
+    tp: &Timepoint,
+    num_macrostates: usize,
+    level: f64,
+    resamples: usize,
+    rng: &mut R,
+) -> IntMap<usize, (f64, f64)> {
+    let n: usize = tp.samples().iter().map(|(_, m)| m).sum();
+    if n == 0 || resamples == 0 {
+        return (0..num_macrostates).map(|idx| (idx, (0.0, 1.0))).collect();
+    }
+    let cumulative: Vec<usize> = tp.samples().iter()
+        .scan(0, |acc, (_, m)| { *acc += m; Some(*acc) })
+        .collect();
+
+    let mut values: IntMap<usize, Vec<f64>> = (0..num_macrostates)
+        .map(|idx| (idx, Vec::with_capacity(resamples)))
+        .collect();
+    let mut draws = vec![0usize; tp.samples().len()];
+    for _ in 0..resamples {
+        draws.iter_mut().for_each(|d| *d = 0);
+        for _ in 0..n {
+            let x = rng.random_range(0..n);
+            draws[cumulative.partition_point(|&c| c <= x)] += 1;
+        }
+        let mut occu: IntMap<usize, f64> = IntMap::default();
+        for ((weights, _), &d) in tp.samples().iter().zip(&draws) {
+            for &(idx, w) in weights {
+                *occu.entry(idx).or_insert(0.0) += w * d as f64 / n as f64;
+            }
+        }
+        for (idx, v) in values.iter_mut() {
+            v.push(occu.get(idx).copied().unwrap_or(0.0));
+        }
+    }
+
+    let alpha = (1.0 - level) / 2.0;
+    values.into_iter().map(|(idx, mut v)| {
+        v.sort_by(f64::total_cmp);
+        let at = |q: f64| v[((v.len() - 1) as f64 * q).round() as usize];
+        (idx, (at(alpha), at(1.0 - alpha)))
+    }).collect()
+}
+
+impl<'a, E: EnergyModel> Timeline<'a, E> {
+    /// Confidence intervals for every macrostate of the registry at each
+    /// time point, according to `self.confidence` (None if disabled).
+    pub fn confidence_intervals(&self) -> Option<Vec<IntMap<usize, (f64, f64)>>> {
+        let num_macrostates = self.registry.len();
+        match self.confidence {
+            ConfidenceMethod::None => None,
+            ConfidenceMethod::Wilson { level } => Some(self.points.iter().map(|tp| {
+                (0..num_macrostates)
+                    .map(|idx| (idx, wilson_interval(tp.count(idx), tp.counter, level)))
+                    .collect()
+            }).collect()),
+            ConfidenceMethod::Bootstrap { level, resamples, seed } => {
+                let mut rng = ChaCha8Rng::seed_from_u64(seed);
+                Some(self.points.iter()
+                    .map(|tp| bootstrap_intervals(tp, num_macrostates, level, resamples, &mut rng))
+                    .collect())
+            }
+        }
+    }
+
+    /// The widest confidence interval over all time points and macrostates.
+    /// Macrostates that were never observed are ignored.
+    pub fn max_interval_width(&self) -> Option<f64> {
+        self.confidence_intervals().map(|intervals| intervals.iter()
+            .zip(&self.points)
+            .flat_map(|(iv, tp)| tp.iter().map(|(idx, _)| iv[&idx].1 - iv[&idx].0))
+            .fold(0.0, f64::max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use ff_energy::NucleotideVec;
+    use ff_energy::ViennaRNA;
+    use crate::MacrostateRegistry;
+
+    #[test]
+    fn test_normal_quantile_and_wilson() {
+        assert!((normal_quantile(0.975) - 1.959964).abs() < 1e-6);
+        assert!((normal_quantile(0.005) + 2.575829).abs() < 1e-6);
+        assert!(normal_quantile(0.5).abs() < 1e-12);
+
+        let (lo, hi) = wilson_interval(50., 100, 0.95);
+        assert!((lo - 0.4038).abs() < 1e-4 && (hi - 0.5962).abs() < 1e-4);
+        let (lo, hi) = wilson_interval(0., 10, 0.95);
+        assert_eq!(lo, 0.0);
+        assert!(hi > 0.2 && hi < 0.35);
+        let (lo, hi) = wilson_interval(5., 10, 0.95);
+        let (lo2, hi2) = wilson_interval(500., 1000, 0.95);
+        assert!(hi2 - lo2 < hi - lo);
+    }
+
+    #[test]
+    fn test_timeline_intervals() {
+        let model = ViennaRNA::default();
+        let sequence = NucleotideVec::try_from("GGGAAAUCC").unwrap();
+        let registry = Arc::new(MacrostateRegistry::from((&sequence, &model)));
+        let mut timeline = Timeline::new(&[0.0, 1.0], registry);
+        for k in 0..200 {
+            timeline.points[0].add(0);
+            timeline.points[1].add_weighted(&[(0, if k % 4 == 0 { 1.0 } else { 0.5 })]);
+        }
+        assert_eq!(timeline.points[1].samples().len(), 2);
+
+        let wilson = timeline.confidence_intervals().unwrap();
+        let (lo, hi) = wilson[0][&0];
+        assert!(lo > 0.98 && hi == 1.0);
+
+        let timeline = timeline.with_confidence(
+            ConfidenceMethod::Bootstrap { level: 0.95, resamples: 500, seed: 7 });
+        let boot = timeline.confidence_intervals().unwrap();
+        assert_eq!(boot[0][&0], (1.0, 1.0));
+        let (lo, hi) = boot[1][&0];
+        let occu = timeline.point(1).occupancy(0);
+        assert!((occu - 0.625).abs() < 1e-12);
+        assert!(lo < occu && occu < hi && hi - lo < 0.1);
+        // Reproducible for a given seed.
+        assert_eq!(boot, timeline.confidence_intervals().unwrap());
+        assert!(timeline.to_string().contains("ci_lower"));
+    }
+}
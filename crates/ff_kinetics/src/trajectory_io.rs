@@ -0,0 +1,423 @@
+//! Recording and replay of single trajectories.
+//!
+//! A trajectory file stores the initial structure and then only the applied
+//! moves, each with the time of the move and the free energy of the
+//! resulting structure. Two encodings are supported:
+//!
+//!  - JSON-lines: a [`TrajectoryHeader`] object on the first line, followed
+//!    by one [`TrajectoryEvent`] object per line.
+//!  - Binary: the magic bytes `FFTR`, a version byte, the length-prefixed
+//!    JSON header, and then fixed-size little-endian records per event.
+//!
+//! A [`Trajectory`] reads either format and reconstructs structures at
+//! arbitrary times, or writes Kinfold-style text.
+
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+
+use ff_structure::NAIDX;
+use ff_structure::PairTable;
+use ff_structure::DotBracketVec;
+
+use crate::reaction::Move;
+use crate::reaction::ApplyMove;
+
+const MAGIC: &[u8; 4] = b"FFTR";
+const VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrajectoryFormat {
+    JsonLines,
+    Binary,
+}
+
+/// The initial state of a trajectory.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrajectoryHeader {
+    pub sequence: String,
+    pub structure: String,
+    pub energy: i32,
+}
+
+impl TrajectoryHeader {
+    /// Check that the structure is well-formed and matches the sequence.
+    pub fn validate(&self) -> io::Result<()> {
+        let structure = DotBracketVec::try_from(self.structure.as_str())
+            .map_err(|e| invalid_data(format!("Invalid header structure: {}", e)))?;
+        PairTable::try_from(&structure)
+            .map_err(|e| invalid_data(format!("Invalid header structure: {}", e)))?;
+        if structure.len() != self.sequence.len() {
+            return Err(invalid_data(format!(
+                "Header structure length ({}) does not match sequence length ({})",
+                structure.len(), self.sequence.len())));
+        }
+        Ok(())
+    }
+}
+
+/// A move applied at `time`, resulting in a structure with free energy `energy`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TrajectoryEvent {
+    pub time: f64,
+    pub energy: i32,
+    #[serde(rename = "move")]
+    pub mv: Move,
+}
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// The moves that turn structure `a` into structure `b`. A single move is
+/// returned whenever possible, otherwise all deletions followed by all
+/// additions.
+pub fn moves_between(a: &DotBracketVec, b: &DotBracketVec) -> Vec<Move> {
+    assert_eq!(a.len(), b.len(), "Structures must have equal length");
+    let pt_a = PairTable::try_from(a).expect("valid structure");
+    let pt_b = PairTable::try_from(b).expect("valid structure");
+    let pairs = |pt: &PairTable, other: &PairTable| -> Vec<(NAIDX, NAIDX)> {
+        pt.iter().enumerate()
+            .filter_map(|(i, &j)| j.filter(|&j| (j as usize) > i && other[i] != Some(j))
+                .map(|j| (i as NAIDX, j)))
+            .collect()
+    };
+    let removed = pairs(&pt_a, &pt_b);
+    let added = pairs(&pt_b, &pt_a);
+
+    let is_helix = |p: &[(NAIDX, NAIDX)]| p.windows(2)
+        .all(|w| w[1].0 == w[0].0 + 1 && w[1].1 + 1 == w[0].1);
+
+    match (removed.as_slice(), added.as_slice()) {
+        ([], []) => vec![],
+        ([], [(i, j)]) => vec![Move::Add { i: *i, j: *j }],
+        ([(i, j)], []) => vec![Move::Del { i: *i, j: *j }],
+        ([(i, j)], [(k, l)]) if i == k || j == l || i == l || j == k => {
+            vec![Move::Shift { i: *i, j: *j, k: *k, l: *l }]
+        }
+        ([], [(i, j), ..]) if is_helix(&added) => {
+            vec![Move::Zip { i: *i, j: *j, len: added.len() as NAIDX }]
+        }
+        ([(i, j), ..], []) if is_helix(&removed) => {
+            vec![Move::Unzip { i: *i, j: *j, len: removed.len() as NAIDX }]
+        }
+        _ => removed.iter().map(|&(i, j)| Move::Del { i, j })
+            .chain(added.iter().map(|&(i, j)| Move::Add { i, j }))
+            .collect(),
+    }
+}
+
+/// Records a trajectory by comparing consecutive structures.
+pub struct TrajectoryWriter<W: Write> {
+    writer: W,
+    format: TrajectoryFormat,
+    current: DotBracketVec,
+}
+
+impl<W: Write> TrajectoryWriter<W> {
+    /// Write the header and start recording.
+    pub fn new(mut writer: W, format: TrajectoryFormat, header: &TrajectoryHeader) -> io::Result<Self> {
+        let current = DotBracketVec::try_from(header.structure.as_str())
+            .map_err(|e| invalid_data(format!("{:?}", e)))?;
+        let json = serde_json::to_string(header)?;
+        match format {
+            TrajectoryFormat::JsonLines => writeln!(writer, "{}", json)?,
+            TrajectoryFormat::Binary => {
+                writer.write_all(MAGIC)?;
+                writer.write_all(&[VERSION])?;
+                writer.write_all(&(json.len() as u32).to_le_bytes())?;
+                writer.write_all(json.as_bytes())?;
+            }
+        }
+        Ok(Self { writer, format, current })
+    }
+
+    /// Record the structure entered at `time`. Nothing is written if the
+    /// structure did not change.
+    pub fn record(&mut self, time: f64, structure: &DotBracketVec, energy: i32) -> io::Result<()> {
+        for mv in moves_between(&self.current, structure) {
+            self.write_event(&TrajectoryEvent { time, energy, mv })?;
+        }
+        self.current.clone_from(structure);
+        Ok(())
+    }
+
+    pub fn write_event(&mut self, event: &TrajectoryEvent) -> io::Result<()> {
+        self.current.apply_move(event.mv);
+        match self.format {
+            TrajectoryFormat::JsonLines => {
+                writeln!(self.writer, "{}", serde_json::to_string(event)?)
+            }
+            TrajectoryFormat::Binary => {
+                let (tag, idx): (u8, Vec<NAIDX>) = match event.mv {
+                    Move::Add { i, j } => (0, vec![i, j]),
+                    Move::Del { i, j } => (1, vec![i, j]),
+                    Move::Shift { i, j, k, l } => (2, vec![i, j, k, l]),
+                    Move::Zip { i, j, len } => (3, vec![i, j, len]),
+                    Move::Unzip { i, j, len } => (4, vec![i, j, len]),
+                };
+                self.writer.write_all(&[tag])?;
+                self.writer.write_all(&event.time.to_le_bytes())?;
+                self.writer.write_all(&event.energy.to_le_bytes())?;
+                for x in idx {
+                    self.writer.write_all(&x.to_le_bytes())?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Flush and return the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// A recorded trajectory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trajectory {
+    pub header: TrajectoryHeader,
+    pub events: Vec<TrajectoryEvent>,
+}
+
+impl Trajectory {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    /// Read a trajectory in either format (detected from the first bytes).
+    pub fn read<R: BufRead>(mut reader: R) -> io::Result<Self> {
+        if reader.fill_buf()?.starts_with(MAGIC) {
+            Self::read_binary(reader)
+        } else {
+            Self::read_jsonl(reader)
+        }
+    }
+
+    fn read_jsonl<R: BufRead>(reader: R) -> io::Result<Self> {
+        let mut lines = reader.lines();
+        let header = lines.next()
+            .ok_or_else(|| invalid_data("Missing trajectory header"))??;
+        let header: TrajectoryHeader = serde_json::from_str(&header)?;
+        header.validate()?;
+        let mut events = Vec::new();
+        for line in lines {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            events.push(serde_json::from_str(&line)?);
+        }
+        Ok(Self { header, events })
+    }
+
+    fn read_binary<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0u8; 5];
+        reader.read_exact(&mut magic)?;
+        if magic[4] != VERSION {
+            return Err(invalid_data(format!("Unsupported trajectory version {}", magic[4])));
+        }
+        let mut len = [0u8; 4];
+        reader.read_exact(&mut len)?;
+        let mut json = vec![0u8; u32::from_le_bytes(len) as usize];
+        reader.read_exact(&mut json)?;
+        let header: TrajectoryHeader = serde_json::from_slice(&json)?;
+        header.validate()?;
+
+        let mut events = Vec::new();
+        let mut tag = [0u8; 1];
+        loop {
+            match reader.read_exact(&mut tag) {
+                Ok(()) => (),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+            let mut time = [0u8; 8];
+            let mut energy = [0u8; 4];
+            reader.read_exact(&mut time)?;
+            reader.read_exact(&mut energy)?;
+            let mut idx = |n: usize| -> io::Result<Vec<NAIDX>> {
+                let mut buf = vec![0u8; 2 * n];
+                reader.read_exact(&mut buf)?;
+                Ok(buf.chunks(2).map(|c| NAIDX::from_le_bytes([c[0], c[1]])).collect())
+            };
+            let mv = match tag[0] {
+                0 => { let x = idx(2)?; Move::Add { i: x[0], j: x[1] } }
+                1 => { let x = idx(2)?; Move::Del { i: x[0], j: x[1] } }
+                2 => { let x = idx(4)?; Move::Shift { i: x[0], j: x[1], k: x[2], l: x[3] } }
+                3 => { let x = idx(3)?; Move::Zip { i: x[0], j: x[1], len: x[2] } }
+                4 => { let x = idx(3)?; Move::Unzip { i: x[0], j: x[1], len: x[2] } }
+                t => return Err(invalid_data(format!("Invalid move tag {}", t))),
+            };
+            events.push(TrajectoryEvent {
+                time: f64::from_le_bytes(time),
+                energy: i32::from_le_bytes(energy),
+                mv,
+            });
+        }
+        Ok(Self { header, events })
+    }
+
+    /// Write the trajectory in the given format.
+    pub fn write<W: Write>(&self, writer: W, format: TrajectoryFormat) -> io::Result<W> {
+        let mut tw = TrajectoryWriter::new(writer, format, &self.header)?;
+        for event in &self.events {
+            tw.write_event(event)?;
+        }
+        tw.finish()
+    }
+
+    pub fn initial_structure(&self) -> DotBracketVec {
+        DotBracketVec::try_from(self.header.structure.as_str())
+            .expect("Trajectory headers are validated when reading")
+    }
+
+    /// The structure at time `t` (moves at exactly `t` are applied).
+    pub fn structure_at(&self, t: f64) -> DotBracketVec {
+        let mut structure = self.initial_structure();
+        for event in self.events.iter().take_while(|e| e.time <= t) {
+            structure.apply_move(event.mv);
+        }
+        structure
+    }
+
+    /// Structures at each of the (ascending) times.
+    pub fn snapshots(&self, times: &[f64]) -> Vec<DotBracketVec> {
+        assert!(times.windows(2).all(|w| w[0] <= w[1]), "Times must be sorted");
+        let mut structure = self.initial_structure();
+        let mut events = self.events.iter().peekable();
+        times.iter().map(|&t| {
+            while let Some(e) = events.next_if(|e| e.time <= t) {
+                structure.apply_move(e.mv);
+            }
+            structure.clone()
+        }).collect()
+    }
+
+    /// All visited states as (time of entry, structure, energy). Events at
+    /// the same time (e.g. a decomposed move) are reported as one state.
+    pub fn states(&self) -> Vec<(f64, DotBracketVec, i32)> {
+        let mut structure = self.initial_structure();
+        let mut result = vec![(0., structure.clone(), self.header.energy)];
+        for (k, event) in self.events.iter().enumerate() {
+            structure.apply_move(event.mv);
+            if self.events.get(k + 1).is_none_or(|next| next.time != event.time) {
+                result.push((event.time, structure.clone(), event.energy));
+            }
+        }
+        result
+    }
+
+    /// Write Kinfold-style text: the sequence, followed by one line per
+    /// state with structure, energy (kcal/mol) and time of entry.
+    pub fn write_kinfold<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "{}", self.header.sequence)?;
+        for (time, structure, energy) in self.states() {
+            writeln!(writer, "{} {:6.2} {:14.8e}", structure, energy as f64 / 100., time)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn dbv(s: &str) -> DotBracketVec {
+        DotBracketVec::try_from(s).unwrap()
+    }
+
+    #[test]
+    fn test_moves_between() {
+        assert_eq!(moves_between(&dbv("........"), &dbv("(......)")),
+            vec![Move::Add { i: 0, j: 7 }]);
+        assert_eq!(moves_between(&dbv("(......)"), &dbv("(.....)."), ),
+            vec![Move::Shift { i: 0, j: 7, k: 0, l: 6 }]);
+        assert_eq!(moves_between(&dbv("........"), &dbv("((....))")),
+            vec![Move::Zip { i: 0, j: 7, len: 2 }]);
+        assert_eq!(moves_between(&dbv("((....))"), &dbv("........")),
+            vec![Move::Unzip { i: 0, j: 7, len: 2 }]);
+        assert_eq!(moves_between(&dbv("((....))"), &dbv(".(....).")).len(), 1);
+        assert_eq!(moves_between(&dbv("(.(..).)"), &dbv(".((..))."))
+            .len(), 2);
+    }
+
+    #[test]
+    fn test_trajectory_roundtrip() {
+        let header = TrajectoryHeader {
+            sequence: "GGGAAACCC".to_string(),
+            structure: ".........".to_string(),
+            energy: 0,
+        };
+        let path = [
+            (0.5, "(.......)", 300),
+            (1.0, "((.....))", 100),
+            (2.0, "(((...)))", -120),
+            (3.0, ".((...)).", -50),
+        ];
+
+        for format in [TrajectoryFormat::JsonLines, TrajectoryFormat::Binary] {
+            let mut writer = TrajectoryWriter::new(Vec::new(), format, &header).unwrap();
+            for (t, s, e) in path {
+                writer.record(t, &dbv(s), e).unwrap();
+            }
+            let bytes = writer.finish().unwrap();
+            let traj = Trajectory::read(Cursor::new(bytes)).unwrap();
+            assert_eq!(traj.header, header);
+            assert_eq!(traj.events.len(), 4);
+
+            assert_eq!(traj.structure_at(0.0), dbv("........."));
+            assert_eq!(traj.structure_at(1.5), dbv("((.....))"));
+            assert_eq!(traj.structure_at(10.), dbv(".((...))."));
+            assert_eq!(traj.snapshots(&[0.75, 2.0, 2.5]),
+                vec![dbv("(.......)"), dbv("(((...)))"), dbv("(((...)))")]);
+
+            let states = traj.states();
+            assert_eq!(states.len(), 5);
+            assert_eq!(states[4].2, -50);
+
+            let other = if format == TrajectoryFormat::Binary {
+                TrajectoryFormat::JsonLines
+            } else {
+                TrajectoryFormat::Binary
+            };
+            let converted = traj.write(Vec::new(), other).unwrap();
+            assert_eq!(Trajectory::read(Cursor::new(converted)).unwrap(), traj);
+        }
+
+        // Malformed headers are rejected when reading.
+        for structure in ["((.......", "(((...)))..", "((.x..)))"] {
+            let json = format!(r#"{{"sequence":"GGGAAACCC","structure":"{}","energy":0}}"#, structure);
+            let err = Trajectory::read(Cursor::new(json)).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn test_kinfold_output() {
+        let traj = Trajectory {
+            header: TrajectoryHeader {
+                sequence: "GGGAAACCC".to_string(),
+                structure: ".........".to_string(),
+                energy: 0,
+            },
+            events: vec![
+                TrajectoryEvent { time: 1.0, energy: 300, mv: Move::Add { i: 0, j: 8 } },
+                // A decomposed move, reported as one state.
+                TrajectoryEvent { time: 2.0, energy: 0, mv: Move::Del { i: 0, j: 8 } },
+                TrajectoryEvent { time: 2.0, energy: 250, mv: Move::Add { i: 1, j: 7 } },
+            ],
+        };
+        let mut out = Vec::new();
+        traj.write_kinfold(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "GGGAAACCC");
+        assert!(lines[3].starts_with(".(.....).   2.50"));
+    }
+}
@@ -0,0 +1,305 @@
+//! Transition paths and committors between two macrostates.
+//!
+//! Every state of a trajectory is labelled with its macrostate (see
+//! [`label_trajectory`]). For a source macrostate A and a target
+//! macrostate B, a *reactive path* is the piece of a trajectory between
+//! the last visit of A and the subsequent first visit of B. Its duration
+//! (from leaving A to entering B) is the transition path time.
+//!
+//! The *committor* q(s) of a structure s is the probability that a
+//! trajectory in s reaches B before A. It is estimated from all visits of
+//! s (outside of A and B) whose future hits A or B within the trajectory;
+//! visits at the end of a trajectory that hit neither are ignored.
+//!
+//! Reactive paths are grouped into *pathways* by the sequence of
+//! macrostates they pass through, such that the dominant routes from A to
+//! B (e.g. A → I1 → B versus A → I2 → B) can be compared. Unassigned
+//! structures (index 0) do not count as intermediates.
+
+use std::fmt;
+use std::io;
+use std::io::Write;
+use ahash::AHashMap;
+
+use ff_structure::DotBracketVec;
+use ff_energy::EnergyModel;
+
+use crate::MacrostateRegistry;
+use crate::trajectory_io::Trajectory;
+
+/// A state of a trajectory: (time of entry, structure, macrostate index).
+pub type LabelledState = (f64, DotBracketVec, usize);
+
+/// Classify every state of a recorded trajectory.
+pub fn label_trajectory<E: EnergyModel>(
+    trajectory: &Trajectory,
+    registry: &MacrostateRegistry<'_, E>,
+) -> Vec<LabelledState> {
+    trajectory.states()
+        .into_iter()
+        .map(|(t, s, _)| {
+            let m = registry.classify(&s);
+            (t, s, m)
+        })
+        .collect()
+}
+
+/// The last exit from the source and the first entry into the target.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReactivePath {
+    /// Time of the first state after the source.
+    pub t_exit: f64,
+    /// Time of entry into the target.
+    pub t_entry: f64,
+    /// All states, from the last source structure to the first target structure.
+    pub structures: Vec<DotBracketVec>,
+    /// The visited macrostates without repetitions and unassigned states.
+    pub macrostates: Vec<usize>,
+}
+
+impl ReactivePath {
+    pub fn transition_time(&self) -> f64 {
+        self.t_entry - self.t_exit
+    }
+}
+
+/// Reactive paths with the same sequence of macrostates.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pathway {
+    pub macrostates: Vec<usize>,
+    pub count: usize,
+    pub mean_transition_time: f64,
+}
+
+/// Reactive paths and committor estimates from A to B.
+#[derive(Debug, Clone)]
+pub struct TransitionPathAnalysis {
+    source: usize,
+    target: usize,
+    paths: Vec<ReactivePath>,
+    /// Per structure: (visits that reach the target first, visits that
+    /// reach the source first).
+    hits: AHashMap<DotBracketVec, (usize, usize)>,
+    num_trajectories: usize,
+}
+
+impl TransitionPathAnalysis {
+    pub fn new(source: usize, target: usize) -> Self {
+        assert_ne!(source, target, "Source and target macrostates must differ");
+        Self {
+            source,
+            target,
+            paths: Vec::new(),
+            hits: AHashMap::new(),
+            num_trajectories: 0,
+        }
+    }
+
+    pub fn source(&self) -> usize {
+        self.source
+    }
+
+    pub fn target(&self) -> usize {
+        self.target
+    }
+
+    pub fn num_trajectories(&self) -> usize {
+        self.num_trajectories
+    }
+
+    /// Add a labelled trajectory (states in chronological order).
+    pub fn add_trajectory(&mut self, states: &[LabelledState]) {
+        self.num_trajectories += 1;
+
+        // Committors: walk backwards and remember which boundary comes next.
+        let mut next: Option<bool> = None;
+        for (_, s, m) in states.iter().rev() {
+            if *m == self.target {
+                next = Some(true);
+            } else if *m == self.source {
+                next = Some(false);
+            } else if let Some(to_target) = next {
+                let (b, a) = self.hits.entry(s.clone()).or_insert((0, 0));
+                if to_target { *b += 1 } else { *a += 1 }
+            }
+        }
+
+        // Reactive paths: from the last source state to the next target state.
+        let mut last_source = None;
+        for (k, (t, _, m)) in states.iter().enumerate() {
+            if *m == self.source {
+                last_source = Some(k);
+            } else if *m == self.target && let Some(a) = last_source.take() {
+                let mut macrostates: Vec<usize> = Vec::new();
+                for (_, _, m) in &states[a..=k] {
+                    if *m != 0 && macrostates.last() != Some(m) {
+                        macrostates.push(*m);
+                    }
+                }
+                self.paths.push(ReactivePath {
+                    t_exit: states[a + 1].0,
+                    t_entry: *t,
+                    structures: states[a..=k].iter().map(|(_, s, _)| s.clone()).collect(),
+                    macrostates,
+                });
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: &TransitionPathAnalysis) {
+        assert_eq!((self.source, self.target), (other.source, other.target),
+            "Cannot merge transition paths between different macrostates");
+        self.paths.extend(other.paths.iter().cloned());
+        for (s, &(b, a)) in &other.hits {
+            let hits = self.hits.entry(s.clone()).or_insert((0, 0));
+            hits.0 += b;
+            hits.1 += a;
+        }
+        self.num_trajectories += other.num_trajectories;
+    }
+
+    pub fn reactive_paths(&self) -> &[ReactivePath] {
+        &self.paths
+    }
+
+    pub fn mean_transition_time(&self) -> Option<f64> {
+        if self.paths.is_empty() {
+            return None;
+        }
+        Some(self.paths.iter().map(|p| p.transition_time()).sum::<f64>() / self.paths.len() as f64)
+    }
+
+    /// The committor estimate of a structure outside of source and target.
+    pub fn committor(&self, structure: &DotBracketVec) -> Option<f64> {
+        self.hits.get(structure).map(|&(b, a)| b as f64 / (a + b) as f64)
+    }
+
+    /// (structure, committor, number of visits) of all structures with a
+    /// committor estimate, sorted by committor (ties by structure).
+    pub fn committors(&self) -> Vec<(DotBracketVec, f64, usize)> {
+        let mut result: Vec<_> = self.hits.iter()
+            .map(|(s, &(b, a))| (s.clone(), b as f64 / (a + b) as f64, a + b))
+            .collect();
+        result.sort_by(|x, y| x.1.total_cmp(&y.1)
+            .then_with(|| x.0.to_string().cmp(&y.0.to_string())));
+        result
+    }
+
+    /// Pathways ordered by the number of reactive paths (ties by macrostates).
+    pub fn pathways(&self) -> Vec<Pathway> {
+        let mut groups: AHashMap<&[usize], (usize, f64)> = AHashMap::new();
+        for path in &self.paths {
+            let (count, time) = groups.entry(&path.macrostates).or_insert((0, 0.0));
+            *count += 1;
+            *time += path.transition_time();
+        }
+        let mut pathways: Vec<Pathway> = groups.into_iter()
+            .map(|(m, (count, time))| Pathway {
+                macrostates: m.to_vec(),
+                count,
+                mean_transition_time: time / count as f64,
+            })
+            .collect();
+        pathways.sort_by(|a, b| b.count.cmp(&a.count)
+            .then_with(|| a.macrostates.cmp(&b.macrostates)));
+        pathways
+    }
+
+    /// Write committors as a table with header `structure committor visits`.
+    pub fn write_committors<W: Write>(&self, mut writer: W, delimiter: char) -> io::Result<()> {
+        writeln!(writer, "structure{d}committor{d}visits", d = delimiter)?;
+        for (s, q, n) in self.committors() {
+            writeln!(writer, "{}{d}{:.6}{d}{}", s, q, n, d = delimiter)?;
+        }
+        Ok(())
+    }
+
+    /// A pathway summary, with macrostate names from the registry.
+    pub fn summary<E: EnergyModel>(&self, registry: &MacrostateRegistry<'_, E>) -> String {
+        let names = registry.macrostates();
+        let mut out = format!("Reactive paths: {} in {} trajectories\n",
+            self.paths.len(), self.num_trajectories);
+        if let Some(t) = self.mean_transition_time() {
+            out += &format!("Mean transition path time: {:.6e}\n", t);
+        }
+        for p in self.pathways() {
+            let route: Vec<&str> = p.macrostates.iter().map(|&m| names[m].name()).collect();
+            out += &format!("{:>8} {:>7.2}% {:>14.6e}  {}\n",
+                p.count,
+                100.0 * p.count as f64 / self.paths.len() as f64,
+                p.mean_transition_time,
+                route.join(" -> "));
+        }
+        out
+    }
+}
+
+impl fmt::Display for ReactivePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for s in &self.structures {
+            writeln!(f, "{}", s)?;
+        }
+        write!(f, "transition time: {:.6e}", self.transition_time())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn db(s: &str) -> DotBracketVec {
+        DotBracketVec::try_from(s).unwrap()
+    }
+
+    #[test]
+    fn test_transition_paths() {
+        let a = db("......");
+        let i = db("(....)");
+        let x = db(".(..).");
+        let b = db("((..))");
+        // A x A x i B i A x
+        let states: Vec<LabelledState> = vec![
+            (0.0, a.clone(), 1),
+            (1.0, x.clone(), 0),
+            (2.0, a.clone(), 1),
+            (3.0, x.clone(), 0),
+            (4.0, i.clone(), 3),
+            (6.0, b.clone(), 2),
+            (7.0, i.clone(), 3),
+            (8.0, a.clone(), 1),
+            (9.0, x.clone(), 0),
+        ];
+        let mut tpa = TransitionPathAnalysis::new(1, 2);
+        tpa.add_trajectory(&states);
+
+        assert_eq!(tpa.reactive_paths().len(), 1);
+        let path = &tpa.reactive_paths()[0];
+        assert_eq!(path.transition_time(), 3.0);
+        assert_eq!(path.structures, vec![a.clone(), x.clone(), i.clone(), b.clone()]);
+        assert_eq!(path.macrostates, vec![1, 3, 2]);
+
+        // x: once to A, once to B, once undetermined; i: once each.
+        assert_eq!(tpa.committor(&x), Some(0.5));
+        assert_eq!(tpa.committor(&i), Some(0.5));
+        assert_eq!(tpa.committor(&a), None);
+
+        // A direct path in a second trajectory.
+        let mut other = TransitionPathAnalysis::new(1, 2);
+        other.add_trajectory(&[(0.0, a.clone(), 1), (0.5, x.clone(), 0), (1.5, b.clone(), 2)]);
+        tpa.merge(&other);
+        assert_eq!(tpa.num_trajectories(), 2);
+        assert_eq!(tpa.committor(&x), Some(2.0 / 3.0));
+        assert_eq!(tpa.mean_transition_time(), Some(2.0));
+
+        let pathways = tpa.pathways();
+        assert_eq!(pathways.len(), 2);
+        assert_eq!(pathways[0], Pathway { macrostates: vec![1, 2], count: 1, mean_transition_time: 1.0 });
+        assert_eq!(pathways[1].macrostates, vec![1, 3, 2]);
+
+        let mut out = Vec::new();
+        tpa.write_committors(&mut out, '\t').unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(text.lines().count(), 3);
+        assert!(text.contains("(....)\t0.500000\t2"));
+    }
+}
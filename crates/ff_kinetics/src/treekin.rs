@@ -0,0 +1,299 @@
+//! Interoperability with barriers and treekin.
+//!
+//! - `.bar` files (barriers output): the sequence, followed by one line per
+//!   local minimum with index, structure, energy, father and barrier height.
+//! - Rate matrices as text: one row per line, entry (i, j) is the rate from
+//!   state i to state j.
+//! - Rate matrices as binary: the dimension (i32), followed by all entries
+//!   (f64) in column-major order. Both use native (little-endian) byte order.
+//!
+//! Files store off-diagonal rates only (the diagonal is written as zero).
+//! When reading, the diagonal is recomputed such that every row of the
+//! generator sums to zero, see [`MasterEquation`](crate::master_equation::MasterEquation).
+
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+use ndarray::Array2;
+
+use ff_structure::DotBracketVec;
+use ff_energy::EnergyModel;
+use ff_energy::NucleotideVec;
+
+use crate::Macrostate;
+use crate::MacrostateRegistry;
+use crate::landscape::BarrierTree;
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// One local minimum of a `.bar` file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BarEntry {
+    pub structure: DotBracketVec,
+    /// Free energy in dcal/mol.
+    pub energy: i32,
+    /// 0-based index of the father, None for the root.
+    pub father: Option<usize>,
+    /// Barrier height in dcal/mol.
+    pub barrier: i32,
+}
+
+/// The content of a `.bar` file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BarFile {
+    pub sequence: String,
+    pub minima: Vec<BarEntry>,
+}
+
+fn dcal(token: &str) -> io::Result<i32> {
+    token.parse::<f64>()
+        .map(|x| (x * 100.).round() as i32)
+        .map_err(|_| invalid_data(format!("Invalid energy '{}'", token)))
+}
+
+impl BarFile {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+
+    /// Parse a `.bar` file. Additional columns (e.g. saddle structures or
+    /// basin sizes) are ignored.
+    pub fn read<R: BufRead>(reader: R) -> io::Result<Self> {
+        let mut lines = reader.lines();
+        let sequence = loop {
+            let line = lines.next().ok_or_else(|| invalid_data("Missing sequence line"))??;
+            if !line.trim().is_empty() {
+                break line.trim().to_string();
+            }
+        };
+        let mut minima = Vec::new();
+        for line in lines {
+            let line = line?;
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            if tokens.is_empty() {
+                continue;
+            }
+            if tokens.len() < 5 {
+                return Err(invalid_data(format!("Invalid .bar line '{}'", line)));
+            }
+            let index: usize = tokens[0].parse()
+                .map_err(|_| invalid_data(format!("Invalid index '{}'", tokens[0])))?;
+            if index != minima.len() + 1 {
+                return Err(invalid_data(format!("Unexpected index {} in .bar file", index)));
+            }
+            let structure = DotBracketVec::try_from(tokens[1])
+                .map_err(|e| invalid_data(format!("Invalid structure: {:?}", e)))?;
+            if structure.len() != sequence.len() {
+                return Err(invalid_data("Structure length does not match sequence"));
+            }
+            let father: usize = tokens[3].parse()
+                .map_err(|_| invalid_data(format!("Invalid father '{}'", tokens[3])))?;
+            minima.push(BarEntry {
+                structure,
+                energy: dcal(tokens[2])?,
+                father: father.checked_sub(1),
+                barrier: dcal(tokens[4])?,
+            });
+        }
+        Ok(Self { sequence, minima })
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "     {}", self.sequence)?;
+        for (k, lm) in self.minima.iter().enumerate() {
+            writeln!(writer, "{:4} {} {:6.2} {:4} {:6.2}",
+                k + 1,
+                lm.structure,
+                lm.energy as f64 / 100.,
+                lm.father.map_or(0, |f| f + 1),
+                lm.barrier as f64 / 100.,
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn from_tree(tree: &BarrierTree, sequence: &NucleotideVec) -> Self {
+        Self {
+            sequence: sequence.to_string(),
+            minima: tree.minima().iter().map(|lm| BarEntry {
+                structure: lm.structure.clone(),
+                energy: lm.energy,
+                father: lm.father,
+                barrier: lm.barrier().unwrap_or(0),
+            }).collect(),
+        }
+    }
+
+    /// A registry with one macrostate per local minimum (`lmin=lm1`,
+    /// `lmin=lm2`, ...), in the order of the file. Registry index `k + 1`
+    /// corresponds to row `k` of the rate matrix.
+    pub fn macrostate_registry<'a, E: EnergyModel>(
+        &self,
+        sequence: &'a NucleotideVec,
+        energy_model: &'a E,
+    ) -> io::Result<MacrostateRegistry<'a, E>> {
+        if NucleotideVec::from_lossy(&self.sequence) != *sequence {
+            return Err(invalid_data("Sequence of .bar file does not match input sequence"));
+        }
+        let mut registry = MacrostateRegistry::from((sequence, energy_model));
+        for (k, lm) in self.minima.iter().enumerate() {
+            registry.insert(Macrostate::from_list(
+                &format!("lmin=lm{}", k + 1),
+                sequence,
+                std::slice::from_ref(&lm.structure),
+                energy_model,
+            )).map_err(|e| invalid_data(&e))?;
+        }
+        Ok(registry)
+    }
+}
+
+/// Set the diagonal such that every row sums to zero.
+pub fn fix_diagonal(rates: &mut Array2<f64>) {
+    for a in 0..rates.nrows() {
+        rates[(a, a)] = 0.0;
+        rates[(a, a)] = -rates.row(a).sum();
+    }
+}
+
+/// Write a rate matrix as text (treekin `--ratesfile` format).
+pub fn write_rates_text<W: Write>(writer: &mut W, rates: &Array2<f64>) -> io::Result<()> {
+    for (a, row) in rates.rows().into_iter().enumerate() {
+        let line: Vec<String> = row.iter()
+            .enumerate()
+            .map(|(b, &r)| format!("{:10.4e}", if a == b { 0.0 } else { r }))
+            .collect();
+        writeln!(writer, "{}", line.join(" "))?;
+    }
+    Ok(())
+}
+
+/// Write a rate matrix in the treekin binary format.
+pub fn write_rates_binary<W: Write>(writer: &mut W, rates: &Array2<f64>) -> io::Result<()> {
+    let n = rates.nrows();
+    writer.write_all(&(n as i32).to_le_bytes())?;
+    for b in 0..n {
+        for a in 0..n {
+            let r = if a == b { 0.0 } else { rates[(a, b)] };
+            writer.write_all(&r.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+pub fn read_rates_text<R: BufRead>(reader: R) -> io::Result<Array2<f64>> {
+    let mut rows: Vec<Vec<f64>> = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        rows.push(line.split_whitespace()
+            .map(|x| x.parse::<f64>().map_err(|_| invalid_data(format!("Invalid rate '{}'", x))))
+            .collect::<io::Result<_>>()?);
+    }
+    let n = rows.len();
+    if rows.iter().any(|r| r.len() != n) {
+        return Err(invalid_data("Rate matrix is not square"));
+    }
+    let mut rates = Array2::from_shape_vec((n, n), rows.concat())
+        .map_err(|e| invalid_data(e.to_string()))?;
+    fix_diagonal(&mut rates);
+    Ok(rates)
+}
+
+pub fn read_rates_binary<R: Read>(mut reader: R) -> io::Result<Array2<f64>> {
+    let mut dim = [0u8; 4];
+    reader.read_exact(&mut dim)?;
+    let n = i32::from_le_bytes(dim);
+    if n < 0 {
+        return Err(invalid_data(format!("Invalid dimension {}", n)));
+    }
+    let n = n as usize;
+    let mut rates = Array2::zeros((n, n));
+    let mut buf = [0u8; 8];
+    for b in 0..n {
+        for a in 0..n {
+            reader.read_exact(&mut buf)?;
+            rates[(a, b)] = f64::from_le_bytes(buf);
+        }
+    }
+    fix_diagonal(&mut rates);
+    Ok(rates)
+}
+
+/// Read a rate matrix in either format (binary files are recognized by
+/// their size).
+pub fn read_rates_file<P: AsRef<Path>>(path: P) -> io::Result<Array2<f64>> {
+    let data = std::fs::read(path)?;
+    if data.len() >= 4 {
+        let n = i32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+        if n >= 0 && data.len() == 4 + 8 * (n as usize) * (n as usize) {
+            return read_rates_binary(&data[..]);
+        }
+    }
+    read_rates_text(&data[..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use ndarray::array;
+    use ff_energy::ViennaRNA;
+    use crate::reaction::MoveSet;
+    use crate::landscape::enumerate_structures;
+
+    #[test]
+    fn test_bar_file_roundtrip() {
+        let model = ViennaRNA::default();
+        let sequence = NucleotideVec::try_from("GGGGAAAACCCC").unwrap();
+        let mut all = Vec::new();
+        enumerate_structures(&sequence, &model, |s| all.push(s.clone()));
+        let tree = BarrierTree::flood(&sequence, &model, MoveSet::default(), &all, 100).unwrap();
+
+        let mut out = Vec::new();
+        tree.write_tree(&mut out, &sequence).unwrap();
+        let bar = BarFile::read(Cursor::new(&out)).unwrap();
+        assert_eq!(bar, BarFile::from_tree(&tree, &sequence));
+
+        let mut again = Vec::new();
+        bar.write(&mut again).unwrap();
+        assert_eq!(again, out);
+
+        let registry = bar.macrostate_registry(&sequence, &model).unwrap();
+        assert_eq!(registry.len(), tree.minima().len() + 1);
+        assert_eq!(registry.classify(&tree.minima()[0].structure), 1);
+
+        let other = NucleotideVec::try_from("GGGGAAAACCCA").unwrap();
+        assert!(bar.macrostate_registry(&other, &model).is_err());
+    }
+
+    #[test]
+    fn test_rate_matrix_formats() {
+        let rates = array![[-3.0, 1.0, 2.0], [0.5, -0.5, 0.0], [0.0, 1e-3, -1e-3]];
+
+        let mut text = Vec::new();
+        write_rates_text(&mut text, &rates).unwrap();
+        let first: Vec<String> = String::from_utf8(text.clone()).unwrap()
+            .split_whitespace().take(2).map(String::from).collect();
+        assert_eq!(first, ["0.0000e0", "1.0000e0"]);
+        let parsed = read_rates_text(Cursor::new(text)).unwrap();
+        assert!(parsed.iter().zip(rates.iter()).all(|(a, b)| (a - b).abs() < 1e-12));
+
+        let mut binary = Vec::new();
+        write_rates_binary(&mut binary, &rates).unwrap();
+        assert_eq!(binary.len(), 4 + 8 * 9);
+        // Column-major: the second entry is the rate from state 1 to state 0.
+        assert_eq!(f64::from_le_bytes(binary[12..20].try_into().unwrap()), 0.5);
+        assert_eq!(read_rates_binary(&binary[..]).unwrap(), rates);
+
+        let path = std::env::temp_dir().join("ff_kinetics_treekin_rates.bin");
+        std::fs::write(&path, &binary).unwrap();
+        assert_eq!(read_rates_file(&path).unwrap(), rates);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
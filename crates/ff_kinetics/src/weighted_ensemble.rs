@@ -0,0 +1,448 @@
+//! Weighted-ensemble (WE) simulations of rare transitions.
+//!
+//! An ensemble of weighted walkers (total weight 1) is propagated with the
+//! SSA for a fixed interval τ. Then every bin of the configuration space
+//! (see [`Binning`]) is resampled to `walkers_per_bin` walkers: walkers are
+//! split into halves, or pairs of walkers are merged, keeping one of them
+//! with probability proportional to its weight (Huber & Kim 1996). This
+//! preserves the expected weight in every bin, so all estimates remain
+//! unbiased, while rarely visited regions are explored by many walkers.
+//!
+//! Walkers that reach the target bin are recycled: their weight is added
+//! to the flux into the target and they restart from an initial
+//! structure. In steady state, the flux per unit time is the rate of the
+//! transition, i.e. the inverse mean first-passage time.
+
+use std::sync::Arc;
+use rand::Rng;
+
+use ff_structure::DotBracketVec;
+use ff_structure::PairTable;
+use ff_energy::EnergyModel;
+use ff_energy::NucleotideVec;
+
+use crate::RateModel;
+use crate::LoopStructure;
+use crate::LoopStructureSSA;
+use crate::MacrostateRegistry;
+use crate::base_pair_distance;
+use crate::reaction::MoveSet;
+
+/// A partition of the structure space into bins.
+pub trait Binning: Send + Sync {
+    fn num_bins(&self) -> usize;
+
+    fn bin(&self, structure: &DotBracketVec) -> usize;
+
+    fn bin_name(&self, bin: usize) -> String;
+}
+
+/// Binning of loop structures during a simulation.
+pub trait LoopBinning<M: EnergyModel>: Binning {
+    /// The bin of the current structure of a loop structure. This is
+    /// evaluated after every reaction, so implementations should avoid
+    /// building the structure.
+    fn bin_loops(&self, ls: &LoopStructure<'_, M>) -> usize;
+}
+
+/// Bins by base-pair distance to a reference structure: bin k holds the
+/// distances in [edges[k-1], edges[k]), with edges[-1] = 0.
+#[derive(Debug, Clone)]
+pub struct DistanceBins {
+    reference: PairTable,
+    num_pairs: usize,
+    edges: Vec<usize>,
+}
+
+impl DistanceBins {
+    pub fn new(reference: PairTable, mut edges: Vec<usize>) -> Self {
+        edges.sort_unstable();
+        edges.dedup();
+        let num_pairs = reference.iter()
+            .enumerate()
+            .filter(|&(i, j)| j.is_some_and(|j| i < j as usize))
+            .count();
+        Self { reference, num_pairs, edges }
+    }
+
+    fn bin_of_distance(&self, d: usize) -> usize {
+        self.edges.partition_point(|&e| e <= d)
+    }
+
+    /// Bins of `width` base-pairs, where bin 0 is the reference itself.
+    pub fn uniform(reference: PairTable, width: usize) -> Self {
+        assert!(width > 0, "Bin width must be positive");
+        let edges = (0..).map(|k| 1 + k * width)
+            .take_while(|&e| e <= reference.len())
+            .collect();
+        Self::new(reference, edges)
+    }
+}
+
+impl Binning for DistanceBins {
+    fn num_bins(&self) -> usize {
+        self.edges.len() + 1
+    }
+
+    fn bin(&self, structure: &DotBracketVec) -> usize {
+        let pt = PairTable::try_from(structure).expect("Invalid structure for binning");
+        self.bin_of_distance(base_pair_distance(&pt, &self.reference))
+    }
+
+    fn bin_name(&self, bin: usize) -> String {
+        let lower = if bin == 0 { 0 } else { self.edges[bin - 1] };
+        match self.edges.get(bin) {
+            Some(&upper) if upper == lower + 1 => format!("d={}", lower),
+            Some(&upper) => format!("d={}-{}", lower, upper - 1),
+            None => format!("d>={}", lower),
+        }
+    }
+}
+
+impl<M: EnergyModel> LoopBinning<M> for DistanceBins {
+    /// The distance from the base-pairs of the loop structure: the pairs of
+    /// both structures minus twice the shared ones.
+    fn bin_loops(&self, ls: &LoopStructure<'_, M>) -> usize {
+        let pairs = ls.pair_list();
+        let shared = pairs.iter()
+            .filter(|&(&i, &j)| self.reference[i as usize] == Some(j))
+            .count();
+        self.bin_of_distance(self.num_pairs + pairs.len() - 2 * shared)
+    }
+}
+
+impl<'a, E: EnergyModel + Sync> Binning for MacrostateRegistry<'a, E> {
+    fn num_bins(&self) -> usize {
+        self.len()
+    }
+
+    fn bin(&self, structure: &DotBracketVec) -> usize {
+        self.classify(structure)
+    }
+
+    fn bin_name(&self, bin: usize) -> String {
+        self.macrostates()[bin].name().to_string()
+    }
+}
+
+impl<'a, E: EnergyModel + Sync, M: EnergyModel> LoopBinning<M> for MacrostateRegistry<'a, E> {
+    fn bin_loops(&self, ls: &LoopStructure<'_, M>) -> usize {
+        self.classify_loops(ls)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Walker {
+    pub structure: DotBracketVec,
+    pub weight: f64,
+}
+
+/// Resample the walkers of one bin to `target` walkers (if there are any).
+fn resample_bin<G: Rng + ?Sized>(mut walkers: Vec<Walker>, target: usize, rng: &mut G) -> Vec<Walker> {
+    if walkers.is_empty() {
+        return walkers;
+    }
+    // Merge the two lightest walkers.
+    while walkers.len() > target {
+        walkers.sort_by(|a, b| b.weight.total_cmp(&a.weight));
+        let w2 = walkers.pop().unwrap();
+        let w1 = walkers.pop().unwrap();
+        let weight = w1.weight + w2.weight;
+        let survivor = if rng.random::<f64>() * weight < w1.weight { w1 } else { w2 };
+        walkers.push(Walker { weight, ..survivor });
+    }
+    // Split the heaviest walker.
+    while walkers.len() < target {
+        let (k, _) = walkers.iter().enumerate()
+            .max_by(|a, b| a.1.weight.total_cmp(&b.1.weight))
+            .unwrap();
+        walkers[k].weight /= 2.0;
+        walkers.push(walkers[k].clone());
+    }
+    walkers
+}
+
+/// Weighted-ensemble simulation of the transition into a target bin.
+pub struct WeightedEnsemble<'a, E: EnergyModel, R: RateModel> {
+    sequence: &'a NucleotideVec,
+    model: &'a E,
+    rate_model: &'a R,
+    move_set: MoveSet,
+    binning: Arc<dyn LoopBinning<E> + 'a>,
+    target: usize,
+    tau: f64,
+    walkers_per_bin: usize,
+    /// Structure of new and recycled walkers.
+    initial: DotBracketVec,
+    walkers: Vec<Walker>,
+    /// Weight that reached the target in every iteration.
+    fluxes: Vec<f64>,
+}
+
+impl<'a, E: EnergyModel, R: RateModel> WeightedEnsemble<'a, E, R> {
+    /// Start `walkers_per_bin` walkers (of equal weight) from `initial`,
+    /// which must not be in the target bin.
+    pub fn new(
+        sequence: &'a NucleotideVec,
+        model: &'a E,
+        rate_model: &'a R,
+        binning: Arc<dyn LoopBinning<E> + 'a>,
+        target: usize,
+        initial: DotBracketVec,
+        tau: f64,
+    ) -> Self {
+        assert!(tau > 0.0, "Resampling interval must be positive");
+        assert!(target < binning.num_bins(), "Invalid target bin");
+        assert_ne!(binning.bin(&initial), target, "Initial structure is in the target bin");
+        let mut we = Self {
+            sequence,
+            model,
+            rate_model,
+            move_set: MoveSet::default(),
+            binning,
+            target,
+            tau,
+            walkers_per_bin: 4,
+            initial,
+            walkers: Vec::new(),
+            fluxes: Vec::new(),
+        };
+        we.reset_walkers();
+        we
+    }
+
+    pub fn with_move_set(mut self, move_set: MoveSet) -> Self {
+        self.move_set = move_set;
+        self
+    }
+
+    pub fn with_walkers_per_bin(mut self, walkers_per_bin: usize) -> Self {
+        assert!(walkers_per_bin > 0, "Need at least one walker per bin");
+        self.walkers_per_bin = walkers_per_bin;
+        self.reset_walkers();
+        self
+    }
+
+    fn reset_walkers(&mut self) {
+        let weight = 1.0 / self.walkers_per_bin as f64;
+        self.walkers = (0..self.walkers_per_bin)
+            .map(|_| Walker { structure: self.initial.clone(), weight })
+            .collect();
+    }
+
+    pub fn walkers(&self) -> &[Walker] {
+        &self.walkers
+    }
+
+    pub fn tau(&self) -> f64 {
+        self.tau
+    }
+
+    pub fn iterations(&self) -> usize {
+        self.fluxes.len()
+    }
+
+    pub fn time(&self) -> f64 {
+        self.fluxes.len() as f64 * self.tau
+    }
+
+    /// The weight that reached the target in each iteration.
+    pub fn fluxes(&self) -> &[f64] {
+        &self.fluxes
+    }
+
+    /// Total weight in every bin.
+    pub fn bin_weights(&self) -> Vec<f64> {
+        let mut weights = vec![0.0; self.binning.num_bins()];
+        for w in &self.walkers {
+            weights[self.binning.bin(&w.structure)] += w.weight;
+        }
+        weights
+    }
+
+    /// Propagate one walker for τ. Returns true if it reached the target.
+    fn propagate<G: Rng + ?Sized>(&self, walker: &mut Walker, rng: &mut G) -> bool {
+        let pairings = PairTable::try_from(&walker.structure).expect("Invalid walker structure");
+        let loops = LoopStructure::try_from((&self.sequence[..], &pairings, self.model))
+            .expect("Incompatible walker structure")
+            .with_move_set(self.move_set);
+        let mut simulator = LoopStructureSSA::from((loops, self.rate_model));
+        let mut reached = false;
+        simulator.simulate(rng, self.tau, |t, tinc, _, ls| {
+            if self.binning.bin_loops(ls) == self.target {
+                reached = true;
+                return false;
+            }
+            // Stop before the reaction that would happen after τ.
+            t + tinc < self.tau
+        });
+        walker.structure = DotBracketVec::from(simulator.loop_structure());
+        reached
+    }
+
+    /// One iteration: propagate, recycle and resample all walkers.
+    /// Returns the weight that reached the target.
+    pub fn step<G: Rng + ?Sized>(&mut self, rng: &mut G) -> f64 {
+        let mut walkers = std::mem::take(&mut self.walkers);
+        let mut flux = 0.0;
+        for walker in walkers.iter_mut() {
+            if self.propagate(walker, rng) {
+                flux += walker.weight;
+                walker.structure = self.initial.clone();
+            }
+        }
+
+        let mut bins: Vec<Vec<Walker>> = vec![Vec::new(); self.binning.num_bins()];
+        for walker in walkers {
+            bins[self.binning.bin(&walker.structure)].push(walker);
+        }
+        self.walkers = bins.into_iter()
+            .flat_map(|b| resample_bin(b, self.walkers_per_bin, rng))
+            .collect();
+        self.fluxes.push(flux);
+        flux
+    }
+
+    pub fn run<G: Rng + ?Sized>(&mut self, rng: &mut G, iterations: usize) {
+        for _ in 0..iterations {
+            self.step(rng);
+        }
+    }
+
+    /// The rate (flux per unit time) averaged over all iterations after
+    /// `burn_in`, with the standard error from (up to) 10 blocks of
+    /// consecutive iterations.
+    pub fn rate(&self, burn_in: usize) -> Option<(f64, f64)> {
+        let fluxes = self.fluxes.get(burn_in..).filter(|f| !f.is_empty())?;
+        let mean = fluxes.iter().sum::<f64>() / fluxes.len() as f64 / self.tau;
+        let block = fluxes.len().div_ceil(10);
+        let blocks: Vec<f64> = fluxes.chunks_exact(block)
+            .map(|c| c.iter().sum::<f64>() / c.len() as f64 / self.tau)
+            .collect();
+        let sem = if blocks.len() < 2 {
+            f64::NAN
+        } else {
+            let b = blocks.len() as f64;
+            let var = blocks.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (b - 1.0);
+            (var / b).sqrt()
+        };
+        Some((mean, sem))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+    use ff_energy::ViennaRNA;
+    use crate::Metropolis;
+
+    fn walker(s: &str, weight: f64) -> Walker {
+        Walker { structure: DotBracketVec::try_from(s).unwrap(), weight }
+    }
+
+    #[test]
+    fn test_resample_bin() {
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let split = resample_bin(vec![walker("....", 0.4)], 4, &mut rng);
+        assert_eq!(split.len(), 4);
+        assert!(split.iter().all(|w| (w.weight - 0.1).abs() < 1e-12));
+
+        let walkers = vec![walker("....", 0.5), walker("(..)", 0.1), walker("....", 0.2)];
+        let merged = resample_bin(walkers, 2, &mut rng);
+        assert_eq!(merged.len(), 2);
+        assert!((merged.iter().map(|w| w.weight).sum::<f64>() - 0.8).abs() < 1e-12);
+        assert!(merged.iter().any(|w| w.weight == 0.5));
+    }
+
+    #[test]
+    fn test_distance_bins() {
+        let reference = PairTable::try_from("((((....))))").unwrap();
+        let bins = DistanceBins::uniform(reference, 2);
+        assert_eq!(bins.num_bins(), 7);
+        assert_eq!(bins.bin(&DotBracketVec::try_from("((((....))))").unwrap()), 0);
+        assert_eq!(bins.bin(&DotBracketVec::try_from(".(((....))).").unwrap()), 1);
+        assert_eq!(bins.bin(&DotBracketVec::try_from("............").unwrap()), 2);
+        assert_eq!(bins.bin_name(0), "d=0");
+
+        // Binning loop structures agrees with binning their structures.
+        let model = ViennaRNA::default();
+        let sequence = NucleotideVec::try_from("GGGGAAAACCCC").unwrap();
+        for s in ["((((....))))", ".(((....))).", "((.(....).))", "............"] {
+            let pt = PairTable::try_from(s).unwrap();
+            let ls = LoopStructure::try_from((&sequence[..], &pt, &model)).unwrap();
+            let dbv = DotBracketVec::try_from(s).unwrap();
+            assert_eq!(bins.bin_loops(&ls), bins.bin(&dbv));
+        }
+        assert_eq!(bins.bin_name(1), "d=1-2");
+    }
+
+    /// Mean first-passage time into `target` by brute-force simulation.
+    fn mean_first_passage_time(
+        sequence: &NucleotideVec,
+        model: &ViennaRNA,
+        rates: &Metropolis,
+        start: &PairTable,
+        target: &PairTable,
+        num_sims: usize,
+        rng: &mut ChaCha8Rng,
+    ) -> f64 {
+        let mut total = 0.0;
+        for _ in 0..num_sims {
+            let loops = LoopStructure::try_from((&sequence[..], start, model)).unwrap();
+            let mut simulator = LoopStructureSSA::from((loops, rates));
+            let mut fpt = None;
+            simulator.simulate(rng, f64::INFINITY, |t, _, _, ls| {
+                if PairTable::from(ls) == *target {
+                    fpt = Some(t);
+                    return false;
+                }
+                true
+            });
+            total += fpt.unwrap();
+        }
+        total / num_sims as f64
+    }
+
+    #[test]
+    fn test_weighted_ensemble() {
+        let model = ViennaRNA::default();
+        let rates = Metropolis::new(model.temperature(), 1e6);
+        let sequence = NucleotideVec::try_from("GGGGAAAACCCC").unwrap();
+        let target = PairTable::try_from("((((....))))").unwrap();
+        let binning = Arc::new(DistanceBins::uniform(target, 1));
+        let open = DotBracketVec::try_from("............").unwrap();
+        let mut we = WeightedEnsemble::new(&sequence, &model, &rates, binning, 0, open, 1e-6)
+            .with_walkers_per_bin(2);
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        we.run(&mut rng, 50);
+        assert_eq!(we.iterations(), 50);
+        let total: f64 = we.bin_weights().iter().sum();
+        assert!((total - 1.0).abs() < 1e-9);
+        assert_eq!(we.bin_weights()[0], 0.0);
+        let (rate, _) = we.rate(10).unwrap();
+        assert!(rate > 0.0);
+        assert!(we.rate(50).is_none());
+    }
+
+    #[test]
+    fn test_weighted_ensemble_rate() {
+        // In steady state, the recycled flux is the inverse mean first-passage time.
+        let model = ViennaRNA::default();
+        let rates = Metropolis::new(model.temperature(), 1.0);
+        let sequence = NucleotideVec::try_from("GGGAAAUCC").unwrap();
+        let target = PairTable::try_from("(((...)))").unwrap();
+        let open = PairTable::try_from(".........").unwrap();
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let mfpt = mean_first_passage_time(&sequence, &model, &rates, &open, &target, 2000, &mut rng);
+
+        let binning = Arc::new(DistanceBins::uniform(target, 1));
+        let mut we = WeightedEnsemble::new(&sequence, &model, &rates, binning, 0,
+            DotBracketVec::from(&open), 10.0).with_walkers_per_bin(4);
+        we.run(&mut rng, 4000);
+        let (rate, sem) = we.rate(100).unwrap();
+        assert!(sem < 0.1 * rate);
+        assert!((rate * mfpt - 1.0).abs() < 0.15, "rate {} vs 1/MFPT {}", rate, 1.0 / mfpt);
+    }
+}
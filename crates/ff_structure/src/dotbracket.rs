@@ -7,7 +7,7 @@ use crate::PairTable;
 use crate::MultiPairTable;
 use crate::StructureError;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum DotBracket {
     Unpaired, // '.'
     Open,     // '('
@@ -40,7 +40,7 @@ impl From<DotBracket> for char {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct DotBracketVec(pub Vec<DotBracket>);
 
 impl Deref for DotBracketVec {
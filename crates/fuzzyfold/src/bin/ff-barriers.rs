@@ -0,0 +1,116 @@
+use std::fs::File;
+use std::io::{stdout, BufWriter, Write};
+use std::path::PathBuf;
+use clap::Parser;
+use anyhow::Result;
+use anyhow::bail;
+use anyhow::anyhow;
+use ndarray::Array2;
+
+use ff_structure::DotBracketVec;
+use ff_energy::EnergyModel;
+use ff_kinetics::Metropolis;
+use ff_kinetics::landscape::BarrierTree;
+use ff_kinetics::landscape::enumerate_structures;
+use ff_kinetics::landscape::write_basin_files;
+use ff_kinetics::treekin::write_rates_text;
+use ff_kinetics::treekin::write_rates_binary;
+
+use fuzzyfold::input_parsers::read_fasta_like_input;
+use fuzzyfold::input_parsers::read_structure_list_file;
+use fuzzyfold::input_parsers::check_structure_lengths;
+use fuzzyfold::energy_parsers::EnergyModelArguments;
+use fuzzyfold::kinetics_parsers::MoveSetParameters;
+use fuzzyfold::kinetics_parsers::RateModelParams;
+
+#[derive(Debug, Parser)]
+#[command(name = "ff-barriers")]
+#[command(version, about = "Barrier tree, basins and macrostate rates by flooding the energy landscape")]
+pub struct Cli {
+    /// Input file (FASTA-like), or "-" for stdin
+    #[arg(value_name = "INPUT", default_value = "-")]
+    input: String,
+
+    /// Enumerate all secondary structures (short sequences only).
+    #[arg(long)]
+    enumerate: bool,
+
+    /// Read structures from files (one structure per line, e.g. RNAsubopt output).
+    #[arg(long, value_name = "FILE", num_args = 1..)]
+    structures: Vec<PathBuf>,
+
+    /// Minimal barrier height (kcal/mol), lower basins are merged.
+    #[arg(long, default_value_t = 0.0)]
+    minh: f64,
+
+    /// Write the basins as macrostate files into this directory.
+    #[arg(short, long, value_name = "DIR")]
+    outdir: Option<PathBuf>,
+
+    /// Write the macrostate rate matrix to FILE (treekin text format).
+    #[arg(long, value_name = "FILE")]
+    rates: Option<PathBuf>,
+
+    /// Write the macrostate rate matrix to FILE (treekin binary format).
+    #[arg(long, value_name = "FILE")]
+    binary_rates: Option<PathBuf>,
+
+    #[command(flatten, next_help_heading = "Kinetic model parameters")]
+    kinetics: RateModelParams,
+
+    #[command(flatten, next_help_heading = "Move set parameters")]
+    moves: MoveSetParameters,
+
+    #[command(flatten, next_help_heading = "Energy model parameters")]
+    energy: EnergyModelArguments,
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    if !cli.enumerate && cli.structures.is_empty() {
+        bail!("No structures: use --enumerate or --structures");
+    }
+
+    let emodel = cli.energy.build_model();
+    let move_set = cli.moves.build_move_set()?;
+    let (_, sequence, _) = read_fasta_like_input(&cli.input)?;
+
+    let mut structures: Vec<DotBracketVec> = Vec::new();
+    if cli.enumerate {
+        enumerate_structures(&sequence, &emodel, |s| structures.push(s.clone()));
+    }
+    for file in &cli.structures {
+        let list = read_structure_list_file(file)?;
+        check_structure_lengths(&list, sequence.len(), &file.display().to_string())?;
+        structures.extend(list);
+    }
+
+    let min_barrier = (cli.minh * 100.).round() as i32;
+    let tree = BarrierTree::flood(&sequence, &emodel, move_set, &structures, min_barrier)
+        .map_err(|e| anyhow!(e))?;
+    let mut out = BufWriter::new(stdout().lock());
+    tree.write_tree(&mut out, &sequence)?;
+    out.flush()?;
+
+    if let Some(dir) = &cli.outdir {
+        write_basin_files(dir, &sequence, &tree.basins(), cli.minh)?;
+    }
+
+    if cli.rates.is_some() || cli.binary_rates.is_some() {
+        let rmodel = Metropolis::new(emodel.temperature(), cli.kinetics.k0);
+        let rates = tree.rate_matrix(&rmodel);
+        let n = rates.len();
+        let rates = Array2::from_shape_vec((n, n), rates.concat())?;
+        if let Some(path) = &cli.rates {
+            let mut fh = BufWriter::new(File::create(path)?);
+            write_rates_text(&mut fh, &rates)?;
+            fh.flush()?;
+        }
+        if let Some(path) = &cli.binary_rates {
+            let mut fh = BufWriter::new(File::create(path)?);
+            write_rates_binary(&mut fh, &rates)?;
+            fh.flush()?;
+        }
+    }
+    Ok(())
+}
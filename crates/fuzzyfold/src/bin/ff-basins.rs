@@ -0,0 +1,128 @@
+use std::path::PathBuf;
+use clap::Parser;
+use colored::*;
+use anyhow::Result;
+use anyhow::bail;
+
+use rand::rng;
+use ff_structure::PairTable;
+use ff_structure::DotBracketVec;
+use ff_energy::EnergyModel;
+use ff_kinetics::LoopStructure;
+use ff_kinetics::LoopStructureSSA;
+use ff_kinetics::Metropolis;
+use ff_kinetics::landscape::GradientBasins;
+use ff_kinetics::landscape::enumerate_structures;
+use ff_kinetics::landscape::write_basin_files;
+
+use fuzzyfold::input_parsers::read_fasta_like_input;
+use fuzzyfold::input_parsers::read_structure_list_file;
+use fuzzyfold::input_parsers::check_structure_lengths;
+use fuzzyfold::energy_parsers::EnergyModelArguments;
+use fuzzyfold::kinetics_parsers::MoveSetParameters;
+use fuzzyfold::kinetics_parsers::RateModelParams;
+
+#[derive(Debug, Parser)]
+#[command(name = "ff-basins")]
+#[command(version, about = "Macrostates from local minima: assign structures to gradient basins")]
+pub struct Cli {
+    /// Input file (FASTA-like), or "-" for stdin
+    #[arg(value_name = "INPUT", default_value = "-")]
+    input: String,
+
+    /// Enumerate all secondary structures (short sequences only).
+    #[arg(long)]
+    enumerate: bool,
+
+    /// Read structures from files (one structure per line, e.g. RNAsubopt output).
+    #[arg(long, value_name = "FILE", num_args = 1..)]
+    structures: Vec<PathBuf>,
+
+    /// Sample structures from this many trajectories, starting at the input structure.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    samples: usize,
+
+    /// Simulation stop time for --samples.
+    #[arg(long, default_value_t = 1e-3)]
+    t_end: f64,
+
+    /// Output directory for the macrostate files.
+    #[arg(short, long, value_name = "DIR", default_value = ".")]
+    outdir: PathBuf,
+
+    /// Only write basins with at least this many structures.
+    #[arg(long, default_value_t = 1)]
+    min_size: usize,
+
+    #[command(flatten, next_help_heading = "Kinetic model parameters")]
+    kinetics: RateModelParams,
+
+    #[command(flatten, next_help_heading = "Move set parameters")]
+    moves: MoveSetParameters,
+
+    #[command(flatten, next_help_heading = "Energy model parameters")]
+    energy: EnergyModelArguments,
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    if !cli.enumerate && cli.structures.is_empty() && cli.samples == 0 {
+        bail!("Nothing to do: use --enumerate, --structures or --samples");
+    }
+
+    let emodel = cli.energy.build_model();
+    let move_set = cli.moves.build_move_set()?;
+    let (header, sequence, structure) = read_fasta_like_input(&cli.input)?;
+    if let Some(h) = header {
+        println!("{}", h.yellow())
+    }
+    println!("{}", sequence);
+
+    let mut basins = GradientBasins::new(&sequence, &emodel, move_set);
+
+    if cli.enumerate {
+        enumerate_structures(&sequence, &emodel, |s| { basins.insert(s); });
+    }
+
+    for file in &cli.structures {
+        let structures = read_structure_list_file(file)?;
+        check_structure_lengths(&structures, sequence.len(), &file.display().to_string())?;
+        for dbv in &structures {
+            basins.insert(dbv);
+        }
+    }
+
+    if cli.samples > 0 {
+        let rmodel = Metropolis::new(emodel.temperature(), cli.kinetics.k0);
+        let pairings = PairTable::try_from(&structure)?;
+        for _ in 0..cli.samples {
+            let loops = LoopStructure::try_from((&sequence[..], &pairings, &emodel))
+                .map_err(anyhow::Error::msg)?
+                .with_move_set(move_set);
+            let mut simulator = LoopStructureSSA::from((loops, &rmodel));
+            let mut visited = Vec::new();
+            simulator.simulate(&mut rng(), cli.t_end, |_, _, _, ls| {
+                visited.push(DotBracketVec::from(ls));
+                true
+            });
+            for dbv in &visited {
+                basins.insert(dbv);
+            }
+        }
+    }
+
+    let sorted: Vec<_> = basins.into_sorted_basins()
+        .into_iter()
+        .filter(|b| b.members.len() >= cli.min_size)
+        .collect();
+    let paths = write_basin_files(&cli.outdir, &sequence, &sorted, 0.0)?;
+    for (basin, path) in sorted.iter().zip(&paths) {
+        println!("{} {:8.2} {:>8} {}",
+            basin.minimum,
+            basin.energy as f64 / 100.,
+            basin.members.len(),
+            path.display().to_string().cyan(),
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,123 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use clap::Parser;
+use colored::*;
+use anyhow::Result;
+use anyhow::bail;
+use ndarray::s;
+
+use rand::rng;
+use ff_energy::EnergyModel;
+use ff_kinetics::Metropolis;
+use ff_kinetics::MacrostateRegistry;
+use ff_kinetics::commit_and_delay::CommitAndDelay;
+use ff_kinetics::commit_and_delay::ExitMacrostateRegistry;
+use ff_kinetics::treekin::write_rates_text;
+use ff_kinetics::treekin::write_rates_binary;
+
+use fuzzyfold::input_parsers::read_fasta_like_input;
+use fuzzyfold::energy_parsers::EnergyModelArguments;
+use fuzzyfold::kinetics_parsers::RateModelParams;
+
+#[derive(Debug, Parser)]
+#[command(name = "ff-commit-delay")]
+#[command(version, about = "Coarse-grained macrostate rates from reactive trajectories (commit-and-delay)")]
+pub struct Cli {
+    /// Input file (FASTA-like), or "-" for stdin
+    #[arg(value_name = "INPUT", default_value = "-")]
+    input: String,
+
+    /// Macrostate files.
+    #[arg(long, value_name = "FILE", num_args = 1.., required = true)]
+    macrostates: Vec<PathBuf>,
+
+    /// Number of reactive trajectories per macrostate.
+    #[arg(short, long, default_value_t = 100)]
+    num_sims: usize,
+
+    /// Give up on reactive trajectories after this simulation time.
+    #[arg(long, default_value_t = f64::MAX)]
+    t_max: f64,
+
+    /// Number of bootstrap resamples for the error estimates.
+    #[arg(long, default_value_t = 100)]
+    bootstrap: usize,
+
+    /// Write the rate matrix to FILE (treekin text format).
+    #[arg(long, value_name = "FILE")]
+    rates: Option<PathBuf>,
+
+    /// Write the rate matrix to FILE (treekin binary format).
+    #[arg(long, value_name = "FILE")]
+    binary_rates: Option<PathBuf>,
+
+    #[command(flatten, next_help_heading = "Kinetic model parameters")]
+    kinetics: RateModelParams,
+
+    #[command(flatten, next_help_heading = "Energy model parameters")]
+    energy: EnergyModelArguments,
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    if cli.num_sims == 0 {
+        bail!("--num-sims must be > 0");
+    }
+
+    let emodel = cli.energy.build_model();
+    let rmodel = Metropolis::new(emodel.temperature(), cli.kinetics.k0);
+    let (_, sequence, _) = read_fasta_like_input(&cli.input)?;
+
+    let mut registry = MacrostateRegistry::from((&sequence, &emodel));
+    registry.insert_files(&cli.macrostates)?;
+    let exit_registry = ExitMacrostateRegistry::from((&registry, &rmodel));
+
+    let mut cad = CommitAndDelay::from(Arc::new(exit_registry))
+        .with_t_max(cli.t_max);
+    cad.gather_data(cli.num_sims);
+    let (rates, errors) = cad.to_rate_matrix_with_errors(&mut rng(), cli.bootstrap);
+
+    for (a, ms) in registry.iter().skip(1) {
+        println!("{:>3} {:>25} {:>8} {:>8}",
+            a,
+            ms.name().yellow(),
+            cad.num_trajectories(a),
+            cad.num_censored(a),
+        );
+    }
+    let censored: usize = (1..registry.len()).map(|a| cad.num_censored(a)).sum();
+    if censored > 0 {
+        eprintln!("{} {} trajectories did not reach a macrostate within --t-max, \
+            the rates are lower bounds.", "Warning:".yellow(), censored);
+    }
+    println!("{}", "rate matrix (from row to column):".cyan());
+    for a in 1..registry.len() {
+        let row: Vec<String> = (1..registry.len())
+            .map(|b| format!("{:12.4e}", rates[(a, b)]))
+            .collect();
+        println!("{}", row.join(" "));
+    }
+    println!("{}", "standard errors:".cyan());
+    for a in 1..registry.len() {
+        let row: Vec<String> = (1..registry.len())
+            .map(|b| format!("{:12.4e}", errors[(a, b)]))
+            .collect();
+        println!("{}", row.join(" "));
+    }
+
+    // Without the unassigned macrostate (index 0).
+    let rates = rates.slice(s![1.., 1..]).to_owned();
+    if let Some(path) = &cli.rates {
+        let mut fh = BufWriter::new(File::create(path)?);
+        write_rates_text(&mut fh, &rates)?;
+        fh.flush()?;
+    }
+    if let Some(path) = &cli.binary_rates {
+        let mut fh = BufWriter::new(File::create(path)?);
+        write_rates_binary(&mut fh, &rates)?;
+        fh.flush()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,161 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use clap::Parser;
+use anyhow::Result;
+use anyhow::bail;
+use anyhow::anyhow;
+use ndarray::Array1;
+
+use ff_structure::DotBracketVec;
+use ff_energy::EnergyModel;
+use ff_kinetics::Metropolis;
+use ff_kinetics::MacrostateRegistry;
+use ff_kinetics::OverlapPolicy;
+use ff_kinetics::timeline::Timeline;
+use ff_kinetics::timeline_plotting::plot_occupancy_over_time;
+use ff_kinetics::landscape::enumerate_structures;
+use ff_kinetics::master_equation::MasterEquation;
+use ff_kinetics::master_equation::macrostate_occupancies;
+use ff_kinetics::treekin::BarFile;
+use ff_kinetics::treekin::read_rates_file;
+
+use fuzzyfold::input_parsers::read_fasta_like_input;
+use fuzzyfold::input_parsers::read_structure_list_file;
+use fuzzyfold::energy_parsers::EnergyModelArguments;
+use fuzzyfold::kinetics_parsers::RateModelParams;
+use fuzzyfold::kinetics_parsers::MoveSetParameters;
+use fuzzyfold::kinetics_parsers::TimelineParameters;
+use fuzzyfold::kinetics_parsers::parse_overlap_policy;
+use fuzzyfold::output::table_writer;
+
+#[derive(Debug, Parser)]
+#[command(name = "ff-master")]
+#[command(version, about = "Exact folding kinetics from the master equation (small state spaces)")]
+pub struct Cli {
+    /// Input file (FASTA-like), or "-" for stdin. The structure is the initial state.
+    #[arg(value_name = "INPUT", default_value = "-")]
+    input: String,
+
+    /// Use all secondary structures as state space (short sequences only).
+    #[arg(long)]
+    enumerate: bool,
+
+    /// Read the state space from files (one structure per line, e.g. RNAsubopt output).
+    #[arg(long, value_name = "FILE", num_args = 1..)]
+    structures: Vec<PathBuf>,
+
+    /// Use a coarse-grained rate matrix (rows: from, columns: to) between
+    /// the macrostates, e.g. from ff-barriers --rates. Text and treekin
+    /// binary files are accepted.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["enumerate", "structures"])]
+    rate_matrix: Option<PathBuf>,
+
+    /// Initial macrostate (1-based) for --rate-matrix.
+    #[arg(long, value_name = "INDEX", default_value_t = 1)]
+    start_state: usize,
+
+    #[arg(long, value_name = "FILE", num_args = 1.., required = false)]
+    macrostates: Vec<PathBuf>,
+
+    /// Export the timeline as a table (TSV for *.tsv files, CSV otherwise).
+    #[arg(long, value_name = "FILE")]
+    table: Option<PathBuf>,
+
+    /// Use the local minima of a barriers .bar file as macrostates.
+    #[arg(long, value_name = "FILE", conflicts_with = "macrostates")]
+    bar: Option<PathBuf>,
+
+    /// Policy for structures in multiple macrostates: first (insertion
+    /// order), probability (highest P(s|macrostate)) or fractional.
+    #[arg(long, value_name = "POLICY", default_value = "first",
+        value_parser = parse_overlap_policy)]
+    overlap: OverlapPolicy,
+
+    #[command(flatten, next_help_heading = "Timeline parameters")]
+    simulation: TimelineParameters,
+
+    #[command(flatten, next_help_heading = "Kinetic model parameters")]
+    kinetics: RateModelParams,
+
+    #[command(flatten, next_help_heading = "Move set parameters")]
+    moves: MoveSetParameters,
+
+    #[command(flatten, next_help_heading = "Energy model parameters")]
+    energy: EnergyModelArguments,
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    cli.simulation.validate()?;
+    let times = cli.simulation.get_output_times();
+
+    let emodel = cli.energy.build_model();
+    let rmodel = Metropolis::new(emodel.temperature(), cli.kinetics.k0);
+    let (header, sequence, structure) = read_fasta_like_input(&cli.input)?;
+    let name = header.as_deref()
+        .and_then(|h| h.strip_prefix('>'))
+        .and_then(|s| s.split_whitespace().next())
+        .unwrap_or("anonymous")
+        .to_string();
+
+    let mut registry = if let Some(path) = &cli.bar {
+        BarFile::from_file(path)?.macrostate_registry(&sequence, &emodel)?
+    } else {
+        MacrostateRegistry::from((&sequence, &emodel))
+    }.with_overlap_policy(cli.overlap);
+    registry.insert_files(&cli.macrostates)?;
+    let registry = Arc::new(registry);
+
+    let occupancies: Vec<Vec<f64>> = if let Some(path) = &cli.rate_matrix {
+        let generator = read_rates_file(path)?;
+        let n = generator.nrows();
+        if n + 1 != registry.len() {
+            bail!("Rate matrix has {} states, but {} macrostates were given", n, registry.len() - 1);
+        }
+        if cli.start_state == 0 || cli.start_state > n {
+            bail!("--start-state must be in 1..={}", n);
+        }
+        let mut p0 = Array1::zeros(n);
+        p0[cli.start_state - 1] = 1.0;
+        MasterEquation::new(generator).occupancies(&p0, &times).into_iter()
+            .map(|p| std::iter::once(0.0).chain(p).collect())
+            .collect()
+    } else {
+        let mut states: Vec<DotBracketVec> = Vec::new();
+        if cli.enumerate {
+            enumerate_structures(&sequence, &emodel, |s| states.push(s.clone()));
+        }
+        for file in &cli.structures {
+            states.extend(read_structure_list_file(file)?);
+        }
+        if states.is_empty() {
+            bail!("No states: use --enumerate, --structures or --rate-matrix");
+        }
+        states.sort_by_cached_key(|s| s.to_string());
+        states.dedup();
+        let start = states.iter().position(|s| *s == structure)
+            .ok_or_else(|| anyhow!("Initial structure {} is not in the state space", structure))?;
+
+        let move_set = cli.moves.build_move_set()?;
+        let me = MasterEquation::from_structures(&sequence, &emodel, &rmodel, move_set, &states)
+            .map_err(|e| anyhow!(e))?;
+        let mut p0 = Array1::zeros(me.len());
+        p0[start] = 1.0;
+        me.occupancies(&p0, &times).iter()
+            .map(|p| macrostate_occupancies(&registry, &states, p))
+            .collect()
+    };
+
+    let timeline = Timeline::from_occupancies(&times, Arc::clone(&registry), &occupancies);
+    println!("{}", timeline);
+    plot_occupancy_over_time(&timeline, &format!("ff_master_{}.svg", name),
+        cli.simulation.t_ext, cli.simulation.t_end);
+
+    if let Some(path) = &cli.table {
+        let (mut fh, delimiter) = table_writer(path)?;
+        timeline.write_table(&mut fh, delimiter)?;
+        fh.flush()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,65 @@
+use std::path::PathBuf;
+use clap::Parser;
+use colored::*;
+use anyhow::Result;
+use anyhow::bail;
+
+use ff_kinetics::timeline_io::merge_timeline_files;
+use ff_kinetics::timeline_plotting::plot_occupancy_series;
+
+#[derive(Debug, Parser)]
+#[command(name = "ff-merge")]
+#[command(version, about = "Merge timeline files of independent ff-timecourse runs")]
+pub struct Cli {
+    /// Timeline files (written with ff-timecourse --timeline).
+    #[arg(value_name = "FILE", num_args = 2.., required = true)]
+    inputs: Vec<PathBuf>,
+
+    /// Write the merged timeline to this file.
+    #[arg(short, long, value_name = "FILE")]
+    output: PathBuf,
+
+    /// Plot the merged occupancies to this file.
+    #[arg(long, value_name = "FILE", default_value = "ff_merge.svg")]
+    plot: PathBuf,
+
+    /// The last time point of the linear scale (of the plot).
+    #[arg(long, default_value_t = 1e-5)]
+    t_ext: f64,
+
+    /// Confidence level of the reported occupancy intervals.
+    #[arg(long, value_name = "LEVEL", default_value_t = 0.95)]
+    confidence: f64,
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    if !(cli.confidence > 0.0 && cli.confidence < 1.0) {
+        bail!("confidence must be in (0, 1), got {}", cli.confidence);
+    }
+
+    let mut merged = merge_timeline_files(&cli.inputs)?;
+    merged.update_intervals(cli.confidence);
+    merged.to_file(&cli.output)?;
+
+    if let Some(meta) = merged.metadata() {
+        println!("{}", meta.sequence);
+        if let Some(run) = &meta.run {
+            println!("seeds: {:?}", run.seeds);
+        }
+    }
+    println!("Merged {} files: {} trajectories -> {}",
+        cli.inputs.len(),
+        merged.num_trajectories(),
+        cli.output.display().to_string().yellow());
+
+    let times = merged.times();
+    let t_end = times.last().copied().unwrap_or(0.0);
+    if t_end <= cli.t_ext {
+        bail!("t_end ({}) must be greater than t_ext ({})", t_end, cli.t_ext);
+    }
+    let title = format!("ff-merge ({} simulations)", merged.num_trajectories());
+    plot_occupancy_series(&merged.occupancy_series(), &title,
+        &cli.plot.to_string_lossy(), cli.t_ext, t_end);
+    Ok(())
+}
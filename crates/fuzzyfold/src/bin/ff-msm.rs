@@ -0,0 +1,216 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use clap::Parser;
+use anyhow::Result;
+use anyhow::bail;
+use anyhow::anyhow;
+use colored::*;
+use rayon::prelude::*;
+use rand::rng;
+use rand::SeedableRng;
+
+use ff_structure::PairTable;
+use ff_structure::DotBracketVec;
+use ff_energy::EnergyModel;
+use ff_kinetics::Metropolis;
+use ff_kinetics::LoopStructure;
+use ff_kinetics::LoopStructureSSA;
+use ff_kinetics::MacrostateRegistry;
+use ff_kinetics::OverlapPolicy;
+use ff_kinetics::checkpoint::CheckpointRng;
+use ff_kinetics::msm::DiscreteTrajectories;
+use ff_kinetics::msm::MarkovStateModel;
+
+use fuzzyfold::input_parsers::read_fasta_like_input;
+use fuzzyfold::energy_parsers::EnergyModelArguments;
+use fuzzyfold::kinetics_parsers::RateModelParams;
+use fuzzyfold::kinetics_parsers::MoveSetParameters;
+use fuzzyfold::kinetics_parsers::parse_overlap_policy;
+
+#[derive(Debug, Parser)]
+#[command(name = "ff-msm")]
+#[command(version, about = "Markov state model estimation from stochastic simulations")]
+pub struct Cli {
+    /// Input file (FASTA-like), or "-" for stdin. The structure is the initial state.
+    #[arg(value_name = "INPUT", default_value = "-")]
+    input: String,
+
+    /// Macrostate files (the states of the model, together with the unassigned state).
+    #[arg(long, value_name = "FILE", num_args = 1.., required = true)]
+    macrostates: Vec<PathBuf>,
+
+    /// Policy for structures in multiple macrostates: first (insertion
+    /// order) or probability (highest P(s|macrostate)).
+    #[arg(long, value_name = "POLICY", default_value = "first",
+        value_parser = parse_overlap_policy)]
+    overlap: OverlapPolicy,
+
+    /// Number of independent trajectories.
+    #[arg(short, long, default_value_t = 10)]
+    num_sims: usize,
+
+    /// Simulation stop time of every trajectory.
+    #[arg(long, default_value_t = 1.0)]
+    t_end: f64,
+
+    /// Sampling interval of the macrostate labels.
+    #[arg(long, default_value_t = 1e-6)]
+    dt: f64,
+
+    /// Lag time of the model (a multiple of --dt).
+    #[arg(long, default_value_t = 1e-5)]
+    lag: f64,
+
+    /// Report implied timescales also at these lag times (multiples of --dt).
+    #[arg(long, value_name = "LAG", num_args = 1.., value_delimiter = ',')]
+    its_lags: Vec<f64>,
+
+    /// Chapman-Kolmogorov test up to this multiple of the lag time (0: off).
+    #[arg(long, value_name = "K", default_value_t = 5)]
+    ck_steps: usize,
+
+    /// Seed for reproducible trajectories.
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Write the transition matrix to FILE (text format with the macrostate index,
+    /// name and stationary probability of every row; rows: from, columns: to).
+    #[arg(long, value_name = "FILE")]
+    matrix: Option<PathBuf>,
+
+    #[command(flatten, next_help_heading = "Kinetic model parameters")]
+    kinetics: RateModelParams,
+
+    #[command(flatten, next_help_heading = "Move set parameters")]
+    moves: MoveSetParameters,
+
+    #[command(flatten, next_help_heading = "Energy model parameters")]
+    energy: EnergyModelArguments,
+}
+
+/// The number of sampling steps of a lag time.
+fn lag_steps(lag: f64, dt: f64) -> Result<usize> {
+    let steps = (lag / dt).round();
+    if steps < 1.0 || ((steps * dt - lag) / lag).abs() > 1e-6 {
+        bail!("Lag time {} is not a positive multiple of dt = {}", lag, dt);
+    }
+    Ok(steps as usize)
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    if cli.overlap == OverlapPolicy::Fractional {
+        bail!("The model needs a unique label per structure, use --overlap first or probability");
+    }
+    if cli.dt <= 0.0 || cli.t_end < cli.lag {
+        bail!("--dt must be positive and --t-end at least the lag time");
+    }
+    let steps = lag_steps(cli.lag, cli.dt)?;
+
+    let emodel = cli.energy.build_model();
+    let rmodel = Metropolis::new(emodel.temperature(), cli.kinetics.k0);
+    let move_set = cli.moves.build_move_set()?;
+
+    let (header, sequence, structure) = read_fasta_like_input(&cli.input)?;
+    let pairings = PairTable::try_from(&structure)?;
+    if let Some(h) = header {
+        println!("{}", h.yellow())
+    }
+    println!("{}\n{}", sequence, structure);
+
+    let mut registry = MacrostateRegistry::from((&sequence, &emodel))
+        .with_overlap_policy(cli.overlap);
+    registry.insert_files(&cli.macrostates)?;
+    let num_samples = (cli.t_end / cli.dt).floor() as usize + 1;
+
+    let labels: Vec<Vec<usize>> = (0..cli.num_sims).into_par_iter()
+        .map(|id| {
+            let mut rng = match cli.seed {
+                Some(seed) => {
+                    let mut r = CheckpointRng::seed_from_u64(seed);
+                    r.set_stream(id as u64);
+                    r
+                }
+                None => CheckpointRng::from_rng(&mut rng()),
+            };
+            let loops = LoopStructure::try_from((&sequence[..], &pairings, &emodel))
+                .unwrap()
+                .with_move_set(move_set);
+            let mut simulator = LoopStructureSSA::from((loops, &rmodel));
+            let mut labels = Vec::with_capacity(num_samples);
+            simulator.simulate(&mut rng, cli.t_end, |t, tinc, _, ls| {
+                if labels.len() < num_samples && t + tinc >= labels.len() as f64 * cli.dt {
+                    let m = registry.classify(&DotBracketVec::from(ls));
+                    while labels.len() < num_samples && t + tinc >= labels.len() as f64 * cli.dt {
+                        labels.push(m);
+                    }
+                }
+                true
+            });
+            labels
+        })
+        .collect();
+
+    let mut data = DiscreteTrajectories::new(registry.len(), cli.dt);
+    for l in labels {
+        data.add(l);
+    }
+
+    let name = |m: usize| registry.macrostates()[m].name().to_string();
+    let msm = MarkovStateModel::estimate(&data.count_transitions(steps), cli.lag)
+        .map_err(|e| anyhow!(e))?;
+    println!("Markov state model at lag time {:.3e} ({} trajectories):", cli.lag, data.len());
+    for (a, &m) in msm.states().iter().enumerate() {
+        println!("{:>4} {:>20} {:8.5}", m, name(m), msm.stationary_distribution()[a]);
+    }
+    println!("Transition matrix (index, stationary probability | rows: from, columns: to):\n{}", msm);
+    if msm.len() < registry.len() {
+        let missing: Vec<String> = (0..registry.len())
+            .filter(|m| !msm.states().contains(m))
+            .map(name)
+            .collect();
+        println!("{} {}", "Not in the connected set:".yellow(), missing.join(", "));
+    }
+
+    let mut lags = vec![cli.lag];
+    lags.extend(cli.its_lags.iter().copied());
+    println!("{:>12} implied timescales", "lag");
+    for &lag in &lags {
+        let model = MarkovStateModel::estimate(&data.count_transitions(lag_steps(lag, cli.dt)?), lag)
+            .map_err(|e| anyhow!(e))?;
+        let its: Vec<String> = model.implied_timescales().iter()
+            .map(|t| format!("{:12.4e}", t))
+            .collect();
+        println!("{:>12.4e} {}", lag, its.join(" "));
+    }
+
+    // Beyond the simulation time, there are no transitions to compare with.
+    let ck_steps = cli.ck_steps.min((num_samples - 1) / steps);
+    if ck_steps < cli.ck_steps {
+        println!("{} Chapman-Kolmogorov test limited to {} lag times by --t-end.",
+            "Warning:".yellow(), ck_steps);
+    }
+    if ck_steps > 0 {
+        let ck = msm.chapman_kolmogorov(&data, steps, ck_steps);
+        println!("Chapman-Kolmogorov test (probability to remain in a state):");
+        println!("{:>12} {:>20} {:>10} {:>10}", "time", "macrostate", "predicted", "estimated");
+        for (k, (pred, est)) in ck.predicted.iter().zip(&ck.estimated).enumerate() {
+            for (a, &m) in ck.states.iter().enumerate() {
+                println!("{:>12.4e} {:>20} {:>10.5} {:>10.5}",
+                    (k + 1) as f64 * cli.lag, name(m), pred[a], est[a]);
+            }
+        }
+        println!("Maximal deviation: {:.5}", ck.max_error());
+    }
+
+    if let Some(path) = &cli.matrix {
+        let mut fh = BufWriter::new(File::create(path)?);
+        let names: Vec<String> = (0..registry.len()).map(name).collect();
+        msm.write_text(&mut fh, &names)?;
+        fh.flush()?;
+        println!("Transition matrix written to: {}", path.display().to_string().yellow());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,86 @@
+use clap::Parser;
+use colored::*;
+use anyhow::anyhow;
+use anyhow::Result;
+
+use rand::rng;
+use ff_energy::EnergyModel;
+use ff_kinetics::BimolecularMetropolis;
+use ff_kinetics::multistrand::MultiStrandSSA;
+use ff_kinetics::multistrand::format_complexes;
+
+use fuzzyfold::input_parsers::read_multi_fasta_like_input;
+use fuzzyfold::energy_parsers::EnergyModelArguments;
+use fuzzyfold::kinetics_parsers::RateModelParams;
+use fuzzyfold::kinetics_parsers::BimolecularRateParams;
+
+#[derive(Debug, Parser)]
+#[command(name = "ff-multistrand")]
+#[command(version, about = "Stochastic Simulation Algorithm for multi-stranded nucleic acid systems")]
+pub struct Cli {
+    /// Input file (FASTA-like, strands separated by '+'), or "-" for stdin
+    #[arg(value_name = "INPUT", default_value = "-")]
+    input: String,
+
+    /// Simulation stop time.
+    #[arg(long, default_value_t = 1.0)]
+    t_end: f64,
+
+    #[command(flatten, next_help_heading = "Kinetic model parameters")]
+    kinetics: RateModelParams,
+
+    #[command(flatten, next_help_heading = "Bimolecular rate parameters")]
+    bimolecular: BimolecularRateParams,
+
+    #[command(flatten, next_help_heading = "Energy model parameters")]
+    energy: EnergyModelArguments,
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    // --- Build simulator ---
+    let emodel = cli.energy.build_model();
+    let rmodel = BimolecularMetropolis::new(
+        emodel.temperature(),
+        cli.kinetics.k0,
+        cli.bimolecular.k_bi,
+        cli.bimolecular.concentration,
+    );
+
+    let (header, strands, pairings) = read_multi_fasta_like_input(&cli.input)?;
+    if let Some(h) = header {
+        println!("{}", h.yellow())
+    }
+    for (k, s) in strands.iter().enumerate() {
+        println!("{:>3}: {}", k, s);
+    }
+
+    let mut simulator = MultiStrandSSA::try_from((&strands[..], &pairings, &emodel, &rmodel))
+        .map_err(|e| anyhow!(e))?;
+
+    println!("{:>8} {:>14} -> {:>14} {:>15} {}",
+        "energy".green(),
+        "arivaltime".cyan(),
+        "waitingtime".cyan(),
+        "mean-waiting".cyan(),
+        "complexes".yellow(),
+    );
+
+    simulator.simulate(
+        &mut rng(), 
+        cli.t_end, 
+        |t, tinc, flux, complexes| {
+            let energy: i32 = complexes.iter().map(|c| c.energy()).sum();
+            println!("{:8.2} {:14.8e} -> {:14.8e} {:15.8e} {}",
+                energy as f64 / 100.,
+                t,
+                t + tinc,
+                1.0 / flux,
+                format_complexes(complexes),
+            );
+            true
+        },
+    );
+    Ok(())
+}
@@ -0,0 +1,112 @@
+use std::io::Write;
+use std::path::PathBuf;
+use clap::Parser;
+use anyhow::Result;
+use anyhow::bail;
+use anyhow::anyhow;
+use colored::*;
+
+use ff_energy::NucleotideVec;
+use ff_energy::EnergyModel;
+use ff_kinetics::MacrostateRegistry;
+use ff_kinetics::OverlapPolicy;
+use ff_kinetics::trajectory_io::Trajectory;
+use ff_kinetics::transition_paths::TransitionPathAnalysis;
+use ff_kinetics::transition_paths::label_trajectory;
+
+use fuzzyfold::energy_parsers::EnergyModelArguments;
+use fuzzyfold::kinetics_parsers::parse_overlap_policy;
+use fuzzyfold::output::table_writer;
+
+#[derive(Debug, Parser)]
+#[command(name = "ff-paths")]
+#[command(version, about = "Transition paths and committors from recorded trajectories")]
+pub struct Cli {
+    /// Recorded trajectories (see ff-trajectory --record).
+    #[arg(value_name = "TRAJECTORY", num_args = 1.., required = true)]
+    trajectories: Vec<PathBuf>,
+
+    /// Macrostate files used to label the trajectories.
+    #[arg(long, value_name = "FILE", num_args = 1.., required = true)]
+    macrostates: Vec<PathBuf>,
+
+    /// Policy for structures in multiple macrostates: first (insertion
+    /// order) or probability (highest P(s|macrostate)).
+    #[arg(long, value_name = "POLICY", default_value = "first",
+        value_parser = parse_overlap_policy)]
+    overlap: OverlapPolicy,
+
+    /// The source macrostate A (by name).
+    #[arg(long, value_name = "NAME")]
+    source: String,
+
+    /// The target macrostate B (by name).
+    #[arg(long, value_name = "NAME")]
+    target: String,
+
+    /// Write committor estimates to FILE (TSV for *.tsv files, CSV otherwise).
+    #[arg(long, value_name = "FILE")]
+    committors: Option<PathBuf>,
+
+    /// Print the structures of the N shortest reactive paths.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    show_paths: usize,
+
+    #[command(flatten, next_help_heading = "Energy model parameters")]
+    energy: EnergyModelArguments,
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    if cli.overlap == OverlapPolicy::Fractional {
+        bail!("Trajectories need a unique label per state, use --overlap first or probability");
+    }
+
+    let trajectories = cli.trajectories.iter()
+        .map(Trajectory::from_file)
+        .collect::<Result<Vec<_>, _>>()?;
+    let sequence = NucleotideVec::try_from(trajectories[0].header.sequence.as_str())?;
+    if let Some(t) = trajectories.iter().find(|t| t.header.sequence != trajectories[0].header.sequence) {
+        bail!("All trajectories must have the same sequence, found {}", t.header.sequence);
+    }
+
+    let emodel = cli.energy.build_model();
+    let mut registry = MacrostateRegistry::from((&sequence, &emodel))
+        .with_overlap_policy(cli.overlap);
+    registry.insert_files(&cli.macrostates)?;
+    let index_of = |name: &str| registry.iter()
+        .find(|(_, m)| m.name() == name)
+        .map(|(idx, _)| idx)
+        .ok_or_else(|| anyhow!("Macrostate '{}' not found", name));
+    let source = index_of(&cli.source)?;
+    let target = index_of(&cli.target)?;
+    if source == target {
+        bail!("Source and target macrostates must differ");
+    }
+
+    let mut analysis = TransitionPathAnalysis::new(source, target);
+    for trajectory in &trajectories {
+        analysis.add_trajectory(&label_trajectory(trajectory, &registry));
+    }
+
+    println!("{}", sequence);
+    println!("{} -> {} ({:.2} °C)", cli.source.yellow(), cli.target.yellow(), emodel.temperature());
+    print!("{}", analysis.summary(&registry));
+
+    if cli.show_paths > 0 {
+        let mut paths: Vec<_> = analysis.reactive_paths().iter().collect();
+        paths.sort_by(|a, b| a.transition_time().total_cmp(&b.transition_time()));
+        for path in paths.into_iter().take(cli.show_paths) {
+            println!("{}", path);
+        }
+    }
+
+    if let Some(path) = &cli.committors {
+        let (mut fh, delimiter) = table_writer(path)?;
+        analysis.write_committors(&mut fh, delimiter)?;
+        fh.flush()?;
+        println!("Committors written to: {}", path.display().to_string().yellow());
+    }
+
+    Ok(())
+}
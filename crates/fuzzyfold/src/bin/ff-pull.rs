@@ -0,0 +1,183 @@
+use std::io::Write;
+use std::path::PathBuf;
+use clap::Parser;
+use anyhow::Result;
+use anyhow::bail;
+use colored::*;
+use rayon::prelude::*;
+use rand::rng;
+use rand::SeedableRng;
+
+use ff_structure::PairTable;
+use ff_structure::DotBracketVec;
+use ff_energy::EnergyModel;
+use ff_kinetics::Metropolis;
+use ff_kinetics::checkpoint::CheckpointRng;
+use ff_kinetics::force::ForceModel;
+use ff_kinetics::force::plot_extension_traces;
+use ff_kinetics::piecewise::ModelSegment;
+use ff_kinetics::piecewise::SegmentedSSA;
+
+use fuzzyfold::input_parsers::read_fasta_like_input;
+use fuzzyfold::energy_parsers::EnergyModelArguments;
+use fuzzyfold::kinetics_parsers::RateModelParams;
+use fuzzyfold::kinetics_parsers::MoveSetParameters;
+use fuzzyfold::kinetics_parsers::ForceParameters;
+use fuzzyfold::output::table_writer;
+
+/// One sample of a pulling trajectory.
+struct Sample {
+    time: f64,
+    force: f64,
+    extension: f64,
+    energy: i32,
+    structure: String,
+}
+
+#[derive(Debug, Parser)]
+#[command(name = "ff-pull")]
+#[command(version, about = "Force spectroscopy: folding kinetics under a force on the ends of the molecule")]
+pub struct Cli {
+    /// Input file (FASTA-like), or "-" for stdin. The structure is the initial state.
+    #[arg(value_name = "INPUT", default_value = "-")]
+    input: String,
+
+    /// Number of independent trajectories.
+    #[arg(short, long, default_value_t = 10)]
+    num_sims: usize,
+
+    /// Simulation stop time.
+    #[arg(long, default_value_t = 1.0)]
+    t_end: f64,
+
+    /// Number of equidistant samples of every trajectory (after time zero).
+    #[arg(long, default_value_t = 1000)]
+    samples: usize,
+
+    /// Seed for reproducible trajectories.
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Write extension-versus-time traces to FILE (TSV for *.tsv files, CSV otherwise).
+    #[arg(long, value_name = "FILE")]
+    traces: Option<PathBuf>,
+
+    /// Plot the extension traces to this SVG file.
+    #[arg(long, value_name = "FILE", default_value = "ff_pull.svg")]
+    plot: PathBuf,
+
+    #[command(flatten, next_help_heading = "Force parameters")]
+    force: ForceParameters,
+
+    #[command(flatten, next_help_heading = "Kinetic model parameters")]
+    kinetics: RateModelParams,
+
+    #[command(flatten, next_help_heading = "Move set parameters")]
+    moves: MoveSetParameters,
+
+    #[command(flatten, next_help_heading = "Energy model parameters")]
+    energy: EnergyModelArguments,
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    if cli.samples == 0 || cli.num_sims == 0 {
+        bail!("--samples and --num-sims must be positive");
+    }
+
+    let emodel = cli.energy.build_model();
+    let rmodel = Metropolis::new(emodel.temperature(), cli.kinetics.k0);
+    let move_set = cli.moves.build_move_set()?;
+    let protocol = cli.force.build_protocol()?;
+    let chain = cli.force.build_chain()?;
+
+    let (header, sequence, structure) = read_fasta_like_input(&cli.input)?;
+    let pairings = PairTable::try_from(&structure)?;
+    if let Some(h) = header {
+        println!("{}", h.yellow())
+    }
+    println!("{}\n{}", sequence, structure);
+
+    // One energy model per segment of constant force.
+    let segments = protocol.segments(cli.t_end, cli.force.force_resolution);
+    let models: Vec<_> = segments.iter()
+        .map(|s| ForceModel::new(&emodel, s.force, chain))
+        .collect();
+    let ssa = SegmentedSSA::new(&sequence, segments.iter().zip(&models)
+        .map(|(s, energy_model)| ModelSegment {
+            start: s.start, end: s.end, energy_model, rate_model: &rmodel,
+        })
+        .collect()).with_move_set(move_set);
+    let times: Vec<f64> = (0..=cli.samples)
+        .map(|k| cli.t_end * k as f64 / cli.samples as f64)
+        .collect();
+    println!("Force: {:.2} pN to {:.2} pN in {} segment(s)",
+        protocol.force_at(0.0), protocol.force_at(cli.t_end), segments.len());
+
+    let traces: Vec<Vec<Sample>> = (0..cli.num_sims).into_par_iter()
+        .map(|id| -> Result<Vec<Sample>> {
+            let mut rng = match cli.seed {
+                Some(seed) => {
+                    let mut r = CheckpointRng::seed_from_u64(seed);
+                    r.set_stream(id as u64);
+                    r
+                }
+                None => CheckpointRng::from_rng(&mut rng()),
+            };
+            let mut trace = Vec::with_capacity(times.len());
+            ssa.simulate_from(&mut rng, &pairings, 0.0, cli.t_end, |seg, t, tinc, ls| {
+                let model = &models[seg];
+                while trace.len() < times.len() && t + tinc >= times[trace.len()] {
+                    let pt = PairTable::from(ls);
+                    trace.push(Sample {
+                        time: times[trace.len()],
+                        force: model.force(),
+                        extension: model.extension(&pt),
+                        energy: ls.energy(),
+                        structure: DotBracketVec::from(&pt).to_string(),
+                    });
+                }
+                true
+            }).map_err(anyhow::Error::msg)?;
+            Ok(trace)
+        })
+        .collect::<Result<_>>()?;
+
+    // Summary of the mean extension at (at most) 20 time points.
+    println!("{:>12} {:>10} {:>12} {:>10}", "time", "force", "extension", "std");
+    let stride = times.len().div_ceil(20);
+    let mut rows: Vec<usize> = (0..times.len()).step_by(stride).collect();
+    if rows.last() != Some(&(times.len() - 1)) {
+        rows.push(times.len() - 1);
+    }
+    for k in rows {
+        let ext: Vec<f64> = traces.iter().map(|t| t[k].extension).collect();
+        let mean = ext.iter().sum::<f64>() / ext.len() as f64;
+        let var = ext.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / ext.len() as f64;
+        println!("{:>12.4e} {:>10.3} {:>12.3} {:>10.3}",
+            times[k], traces[0][k].force, mean, var.sqrt());
+    }
+
+    if let Some(path) = &cli.traces {
+        let (mut fh, delimiter) = table_writer(path)?;
+        writeln!(fh, "{}", ["trajectory", "time", "force", "extension", "energy", "structure"]
+            .join(&delimiter.to_string()))?;
+        for (id, trace) in traces.iter().enumerate() {
+            for s in trace {
+                writeln!(fh, "{id}{d}{:e}{d}{:.4}{d}{:.4}{d}{:.2}{d}{}",
+                    s.time, s.force, s.extension, s.energy as f64 / 100., s.structure,
+                    d = delimiter)?;
+            }
+        }
+        fh.flush()?;
+        println!("Traces written to: {}", path.display().to_string().yellow());
+    }
+
+    let points: Vec<Vec<(f64, f64)>> = traces.iter()
+        .map(|t| t.iter().map(|s| (s.time, s.extension)).collect())
+        .collect();
+    plot_extension_traces(&points, "Extension", &cli.plot.to_string_lossy());
+    println!("Plot written to: {}", cli.plot.display().to_string().yellow());
+
+    Ok(())
+}
@@ -0,0 +1,64 @@
+use std::fs::File;
+use std::io::{stdin, stdout, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use clap::Parser;
+use anyhow::Result;
+
+use ff_kinetics::trajectory_io::Trajectory;
+use ff_kinetics::trajectory_io::TrajectoryFormat;
+
+#[derive(Debug, Parser)]
+#[command(name = "ff-replay")]
+#[command(version, about = "Replay and convert recorded trajectories (see ff-trajectory --record)")]
+pub struct Cli {
+    /// Recorded trajectory (JSON-lines or binary), or "-" for stdin
+    #[arg(value_name = "INPUT", default_value = "-")]
+    input: String,
+
+    /// Print the structures at these times instead of the full trajectory.
+    #[arg(long, value_name = "TIME", num_args = 1.., value_delimiter = ',')]
+    times: Vec<f64>,
+
+    /// Convert the trajectory and write it to FILE.
+    #[arg(long, value_name = "FILE", conflicts_with = "times")]
+    convert: Option<PathBuf>,
+
+    /// Use the binary format for --convert (default: JSON-lines).
+    #[arg(long, requires = "convert")]
+    binary: bool,
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    let trajectory = if cli.input == "-" {
+        Trajectory::read(stdin().lock())?
+    } else {
+        Trajectory::read(BufReader::new(File::open(&cli.input)?))?
+    };
+
+    if let Some(path) = &cli.convert {
+        let format = if cli.binary {
+            TrajectoryFormat::Binary
+        } else {
+            TrajectoryFormat::JsonLines
+        };
+        trajectory.write(BufWriter::new(File::create(path)?), format)?
+            .flush()?;
+        return Ok(());
+    }
+
+    let mut out = BufWriter::new(stdout().lock());
+    if cli.times.is_empty() {
+        trajectory.write_kinfold(&mut out)?;
+    } else {
+        let mut times = cli.times.clone();
+        times.sort_by(|a, b| a.total_cmp(b));
+        writeln!(out, "{}", trajectory.header.sequence)?;
+        for (t, s) in times.iter().zip(trajectory.snapshots(&times)) {
+            writeln!(out, "{} {:14.8e}", s, t)?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
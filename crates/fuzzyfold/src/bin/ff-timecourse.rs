@@ -1,30 +1,58 @@
 use clap::Parser;
 use anyhow::Result;
+use anyhow::anyhow;
 use colored::*;
 use serde_json::to_string_pretty;
 use std::fs;
+use std::io::Write;
+use std::collections::BTreeMap;
 use std::sync::Arc;
+use std::sync::Mutex;
 use std::path::Path;
 use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
 use rayon::prelude::*;
 use indicatif::ProgressBar;
 use indicatif::ProgressStyle;
 use rand::rng;
+use rand::SeedableRng;
 
 use ff_structure::PairTable;
 use ff_structure::DotBracketVec;
 use ff_energy::EnergyModel;
 use ff_kinetics::Metropolis;
-use ff_kinetics::LoopStructure;
-use ff_kinetics::LoopStructureSSA;
 use ff_kinetics::timeline::Timeline;
+use ff_kinetics::timeline_io::RunMetadata;
 use ff_kinetics::timeline_plotting::plot_occupancy_over_time;
 use ff_kinetics::MacrostateRegistry;
+use ff_kinetics::OverlapPolicy;
+use ff_kinetics::first_passage::FirstPassageTimes;
+use ff_kinetics::checkpoint::Checkpoint;
+use ff_kinetics::checkpoint::CheckpointRng;
+use ff_kinetics::checkpoint::TrajectoryCheckpoint;
+use ff_kinetics::observables::ObservableTimecourse;
+use ff_kinetics::pair_probabilities::PairProbabilities;
+use ff_kinetics::structure_discovery::StructureCounts;
+use ff_kinetics::structure_discovery::discovered_name;
+use ff_kinetics::structure_discovery::write_structure_files;
+use ff_kinetics::temperature::TemperatureSchedule;
+use ff_kinetics::piecewise::ModelSegment;
+use ff_kinetics::piecewise::SegmentedSSA;
+use ff_kinetics::landscape::BoltzmannSampler;
 
 use fuzzyfold::input_parsers::read_fasta_like_input;
 use fuzzyfold::energy_parsers::EnergyModelArguments;
 use fuzzyfold::kinetics_parsers::RateModelParams;
 use fuzzyfold::kinetics_parsers::TimelineParameters;
+use fuzzyfold::kinetics_parsers::ConfidenceParameters;
+use fuzzyfold::kinetics_parsers::FirstPassageParameters;
+use fuzzyfold::kinetics_parsers::ObservableParameters;
+use fuzzyfold::kinetics_parsers::PairProbabilityParameters;
+use fuzzyfold::kinetics_parsers::TemperatureScheduleParameters;
+use fuzzyfold::kinetics_parsers::parse_overlap_policy;
+use fuzzyfold::kinetics_parsers::MoveSetParameters;
+use fuzzyfold::output::table_writer;
 
 #[derive(Debug, Parser)]
 #[command(name = "ff-simulate")]
@@ -40,16 +68,78 @@ pub struct Cli {
     #[arg(long, value_name = "FILE", num_args = 1.., required = false)]
     macrostates: Vec<PathBuf>,
 
+    /// Policy for structures in multiple macrostates: first (insertion
+    /// order), probability (highest P(s|macrostate)) or fractional.
+    #[arg(long, value_name = "POLICY", default_value = "first",
+        value_parser = parse_overlap_policy)]
+    overlap: OverlapPolicy,
+
+    /// Track the N structures with the highest occupancy instead of
+    /// predefined macrostates (all others are reported as "other").
+    #[arg(long, value_name = "N", conflicts_with_all = ["macrostates", "resume"])]
+    discover: Option<usize>,
+
+    /// Number of structures tracked per output time for --discover (the
+    /// least frequent one is replaced when a new structure is visited).
+    #[arg(long, value_name = "N", default_value_t = 1000, requires = "discover")]
+    discover_capacity: usize,
+
+    /// Write the discovered structures as macrostate files into this directory.
+    #[arg(long, value_name = "DIR", requires = "discover")]
+    discover_dir: Option<PathBuf>,
+
     /// Backup/Store timeline in this file.
     #[arg(long, value_name = "FILE")]
     timeline: Option<PathBuf>,
 
+    /// Export the timeline as a table (TSV for *.tsv files, CSV otherwise).
+    #[arg(long, value_name = "FILE")]
+    table: Option<PathBuf>,
+
+    /// Random seed (trajectories are reproducible for a given seed).
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Store per-trajectory first-passage times in this file.
+    #[arg(long, value_name = "FILE")]
+    fpt_file: Option<PathBuf>,
+
+    /// Periodically write the state of the simulation to this file.
+    #[arg(long, value_name = "FILE")]
+    checkpoint: Option<PathBuf>,
+
+    /// Seconds between two checkpoints.
+    #[arg(long, value_name = "SECONDS", default_value_t = 60)]
+    checkpoint_interval: u64,
+
+    /// Resume the simulation from the --checkpoint file (if it exists).
+    #[arg(long, requires = "checkpoint")]
+    resume: bool,
+
     #[command(flatten, next_help_heading = "Simulation parameters")]
     simulation: TimelineParameters,
 
+    #[command(flatten, next_help_heading = "Confidence interval parameters")]
+    confidence: ConfidenceParameters,
+
+    #[command(flatten, next_help_heading = "First-passage time parameters")]
+    fpt: FirstPassageParameters,
+
+    #[command(flatten, next_help_heading = "Observable parameters")]
+    observables: ObservableParameters,
+
+    #[command(flatten, next_help_heading = "Base-pair probability parameters")]
+    pair_probs: PairProbabilityParameters,
+
     #[command(flatten, next_help_heading = "Kinetic model parameters")]
     kinetics: RateModelParams,
 
+    #[command(flatten, next_help_heading = "Temperature schedule parameters")]
+    schedule: TemperatureScheduleParameters,
+
+    #[command(flatten, next_help_heading = "Move set parameters")]
+    moves: MoveSetParameters,
+
     #[command(flatten, next_help_heading = "Energy model parameters")]
     energy: EnergyModelArguments,
 }
@@ -57,13 +147,32 @@ pub struct Cli {
 fn main() -> Result<()> {
     let cli = Cli::parse();
     cli.simulation.validate()?;
+    let confidence = cli.confidence.build_method()?;
 
     // --- Build simulator ---
     let emodel = cli.energy.build_model();
-    let rmodel = Metropolis::new(emodel.temperature(), cli.kinetics.k0);
+    let move_set = cli.moves.build_move_set()?;
+
+    // Energy and rate models for each segment of constant temperature.
+    let schedule = cli.schedule.build_schedule()?;
+    let segments = schedule.clone()
+        .unwrap_or_else(|| TemperatureSchedule::constant(emodel.temperature()))
+        .segments(cli.simulation.t_end);
+    let models: Vec<_> = segments.iter()
+        .map(|s| cli.energy.build_model_at(s.temperature))
+        .collect();
+    let rmodels: Vec<_> = segments.iter()
+        .map(|s| Metropolis::new(s.temperature, cli.kinetics.k0))
+        .collect();
+    let model_segments = segments.iter().zip(models.iter().zip(&rmodels))
+        .map(|(s, (energy_model, rate_model))| ModelSegment {
+            start: s.start, end: s.end, energy_model, rate_model,
+        })
+        .collect();
 
     let (header, sequence, structure) = read_fasta_like_input(&cli.input)?;
     let pairings = PairTable::try_from(&structure)?;
+    let ssa = SegmentedSSA::new(&sequence, model_segments).with_move_set(move_set);
 
     let name = if let Some(h) = header {
         println!("{}", h.yellow());
@@ -81,20 +190,64 @@ fn main() -> Result<()> {
         cli.num_sims, cli.kinetics, cli.simulation, cli.energy);
 
     let times = cli.simulation.get_output_times();
-    let mut registry = MacrostateRegistry::from((&sequence, &emodel));
+    let mut registry = MacrostateRegistry::from((&sequence, &emodel))
+        .with_overlap_policy(cli.overlap);
     let _ = registry.insert_files(&cli.macrostates);
 
+    let stop = cli.fpt.build_stop_condition(&registry)?;
+    let observables = cli.observables.build_observables(sequence.len())?;
+    if cli.resume && !observables.is_empty() {
+        anyhow::bail!("Observables are not stored in checkpoints (--observe conflicts with --resume).");
+    }
+    if cli.resume && cli.pair_probs.is_active() {
+        anyhow::bail!("Base-pair probabilities are not stored in checkpoints (conflicts with --resume).");
+    }
+    let occupancies = !cli.observables.observables_only;
+    if !occupancies && (cli.discover.is_some() || cli.timeline.is_some()
+        || cli.table.is_some() || !cli.macrostates.is_empty()) {
+        anyhow::bail!("--observables-only conflicts with --discover, --timeline, --table and --macrostates.");
+    }
+    if cli.discover.is_some() && cli.timeline.as_ref().is_some_and(|p| p.exists()) {
+        anyhow::bail!("Discovered structures cannot extend an existing timeline file.");
+    }
+
     println!("Macrostates:\n{}", registry.iter()
         .map(|(_, m)| format!(" - {} {:6.2}", m.name(), m.ensemble_energy().unwrap_or(0.0)))
         .collect::<Vec<_>>().join("\n"));
 
     let shared_registry = Arc::new(registry);
 
+    if schedule.is_some() {
+        println!("Temperature schedule:\n{}", segments.iter()
+            .map(|s| format!(" - [{:.3e}, {:.3e}) {:6.2} °C", s.start, s.end, s.temperature))
+            .collect::<Vec<_>>().join("\n"));
+    }
+    let sampler = if cli.schedule.equilibrium_start {
+        let t0 = schedule.as_ref().map_or(emodel.temperature(), |s| s.initial_temperature());
+        let sampler = BoltzmannSampler::new(&sequence, &cli.energy.build_model_at(t0))
+            .map_err(|e| anyhow!(e))?;
+        println!("Initial structures are sampled from {} structures at {:.2} °C (ensemble energy {:.2}).",
+            sampler.len(), t0, sampler.ensemble_energy());
+        Some(sampler)
+    } else {
+        None
+    };
+
+    let run = RunMetadata {
+        energy_parameters: cli.energy.model_parameters.as_ref().map(|p| p.display().to_string()),
+        temperature: cli.energy.temperature,
+        temperature_schedule: schedule.clone(),
+        k0: cli.kinetics.k0,
+        move_set,
+        seeds: cli.seed.into_iter().collect(),
+        software_version: env!("CARGO_PKG_VERSION").to_string(),
+    };
+
     // If timeline.json exists, reload instead of starting empty
     let mut master = if let Some(path) = &cli.timeline {
         if Path::new(path).exists() {
             println!("Loading existing timeline from: {}", path.display());
-            Timeline::from_file(path, &times, Arc::clone(&shared_registry))?
+            Timeline::from_file(path, &times, Arc::clone(&shared_registry), Some(&run))?
         } else {
             println!("A new timeline file will be created: {}", 
                 path.display());
@@ -104,8 +257,63 @@ fn main() -> Result<()> {
         Timeline::new(&times, Arc::clone(&shared_registry))
     };
 
+    // Completed trajectories of this run (and those of a resumed checkpoint).
+    let mut finished = Timeline::new(&times, Arc::clone(&shared_registry));
+    let mut fpts = FirstPassageTimes::new(cli.simulation.t_end);
+    let mut completed = 0;
+    let mut first_id = 0;
+    let mut jobs: Vec<Option<TrajectoryCheckpoint>> = Vec::new();
+    if cli.resume {
+        let path = cli.checkpoint.as_ref().expect("required by --resume");
+        if path.exists() {
+            let cp = Checkpoint::load(path)?;
+            if cp.sequence != sequence.to_string() {
+                anyhow::bail!("Checkpoint sequence does not match the input sequence.");
+            }
+            if cp.num_sims != cli.num_sims {
+                anyhow::bail!("Checkpoint was written for --num-sims {}.", cp.num_sims);
+            }
+            println!("Resuming from checkpoint: {} ({} completed, {} in progress)",
+                path.display(), cp.completed, cp.in_progress.len());
+            finished.merge(Timeline::from_serializable(cp.timeline.clone(), 
+                &times, Arc::clone(&shared_registry))?)?;
+            fpts.merge(cp.fpt.clone());
+            completed = cp.completed;
+            first_id = cp.next_id;
+            jobs.extend(std::iter::repeat_n(None, cp.num_pending()));
+            jobs.extend(cp.in_progress.into_iter().map(Some));
+        } else {
+            println!("No checkpoint found, starting from scratch: {}", path.display());
+        }
+    }
+    if jobs.is_empty() && completed == 0 {
+        jobs.extend(std::iter::repeat_n(None, cli.num_sims));
+    }
+    // New trajectories never reuse the id (and random stream) of a trajectory
+    // that was started before.
+    let next_id = first_id + jobs.iter().filter(|j| j.is_none()).count();
+
+    let state = Mutex::new(CheckpointState {
+        timeline: finished,
+        observed: ObservableTimecourse::new(&times, observables.clone()),
+        pair_probs: cli.pair_probs.is_active().then(|| PairProbabilities::new(sequence.len(), &times)),
+        structures: cli.discover.map(|_| StructureCounts::new(&times, cli.discover_capacity)),
+        fpts,
+        completed,
+        in_progress: jobs.iter().flatten().map(|j| (j.id, j.clone())).collect(),
+        next_id,
+        last_write: Instant::now(),
+    });
+    let interval = Duration::from_secs(cli.checkpoint_interval);
+    let write_checkpoint = |state: &CheckpointState<_>| -> Result<()> {
+        if let Some(path) = &cli.checkpoint {
+            state.to_checkpoint(&sequence.to_string(), cli.num_sims).save(path)?;
+        }
+        Ok(())
+    };
+
     println!("Simulation progress:");
-    let pb = ProgressBar::new(cli.num_sims as u64);
+    let pb = ProgressBar::new(jobs.len() as u64);
     pb.set_style(
         ProgressStyle::default_bar()
         .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta})")
@@ -113,52 +321,233 @@ fn main() -> Result<()> {
         .progress_chars("#>-"),
     );
 
-    let timelines: Vec<_> = (0..cli.num_sims)
-        .into_par_iter()
-        .map_init(
+    jobs.into_par_iter()
+        .enumerate()
+        .try_for_each_init(
             || pb.clone(), // each thread gets a clone
-            |pb, _| {
+            |pb, (k, job)| -> Result<()> {
                 let registry = Arc::clone(&shared_registry);
-                let mut timeline = Timeline::new(&times, registry);
-
-                let loops = LoopStructure::try_from((&sequence[..], &pairings, &emodel)).unwrap();
-                let mut simulator = LoopStructureSSA::from((loops, &rmodel));
-                let mut t_idx = 0;
-                simulator.simulate(
-                    &mut rng(),
-                    cli.simulation.t_end,
-                    |t, tinc, _, ls| {
-                        while t_idx < times.len() && t + tinc >= times[t_idx] {
-                            let structure = DotBracketVec::from(ls);
-                            timeline.assign_structure(t_idx, &structure);
-                            t_idx += 1;
-                        }
-                        true
-                    },
-                );
+                let fresh = job.is_none();
+                let (id, t_start, mut t_idx, pairings, mut rng, mut timeline) = match job {
+                    Some(cp) => (cp.id, cp.time, cp.t_idx,
+                        PairTable::try_from(cp.structure.as_str())?, cp.rng,
+                        Timeline::from_serializable(cp.timeline, &times, registry)?),
+                    None => (first_id + k, 0., 0, pairings.clone(),
+                        match cli.seed {
+                            Some(seed) => {
+                                let mut r = CheckpointRng::seed_from_u64(seed);
+                                r.set_stream((first_id + k) as u64);
+                                r
+                            }
+                            None => CheckpointRng::from_rng(&mut rng()),
+                        },
+                        Timeline::new(&times, registry)),
+                };
 
+                let mut pairings = match &sampler {
+                    Some(sampler) if fresh => PairTable::try_from(sampler.sample(&mut rng))?,
+                    _ => pairings,
+                };
+
+                let mut observed = ObservableTimecourse::new(&times, observables.clone());
+                let mut pair_probs = cli.pair_probs.is_active()
+                    .then(|| PairProbabilities::new(sequence.len(), &times));
+                let mut structures = cli.discover.map(|_| StructureCounts::new(&times, cli.discover_capacity));
+                let mut fpt = None;
+                let mut t_now = t_start;
+                let mut last_snapshot = Instant::now();
+                loop {
+                    // Pause the simulation whenever a snapshot is due. Continuing
+                    // from the snapshot is then identical to resuming from the
+                    // checkpoint (same structure, time and RNG state).
+                    let mut pause = None;
+                    pairings = ssa.simulate_from(
+                        &mut rng,
+                        &pairings,
+                        t_now,
+                        cli.simulation.t_end,
+                        |_, t, tinc, ls| {
+                            if !stop.is_empty() && (stop.is_stop_hash(ls.structure_hash())
+                                || (stop.has_macrostates()
+                                    && stop.is_stop_macrostate(timeline.registry.classify_loops(ls)))) {
+                                // The stop structure is absorbing for all remaining time points.
+                                let structure = structures.as_ref().map(|_| DotBracketVec::from(ls));
+                                while t_idx < times.len() {
+                                    if occupancies {
+                                        timeline.assign_loops(t_idx, ls);
+                                    }
+                                    observed.record_loops(t_idx, ls);
+                                    if let Some(pp) = &mut pair_probs {
+                                        pp.record_loops(t_idx, ls);
+                                    }
+                                    if let (Some(sc), Some(structure)) = (&mut structures, &structure) {
+                                        sc.record(t_idx, structure);
+                                    }
+                                    t_idx += 1;
+                                }
+                                fpt = Some(t);
+                                return false;
+                            }
+                            if cli.checkpoint.is_some() && last_snapshot.elapsed() >= interval {
+                                pause = Some(t);
+                                return false;
+                            }
+                            while t_idx < times.len() && t + tinc >= times[t_idx] {
+                                if occupancies {
+                                    timeline.assign_loops(t_idx, ls);
+                                }
+                                observed.record_loops(t_idx, ls);
+                                if let Some(sc) = &mut structures {
+                                    sc.record(t_idx, &DotBracketVec::from(ls));
+                                }
+                                if let Some(pp) = &mut pair_probs {
+                                    pp.record_loops(t_idx, ls);
+                                }
+                                t_idx += 1;
+                            }
+                            true
+                        },
+                    ).map_err(anyhow::Error::msg)?;
+                    let Some(t) = pause else { break };
+                    t_now = t;
+                    last_snapshot = Instant::now();
+
+                    let snapshot = TrajectoryCheckpoint {
+                        id, 
+                        time: t, 
+                        structure: DotBracketVec::from(&pairings).to_string(), 
+                        t_idx, 
+                        rng: rng.clone(),
+                        timeline: timeline.to_serializable(),
+                    };
+                    let mut state = state.lock().unwrap();
+                    state.in_progress.insert(id, snapshot);
+                    if state.last_write.elapsed() >= interval {
+                        state.last_write = Instant::now();
+                        write_checkpoint(&state)?;
+                    }
+                }
+
+                let mut state = state.lock().unwrap();
+                state.timeline.merge(timeline)?;
+                state.observed.merge(&observed);
+                if let (Some(total), Some(pp)) = (&mut state.pair_probs, &pair_probs) {
+                    total.merge(pp);
+                }
+                if let (Some(total), Some(sc)) = (&mut state.structures, &structures) {
+                    total.merge(sc);
+                }
+                state.fpts.add(fpt);
+                state.completed += 1;
+                state.in_progress.remove(&id);
                 pb.inc(1);
-                timeline
+                Ok(())
             },
-        ).collect();
+        )?;
     pb.finish_with_message("All simulations complete!");
 
-    // Master timeline
-    for timeline in timelines {
-        master.merge(timeline);
-    }
+    let state = state.into_inner().unwrap();
+    write_checkpoint(&state)?;
+    let CheckpointState { timeline, observed, pair_probs, structures, fpts, .. } = state;
+    let master = if let (Some(n), Some(structures)) = (cli.discover, structures) {
+        let top = structures.top_structures(n);
+        println!("Tracked {} distinct structures (at most {} per output time), reporting the top {}:",
+            structures.num_structures(), structures.capacity(), top.len());
+        for (k, (s, peak)) in top.iter().enumerate() {
+            println!(" - {:>6} {} {:6.4}", discovered_name(k), s, peak);
+        }
+        let top: Vec<DotBracketVec> = top.into_iter().map(|(s, _)| s).collect();
+        if let Some(dir) = &cli.discover_dir {
+            write_structure_files(dir, &sequence, &top)?;
+            println!("Macrostate files written to: {}", dir.display().to_string().yellow());
+        }
+        let registry = StructureCounts::registry(&top, &sequence, &emodel)
+            .map_err(|e| anyhow!(e))?;
+        structures.timeline(Arc::new(registry)).with_run_metadata(run)
+    } else {
+        master.merge(timeline.with_run_metadata(run))?;
+        master
+    };
+    let master = master.with_confidence(confidence);
 
-    println!("Final Timeline:\n{}", master);
-    plot_occupancy_over_time(&master, &format!("ff_{}.svg", name), cli.simulation.t_ext, cli.simulation.t_end);
+    if occupancies {
+        println!("Final Timeline:\n{}", master);
+        if let Some(width) = master.max_interval_width() {
+            println!("Widest confidence interval: {:.4}", width);
+        }
+    }
+    if !observed.is_empty() {
+        println!("Observables:\n{}", observed);
+    }
+    if cli.fpt.is_active() {
+        print!("{}", fpts.summary());
+    }
+    if occupancies {
+        plot_occupancy_over_time(&master, &format!("ff_{}.svg", name), cli.simulation.t_ext, cli.simulation.t_end);
+    }
 
     if let Some(path) = cli.timeline {
-        let serial = master.to_serializable();
-        let json = to_string_pretty(&serial)?;
+        master.to_file(path)?;
+    }
+
+    if let Some(path) = cli.table {
+        let (mut fh, delimiter) = table_writer(path)?;
+        master.write_table(&mut fh, delimiter)?;
+        fh.flush()?;
+    }
+
+    if let Some(path) = cli.observables.observables_file {
+        let (mut fh, delimiter) = table_writer(path)?;
+        observed.write_table(&mut fh, delimiter)?;
+        fh.flush()?;
+    }
+
+    if let Some(pair_probs) = &pair_probs {
+        if let Some(path) = &cli.pair_probs.pair_probabilities {
+            let (mut fh, delimiter) = table_writer(path)?;
+            pair_probs.write_sparse(&mut fh, delimiter, cli.pair_probs.pp_cutoff)?;
+            fh.flush()?;
+        }
+
+        for &time in &cli.pair_probs.dotplot_times {
+            let t_idx = pair_probs.closest_time_index(time).expect("output times");
+            let filename = format!("ff_{}_dp_{:.3e}.svg", name, times[t_idx]);
+            pair_probs.plot_dot_plot(t_idx, &sequence.to_string(), &filename);
+            println!("Dot plot at t = {:.3e}: {}", times[t_idx], filename.yellow());
+        }
+    }
+
+    if let Some(path) = cli.fpt_file {
+        let json = to_string_pretty(&fpts)?;
         fs::write(path, json)?;
     }
 
     Ok(())
 }
 
+/// Shared state of all trajectories, used to write checkpoints.
+struct CheckpointState<'a, E: EnergyModel> {
+    timeline: Timeline<'a, E>,
+    observed: ObservableTimecourse,
+    pair_probs: Option<PairProbabilities>,
+    structures: Option<StructureCounts>,
+    fpts: FirstPassageTimes,
+    completed: usize,
+    in_progress: BTreeMap<usize, TrajectoryCheckpoint>,
+    next_id: usize,
+    last_write: Instant,
+}
 
-
+impl<'a, E: EnergyModel> CheckpointState<'a, E> {
+    fn to_checkpoint(&self, sequence: &str, num_sims: usize) -> Checkpoint {
+        Checkpoint {
+            sequence: sequence.to_string(),
+            num_sims,
+            completed: self.completed,
+            timeline: self.timeline.to_serializable(),
+            fpt: self.fpts.clone(),
+            in_progress: self.in_progress.values().cloned().collect(),
+            next_id: self.next_id,
+        }
+    }
+}
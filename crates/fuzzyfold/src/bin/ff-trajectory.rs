@@ -1,3 +1,6 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
 use clap::Args;
 use clap::Parser;
 use colored::*;
@@ -5,13 +8,22 @@ use anyhow::Result;
 
 use rand::rng;
 use ff_structure::PairTable;
+use ff_structure::DotBracketVec;
 use ff_energy::EnergyModel;
 use ff_kinetics::LoopStructure;
 use ff_kinetics::LoopStructureSSA;
 use ff_kinetics::Metropolis;
+use ff_kinetics::MacrostateRegistry;
+use ff_kinetics::OverlapPolicy;
+use ff_kinetics::trajectory_io::TrajectoryFormat;
+use ff_kinetics::trajectory_io::TrajectoryHeader;
+use ff_kinetics::trajectory_io::TrajectoryWriter;
 
 use fuzzyfold::input_parsers::read_fasta_like_input;
 use fuzzyfold::energy_parsers::EnergyModelArguments;
+use fuzzyfold::kinetics_parsers::FirstPassageParameters;
+use fuzzyfold::kinetics_parsers::parse_overlap_policy;
+use fuzzyfold::kinetics_parsers::MoveSetParameters;
 //TODO: support seeded rng.
 
 #[derive(Debug, Args)]
@@ -33,9 +45,33 @@ pub struct Cli {
     #[arg(long, default_value_t = 1.0)]
     t_end: f64,
 
+    /// Macrostate files (required for --stop-macrostate).
+    #[arg(long, value_name = "FILE", num_args = 1.., required = false)]
+    macrostates: Vec<PathBuf>,
+
+    /// Policy for structures in multiple macrostates: first (insertion
+    /// order), probability (highest P(s|macrostate)) or fractional.
+    #[arg(long, value_name = "POLICY", default_value = "first",
+        value_parser = parse_overlap_policy)]
+    overlap: OverlapPolicy,
+
+    /// Record the trajectory (initial structure and moves) to FILE.
+    #[arg(long, value_name = "FILE")]
+    record: Option<PathBuf>,
+
+    /// Use the compact binary format for --record (default: JSON-lines).
+    #[arg(long, requires = "record")]
+    binary: bool,
+
+    #[command(flatten, next_help_heading = "First-passage time parameters")]
+    fpt: FirstPassageParameters,
+
     #[command(flatten, next_help_heading = "Kinetic model parameters")]
     kinetics: RateModelParams,
 
+    #[command(flatten, next_help_heading = "Move set parameters")]
+    moves: MoveSetParameters,
+
     #[command(flatten, next_help_heading = "Energy model parameters")]
     energy: EnergyModelArguments,
 }
@@ -46,6 +82,7 @@ fn main() -> Result<()> {
     // --- Build simulator ---
     let emodel = cli.energy.build_model();
     let rmodel = Metropolis::new(emodel.temperature(), cli.kinetics.k0);
+    let move_set = cli.moves.build_move_set()?;
 
     let (header, sequence, structure) = read_fasta_like_input(&cli.input)?;
     let pairings = PairTable::try_from(&structure)?;
@@ -60,13 +97,53 @@ fn main() -> Result<()> {
         "mean-waiting".cyan(),
     );
 
-    let loops = LoopStructure::try_from((&sequence[..], &pairings, &emodel)).unwrap();
+    let mut registry = MacrostateRegistry::from((&sequence, &emodel))
+        .with_overlap_policy(cli.overlap);
+    registry.insert_files(&cli.macrostates)?;
+    let stop = cli.fpt.build_stop_condition(&registry)?;
+
+    let loops = LoopStructure::try_from((&sequence[..], &pairings, &emodel))
+        .unwrap()
+        .with_move_set(move_set);
     let mut simulator = LoopStructureSSA::from((loops, &rmodel));
 
+    let mut recorder = match &cli.record {
+        Some(path) => {
+            let format = if cli.binary { 
+                TrajectoryFormat::Binary 
+            } else { 
+                TrajectoryFormat::JsonLines 
+            };
+            let header = TrajectoryHeader {
+                sequence: sequence.to_string(),
+                structure: structure.to_string(),
+                energy: emodel.energy_of_structure(&sequence, &pairings),
+            };
+            Some(TrajectoryWriter::new(BufWriter::new(File::create(path)?), format, &header)?)
+        }
+        None => None,
+    };
+    let mut record_error = None;
+
+    let mut fpt = None;
     simulator.simulate(
         &mut rng(), 
         cli.t_end, 
         |t, tinc, flux, ls| {
+            if let Some(rec) = recorder.as_mut()
+                && let Err(e) = rec.record(t, &DotBracketVec::from(ls), ls.energy()) {
+                record_error = Some(e);
+                return false;
+            }
+            if !stop.is_empty() {
+                let structure = DotBracketVec::from(ls);
+                if stop.is_stop_structure(&structure) || (stop.has_macrostates() 
+                    && stop.is_stop_macrostate(registry.classify(&structure))) {
+                    println!("{} {:8.2} {:14.8e}", ls, ls.energy() as f64 / 100., t);
+                    fpt = Some(t);
+                    return false;
+                }
+            }
             println!("{} {:8.2} {:14.8e} -> {:14.8e} {:15.8e}",
                 ls,
                 ls.energy() as f64 / 100.,
@@ -77,6 +154,20 @@ fn main() -> Result<()> {
             true
         },
     );
+
+    if let Some(e) = record_error {
+        return Err(e.into());
+    }
+    if let Some(rec) = recorder {
+        rec.finish()?;
+    }
+
+    if cli.fpt.is_active() {
+        match fpt {
+            Some(t) => println!("{} {:14.8e}", "first-passage time:".yellow(), t),
+            None => println!("{} > {:14.8e}", "first-passage time:".yellow(), cli.t_end),
+        }
+    }
     Ok(())
 }
 
@@ -0,0 +1,191 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use clap::Parser;
+use anyhow::Result;
+use anyhow::bail;
+use anyhow::anyhow;
+use colored::*;
+use indicatif::ProgressBar;
+use indicatif::ProgressStyle;
+use rand::rng;
+use rand::SeedableRng;
+
+use ff_structure::PairTable;
+use ff_energy::EnergyModel;
+use ff_kinetics::Metropolis;
+use ff_kinetics::MacrostateRegistry;
+use ff_kinetics::OverlapPolicy;
+use ff_kinetics::checkpoint::CheckpointRng;
+use ff_kinetics::weighted_ensemble::LoopBinning;
+use ff_kinetics::weighted_ensemble::DistanceBins;
+use ff_kinetics::weighted_ensemble::WeightedEnsemble;
+
+use fuzzyfold::input_parsers::read_fasta_like_input;
+use fuzzyfold::energy_parsers::EnergyModelArguments;
+use fuzzyfold::kinetics_parsers::RateModelParams;
+use fuzzyfold::kinetics_parsers::MoveSetParameters;
+use fuzzyfold::kinetics_parsers::parse_overlap_policy;
+use fuzzyfold::output::table_writer;
+
+#[derive(Debug, Parser)]
+#[command(name = "ff-weighted-ensemble")]
+#[command(version, about = "Weighted-ensemble rate estimates for rare folding transitions")]
+pub struct Cli {
+    /// Input file (FASTA-like), or "-" for stdin. The structure is the initial state.
+    #[arg(value_name = "INPUT", default_value = "-")]
+    input: String,
+
+    /// Bin by macrostates (the unassigned macrostate is a bin as well).
+    #[arg(long, value_name = "FILE", num_args = 1.., requires = "target")]
+    macrostates: Vec<PathBuf>,
+
+    /// Policy for structures in multiple macrostates: first (insertion
+    /// order) or probability (highest P(s|macrostate)).
+    #[arg(long, value_name = "POLICY", default_value = "first",
+        value_parser = parse_overlap_policy)]
+    overlap: OverlapPolicy,
+
+    /// The target macrostate (by name).
+    #[arg(long, value_name = "NAME", requires = "macrostates")]
+    target: Option<String>,
+
+    /// Bin by base-pair distance to this target structure.
+    #[arg(long, value_name = "DBR", conflicts_with_all = ["macrostates", "target"])]
+    target_structure: Option<String>,
+
+    /// Width of the distance bins (the target structure is a bin on its own).
+    #[arg(long, value_name = "PAIRS", default_value_t = 1, requires = "target_structure")]
+    bin_width: usize,
+
+    /// Resampling interval.
+    #[arg(long, default_value_t = 1e-6)]
+    tau: f64,
+
+    /// Number of resampling iterations.
+    #[arg(long, default_value_t = 1000)]
+    iterations: usize,
+
+    /// Number of walkers per occupied bin.
+    #[arg(long, default_value_t = 4)]
+    walkers: usize,
+
+    /// Iterations excluded from the rate estimate (default: the first 20%).
+    #[arg(long)]
+    burn_in: Option<usize>,
+
+    /// Seed for reproducible simulations.
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Write the flux into the target per iteration to FILE (TSV for *.tsv files, CSV otherwise).
+    #[arg(long, value_name = "FILE")]
+    flux: Option<PathBuf>,
+
+    #[command(flatten, next_help_heading = "Kinetic model parameters")]
+    kinetics: RateModelParams,
+
+    #[command(flatten, next_help_heading = "Move set parameters")]
+    moves: MoveSetParameters,
+
+    #[command(flatten, next_help_heading = "Energy model parameters")]
+    energy: EnergyModelArguments,
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    if cli.tau <= 0.0 || cli.walkers == 0 {
+        bail!("--tau and --walkers must be positive");
+    }
+
+    let emodel = cli.energy.build_model();
+    let rmodel = Metropolis::new(emodel.temperature(), cli.kinetics.k0);
+    let move_set = cli.moves.build_move_set()?;
+
+    let (header, sequence, structure) = read_fasta_like_input(&cli.input)?;
+    if let Some(h) = header {
+        println!("{}", h.yellow())
+    }
+    println!("{}\n{}", sequence, structure);
+
+    let mut registry = MacrostateRegistry::from((&sequence, &emodel))
+        .with_overlap_policy(cli.overlap);
+    let (binning, target): (Arc<dyn LoopBinning<_>>, usize) = match (&cli.target, &cli.target_structure) {
+        (Some(name), _) => {
+            if cli.overlap == OverlapPolicy::Fractional {
+                bail!("Bins need a unique label per structure, use --overlap first or probability");
+            }
+            registry.insert_files(&cli.macrostates)?;
+            let target = registry.iter()
+                .find(|(_, m)| m.name() == name)
+                .map(|(idx, _)| idx)
+                .ok_or_else(|| anyhow!("Macrostate '{}' not found", name))?;
+            (Arc::new(registry), target)
+        }
+        (None, Some(s)) => {
+            let reference = PairTable::try_from(s.as_str())?;
+            if reference.len() != sequence.len() {
+                bail!("Target structure length ({}) does not match sequence length ({})",
+                    reference.len(), sequence.len());
+            }
+            (Arc::new(DistanceBins::uniform(reference, cli.bin_width)), 0)
+        }
+        (None, None) => bail!("Specify bins with --macrostates and --target, or --target-structure"),
+    };
+    if binning.bin(&structure) == target {
+        bail!("The initial structure is already in the target bin");
+    }
+    println!("Target: {} ({} bins)", binning.bin_name(target).yellow(), binning.num_bins());
+
+    let mut rng = match cli.seed {
+        Some(seed) => CheckpointRng::seed_from_u64(seed),
+        None => CheckpointRng::from_rng(&mut rng()),
+    };
+    let mut we = WeightedEnsemble::new(&sequence, &emodel, &rmodel,
+        Arc::clone(&binning), target, structure, cli.tau)
+        .with_move_set(move_set)
+        .with_walkers_per_bin(cli.walkers);
+
+    let pb = ProgressBar::new(cli.iterations as u64);
+    pb.set_style(ProgressStyle::with_template(
+        "[{elapsed_precise}] [{bar:40.cyan/blue}] {pos:>7}/{len:7} {msg}")
+        .unwrap()
+        .progress_chars("##-"));
+    let mut num_walkers = Vec::with_capacity(cli.iterations);
+    for _ in 0..cli.iterations {
+        we.step(&mut rng);
+        num_walkers.push(we.walkers().len());
+        pb.set_message(format!("{} walkers", we.walkers().len()));
+        pb.inc(1);
+    }
+    pb.finish_and_clear();
+
+    println!("{:>20} {:>12}", "bin", "weight");
+    for (bin, w) in we.bin_weights().into_iter().enumerate().filter(|&(_, w)| w > 0.0) {
+        println!("{:>20} {:12.4e}", binning.bin_name(bin), w);
+    }
+
+    let burn_in = cli.burn_in.unwrap_or(cli.iterations / 5);
+    match we.rate(burn_in) {
+        Some((rate, sem)) if rate > 0.0 => {
+            println!("{} {:.4e} +/- {:.4e} /s", "Rate:".yellow(), rate, sem);
+            println!("{} {:.4e} s", "Mean first-passage time:".yellow(), 1.0 / rate);
+        }
+        Some(_) => println!("{} no flux into the target after {:.4e} s",
+            "Rate:".yellow(), we.time()),
+        None => bail!("--burn-in ({}) must be smaller than --iterations ({})", burn_in, cli.iterations),
+    }
+
+    if let Some(path) = &cli.flux {
+        let (mut fh, delimiter) = table_writer(path)?;
+        writeln!(fh, "iteration{d}time{d}flux{d}walkers", d = delimiter)?;
+        for (k, (flux, n)) in we.fluxes().iter().zip(&num_walkers).enumerate() {
+            writeln!(fh, "{}{d}{:e}{d}{:e}{d}{}",
+                k + 1, (k + 1) as f64 * cli.tau, flux, n, d = delimiter)?;
+        }
+        fh.flush()?;
+        println!("Flux written to: {}", path.display().to_string().yellow());
+    }
+
+    Ok(())
+}
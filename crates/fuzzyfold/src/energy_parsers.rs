@@ -17,15 +17,20 @@ pub struct EnergyModelArguments {
 
 impl EnergyModelArguments {
     pub fn build_model(&self) -> ViennaRNA {
+        self.build_model_at(self.temperature)
+    }
+
+    /// Build the model at a different temperature (e.g. for temperature schedules).
+    pub fn build_model_at(&self, temperature: f64) -> ViennaRNA {
         debug!("Using parameter file: {:?}", self.model_parameters);
-        debug!("Temperature: {} °C", self.temperature);
+        debug!("Temperature: {} °C", temperature);
         let mut model = if let Some(path) = &self.model_parameters {
             ViennaRNA::from_parameter_file(path)
                 .expect("Failed to load parameter file")
         } else {
             ViennaRNA::default()
         };
-        model.set_temperature(self.temperature);
+        model.set_temperature(temperature);
         model
     }
 }
@@ -4,7 +4,9 @@ use std::path::Path;
 
 use anyhow::{anyhow, Result};
 use paste::paste;
+use ff_structure::PairTable;
 use ff_structure::DotBracketVec;
+use ff_structure::MultiPairTable;
 use ff_energy::NucleotideVec;
 
 // ============================================================
@@ -84,6 +86,98 @@ pub fn read_eval<R: BufRead>(reader: R) -> Result<(Option<String>, NucleotideVec
     parse_fasta_like(reader, FastaMode::Strict)
 }
 
+/// Read a list of structures, one per line (the first token of each line),
+/// e.g. RNAsubopt output. Lines that are not dot-bracket strings (headers,
+/// sequences) are skipped, structures with unbalanced brackets are an error.
+pub fn read_structure_list<R: BufRead>(reader: R) -> Result<Vec<DotBracketVec>> {
+    let mut structures = Vec::new();
+    for (lineno, line) in reader.lines().enumerate() {
+        let line = line?;
+        let Some(token) = line.split_whitespace().next() else {
+            continue;
+        };
+        if let Ok(dbv) = DotBracketVec::try_from(token) {
+            PairTable::try_from(&dbv)
+                .map_err(|e| anyhow!("Invalid structure at line {}: {}", lineno + 1, e))?;
+            structures.push(dbv);
+        }
+    }
+    Ok(structures)
+}
+
+/// Check that all structures (e.g. from [`read_structure_list`]) have the
+/// length of the sequence.
+pub fn check_structure_lengths(structures: &[DotBracketVec], length: usize, source: &str
+) -> Result<()> {
+    match structures.iter().find(|s| s.len() != length) {
+        Some(s) => Err(anyhow!("Structure length mismatch in {}: {} (expected length {})",
+            source, s, length)),
+        None => Ok(()),
+    }
+}
+
+/// Multi-stranded variant of [`read_fasta_like`]: strands are separated by 
+/// '+' (or '&') in both the sequence and the (optional) structure line.
+pub fn read_multi_fasta_like<R: BufRead>(reader: R) -> Result<(Option<String>, Vec<NucleotideVec>, MultiPairTable)> {
+    let mut header: Option<String> = None;
+    let mut sequence: Option<String> = None;
+    let mut structure: Option<String> = None;
+
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            if sequence.is_some() && structure.is_some() {
+                break;
+            } else {
+                continue;
+            }
+        }
+
+        if line.starts_with('>') {
+            header = Some(line.to_string());
+        } else if sequence.is_none() {
+            sequence = Some(line.split_whitespace().next().unwrap().to_string());
+        } else if structure.is_none() {
+            structure = Some(line.split_whitespace().next().unwrap().to_string());
+            break;
+        }
+    }
+
+    let sequence = sequence.ok_or_else(|| anyhow!("Missing sequence line"))?;
+    let strands: Vec<NucleotideVec> = sequence
+        .split(['+', '&'])
+        .map(NucleotideVec::from_lossy)
+        .collect();
+    if strands.iter().any(|s| s.is_empty()) {
+        return Err(anyhow!("Empty strand in sequence '{}'", sequence));
+    }
+
+    let structure = structure.unwrap_or_else(|| strands.iter()
+        .map(|s| ".".repeat(s.len()))
+        .collect::<Vec<_>>()
+        .join("+"));
+    let pairings = MultiPairTable::try_from(structure.as_str())?;
+
+    if pairings.num_strands() != strands.len() {
+        return Err(anyhow!(
+            "Number of strands in sequence ({}) and structure ({}) do not match",
+            strands.len(),
+            pairings.num_strands()
+        ));
+    }
+    for (k, (seq, pt)) in strands.iter().zip(&pairings.0).enumerate() {
+        if seq.len() != pt.len() {
+            return Err(anyhow!(
+                "Strand {}: sequence length ({}) and structure length ({}) do not match",
+                k, seq.len(), pt.len()
+            ));
+        }
+    }
+
+    Ok((header, strands, pairings))
+}
+
 // ============================================================
 //  Macro generating file/string/stdin/input helpers
 // ============================================================
@@ -141,6 +235,12 @@ type FastaResult = Result<(Option<String>, NucleotideVec, DotBracketVec)>;
 define_input_variants!(read_fasta_like, FastaResult);
 define_input_variants!(read_eval, FastaResult);
 
+type MultiFastaResult = Result<(Option<String>, Vec<NucleotideVec>, MultiPairTable)>;
+
+define_input_variants!(read_multi_fasta_like, MultiFastaResult);
+
+define_input_variants!(read_structure_list, Result<Vec<DotBracketVec>>);
+
 // ============================================================
 //  Example helper: ruler()
 // ============================================================
@@ -201,5 +301,34 @@ mod tests {
         let err = read_eval_string(missing);
         assert!(err.is_err(), "Missing structure line should fail in strict mode");
     }
+
+    #[test]
+    fn test_read_structure_list() {
+        let input = ">subopt\nGGGAAACCC -1.20 0.00\n(((...))) -1.20\n.........  0.00\n";
+        let structures = read_structure_list_string(input).unwrap();
+        assert_eq!(structures.len(), 2);
+        assert!(check_structure_lengths(&structures, 9, "input").is_ok());
+        assert!(check_structure_lengths(&structures, 10, "input").is_err());
+
+        let err = read_structure_list_string("(((...)))\n((((....))).\n").unwrap_err();
+        assert!(err.to_string().contains("line 2"));
+    }
+
+    #[test]
+    fn test_read_multi_fasta_like() {
+        let input = ">duplex\nGGGAA+UUCCC\n(((..+..)))\n";
+        let (hdr, strands, pt) = read_multi_fasta_like_string(input).unwrap();
+        assert_eq!(hdr, Some(">duplex".into()));
+        assert_eq!(strands.len(), 2);
+        assert_eq!(strands[1].to_string(), "UUCCC");
+        assert_eq!(*pt.get_pair((0, 0)), Some((1, 4)));
+
+        let (_, strands, pt) = read_multi_fasta_like_string("GGG&CC\n").unwrap();
+        assert_eq!(strands.len(), 2);
+        assert_eq!(pt.len(), 5);
+
+        let mismatch = "GGGAA+UUCCC\n(((...+.)))\n";
+        assert!(read_multi_fasta_like_string(mismatch).is_err());
+    }
 }
 
@@ -1,6 +1,25 @@
+use std::sync::Arc;
+use std::path::PathBuf;
 use clap::Args;
 use anyhow::Result;
 use anyhow::bail;
+use anyhow::anyhow;
+use ff_structure::DotBracketVec;
+use ff_structure::PairTable;
+use ff_energy::EnergyModel;
+use ff_kinetics::MacrostateRegistry;
+use ff_kinetics::OverlapPolicy;
+use ff_kinetics::reaction::MoveSet;
+use ff_kinetics::first_passage::StopCondition;
+use ff_kinetics::timeline_statistics::ConfidenceMethod;
+use ff_kinetics::observables::Observable;
+use ff_kinetics::observables::Energy;
+use ff_kinetics::observables::BasePairCount;
+use ff_kinetics::observables::PairingProbability;
+use ff_kinetics::observables::TargetDistance;
+use ff_kinetics::temperature::TemperatureSchedule;
+use ff_kinetics::force::ForceProtocol;
+use ff_kinetics::force::ChainParameters;
 
 #[derive(Debug, Args)]
 pub struct RateModelParams {
@@ -9,6 +28,40 @@ pub struct RateModelParams {
     pub k0: f64,
 }
 
+#[derive(Debug, Args)]
+pub struct BimolecularRateParams {
+    /// Bimolecular rate constant of join moves (/M/s).
+    #[arg(long, default_value_t = 1e6)]
+    pub k_bi: f64,
+
+    /// Strand concentration (M).
+    #[arg(long, default_value_t = 1e-6)]
+    pub concentration: f64,
+}
+
+#[derive(Debug, Args)]
+pub struct MoveSetParameters {
+    /// Enable shift moves (a base-pair slides one partner to a new position).
+    #[arg(long)]
+    pub shift_moves: bool,
+
+    /// Enable zipping/unzipping of helices with this many stacked pairs (>= 2).
+    #[arg(long, value_name = "LEN", default_value_t = 0)]
+    pub helix_length: usize,
+}
+
+impl MoveSetParameters {
+    pub fn build_move_set(&self) -> Result<MoveSet> {
+        if self.helix_length == 1 {
+            bail!("helix_length must be >= 2 (or 0 to disable helix moves)");
+        }
+        Ok(MoveSet {
+            shift: self.shift_moves,
+            helix_length: self.helix_length,
+        })
+    }
+}
+
 #[derive(Debug, Args)]
 pub struct TimelineParameters {
     /// The last time point of the linear scale.
@@ -70,3 +123,246 @@ impl TimelineParameters {
 }
 
 
+#[derive(Debug, Args)]
+pub struct ConfidenceParameters {
+    /// Confidence level of the reported occupancy intervals.
+    #[arg(long, value_name = "LEVEL", default_value_t = 0.95)]
+    pub confidence: f64,
+
+    /// Use bootstrap intervals with this many resamples (0: binomial intervals).
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub bootstrap: usize,
+
+    /// Random seed for bootstrap resampling.
+    #[arg(long, default_value_t = 0)]
+    pub bootstrap_seed: u64,
+}
+
+impl ConfidenceParameters {
+    pub fn build_method(&self) -> Result<ConfidenceMethod> {
+        if !(self.confidence > 0.0 && self.confidence < 1.0) {
+            bail!("confidence must be in (0, 1), got {}", self.confidence);
+        }
+        Ok(if self.bootstrap == 0 {
+            ConfidenceMethod::Wilson { level: self.confidence }
+        } else {
+            ConfidenceMethod::Bootstrap {
+                level: self.confidence,
+                resamples: self.bootstrap,
+                seed: self.bootstrap_seed,
+            }
+        })
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct ObservableParameters {
+    /// Observables averaged over trajectories: energy, pairs, pairing
+    /// (per position) or distance (to --target).
+    #[arg(long, value_name = "NAME", num_args = 1..)]
+    pub observe: Vec<String>,
+
+    /// Target structure for the distance observable.
+    #[arg(long, value_name = "DBR")]
+    pub target: Option<String>,
+
+    /// Write mean and variance of all observables to this file (TSV for *.tsv files, CSV otherwise).
+    #[arg(long, value_name = "FILE")]
+    pub observables_file: Option<PathBuf>,
+
+    /// Record only the observables, without macrostate occupancies.
+    #[arg(long, requires = "observe")]
+    pub observables_only: bool,
+}
+
+impl ObservableParameters {
+    pub fn build_observables(&self, length: usize) -> Result<Vec<Arc<dyn Observable>>> {
+        self.observe.iter().map(|name| -> Result<Arc<dyn Observable>> {
+            Ok(match name.as_str() {
+                "energy" => Arc::new(Energy),
+                "pairs" => Arc::new(BasePairCount),
+                "pairing" => Arc::new(PairingProbability { length }),
+                "distance" => {
+                    let target = self.target.as_deref()
+                        .ok_or_else(|| anyhow!("The distance observable requires --target"))?;
+                    if target.len() != length {
+                        bail!("Target structure length mismatch: {}", target);
+                    }
+                    Arc::new(TargetDistance::new(PairTable::try_from(target)?))
+                }
+                _ => bail!("Invalid observable '{}' (use energy, pairs, pairing or distance)", name),
+            })
+        }).collect()
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct PairProbabilityParameters {
+    /// Write base-pair (and unpaired) probabilities at each output time
+    /// as sparse matrices (TSV for *.tsv files, CSV otherwise).
+    #[arg(long, value_name = "FILE")]
+    pub pair_probabilities: Option<PathBuf>,
+
+    /// Omit probabilities below this value from the --pair-probabilities file.
+    #[arg(long, value_name = "P", default_value_t = 0.0)]
+    pub pp_cutoff: f64,
+
+    /// Draw dot plots at the output times closest to these times.
+    #[arg(long, value_name = "TIME", num_args = 1..)]
+    pub dotplot_times: Vec<f64>,
+}
+
+impl PairProbabilityParameters {
+    /// True if pair probabilities need to be accumulated.
+    pub fn is_active(&self) -> bool {
+        self.pair_probabilities.is_some() || !self.dotplot_times.is_empty()
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct TemperatureScheduleParameters {
+    /// Temperature breakpoints (e.g. 0:37 1e-3:80 2e-3:37). The schedule
+    /// replaces --temperature for the kinetics, macrostate energies are
+    /// evaluated at --temperature.
+    #[arg(long = "temperature-schedule", value_name = "TIME:CELSIUS", num_args = 1..,
+        value_parser = parse_breakpoint)]
+    pub breakpoints: Vec<(f64, f64)>,
+
+    /// Linear temperature ramps between breakpoints (default: jumps).
+    #[arg(long, requires = "breakpoints")]
+    pub ramp: bool,
+
+    /// Maximal temperature change (°C) between two rate updates of a ramp.
+    #[arg(long, value_name = "CELSIUS", default_value_t = 1.0)]
+    pub ramp_resolution: f64,
+
+    /// Sample initial structures from the equilibrium ensemble at the
+    /// starting temperature (enumerates all structures, at most two million).
+    #[arg(long)]
+    pub equilibrium_start: bool,
+}
+
+impl TemperatureScheduleParameters {
+    /// The schedule, or None if no breakpoints were given.
+    pub fn build_schedule(&self) -> Result<Option<TemperatureSchedule>> {
+        if self.breakpoints.is_empty() {
+            return Ok(None);
+        }
+        let schedule = if self.ramp {
+            TemperatureSchedule::linear(self.breakpoints.clone(), self.ramp_resolution)
+        } else {
+            TemperatureSchedule::stepwise(self.breakpoints.clone())
+        };
+        schedule.map(Some).map_err(|e| anyhow!(e))
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct ForceParameters {
+    /// Force on the ends of the molecule at time zero (pN).
+    #[arg(long, value_name = "PN", default_value_t = 0.0)]
+    pub force: f64,
+
+    /// Increase the force at this loading rate (pN/s, 0: constant force).
+    #[arg(long, value_name = "PN_PER_S", default_value_t = 0.0)]
+    pub loading_rate: f64,
+
+    /// Maximal force change (pN) between two rate updates of a force ramp.
+    #[arg(long, value_name = "PN", default_value_t = 0.1)]
+    pub force_resolution: f64,
+
+    /// Kuhn length of single-stranded RNA (nm).
+    #[arg(long, value_name = "NM", default_value_t = 1.9)]
+    pub kuhn_length: f64,
+
+    /// Contour length per nucleotide (nm).
+    #[arg(long, value_name = "NM", default_value_t = 0.7)]
+    pub nt_length: f64,
+
+    /// Length of an exterior helix along the force (nm).
+    #[arg(long, value_name = "NM", default_value_t = 2.0)]
+    pub helix_width: f64,
+}
+
+impl ForceParameters {
+    pub fn build_protocol(&self) -> Result<ForceProtocol> {
+        if self.force < 0.0 || self.loading_rate < 0.0 {
+            bail!("force and loading rate must be non-negative");
+        }
+        if self.force_resolution <= 0.0 {
+            bail!("force resolution must be positive, got {}", self.force_resolution);
+        }
+        Ok(ForceProtocol { initial_force: self.force, loading_rate: self.loading_rate })
+    }
+
+    pub fn build_chain(&self) -> Result<ChainParameters> {
+        if self.kuhn_length <= 0.0 || self.nt_length <= 0.0 || self.helix_width < 0.0 {
+            bail!("chain lengths must be positive");
+        }
+        Ok(ChainParameters {
+            kuhn_length: self.kuhn_length,
+            nt_length: self.nt_length,
+            helix_width: self.helix_width,
+        })
+    }
+}
+
+/// Parse a temperature breakpoint TIME:CELSIUS.
+pub fn parse_breakpoint(s: &str) -> Result<(f64, f64), String> {
+    let (t, temp) = s.split_once(':')
+        .ok_or_else(|| format!("Invalid breakpoint '{}' (use TIME:CELSIUS)", s))?;
+    let t = t.trim().parse::<f64>().map_err(|e| format!("Invalid time '{}': {}", t, e))?;
+    let temp = temp.trim().parse::<f64>().map_err(|e| format!("Invalid temperature '{}': {}", temp, e))?;
+    Ok((t, temp))
+}
+
+/// Parse the policy for structures in overlapping macrostates.
+pub fn parse_overlap_policy(s: &str) -> Result<OverlapPolicy, String> {
+    match s {
+        "first" => Ok(OverlapPolicy::FirstMatch),
+        "probability" => Ok(OverlapPolicy::HighestProbability),
+        "fractional" => Ok(OverlapPolicy::Fractional),
+        _ => Err(format!("Invalid overlap policy '{}' (use first, probability or fractional)", s)),
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct FirstPassageParameters {
+    /// Stop a trajectory when it enters this structure (first-passage time mode).
+    #[arg(long = "stop-structure", value_name = "DBR", num_args = 1..)]
+    pub stop_structures: Vec<String>,
+
+    /// Stop a trajectory when it enters this macrostate (by name).
+    #[arg(long = "stop-macrostate", value_name = "NAME", num_args = 1..)]
+    pub stop_macrostates: Vec<String>,
+}
+
+impl FirstPassageParameters {
+    /// True if any stop structures or stop macrostates were given.
+    pub fn is_active(&self) -> bool {
+        !self.stop_structures.is_empty() || !self.stop_macrostates.is_empty()
+    }
+
+    /// Build the stop condition, resolving macrostate names in the registry.
+    pub fn build_stop_condition<E: EnergyModel>(&self, 
+        registry: &MacrostateRegistry<'_, E>
+    ) -> Result<StopCondition> {
+        let mut stop = StopCondition::new();
+        for s in &self.stop_structures {
+            let structure = DotBracketVec::try_from(s.as_str())?;
+            if structure.len() != registry.sequence().len() {
+                bail!("Stop structure length ({}) does not match sequence length ({})",
+                    structure.len(), registry.sequence().len());
+            }
+            stop.add_structure(structure)
+                .map_err(|e| anyhow!("Invalid stop structure '{}': {}", s, e))?;
+        }
+        for name in &self.stop_macrostates {
+            let (idx, _) = registry.iter()
+                .find(|(_, m)| m.name() == name)
+                .ok_or_else(|| anyhow!("Stop macrostate '{}' not found", name))?;
+            stop.add_macrostate(idx);
+        }
+        Ok(stop)
+    }
+}
@@ -35,3 +35,6 @@ pub mod energy_parsers;
 /// Exposing the currently supported parameters of fuzzyfold's rate models and simulation parameters.
 pub mod kinetics_parsers;
 
+
+/// Helpers for the files written by the binaries.
+pub mod output;
@@ -0,0 +1,12 @@
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// Create a buffered writer for a table file, together with its column
+/// delimiter: TSV for *.tsv files, CSV otherwise.
+pub fn table_writer<P: AsRef<Path>>(path: P) -> io::Result<(BufWriter<File>, char)> {
+    let path = path.as_ref();
+    let delimiter = if path.extension().is_some_and(|e| e == "tsv") { '\t' } else { ',' };
+    Ok((BufWriter::new(File::create(path)?), delimiter))
+}